@@ -1,41 +1,165 @@
 use std::fs;
 use std::io::{self, Write, Read};
 use std::net::UdpSocket;
-use udp_dtls::{Certificate, DtlsConnector, UdpChannel, SrtpProfile};
-use std::time::{Duration, Instant};
+use std::path::PathBuf;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::collections::HashMap;
+use udp_dtls::{Certificate, DtlsConnector, DtlsStream, SrtpProfile};
+use std::time::{Duration, Instant};
 use csv::Writer;
-use serde_json::json;
+use serde_json::{json, Value};
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+use ons_common::clock::{DelayBreakdown, EchoTimestamps, OneWayDelay};
+use ons_common::dashboard::{Dashboard, DashboardOptions};
+use ons_common::handshake::{Handshake, HandshakeBench, HandshakeOptions, PhaseTimer};
+use ons_common::histogram::{HistogramOptions, LatencyHistogram};
+use ons_common::manifest::{ManifestOptions, RunManifest};
+use ons_common::metrics::{Metrics, MetricsOptions, Side};
+use ons_common::outstanding::{DrainOptions, Echo, OutstandingTicks};
+use ons_common::receive::{self, Arrival};
+use ons_common::timestamping::{self, StackTimestamps, TimestampingOptions};
+use ons_common::scheduler::{SchedulerOptions, TickScheduler};
+use ons_common::stop::Stop;
+use ons_common::timeseries::{TimeSeries, TimeSeriesOptions, TransportSnapshot};
+use ons_common::sequence::DatagramMetrics;
+use ons_common::wire::{now_micros, TickMessage};
+use ons_common::workload::WorkloadOptions;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Server to connect to
+    #[arg(long, default_value = "204.48.31.168:4444")]
+    server: String,
+
+    /// Trust the root certificate at this path, encoded as PEM
+    #[arg(long, default_value = "/users/dorlando/ons/dtls_udp/signallite.io.pem")]
+    tls_cert: PathBuf,
+
+    /// Name to verify the server's certificate against
+    #[arg(long, default_value = "signallite.io")]
+    server_name: String,
+
+    /// Write the serialized RTT histogram here so runs can be merged later
+    #[arg(long, default_value = "udp_rtt.hdr")]
+    histogram_file: String,
+
+    #[command(flatten)]
+    histogram: HistogramOptions,
+
+    #[command(flatten)]
+    drain: DrainOptions,
+
+    #[command(flatten)]
+    timeseries: TimeSeriesOptions,
+
+    #[command(flatten)]
+    scheduler: SchedulerOptions,
+
+    #[command(flatten)]
+    timestamping: TimestampingOptions,
 
-fn save_measurements(rtt_samples: &[u128]) -> Result<(), Box<dyn std::error::Error>> {
+    #[command(flatten)]
+    handshake: HandshakeOptions,
+
+    #[command(flatten)]
+    workload: WorkloadOptions,
+
+    #[command(flatten)]
+    manifest: ManifestOptions,
+
+    #[command(flatten)]
+    dashboard: DashboardOptions,
+
+    #[command(flatten)]
+    metrics: MetricsOptions,
+}
+
+// Defaults for --tick-rate and --simulation-duration-secs
+const TICK_RATE: u64 = 128;
+const SIMULATION_DURATION_SECS: u64 = 180;
+/// Largest tick message that still fits the 1500-byte buffers with the server's
+/// timestamps and the DTLS record overhead added
+const MAX_PAYLOAD: usize = 1200;
+
+/// Stand-in for `udp_dtls::UdpChannel` that picks up the kernel and NIC
+/// timestamps of each datagram on the way through to the DTLS layer.
+#[derive(Debug)]
+struct TimestampedChannel {
+    socket: UdpSocket,
+    timestamping: bool,
+    stamps: Arc<Mutex<StackTimestamps>>,
+}
+
+impl Read for TimestampedChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.timestamping {
+            return self.socket.recv(buf);
+        }
+        let received = timestamping::recv_timestamped(self.socket.as_raw_fd(), buf)?;
+        self.stamps.lock().unwrap().received(now_micros(), received.stamps);
+        Ok(received.len)
+    }
+}
+
+impl Write for TimestampedChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.timestamping {
+            return self.socket.send(buf);
+        }
+        let user_us = now_micros();
+        let size = self.socket.send(buf)?;
+        let mut stamps = self.stamps.lock().unwrap();
+        stamps.sent(user_us);
+        // Transmit stamps of earlier datagrams have usually come back by now
+        let _ = stamps.poll_tx(self.socket.as_raw_fd());
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn save_measurements(rtt_samples: &[u128], breakdowns: &[Option<DelayBreakdown>]) -> Result<(), Box<dyn std::error::Error>> {
     let mut writer = Writer::from_path("udp_measurements.csv")?;
-    writer.write_record(&["rtt"])?;
-    for rtt in rtt_samples {
-        writer.write_record(&[rtt.to_string()])?;
+    writer.write_record(&["rtt", "forward_us", "residence_us", "return_us"])?;
+    for (rtt, breakdown) in rtt_samples.iter().zip(breakdowns) {
+        let (forward, residence, ret) = match breakdown {
+            Some(b) => (b.forward_us.to_string(), b.residence_us.to_string(), b.return_us.to_string()),
+            None => (String::new(), String::new(), String::new()),
+        };
+        writer.write_record(&[rtt.to_string(), forward, residence, ret])?;
     }
     writer.flush()?;
     Ok(())
 }
 
-fn save_summary(rtt_samples: &[u128]) -> Result<(), Box<dyn std::error::Error>> {
-    let mut sorted = rtt_samples.to_vec();
-    sorted.sort_unstable();
-    let avg = rtt_samples.iter().sum::<u128>() as f64 / rtt_samples.len() as f64;
-    let p50 = sorted[rtt_samples.len() / 2];
-    let p95 = sorted[(rtt_samples.len() as f64 * 0.95) as usize];
-    let p99 = sorted[(rtt_samples.len() as f64 * 0.99) as usize];
-
+fn save_summary(
+    rtt: &LatencyHistogram,
+    workload: Value,
+    one_way_delay: Value,
+    datagram: Value,
+    echoes: Value,
+    scheduler: Value,
+    stack_timestamps: Value,
+    handshake: Value,
+) -> Result<(), Box<dyn std::error::Error>> {
     let summary = json!({
-        "sample_count": rtt_samples.len(),
+        "sample_count": rtt.len(),
+        "workload": workload,
         "metrics": {
-            "rtt": {
-                "avg": avg,
-                "p50": p50,
-                "p95": p95,
-                "p99": p99
-            }
-        }
+            "rtt": rtt.summary()
+        },
+        "one_way_delay": one_way_delay,
+        "datagram": datagram,
+        "echoes": echoes,
+        "scheduler": scheduler,
+        "stack_timestamps": stack_timestamps,
+        "handshake": handshake
     });
 
     let mut file = fs::File::create("udp_summary.json")?;
@@ -43,10 +167,77 @@ fn save_summary(rtt_samples: &[u128]) -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
+/// Set up the client socket in blocking mode for the handshake
+fn open_socket(server: &str) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(server)?;
+    socket.set_read_timeout(Some(Duration::from_secs(2)))?;
+    Ok(socket)
+}
+
+/// Run the DTLS handshake with `server_name` over `channel`, timing it.
+fn dtls_handshake(connector: &DtlsConnector, server_name: &str, channel: TimestampedChannel)
+    -> Result<(DtlsStream<TimestampedChannel>, Handshake), Box<dyn std::error::Error>> {
+    let mut timer = PhaseTimer::start();
+    let dtls_client = connector.connect(server_name, channel).map_err(|e| {
+        eprintln!("DTLS connection error: {:?}", e);
+        io::Error::new(io::ErrorKind::Other, format!("DTLS connection failed: {:?}", e))
+    })?;
+    timer.mark("dtls_handshake");
+    // udp-dtls has no session cache, so every handshake is a full one
+    Ok((dtls_client, timer.finish().with_resumption(Some(false), None)))
+}
+
+/// Open and close `runs` DTLS connections, each on a fresh socket, and save the handshake distribution.
+fn connect_bench(connector: &DtlsConnector, args: &Args, runs: u32, stop: &Stop) -> Result<(), Box<dyn std::error::Error>> {
+    if args.handshake.resume {
+        println!("udp-dtls cannot resume DTLS sessions; every connection will do a full handshake");
+    }
+    let mut bench = HandshakeBench::new("udp", &args.histogram);
+    for _ in 0..runs {
+        if stop.requested() {
+            break;
+        }
+        let attempt = open_socket(&args.server).map_err(Into::into).and_then(|socket| {
+            let channel = TimestampedChannel {
+                socket,
+                timestamping: false,
+                stamps: Arc::new(Mutex::new(StackTimestamps::new(&args.timestamping))),
+            };
+            dtls_handshake(connector, &args.server_name, channel)
+        });
+        match attempt {
+            Ok((mut dtls_client, handshake)) => {
+                bench.record(Ok(handshake));
+                let _ = dtls_client.shutdown();
+            }
+            Err(e) => bench.record(Err(e.to_string())),
+        }
+        thread::sleep(args.handshake.interval());
+    }
+    let path = bench.save(&args.handshake)?;
+    println!("Handshake summary saved to {}", path.display());
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    // Per-tick lines are at trace level: RUST_LOG=trace brings them back
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+    if args.workload.payload_bytes > MAX_PAYLOAD {
+        return Err(format!("--payload-bytes is limited to {} so each tick fits one datagram", MAX_PAYLOAD).into());
+    }
+    args.scheduler.apply_to_current_thread()?;
+    // SIGINT or SIGTERM end the tick loop at the next tick, then the usual saving runs
+    let stop = Stop::on_signals()?;
+    let mut manifest = RunManifest::start("udp", &args, args.workload.summary(TICK_RATE, SIMULATION_DURATION_SECS), &args.manifest);
+    println!("Run manifest saved to {}", manifest.save()?.display());
+
     // Load the root CA certificate.
-    let root_ca_data = fs::read("/users/dorlando/ons/dtls_udp/signallite.io.pem")?;
-    //let root_ca_data = fs::read("/users/dorlando/ons/certs/fullchain1.pem")?;
+    let root_ca_data = fs::read(&args.tls_cert)
+        .map_err(|e| format!("could not read certificate {}: {}", args.tls_cert.display(), e))?;
     let root_ca = match Certificate::from_pem(&root_ca_data) {
         Ok(cert) => cert,
         Err(e) => {
@@ -56,7 +247,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
     println!("Certificate loaded successfully");
-    let connection_start = Instant::now();
 
     // Set up the DTLS connector.
     let connector = DtlsConnector::builder()
@@ -70,118 +260,240 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     println!("DTLS connector created successfully");
 
-    // Set up the client socket in blocking mode for the handshake
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-    socket.connect("204.48.31.168:4444")?;
-    //socket.connect("149.43.80.144:4444")?;
-    socket.set_read_timeout(Some(Duration::from_secs(2)))?;
-    println!("Connecting to server at port 4444");
+    if let Some(runs) = args.handshake.connect_bench {
+        connect_bench(&connector, &args, runs, &stop)?;
+        let handshake_path = args.handshake.path("udp");
+        manifest.finish(&[handshake_path.with_extension("csv"), handshake_path]);
+        manifest.save()?;
+        return Ok(());
+    }
 
-    let client_channel = UdpChannel {
+    let socket = open_socket(&args.server)?;
+    println!("Connecting to server at {}", args.server);
+
+    // Before the handshake, so every datagram the channel sends is counted
+    args.timestamping.apply(socket.as_raw_fd())?;
+    let stack_stamps = Arc::new(Mutex::new(StackTimestamps::new(&args.timestamping)));
+    let client_channel = TimestampedChannel {
         socket: socket.try_clone().expect("Failed to clone socket"),
-        remote_addr: socket.peer_addr()?,
+        timestamping: args.timestamping.enabled(),
+        stamps: stack_stamps.clone(),
     };
 
     // Perform DTLS handshake with blocking socket
-    let mut dtls_client = match connector.connect("signallite.io", client_channel) {
-        Ok(client) => client,
-        Err(e) => {
-            eprintln!("DTLS connection error: {:?}", e);
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, 
-                format!("DTLS connection failed: {:?}", e))));
-        }
-    };
+    let (dtls_client, handshake) = dtls_handshake(&connector, &args.server_name, client_channel)?;
 
     // Handshake succeeded, now set socket to non-blocking for our tick loop
     socket.set_nonblocking(true)?;
+    handshake.log();
 
-    let connection_end = Instant::now();
-    println!("Connection established in {} ms", connection_end.duration_since(connection_start).as_millis());
+    // A dedicated thread waits on the socket and timestamps echoes as they arrive,
+    // so the RTT does not depend on when the tick loop gets round to reading them.
+    let dtls_client = Arc::new(Mutex::new(dtls_client));
+    let receiving = Arc::new(AtomicBool::new(true));
+    let (arrival_tx, arrivals) = mpsc::channel::<Arrival>();
+    let receiver = {
+        let dtls_client = dtls_client.clone();
+        let receiving = receiving.clone();
+        let stack_stamps = stack_stamps.clone();
+        let timestamping = args.timestamping.enabled();
+        let fd = socket.as_raw_fd();
+        thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            while receiving.load(Ordering::Relaxed) {
+                match receive::wait_readable(fd, Duration::from_millis(50)) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        eprintln!("Error waiting on UDP socket: {}", e);
+                        break;
+                    }
+                }
+                // Stamp before taking the lock, in case the tick loop is mid-send
+                let mut woken = Some(Arrival::now(Vec::new()));
+                let mut dtls = dtls_client.lock().unwrap();
+                loop {
+                    match dtls.read(&mut buf) {
+                        Ok(size) if size > 0 => {
+                            let mut arrival = woken.take().unwrap_or_else(|| Arrival::now(Vec::new()));
+                            arrival.data = buf[..size].to_vec();
+                            if arrival_tx.send(arrival).is_err() {
+                                return;
+                            }
+                        }
+                        Ok(_) => break,
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            eprintln!("Error reading from DTLS connection: {}", e);
+                            break;
+                        }
+                    }
+                }
+                drop(dtls);
+                // Transmit stamps wake poll() too; collect them so it doesn't spin
+                if timestamping {
+                    let _ = stack_stamps.lock().unwrap().poll_tx(fd);
+                }
+            }
+        })
+    };
 
-    // Define tick rate and compute tick duration with microsecond precision.
-    const TICK_RATE: u32 = 128;
-    let tick_duration = Duration::from_micros(1_000_000 / TICK_RATE as u64);
+    let tick_duration = args.workload.tick_duration(TICK_RATE);
     println!("Tick duration: {} µs", tick_duration.as_micros());
+    let mut scheduler = TickScheduler::new(tick_duration, &args.scheduler);
 
-    // Set simulation duration to 3 minutes.
-    let simulation_duration = Duration::from_secs(180);
+    let simulation_duration = args.workload.duration(SIMULATION_DURATION_SECS);
     let simulation_start = Instant::now();
     let simulation_end = simulation_start + simulation_duration;
+    let drain_end = simulation_end + args.drain.drain();
+    let mut timeseries = TimeSeries::new(simulation_start);
 
     let mut tick_count: u64 = 0;
     // Store the Instant at which each tick message is sent.
-    let mut sent_timestamps: HashMap<u64, Instant> = HashMap::new();
+    // Along with the wall-clock send time, for splitting the RTT into one-way delays.
+    let mut sent_timestamps: OutstandingTicks<(Instant, u64)> = OutstandingTicks::new(&args.drain);
     let mut rtt_samples: Vec<u128> = Vec::new();
-    let mut buf = [0u8; 1500];
+    let mut rtt_histogram = LatencyHistogram::new(&args.histogram);
+    let mut one_way_delay = OneWayDelay::new();
+    let mut datagram_metrics = DatagramMetrics::new();
+    let dashboard = Dashboard::start(&args.dashboard, &format!("DTLS {}", args.server), simulation_duration, &stop)?;
+    dashboard.export(Metrics::start(&args.metrics, "udp_client", Side::Client)?.session(&args.server));
+    let live = dashboard.live();
 
     // Run the simulation tick loop.
-    while Instant::now() < simulation_end {
+    while Instant::now() < drain_end {
+        if stop.requested() {
+            println!("Stopping early after {} ticks", tick_count);
+            break;
+        }
+        let slot = scheduler.wait_blocking();
         let tick_start = Instant::now();
+        sent_timestamps.expire();
 
-        // Process any incoming echoed messages (non-blocking).
-        loop {
-            match dtls_client.read(&mut buf) {
-                Ok(size) if size > 0 => {
-                    let received_str = String::from_utf8_lossy(&buf[..size]);
-                    // Expecting JSON of the form: {"tick": <number>, "timestamp": <µs>}
-                    if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&received_str) {
-                        if let Some(tick_val) = parsed.get("tick") {
-                            if let Some(tick) = tick_val.as_u64() {
-                                if let Some(sent_time) = sent_timestamps.remove(&tick) {
-                                    let rtt = Instant::now().duration_since(sent_time);
-                                    rtt_samples.push(rtt.as_micros());
-                                    println!("Tick {}: Received echo, RTT: {} µs", tick, rtt.as_micros());
-                                }
+        // Match the echoes the receive thread has timestamped since the last tick.
+        while let Ok(arrival) = arrivals.try_recv() {
+            let received_str = String::from_utf8_lossy(&arrival.data);
+            // Expecting JSON of the form: {"tick": <number>, "timestamp": <µs>}
+            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&received_str) {
+                if let Some(tick_val) = parsed.get("tick") {
+                    if let Some(tick) = tick_val.as_u64() {
+                        let received_at = arrival.at;
+                        match sent_timestamps.take_at(tick, received_at) {
+                            Echo::OnTime((sent_time, sent_wall)) => {
+                                let rtt = received_at.saturating_duration_since(sent_time);
+                                timeseries.received(tick, received_at, false);
+                                rtt_samples.push(rtt.as_micros());
+                                rtt_histogram.record(rtt.as_micros() as u64);
+                                datagram_metrics.record(tick, rtt.as_micros() as i64);
+                                let stamps = TickMessage::parse(&arrival.data)
+                                    .and_then(|echo| EchoTimestamps::from_echo(&echo, sent_wall, arrival.wall_us));
+                                one_way_delay.record(stamps);
+                                live.echo(rtt.as_micros() as u64);
+                                tracing::trace!("Tick {}: Received echo, RTT: {} µs", tick, rtt.as_micros());
+                            }
+                            Echo::Late((sent_time, _)) => {
+                                // Arrived, so not lost, but kept out of the RTT statistics
+                                let rtt = received_at.saturating_duration_since(sent_time);
+                                timeseries.received(tick, received_at, true);
+                                datagram_metrics.record(tick, rtt.as_micros() as i64);
+                                live.late();
+                                tracing::trace!("Tick {}: Late echo after {} µs", tick, rtt.as_micros());
                             }
+                            Echo::Unknown => datagram_metrics.record_unmatched(tick),
                         }
                     }
-                },
-                Ok(0) => break, // No more data available.
-                Ok(_) => (), // This handles any other size (which shouldn't happen, but the compiler wants this case)
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
-                Err(e) => {
-                    eprintln!("Error reading from DTLS connection: {}", e);
-                    break;
                 }
             }
         }
 
-        // Prepare the tick message with the tick number and a precise timestamp (in µs from simulation start).
-        let timestamp = Instant::now().duration_since(simulation_start).as_micros();
-        let message = format!(r#"{{"tick":{},"timestamp":{}}}"#, tick_count, timestamp);
-        sent_timestamps.insert(tick_count, Instant::now());
-
-        // Send the tick message.
-        match dtls_client.write_all(message.as_bytes()) {
-            Ok(_) => println!("Sent tick {} at {} µs", tick_count, timestamp),
-            Err(e) => {
-                eprintln!("Error sending tick message: {:?}", e);
-                break;
+        // After the last send, keep reading until the drain period ends or nothing is outstanding.
+        if Instant::now() < simulation_end {
+            // Prepare the tick message with the tick number and a precise timestamp (in µs from simulation start).
+            let timestamp = Instant::now().duration_since(simulation_start).as_micros();
+            let message = args.workload.message(tick_count, timestamp);
+            sent_timestamps.insert(tick_count, (Instant::now(), now_micros()));
+            // Nothing below the DTLS layer is visible from userspace, so no transport snapshot
+            timeseries.sent(tick_count, Instant::now(), message.len(), TransportSnapshot::default());
+
+            // Send the tick message.
+            let sent = dtls_client.lock().unwrap().write_all(message.as_bytes());
+            match sent {
+                Ok(_) => {
+                    scheduler.sent(&slot);
+                    live.sent(slot.deadline.elapsed(), TransportSnapshot::default());
+                    tracing::trace!("Sent tick {} at {} µs", tick_count, timestamp)
+                }
+                Err(e) => {
+                    eprintln!("Error sending tick message: {:?}", e);
+                    break;
+                }
             }
+            tick_count += 1;
+        } else if sent_timestamps.pending() == 0 {
+            break;
         }
-        tick_count += 1;
+        live.outstanding(sent_timestamps.pending());
 
-        // Sleep until the next tick boundary (adjusting for the time already spent processing).
-        let elapsed = tick_start.elapsed();
-        if elapsed < tick_duration {
-            thread::sleep(tick_duration - elapsed);
+        // The scheduler sleeps until the next absolute deadline; just flag ticks that overran it.
+        if tick_start.elapsed() >= tick_duration && tick_count > 0 && Instant::now() < simulation_end {
+            timeseries.mark_overrun(tick_count - 1);
+            live.overrun();
         }
     }
 
+    receiving.store(false, Ordering::Relaxed);
+    receiver.join().expect("Receive thread panicked");
+    dashboard.finish();
+    let mut results: Vec<PathBuf> = vec!["udp_measurements.csv".into(), "udp_summary.json".into(), args.histogram_file.clone().into()];
+    let stack_timestamps = if args.timestamping.enabled() {
+        let mut stamps = stack_stamps.lock().unwrap();
+        let _ = stamps.poll_tx(socket.as_raw_fd());
+        let path = stamps.save(&args.timestamping, "udp")?;
+        println!("Per-packet stack timestamps saved to {}", path.display());
+        results.push(path);
+        stamps.summary()
+    } else {
+        Value::Null
+    };
+
     // After simulation, save and summarize the RTT data.
     if !rtt_samples.is_empty() {
         println!("Saving RTT data...");
-        save_measurements(&rtt_samples).expect("Failed to save measurements");
-        save_summary(&rtt_samples).expect("Failed to save summary");
+        let breakdowns = match one_way_delay.estimate_offset() {
+            Some(offset) => {
+                println!("Estimated server clock offset: {} µs", offset.offset_us);
+                one_way_delay.breakdown(&offset)
+            }
+            None => vec![None; rtt_samples.len()],
+        };
+        save_measurements(&rtt_samples, &breakdowns).expect("Failed to save measurements");
+        save_summary(&rtt_histogram, args.workload.summary(TICK_RATE, SIMULATION_DURATION_SECS), one_way_delay.summary(),
+            datagram_metrics.summary(tick_count),
+            sent_timestamps.summary(tick_count, rtt_samples.len() as u64), scheduler.summary(), stack_timestamps,
+            handshake.summary())
+            .expect("Failed to save summary");
+        rtt_histogram.save(&args.histogram_file).expect("Failed to save RTT histogram");
 
         let total_rtt: u128 = rtt_samples.iter().sum();
         let average_rtt = total_rtt as f64 / rtt_samples.len() as f64;
         println!("Total ticks sent (with RTT measured): {}", rtt_samples.len());
         println!("Average RTT: {:.2} µs", average_rtt);
+        println!("Late: {}, lost: {}, reordered: {}, duplicates: {}, jitter: {:.1} µs",
+            sent_timestamps.late(), tick_count - datagram_metrics.received(), datagram_metrics.reordered(),
+            datagram_metrics.duplicates(), datagram_metrics.jitter_us());
     } else {
         println!("No RTT data collected.");
     }
 
+    // Last, so a failure here can't cost the RTT measurements and summary above
+    match timeseries.save(&args.timeseries, "udp") {
+        Ok(path) => println!("Per-tick time series saved to {}", path.display()),
+        Err(e) => eprintln!("Failed to save the per-tick time series: {}", e),
+    }
+    results.push(args.timeseries.path("udp"));
+    manifest.finish(&results);
+    manifest.save()?;
+
     println!("Client simulation complete after 3 minutes.");
     Ok(())
 }
@@ -1,14 +1,71 @@
+use std::collections::VecDeque;
 use std::io::{self, Read, Write};
 use std::net::UdpSocket;
+use std::os::unix::io::AsRawFd;
 use udp_dtls::{DtlsAcceptor, Identity, UdpChannel}; 
 use std::fs;
 use std::time::{Duration, Instant};
 use std::thread;
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+use ons_common::echo::{EchoOptions, EchoStats};
+use ons_common::manifest::{ManifestOptions, RunManifest};
+use ons_common::metrics::{Metrics, MetricsOptions, Side};
+use ons_common::receive;
+use ons_common::scheduler::{SchedulerOptions, TickScheduler};
+use ons_common::stop::Stop;
+use ons_common::wire::{now_micros, stamp_echo};
 
-const TICK_RATE: u32 = 128; // ticks per second; change as needed
-const TICK_DURATION: Duration = Duration::from_micros(1_000_000u64 / TICK_RATE as u64); 
+const TICK_RATE: u64 = 128; // default ticks per second
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(flatten)]
+    scheduler: SchedulerOptions,
+
+    #[command(flatten)]
+    echo: EchoOptions,
+
+    /// Server ticks per second, which tick-batched echoes are sent on
+    #[arg(long, default_value_t = TICK_RATE)]
+    tick_rate: u64,
+
+    /// Write the echo mode and server residence times here, refreshed every second
+    #[arg(long, default_value = "udp_server_summary.json")]
+    summary_file: String,
+
+    #[command(flatten)]
+    manifest: ManifestOptions,
+
+    #[command(flatten)]
+    metrics: MetricsOptions,
+}
+
+// Write the summary and mark the manifest finished as of now
+fn save_summary(stats: &EchoStats, manifest: &mut RunManifest, summary_file: &str) {
+    if let Err(e) = stats.save(summary_file) {
+        eprintln!("Failed to save server summary: {}", e);
+    }
+    manifest.finish(&[summary_file]);
+    if let Err(e) = manifest.save() {
+        eprintln!("Failed to update run manifest: {}", e);
+    }
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+    args.scheduler.apply_to_current_thread()?;
+    // SIGINT ends the session at the next tick, after a last summary is written
+    let stop = Stop::on_signals()?;
+    let tick_duration = Duration::from_micros(1_000_000 / args.tick_rate.max(1));
+    let mut manifest = RunManifest::start("udp_server", &args, serde_json::json!({ "tick_rate": args.tick_rate }), &args.manifest);
+    println!("Run manifest saved to {}", manifest.save()?.display());
+    let metrics = Metrics::start(&args.metrics, "udp_server", Side::Server)?;
+
     // Load PKCS#12 identity from the generated `identity.p12` file
     let pkcs12_data = fs::read("identity_backup.p12")?;
     let identity = Identity::from_pkcs12(&pkcs12_data, "")?;
@@ -20,13 +77,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let socket = UdpSocket::bind("0.0.0.0:4444")?;
     // Keep socket in blocking mode during handshake phase
     println!("Server listening on 0.0.0.0:4444");
+    println!("Echo mode: {:?}", args.echo.echo_mode);
 
-    loop {
+    while !stop.requested() {
         // Wait for an initial packet from a client to learn its address for DTLS setup.
         let mut buf = [0u8; 1500];
         
-        // Handle incoming connection
-        let (_, addr) = socket.recv_from(&mut buf)?;
+        // Handle incoming connection, waking now and then to see if we were interrupted
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let addr = match socket.recv_from(&mut buf) {
+            Ok((_, addr)) => addr,
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        socket.set_read_timeout(None)?;
         println!("Received initial packet from {}", addr);
         
         // Set up the DTLS channel using the client's address.
@@ -49,10 +113,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         socket.set_nonblocking(true)?;
         
         // Wait for the first simulation message to synchronize tick timing.
-        let first_message = loop {
+        let (first_message, first_recv_us) = loop {
+            if stop.requested() {
+                return Ok(());
+            }
             let mut message = [0u8; 1500];
             match dtls_server.read(&mut message) {
-                Ok(size) if size > 0 => break message[..size].to_vec(),
+                Ok(size) if size > 0 => break (message[..size].to_vec(), now_micros()),
                 Ok(_) => continue,
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                     // No message yet; sleep briefly and retry.
@@ -67,24 +134,37 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         };
 
         println!("Received first simulation message from client, starting tick loop");
+        let session = metrics.session(&addr.to_string());
+        session.received(1);
 
         // Immediately echo the first simulation message.
-        dtls_server.write_all(&first_message)?;
+        let mut stats = EchoStats::new(&args.echo, tick_duration).with_metrics(session.clone());
+        let send_us = now_micros();
+        dtls_server.write_all(&stamp_echo(&first_message, first_recv_us, send_us))?;
+        stats.echoed(first_recv_us, send_us);
+        let mut last_saved = Instant::now();
 
-        // Start the tick loop.
-        loop {
+        // Start the tick loop on absolute deadlines, so processing time doesn't drift the schedule.
+        // In immediate mode there is no tick: wait on the socket and echo as messages arrive.
+        let mut scheduler = TickScheduler::new(tick_duration, &args.scheduler);
+        let mut backlog: VecDeque<(Vec<u8>, u64)> = VecDeque::new();
+        while !stop.requested() {
+            if args.echo.immediate() {
+                receive::wait_readable(socket.as_raw_fd(), Duration::from_millis(100))?;
+            } else {
+                scheduler.wait_blocking();
+            }
             let tick_start = Instant::now();
 
-            // Process all available incoming messages during this tick.
+            // Read all available incoming messages.
             loop {
                 let mut message = [0u8; 1500];
                 match dtls_server.read(&mut message) {
                     Ok(size) if size > 0 => {
-                        let received_str = String::from_utf8_lossy(&message[..size]);
-                        println!("Tick processing: received from {}: {}", addr, received_str);
-                        // Echo the message back to the client.
-                        dtls_server.write_all(&message[..size])?;
-                        println!("Tick processing: echoed message to {}", addr);
+                        let recv_us = now_micros();
+                        session.received(1);
+                        tracing::trace!("Tick processing: received from {}: {}", addr, String::from_utf8_lossy(&message[..size]));
+                        backlog.push_back((message[..size].to_vec(), recv_us));
                     },
                     Ok(_) => break, // No data was read.
                     Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
@@ -95,11 +175,33 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
 
-            // Sleep until the next tick boundary (accounting for processing time).
-            let elapsed = tick_start.elapsed();
-            if elapsed < TICK_DURATION {
-                thread::sleep(TICK_DURATION - elapsed);
+            // Echo this tick's batch back to the client; in immediate mode that is everything read.
+            let batch = if args.echo.immediate() {
+                backlog.drain(..).collect()
+            } else {
+                let batch = args.echo.take_tick(&mut backlog);
+                stats.tick(batch.len(), backlog.len());
+                batch
+            };
+            for (message, recv_us) in batch {
+                let send_us = now_micros();
+                dtls_server.write_all(&stamp_echo(&message, recv_us, send_us))?;
+                stats.echoed(recv_us, send_us);
+                tracing::trace!("Tick processing: echoed message to {}", addr);
+            }
+            if !args.echo.immediate() && tick_start.elapsed() > tick_duration {
+                stats.overrun();
+                tracing::debug!("Tick processing for {} took {:?}, longer than the tick period", addr, tick_start.elapsed());
+            }
+
+            // The session only ends when the process does, so keep the summary on disk current.
+            if last_saved.elapsed() >= Duration::from_secs(1) {
+                save_summary(&stats, &mut manifest, &args.summary_file);
+                last_saved = Instant::now();
             }
         }
+        println!("Stopping; saving the final summary");
+        save_summary(&stats, &mut manifest, &args.summary_file);
     }
+    Ok(())
 }
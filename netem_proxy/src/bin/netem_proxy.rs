@@ -0,0 +1,124 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::time::Duration;
+use clap::{Parser, ValueEnum};
+use serde_json::json;
+use netem_proxy::impairment::Impairment;
+use netem_proxy::{tcp, udp, Directions};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Protocol {
+    Udp,
+    Tcp,
+    /// UDP and TCP on the same port
+    Both,
+}
+
+/// Forward traffic between a benchmark client and server through an impaired link.
+///
+/// Impairments are written like tc netem options, e.g. "loss=0.1%,delay=20ms,jitter=2ms".
+/// Keys: loss, delay, jitter, reorder, duplicate (percentages or durations), rate (e.g.
+/// 10mbit), limit (packets queued per direction) and retransmit (TCP stall per lost segment).
+/// gemodel=p[/r[/1-h[/1-k]]] makes loss bursty; distribution=normal|pareto shapes the delay
+/// around its mean, delay-samples=FILE draws it from recorded milliseconds, and trace=FILE
+/// replays a CSV of per-packet delay_us and lost values instead.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Address the client connects to
+    #[arg(long)]
+    listen: SocketAddr,
+
+    /// Server to forward to, as host:port
+    #[arg(long)]
+    upstream: String,
+
+    /// Which traffic to forward
+    #[arg(long, value_enum, default_value_t = Protocol::Udp)]
+    protocol: Protocol,
+
+    /// Impairments for both directions
+    #[arg(long, default_value = "")]
+    impair: Impairment,
+
+    /// Impairments for client-to-server traffic, replacing --impair for that direction
+    #[arg(long)]
+    up: Option<Impairment>,
+
+    /// Impairments for server-to-client traffic, replacing --impair for that direction
+    #[arg(long)]
+    down: Option<Impairment>,
+
+    /// Seed the loss and delay models so runs are repeatable
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Write the impairments and per-direction counters here every --stats-interval-ms
+    #[arg(long, default_value = "netem_proxy_summary.json")]
+    summary_file: String,
+
+    #[arg(long, default_value = "1000")]
+    stats_interval_ms: u64,
+}
+
+fn save_summary(args: &Args, upstream: SocketAddr, directions: &Directions) -> Result<(), Box<dyn std::error::Error>> {
+    let summary = json!({
+        "seed": args.seed,
+        "listen": args.listen,
+        "upstream": upstream,
+        "up": { "impairment": directions.up, "stats": &*directions.up_stats.lock().unwrap() },
+        "down": { "impairment": directions.down, "stats": &*directions.down_stats.lock().unwrap() }
+    });
+    fs::write(&args.summary_file, serde_json::to_string_pretty(&summary)?)?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let upstream: SocketAddr = tokio::net::lookup_host(&args.upstream)
+        .await?
+        .next()
+        .ok_or_else(|| format!("could not resolve {}", args.upstream))?;
+
+    let up = args.up.clone().unwrap_or_else(|| args.impair.clone());
+    let down = args.down.clone().unwrap_or_else(|| args.impair.clone());
+    for (direction, impairment) in [("Client -> server", &up), ("Server -> client", &down)] {
+        println!("{}: {}", direction, impairment);
+        if let Some(burst) = impairment.loss.mean_burst() {
+            println!("  mean loss {:.3}%, mean bad-state run {:.1} packets", impairment.loss.mean_loss() * 100.0, burst);
+        }
+    }
+    let directions = Directions::new(up, down, args.seed);
+
+    let mut tasks = Vec::new();
+    if args.protocol != Protocol::Tcp {
+        tasks.push(tokio::spawn(udp::run(args.listen, upstream, directions.clone())));
+    }
+    if args.protocol != Protocol::Udp {
+        tasks.push(tokio::spawn(tcp::run(args.listen, upstream, directions.clone())));
+    }
+
+    let mut stats_interval = tokio::time::interval(Duration::from_millis(args.stats_interval_ms.max(1)));
+    // Listeners only return on error, so stop as soon as one does
+    while !tasks.iter().any(|task| task.is_finished()) {
+        tokio::select! {
+            _ = stats_interval.tick() => (),
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        if let Err(e) = save_summary(&args, upstream, &directions) {
+            eprintln!("Failed to write {}: {}", args.summary_file, e);
+        }
+    }
+    save_summary(&args, upstream, &directions)?;
+    println!("Summary saved to {}", args.summary_file);
+
+    for task in tasks {
+        if task.is_finished() {
+            task.await??;
+        } else {
+            task.abort();
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,309 @@
+// What happens to traffic in one direction, written the way tc netem takes it:
+// `loss=0.1%,delay=20ms,jitter=2ms,rate=10mbit`.
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use serde::{Serialize, Serializer};
+use crate::model::{parse_percent, DelayDistribution, LossModel, Trace};
+
+/// Impairments applied to one direction of a flow.
+///
+/// Probabilities are stored as fractions but written as percentages in the spec.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Impairment {
+    /// Which datagrams are dropped; on TCP, which segments need retransmitting
+    pub loss: LossModel,
+    /// Fixed one-way delay added to every packet
+    #[serde(rename = "delay_us", serialize_with = "micros")]
+    pub delay: Duration,
+    /// Spread of the delay; its meaning depends on the distribution
+    #[serde(rename = "jitter_us", serialize_with = "micros")]
+    pub jitter: Duration,
+    pub distribution: DelayDistribution,
+    /// Recorded per-packet delays and losses, replayed in place of the models above
+    pub trace: Option<Trace>,
+    /// Chance a datagram skips the delay and overtakes the ones queued ahead of it
+    pub reorder: f64,
+    /// Chance a datagram is sent twice
+    pub duplicate: f64,
+    /// Bottleneck rate in bits per second; unlimited if unset
+    pub rate_bps: Option<u64>,
+    /// Packets the direction can hold before datagrams are tail-dropped and TCP stops reading
+    pub limit: usize,
+    /// Stall added to a "lost" TCP segment, standing in for the sender's retransmission
+    #[serde(rename = "retransmit_us", serialize_with = "micros")]
+    pub retransmit: Duration,
+}
+
+impl Default for Impairment {
+    fn default() -> Self {
+        Impairment {
+            loss: LossModel::default(),
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            distribution: DelayDistribution::default(),
+            trace: None,
+            reorder: 0.0,
+            duplicate: 0.0,
+            rate_bps: None,
+            // netem's default queue length
+            limit: 1000,
+            // Linux's minimum RTO
+            retransmit: Duration::from_millis(200),
+        }
+    }
+}
+
+impl FromStr for Impairment {
+    type Err = String;
+
+    /// Parse comma- or space-separated `key=value` pairs. An empty spec, or `none`, means no impairment.
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut impairment = Impairment::default();
+        if spec.trim() == "none" {
+            return Ok(impairment);
+        }
+        for pair in spec.split(|c: char| c == ',' || c.is_whitespace()).filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(|| format!("expected key=value, got '{}'", pair))?;
+            match key {
+                "loss" => impairment.loss = LossModel::Random { rate: parse_percent(value)? },
+                "gemodel" => impairment.loss = LossModel::parse_gilbert_elliott(value)?,
+                "delay" => impairment.delay = parse_duration(value)?,
+                "jitter" => impairment.jitter = parse_duration(value)?,
+                "distribution" => impairment.distribution = DelayDistribution::parse(value)?,
+                "delay-samples" => impairment.distribution = DelayDistribution::empirical(Path::new(value))?,
+                "trace" => impairment.trace = Some(Trace::load(Path::new(value))?),
+                "reorder" => impairment.reorder = parse_percent(value)?,
+                "duplicate" => impairment.duplicate = parse_percent(value)?,
+                "rate" => impairment.rate_bps = Some(parse_rate(value)?),
+                "limit" => impairment.limit = value.parse().map_err(|_| format!("invalid limit '{}'", value))?,
+                "retransmit" => impairment.retransmit = parse_duration(value)?,
+                _ => return Err(format!(
+                    "unknown impairment '{}' (expected loss, gemodel, delay, jitter, distribution, delay-samples, trace, \
+                     reorder, duplicate, rate, limit or retransmit)", key)),
+            }
+        }
+        if impairment.limit == 0 {
+            return Err("limit must be at least 1".to_string());
+        }
+        Ok(impairment)
+    }
+}
+
+/// The spec that parses back to this impairment, leaving out anything at its default
+impl fmt::Display for Impairment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let defaults = Impairment::default();
+        let ms = |d: Duration| d.as_secs_f64() * 1e3;
+        let mut parts = Vec::new();
+        match &self.loss {
+            LossModel::Random { rate } if *rate > 0.0 => parts.push(format!("loss={}%", rate * 100.0)),
+            LossModel::Random { .. } => (),
+            LossModel::GilbertElliott { p, r, bad_loss, good_loss } => parts.push(format!("gemodel={}%/{}%/{}%/{}%",
+                p * 100.0, r * 100.0, bad_loss * 100.0, good_loss * 100.0)),
+        }
+        if !self.delay.is_zero() {
+            parts.push(format!("delay={}ms", ms(self.delay)));
+        }
+        if !self.jitter.is_zero() {
+            parts.push(format!("jitter={}ms", ms(self.jitter)));
+        }
+        match &self.distribution {
+            DelayDistribution::Uniform => (),
+            DelayDistribution::Normal => parts.push("distribution=normal".to_string()),
+            DelayDistribution::Pareto => parts.push("distribution=pareto".to_string()),
+            DelayDistribution::Empirical { path, .. } => parts.push(format!("delay-samples={}", path.display())),
+        }
+        if let Some(trace) = &self.trace {
+            parts.push(format!("trace={}", trace.path.display()));
+        }
+        if self.reorder > 0.0 {
+            parts.push(format!("reorder={}%", self.reorder * 100.0));
+        }
+        if self.duplicate > 0.0 {
+            parts.push(format!("duplicate={}%", self.duplicate * 100.0));
+        }
+        if let Some(rate) = self.rate_bps {
+            parts.push(format!("rate={}bit", rate));
+        }
+        if self.limit != defaults.limit {
+            parts.push(format!("limit={}", self.limit));
+        }
+        if self.retransmit != defaults.retransmit {
+            parts.push(format!("retransmit={}ms", ms(self.retransmit)));
+        }
+        if parts.is_empty() {
+            return write!(f, "none");
+        }
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl Impairment {
+    /// The same impairment as arguments to `tc qdisc ... netem`, for shaping a real
+    /// interface. The rate is left out, since tbf applies it, and so is `retransmit`:
+    /// the kernel's TCP retransmits lost segments itself. netem's pareto table is its own
+    /// shape rather than the proxy's, but with the same mean and spread.
+    pub fn netem_args(&self) -> Result<Vec<String>, String> {
+        let ms = |d: Duration| format!("{}ms", d.as_secs_f64() * 1e3);
+        let percent = |p: f64| format!("{}%", p * 100.0);
+        let mut args = vec!["limit".to_string(), self.limit.to_string()];
+        if self.trace.is_some() {
+            return Err("trace replay is only supported by the proxy".to_string());
+        }
+        // Checked up front: the samples apply even without a jitter
+        if let DelayDistribution::Empirical { .. } = self.distribution {
+            return Err("delay-samples is only supported by the proxy".to_string());
+        }
+        if !self.delay.is_zero() || !self.jitter.is_zero() {
+            args.extend(["delay".to_string(), ms(self.delay)]);
+            if !self.jitter.is_zero() {
+                args.push(ms(self.jitter));
+                match &self.distribution {
+                    DelayDistribution::Uniform | DelayDistribution::Empirical { .. } => (),
+                    DelayDistribution::Normal => args.extend(["distribution".to_string(), "normal".to_string()]),
+                    DelayDistribution::Pareto => args.extend(["distribution".to_string(), "pareto".to_string()]),
+                }
+            }
+        }
+        match self.loss {
+            LossModel::Random { rate } if rate > 0.0 => args.extend(["loss".to_string(), "random".to_string(), percent(rate)]),
+            LossModel::Random { .. } => (),
+            LossModel::GilbertElliott { p, r, bad_loss, good_loss } => args.extend([
+                "loss".to_string(),
+                "gemodel".to_string(),
+                percent(p),
+                percent(r),
+                percent(bad_loss),
+                percent(good_loss),
+            ]),
+        }
+        if self.duplicate > 0.0 {
+            args.extend(["duplicate".to_string(), percent(self.duplicate)]);
+        }
+        if self.reorder > 0.0 {
+            // Like the proxy, netem sends these immediately and delays the rest
+            args.extend(["reorder".to_string(), percent(self.reorder)]);
+        }
+        Ok(args)
+    }
+}
+
+fn micros<S: Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(d.as_micros() as u64)
+}
+
+/// `20ms`, `1.5ms`, `500us` or `1s`
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid duration '{}'", value))?;
+    let seconds = match unit {
+        "s" => number,
+        "ms" | "" => number / 1e3,
+        "us" | "µs" => number / 1e6,
+        _ => return Err(format!("unknown duration unit in '{}' (expected s, ms or us)", value)),
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// `10mbit`, `500kbit`, `1gbit` or a plain number of bits per second
+fn parse_rate(value: &str) -> Result<u64, String> {
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid rate '{}'", value))?;
+    let scale = match unit.to_ascii_lowercase().as_str() {
+        "" | "bit" => 1.0,
+        "kbit" => 1e3,
+        "mbit" => 1e6,
+        "gbit" => 1e9,
+        _ => return Err(format!("unknown rate unit in '{}' (expected bit, kbit, mbit or gbit)", value)),
+    };
+    let bps = (number * scale) as u64;
+    if bps == 0 {
+        return Err(format!("rate '{}' must be above zero", value));
+    }
+    Ok(bps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_seconds_milliseconds_or_microseconds() {
+        assert_eq!(parse_duration("20ms"), Ok(Duration::from_millis(20)));
+        assert_eq!(parse_duration("1.5ms"), Ok(Duration::from_micros(1500)));
+        assert_eq!(parse_duration("500us"), Ok(Duration::from_micros(500)));
+        assert_eq!(parse_duration("500µs"), Ok(Duration::from_micros(500)));
+        assert_eq!(parse_duration("1s"), Ok(Duration::from_secs(1)));
+        // Milliseconds when the unit is left off, as netem reads it
+        assert_eq!(parse_duration("20"), Ok(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn bad_durations_are_rejected() {
+        for bad in ["", "ms", "-5ms", "1.2.3ms", "20min", "20 ms"] {
+            assert!(parse_duration(bad).is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn rates_take_bit_multiples_in_any_case() {
+        assert_eq!(parse_rate("10mbit"), Ok(10_000_000));
+        assert_eq!(parse_rate("500kbit"), Ok(500_000));
+        assert_eq!(parse_rate("1gbit"), Ok(1_000_000_000));
+        assert_eq!(parse_rate("1.5Mbit"), Ok(1_500_000));
+        assert_eq!(parse_rate("64000"), Ok(64_000));
+        assert_eq!(parse_rate("64000bit"), Ok(64_000));
+    }
+
+    #[test]
+    fn bad_rates_are_rejected() {
+        for bad in ["", "fast", "0mbit", "0.0001bit", "10mbps", "-1mbit"] {
+            assert!(parse_rate(bad).is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn specs_set_each_field() {
+        let impairment: Impairment =
+            "loss=0.5%, delay=20ms jitter=2ms,distribution=normal,reorder=1%,duplicate=2,rate=10mbit,limit=50,retransmit=300ms"
+                .parse()
+                .unwrap();
+        assert_eq!(impairment.loss, LossModel::Random { rate: 0.005 });
+        assert_eq!(impairment.delay, Duration::from_millis(20));
+        assert_eq!(impairment.jitter, Duration::from_millis(2));
+        assert_eq!(impairment.distribution, DelayDistribution::Normal);
+        assert_eq!(impairment.reorder, 0.01);
+        assert_eq!(impairment.duplicate, 0.02);
+        assert_eq!(impairment.rate_bps, Some(10_000_000));
+        assert_eq!(impairment.limit, 50);
+        assert_eq!(impairment.retransmit, Duration::from_millis(300));
+        assert_eq!("".parse::<Impairment>(), Ok(Impairment::default()));
+    }
+
+    #[test]
+    fn bad_specs_are_rejected() {
+        for bad in ["loss", "loss=", "loss=200%", "speed=1", "limit=0", "limit=-1", "delay=5parsecs", "distribution=lognormal"] {
+            assert!(bad.parse::<Impairment>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_impairment() {
+        for spec in [
+            "none",
+            "loss=0.1%",
+            "gemodel=1%/30%/100%/0%",
+            "delay=20ms,jitter=2.5ms,distribution=pareto",
+            "delay=0.5ms,reorder=25%,duplicate=1%",
+            "rate=1500000bit,limit=20,retransmit=1000ms",
+        ] {
+            let impairment: Impairment = spec.parse().unwrap();
+            assert_eq!(impairment.to_string(), spec);
+            assert_eq!(impairment.to_string().parse::<Impairment>(), Ok(impairment));
+        }
+    }
+}
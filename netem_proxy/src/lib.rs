@@ -0,0 +1,55 @@
+// Userspace network impairment proxy: forwards UDP and TCP between a client and a
+// server, applying loss, delay, jitter, reordering, duplication and a rate limit to
+// each direction.
+pub mod impairment;
+pub mod link;
+pub mod model;
+pub mod tcp;
+pub mod udp;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use impairment::Impairment;
+use link::SharedStats;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+/// The impairments for each direction and the counters they share across flows
+#[derive(Clone)]
+pub struct Directions {
+    /// Client to server
+    pub up: Impairment,
+    /// Server to client
+    pub down: Impairment,
+    pub up_stats: SharedStats,
+    pub down_stats: SharedStats,
+    seed: Option<u64>,
+    flows: Arc<AtomicU64>,
+}
+
+impl Directions {
+    pub fn new(up: Impairment, down: Impairment, seed: Option<u64>) -> Self {
+        Directions {
+            up,
+            down,
+            up_stats: Default::default(),
+            down_stats: Default::default(),
+            seed,
+            flows: Default::default(),
+        }
+    }
+
+    /// Random number generators for a new flow's up and down links. With a seed, the
+    /// nth flow always gets the same pair, so a run sees the same sequence of losses
+    /// and delays each time it sends the same packets.
+    pub fn flow_rngs(&self) -> (StdRng, StdRng) {
+        let flow = self.flows.fetch_add(1, Ordering::Relaxed);
+        match self.seed {
+            Some(seed) => {
+                let base = seed.wrapping_add(flow.wrapping_mul(2));
+                (StdRng::seed_from_u64(base), StdRng::seed_from_u64(base.wrapping_add(1)))
+            }
+            None => (StdRng::from_entropy(), StdRng::from_entropy()),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+// One direction of a proxied flow: decides each packet's fate and release time, then
+// holds packets in a delay queue until they are due.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rand::rngs::StdRng;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+use crate::impairment::Impairment;
+use crate::model::chance;
+
+/// How the payloads on a link may be treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Independent datagrams: may be dropped, duplicated or reordered
+    Datagram,
+    /// Chunks of a byte stream: always delivered once and in order. Loss stalls the
+    /// stream for the retransmission time instead, and a full queue stops reading
+    Stream,
+}
+
+/// Counters for one direction, shared by every flow the proxy carries that way
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct LinkStats {
+    pub packets: u64,
+    pub bytes: u64,
+    pub delivered: u64,
+    pub lost: u64,
+    pub queue_drops: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub retransmits: u64,
+    pub max_queue: usize,
+}
+
+pub type SharedStats = Arc<Mutex<LinkStats>>;
+
+pub struct Link {
+    impairment: Impairment,
+    framing: Framing,
+    rng: StdRng,
+    /// Whether the Gilbert-Elliott chain is in its bad state
+    loss_bad: bool,
+    /// Next packet's row in the replayed trace
+    trace_index: usize,
+    /// Release time of the last packet that kept its place, so jitter doesn't reorder
+    last_release: Option<Instant>,
+    /// When the bottleneck finishes sending what it already has
+    busy_until: Option<Instant>,
+    stats: SharedStats,
+}
+
+impl Link {
+    pub fn new(impairment: Impairment, framing: Framing, stats: SharedStats, rng: StdRng) -> Self {
+        Link { impairment, framing, rng, loss_bad: false, trace_index: 0, last_release: None, busy_until: None, stats }
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        chance(&mut self.rng, probability)
+    }
+
+    /// Whether the next packet is lost and how long it is delayed, from the trace if
+    /// one is being replayed, otherwise from the loss model and delay distribution
+    fn fate(&mut self) -> (bool, Duration) {
+        if let Some(trace) = &self.impairment.trace {
+            let entry = trace.get(self.trace_index);
+            self.trace_index += 1;
+            return (entry.lost, Duration::from_micros(entry.delay_us.unwrap_or(0)));
+        }
+        let lost = self.impairment.loss.lose(&mut self.loss_bad, &mut self.rng);
+        let delay = self.impairment.distribution.sample(self.impairment.delay, self.impairment.jitter, &mut self.rng);
+        (lost, delay)
+    }
+
+    /// Time on the wire at the bottleneck rate, starting no earlier than `at`
+    fn serialize(&mut self, at: Instant, len: usize) -> Instant {
+        let Some(rate) = self.impairment.rate_bps else { return at };
+        let start = self.busy_until.map_or(at, |busy| busy.max(at));
+        let done = start + Duration::from_secs_f64(len as f64 * 8.0 / rate as f64);
+        self.busy_until = Some(done);
+        done
+    }
+
+    /// Whether the link can take another packet with `queued` already waiting
+    pub fn accepting(&self, queued: usize) -> bool {
+        self.framing == Framing::Datagram || queued < self.impairment.limit
+    }
+
+    /// Decide what happens to a packet of `len` bytes arriving at `now`: the times its
+    /// copies are released, or nothing if it is dropped.
+    pub fn admit(&mut self, len: usize, now: Instant, queued: usize) -> Vec<Instant> {
+        let mut stats = LinkStats { packets: 1, bytes: len as u64, ..Default::default() };
+        let releases = if self.framing == Framing::Datagram && queued >= self.impairment.limit {
+            stats.queue_drops = 1;
+            Vec::new()
+        } else {
+            self.schedule(len, now, &mut stats)
+        };
+        stats.delivered = releases.len() as u64;
+
+        let mut shared = self.stats.lock().unwrap();
+        shared.packets += stats.packets;
+        shared.bytes += stats.bytes;
+        shared.delivered += stats.delivered;
+        shared.lost += stats.lost;
+        shared.queue_drops += stats.queue_drops;
+        shared.duplicated += stats.duplicated;
+        shared.reordered += stats.reordered;
+        shared.retransmits += stats.retransmits;
+        shared.max_queue = shared.max_queue.max(queued + releases.len());
+        releases
+    }
+
+    /// Loss, delay, reordering, rate and duplication for a packet the queue has room for
+    fn schedule(&mut self, len: usize, now: Instant, stats: &mut LinkStats) -> Vec<Instant> {
+        let datagram = self.framing == Framing::Datagram;
+        let (lost, delay) = self.fate();
+        if lost && datagram {
+            stats.lost = 1;
+            return Vec::new();
+        }
+        let mut release = now + delay;
+        if lost {
+            stats.retransmits = 1;
+            release += self.impairment.retransmit;
+        }
+
+        let reordered = datagram && !delay.is_zero() && self.chance(self.impairment.reorder);
+        if reordered {
+            stats.reordered = 1;
+            release = now;
+        } else if let Some(last) = self.last_release {
+            release = release.max(last);
+        }
+        let release = self.serialize(release, len);
+        if !reordered {
+            self.last_release = Some(release);
+        }
+
+        let mut releases = vec![release];
+        if datagram && self.chance(self.impairment.duplicate) {
+            stats.duplicated = 1;
+            releases.push(self.serialize(release, len));
+        }
+        releases
+    }
+}
+
+/// Carry packets from `rx` through `link`, handing each to `deliver` once it is due.
+///
+/// Returns when `rx` closes and everything queued has been delivered, or when
+/// `deliver` fails.
+pub async fn pipe<F, Fut>(mut link: Link, mut rx: mpsc::Receiver<Vec<u8>>, mut deliver: F) -> io::Result<()>
+where
+    F: FnMut(Vec<u8>) -> Fut,
+    Fut: Future<Output = io::Result<()>>,
+{
+    // Ordered by release time, then arrival, so packets due together keep their order
+    let mut queue: BinaryHeap<Reverse<(Instant, u64, Vec<u8>)>> = BinaryHeap::new();
+    let mut seq: u64 = 0;
+    let mut open = true;
+
+    loop {
+        let next = queue.peek().map(|Reverse((release, _, _))| *release);
+        if !open && next.is_none() {
+            return Ok(());
+        }
+        tokio::select! {
+            packet = rx.recv(), if open && link.accepting(queue.len()) => match packet {
+                Some(data) => {
+                    let releases = link.admit(data.len(), Instant::now(), queue.len());
+                    if let Some((&last, copies)) = releases.split_last() {
+                        for &release in copies {
+                            queue.push(Reverse((release, seq, data.clone())));
+                            seq += 1;
+                        }
+                        queue.push(Reverse((last, seq, data)));
+                        seq += 1;
+                    }
+                }
+                None => open = false,
+            },
+            _ = sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                let now = Instant::now();
+                while let Some(Reverse((release, _, _))) = queue.peek() {
+                    if *release > now {
+                        break;
+                    }
+                    let Reverse((_, _, data)) = queue.pop().unwrap();
+                    deliver(data).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn link(spec: &str, framing: Framing) -> Link {
+        Link::new(spec.parse().unwrap(), framing, SharedStats::default(), StdRng::seed_from_u64(1))
+    }
+
+    fn schedule(link: &mut Link, len: usize, now: Instant) -> (Vec<Instant>, LinkStats) {
+        let mut stats = LinkStats::default();
+        (link.schedule(len, now, &mut stats), stats)
+    }
+
+    #[test]
+    fn delay_is_added_to_every_packet() {
+        let mut link = link("delay=20ms", Framing::Datagram);
+        let now = Instant::now();
+        assert_eq!(schedule(&mut link, 100, now).0, vec![now + Duration::from_millis(20)]);
+        assert_eq!(schedule(&mut link, 100, now + Duration::from_millis(5)).0, vec![now + Duration::from_millis(25)]);
+    }
+
+    #[test]
+    fn rate_queues_packets_behind_each_other() {
+        // 8 kbit/s moves a 100-byte packet in 100 ms
+        let mut link = link("rate=8kbit", Framing::Datagram);
+        let now = Instant::now();
+        assert_eq!(schedule(&mut link, 100, now).0, vec![now + Duration::from_millis(100)]);
+        assert_eq!(schedule(&mut link, 100, now).0, vec![now + Duration::from_millis(200)]);
+        // An idle bottleneck starts on a packet as soon as it arrives
+        let later = now + Duration::from_secs(1);
+        assert_eq!(schedule(&mut link, 50, later).0, vec![later + Duration::from_millis(50)]);
+    }
+
+    #[test]
+    fn delay_comes_before_the_bottleneck() {
+        let mut link = link("delay=20ms,rate=8kbit", Framing::Datagram);
+        let now = Instant::now();
+        assert_eq!(schedule(&mut link, 100, now).0, vec![now + Duration::from_millis(120)]);
+        assert_eq!(schedule(&mut link, 100, now).0, vec![now + Duration::from_millis(220)]);
+    }
+
+    #[test]
+    fn lost_datagrams_are_dropped_and_lost_segments_retransmitted() {
+        let now = Instant::now();
+        let (releases, stats) = schedule(&mut link("loss=100%,delay=10ms", Framing::Datagram), 100, now);
+        assert!(releases.is_empty());
+        assert_eq!(stats.lost, 1);
+
+        let (releases, stats) =
+            schedule(&mut link("loss=100%,delay=10ms,retransmit=200ms", Framing::Stream), 100, now);
+        assert_eq!(releases, vec![now + Duration::from_millis(210)]);
+        assert_eq!(stats.retransmits, 1);
+    }
+}
@@ -0,0 +1,265 @@
+// Loss and delay models for a link, and per-packet traces to replay instead of them.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand_distr::{Distribution as _, Normal, Pareto};
+use serde::{Deserialize, Serialize};
+
+/// Tail index of the pareto delay distribution; lower is heavier-tailed
+const PARETO_SHAPE: f64 = 3.0;
+
+/// How packets are chosen for loss
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum LossModel {
+    /// Each packet lost independently with the same probability
+    Random { rate: f64 },
+    /// Two-state Markov chain, as netem's gemodel: `p` moves good to bad, `r` bad to good,
+    /// and each state has its own loss probability. Losses cluster while the chain is bad.
+    GilbertElliott { p: f64, r: f64, bad_loss: f64, good_loss: f64 },
+}
+
+impl Default for LossModel {
+    fn default() -> Self {
+        LossModel::Random { rate: 0.0 }
+    }
+}
+
+impl LossModel {
+    /// Long-run fraction of packets lost
+    pub fn mean_loss(&self) -> f64 {
+        match *self {
+            LossModel::Random { rate } => rate,
+            LossModel::GilbertElliott { p, r, bad_loss, good_loss } => {
+                if p + r == 0.0 {
+                    return good_loss;
+                }
+                let bad = p / (p + r);
+                bad * bad_loss + (1.0 - bad) * good_loss
+            }
+        }
+    }
+
+    /// Mean number of packets per stay in the bad state
+    pub fn mean_burst(&self) -> Option<f64> {
+        match *self {
+            LossModel::GilbertElliott { r, .. } if r > 0.0 => Some(1.0 / r),
+            _ => None,
+        }
+    }
+
+    /// Decide whether the next packet is lost, advancing the chain in `bad`. As in
+    /// netem, the chain moves first and the packet takes its chance in the new state.
+    pub fn lose(&self, bad: &mut bool, rng: &mut StdRng) -> bool {
+        match *self {
+            LossModel::Random { rate } => chance(rng, rate),
+            LossModel::GilbertElliott { p, r, bad_loss, good_loss } => {
+                *bad = if *bad { !chance(rng, r) } else { chance(rng, p) };
+                chance(rng, if *bad { bad_loss } else { good_loss })
+            }
+        }
+    }
+
+    /// Parse netem's gemodel arguments, `p[/r[/1-h[/1-k]]]`: the two transition
+    /// probabilities, then the loss in the bad (default 100%) and good (default 0%)
+    /// states. Without `r`, netem takes it as 1 - p.
+    pub fn parse_gilbert_elliott(value: &str) -> Result<Self, String> {
+        let parts = value.split('/').map(parse_percent).collect::<Result<Vec<_>, _>>()?;
+        match parts[..] {
+            [p] => Ok(LossModel::GilbertElliott { p, r: 1.0 - p, bad_loss: 1.0, good_loss: 0.0 }),
+            [p, r] => Ok(LossModel::GilbertElliott { p, r, bad_loss: 1.0, good_loss: 0.0 }),
+            [p, r, bad_loss] => Ok(LossModel::GilbertElliott { p, r, bad_loss, good_loss: 0.0 }),
+            [p, r, bad_loss, good_loss] => Ok(LossModel::GilbertElliott { p, r, bad_loss, good_loss }),
+            _ => Err(format!("gemodel takes p[/r[/1-h[/1-k]]], got '{}'", value)),
+        }
+    }
+}
+
+/// Shape of the delay around its mean
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DelayDistribution {
+    /// `delay` plus or minus up to `jitter`
+    #[default]
+    Uniform,
+    /// Normal around `delay` with `jitter` as the standard deviation
+    Normal,
+    /// `delay` plus a heavy-tailed extra whose mean is `jitter`
+    Pareto,
+    /// `delay` plus a value drawn from recorded samples; `jitter` is ignored
+    Empirical {
+        path: PathBuf,
+        #[serde(skip)]
+        samples: Arc<Vec<Duration>>,
+    },
+}
+
+impl DelayDistribution {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "uniform" => Ok(DelayDistribution::Uniform),
+            "normal" => Ok(DelayDistribution::Normal),
+            "pareto" => Ok(DelayDistribution::Pareto),
+            _ => Err(format!("unknown distribution '{}' (expected uniform, normal or pareto)", value)),
+        }
+    }
+
+    /// Load delay samples, one per line in milliseconds; lines that aren't numbers are skipped.
+    pub fn empirical(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        let samples: Vec<Duration> = text
+            .lines()
+            .filter_map(|line| line.split(',').next()?.trim().parse::<f64>().ok())
+            .filter(|ms| *ms >= 0.0)
+            .map(|ms| Duration::from_secs_f64(ms / 1e3))
+            .collect();
+        if samples.is_empty() {
+            return Err(format!("no delay samples in {}", path.display()));
+        }
+        Ok(DelayDistribution::Empirical { path: path.to_path_buf(), samples: Arc::new(samples) })
+    }
+
+    pub fn sample(&self, delay: Duration, jitter: Duration, rng: &mut StdRng) -> Duration {
+        let (delay_s, jitter_s) = (delay.as_secs_f64(), jitter.as_secs_f64());
+        let seconds = match self {
+            DelayDistribution::Empirical { samples, .. } => delay_s + samples[rng.gen_range(0..samples.len())].as_secs_f64(),
+            _ if jitter.is_zero() => delay_s,
+            DelayDistribution::Uniform => delay_s + rng.gen_range(-jitter_s..=jitter_s),
+            DelayDistribution::Normal => Normal::new(delay_s, jitter_s).map_or(delay_s, |n| n.sample(rng)),
+            DelayDistribution::Pareto => {
+                // Lomax: a pareto shifted to start at zero, scaled so its mean is the jitter
+                let scale = jitter_s * (PARETO_SHAPE - 1.0);
+                let extra = Pareto::new(scale, PARETO_SHAPE).map_or(0.0, |p| p.sample(rng) - scale);
+                delay_s + extra
+            }
+        };
+        Duration::from_secs_f64(seconds.max(0.0))
+    }
+}
+
+/// One packet of a recorded trace
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TraceEntry {
+    /// Empty for lost packets in most recordings
+    #[serde(default)]
+    pub delay_us: Option<u64>,
+    #[serde(default, deserialize_with = "flag")]
+    pub lost: bool,
+}
+
+/// Per-packet delays and losses to apply in order, wrapping round at the end
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trace {
+    pub path: PathBuf,
+    pub packets: usize,
+    #[serde(skip)]
+    entries: Arc<Vec<TraceEntry>>,
+}
+
+impl Trace {
+    /// Load a CSV with `delay_us` and `lost` columns, one row per packet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut reader = csv::Reader::from_path(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        let entries = reader
+            .deserialize()
+            .collect::<Result<Vec<TraceEntry>, _>>()
+            .map_err(|e| format!("invalid trace {}: {}", path.display(), e))?;
+        if entries.is_empty() {
+            return Err(format!("no packets in trace {}", path.display()));
+        }
+        Ok(Trace { path: path.to_path_buf(), packets: entries.len(), entries: Arc::new(entries) })
+    }
+
+    pub fn get(&self, index: usize) -> &TraceEntry {
+        &self.entries[index % self.entries.len()]
+    }
+}
+
+fn flag<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Ok(matches!(value.trim(), "1" | "true" | "True" | "yes"))
+}
+
+pub(crate) fn chance(rng: &mut StdRng, probability: f64) -> bool {
+    probability > 0.0 && rng.gen_bool(probability.min(1.0))
+}
+
+/// `0.1%` or `0.1`, both meaning one in a thousand
+pub(crate) fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent: f64 = value.trim_end_matches('%').parse().map_err(|_| format!("invalid percentage '{}'", value))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("percentage '{}' is outside 0-100", value));
+    }
+    Ok(percent / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn gilbert_elliott(p: f64, r: f64, bad_loss: f64, good_loss: f64) -> LossModel {
+        LossModel::GilbertElliott { p, r, bad_loss, good_loss }
+    }
+
+    /// Fraction of `packets` lost, and the mean length of a run of losses
+    fn simulate(model: &LossModel, packets: usize) -> (f64, f64) {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut bad = false;
+        let (mut lost, mut runs, mut previous) = (0, 0, false);
+        for _ in 0..packets {
+            let this = model.lose(&mut bad, &mut rng);
+            lost += this as usize;
+            runs += (this && !previous) as usize;
+            previous = this;
+        }
+        (lost as f64 / packets as f64, lost as f64 / runs.max(1) as f64)
+    }
+
+    #[test]
+    fn gemodel_takes_one_to_four_parameters() {
+        assert_eq!(LossModel::parse_gilbert_elliott("10%"), Ok(gilbert_elliott(0.1, 0.9, 1.0, 0.0)));
+        assert_eq!(LossModel::parse_gilbert_elliott("1%/30%"), Ok(gilbert_elliott(0.01, 0.3, 1.0, 0.0)));
+        assert_eq!(LossModel::parse_gilbert_elliott("1/30/50"), Ok(gilbert_elliott(0.01, 0.3, 0.5, 0.0)));
+        assert_eq!(LossModel::parse_gilbert_elliott("1%/30%/50%/2%"), Ok(gilbert_elliott(0.01, 0.3, 0.5, 0.02)));
+    }
+
+    #[test]
+    fn bad_gemodels_are_rejected() {
+        for bad in ["", "1%/", "x", "150%", "1/2/3/4/5"] {
+            assert!(LossModel::parse_gilbert_elliott(bad).is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn simulated_loss_matches_the_stationary_rate() {
+        let model = gilbert_elliott(0.05, 0.3, 0.8, 0.01);
+        // Bad a seventh of the time: 0.05 / (0.05 + 0.3)
+        let expected = 0.05 / 0.35 * 0.8 + 0.3 / 0.35 * 0.01;
+        assert!((model.mean_loss() - expected).abs() < 1e-12);
+        let (loss, _) = simulate(&model, 500_000);
+        assert!((loss - expected).abs() < 0.005, "simulated {} against {}", loss, expected);
+    }
+
+    #[test]
+    fn losses_come_in_bursts_of_one_over_r() {
+        let model = gilbert_elliott(0.01, 0.25, 1.0, 0.0);
+        assert_eq!(model.mean_burst(), Some(4.0));
+        let (loss, burst) = simulate(&model, 500_000);
+        assert!((loss - model.mean_loss()).abs() < 0.005, "simulated {} against {}", loss, model.mean_loss());
+        assert!((burst - 4.0).abs() < 0.2, "mean burst {}", burst);
+    }
+
+    #[test]
+    fn one_parameter_gemodel_is_random_loss() {
+        // With r = 1 - p the next state doesn't depend on this one, so every packet
+        // is lost with probability p and bursts are as long as chance makes them
+        let model = LossModel::parse_gilbert_elliott("10%").unwrap();
+        let (loss, burst) = simulate(&model, 500_000);
+        assert!((loss - 0.1).abs() < 0.005, "simulated {}", loss);
+        assert!((burst - 1.0 / 0.9).abs() < 0.05, "mean burst {}", burst);
+    }
+}
@@ -0,0 +1,82 @@
+// TCP forwarding. Bytes are relayed in the chunks they are read in, each chunk
+// treated as a segment by the link. A userspace proxy terminates TCP on both
+// sides, so it can't make the endpoints retransmit: "loss" instead holds the
+// stream for the retransmission time, which is what the application sees.
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use std::sync::Arc;
+use crate::link::{pipe, Framing, Link};
+use crate::Directions;
+
+/// Largest chunk read from either side at once
+const READ_CHUNK: usize = 16 * 1024;
+
+pub async fn run(listen: SocketAddr, upstream: SocketAddr, directions: Directions) -> io::Result<()> {
+    let listener = TcpListener::bind(listen).await?;
+    println!("TCP: forwarding {} -> {}", listen, upstream);
+    for impairment in [&directions.up, &directions.down] {
+        if impairment.reorder > 0.0 || impairment.duplicate > 0.0 {
+            println!("TCP: reorder and duplicate don't apply to a byte stream and are ignored");
+            break;
+        }
+    }
+
+    loop {
+        let (client, peer) = listener.accept().await?;
+        let directions = directions.clone();
+        tokio::spawn(async move {
+            if let Err(e) = relay(client, peer, upstream, directions).await {
+                eprintln!("TCP: connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn relay(client: TcpStream, peer: SocketAddr, upstream: SocketAddr, directions: Directions) -> io::Result<()> {
+    let server = TcpStream::connect(upstream).await?;
+    // Batching would add delay the impairments don't account for
+    client.set_nodelay(true)?;
+    server.set_nodelay(true)?;
+    println!("TCP: new connection from {}", peer);
+
+    let (client_read, client_write) = client.into_split();
+    let (server_read, server_write) = server.into_split();
+    let (up_rng, down_rng) = directions.flow_rngs();
+    let uplink = Link::new(directions.up.clone(), Framing::Stream, directions.up_stats.clone(), up_rng);
+    let downlink = Link::new(directions.down.clone(), Framing::Stream, directions.down_stats.clone(), down_rng);
+    let up = tokio::spawn(forward(client_read, server_write, uplink));
+    let down = tokio::spawn(forward(server_read, client_write, downlink));
+    let (up, down) = tokio::join!(up, down);
+    up??;
+    down??;
+    println!("TCP: connection from {} closed", peer);
+    Ok(())
+}
+
+/// Relay one direction until the reader hits EOF, then pass the shutdown on.
+async fn forward(mut from: OwnedReadHalf, to: OwnedWriteHalf, link: Link) -> io::Result<()> {
+    // Capacity one: the link's own queue limit provides the backpressure
+    let (tx, rx) = mpsc::channel::<Vec<u8>>(1);
+    let to = Arc::new(Mutex::new(to));
+    let writer = to.clone();
+    let delivered = tokio::spawn(pipe(link, rx, move |data| {
+        let writer = writer.clone();
+        async move { writer.lock().await.write_all(&data).await }
+    }));
+
+    let mut buf = vec![0u8; READ_CHUNK];
+    loop {
+        let len = from.read(&mut buf).await?;
+        if len == 0 || tx.send(buf[..len].to_vec()).await.is_err() {
+            break;
+        }
+    }
+    drop(tx);
+    delivered.await??;
+    let mut to = to.lock().await;
+    to.shutdown().await
+}
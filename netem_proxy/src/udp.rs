@@ -0,0 +1,105 @@
+// UDP forwarding. Each client address gets its own upstream socket, so the server
+// sees one peer per client, and its own pair of links.
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use crate::link::{pipe, Framing, Link};
+use crate::Directions;
+
+/// Largest datagram forwarded
+const MAX_DATAGRAM: usize = 65_535;
+
+pub async fn run(listen: SocketAddr, upstream: SocketAddr, directions: Directions) -> io::Result<()> {
+    let socket = Arc::new(UdpSocket::bind(listen).await?);
+    println!("UDP: forwarding {} -> {}", listen, upstream);
+    let mut sessions: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>> = HashMap::new();
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+
+    loop {
+        let (len, client) = socket.recv_from(&mut buf).await?;
+        if sessions.get(&client).is_none_or(|uplink| uplink.is_closed()) {
+            match open_session(&socket, client, upstream, &directions).await {
+                Ok(uplink) => {
+                    println!("UDP: new session from {}", client);
+                    sessions.insert(client, uplink);
+                }
+                Err(e) => {
+                    eprintln!("UDP: could not open upstream socket for {}: {}", client, e);
+                    continue;
+                }
+            }
+        }
+        // A full channel means the link task is behind; treat it like a full queue
+        if sessions[&client].try_send(buf[..len].to_vec()).is_err() {
+            directions.up_stats.lock().unwrap().queue_drops += 1;
+        }
+    }
+}
+
+/// Start the two links for a new client and return the sender feeding its uplink.
+async fn open_session(
+    socket: &Arc<UdpSocket>,
+    client: SocketAddr,
+    upstream: SocketAddr,
+    directions: &Directions,
+) -> io::Result<mpsc::Sender<Vec<u8>>> {
+    let bind: SocketAddr = if upstream.is_ipv4() { "0.0.0.0:0".parse().unwrap() } else { "[::]:0".parse().unwrap() };
+    let server = Arc::new(UdpSocket::bind(bind).await?);
+    server.connect(upstream).await?;
+
+    let (up_tx, up_rx) = mpsc::channel::<Vec<u8>>(directions.up.limit);
+    let (down_tx, down_rx) = mpsc::channel::<Vec<u8>>(directions.down.limit);
+
+    let (up_rng, down_rng) = directions.flow_rngs();
+    let uplink = Link::new(directions.up.clone(), Framing::Datagram, directions.up_stats.clone(), up_rng);
+    let to_server = server.clone();
+    tokio::spawn(async move {
+        let sent = pipe(uplink, up_rx, |data| {
+            let to_server = to_server.clone();
+            async move { to_server.send(&data).await.map(|_| ()) }
+        })
+        .await;
+        if let Err(e) = sent {
+            eprintln!("UDP: forwarding to the server for {} failed: {}", client, e);
+        }
+    });
+
+    let downlink = Link::new(directions.down.clone(), Framing::Datagram, directions.down_stats.clone(), down_rng);
+    let to_client = socket.clone();
+    tokio::spawn(async move {
+        let sent = pipe(downlink, down_rx, |data| {
+            let to_client = to_client.clone();
+            async move { to_client.send_to(&data, client).await.map(|_| ()) }
+        })
+        .await;
+        if let Err(e) = sent {
+            eprintln!("UDP: forwarding to {} failed: {}", client, e);
+        }
+    });
+
+    let down_stats = directions.down_stats.clone();
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            match server.recv(&mut buf).await {
+                Ok(len) => {
+                    if down_tx.try_send(buf[..len].to_vec()).is_err() {
+                        if down_tx.is_closed() {
+                            break;
+                        }
+                        down_stats.lock().unwrap().queue_drops += 1;
+                    }
+                }
+                Err(e) => {
+                    // ICMP port unreachable while the server is restarting shows up here
+                    eprintln!("UDP: receive from the server for {} failed: {}", client, e);
+                }
+            }
+        }
+    });
+
+    Ok(up_tx)
+}
@@ -0,0 +1,37 @@
+// Record the commit and build profile the benchmarks were built from, for run manifests.
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    if let Some(commit) = git(&["rev-parse", "HEAD"]) {
+        println!("cargo:rustc-env=ONS_GIT_COMMIT={}", commit);
+    }
+    if let Some(status) = git(&["status", "--porcelain", "--untracked-files=no"]) {
+        println!("cargo:rustc-env=ONS_GIT_DIRTY={}", !status.is_empty());
+    }
+    println!("cargo:rustc-env=ONS_BUILD_PROFILE={}", env::var("PROFILE").unwrap_or_default());
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    if let Ok(output) = Command::new(rustc).arg("-V").output() {
+        println!("cargo:rustc-env=ONS_RUSTC_VERSION={}", String::from_utf8_lossy(&output.stdout).trim());
+    }
+
+    // Rerun when a commit, checkout or staging changes what git reports. An unstaged edit
+    // alone doesn't touch any of these, so the dirty flag can lag until the index is
+    // next rewritten (any `git status` or `git add` does).
+    let mut watched = vec!["HEAD".to_string(), "index".to_string(), "packed-refs".to_string()];
+    if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        watched.push(head_ref);
+    }
+    for path in watched {
+        // A missing path is always out of date, so skip refs that only live in packed-refs
+        if let Some(path) = git(&["rev-parse", "--git-path", &path]).filter(|p| Path::new(p).exists()) {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+// NTP-style clock offset estimation and one-way delay breakdown.
+//
+// Every echo carries four wall-clock timestamps: client send (t0), server receive (t1),
+// server send (t2) and client receive (t3). Per sample the offset of the server clock is
+// θ = ((t1 - t0) + (t2 - t3)) / 2 and the network delay is δ = (t3 - t0) - (t2 - t1).
+// Queueing inflates δ and skews θ, so the offset is taken from the samples with the
+// smallest δ, as NTP's clock filter does. Clock drift over a run is not modelled.
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::histogram::LatencyHistogram;
+use crate::wire::TickMessage;
+
+/// Fraction of samples (those with the lowest network delay) used for the offset estimate
+const OFFSET_FILTER_FRACTION: f64 = 0.1;
+
+/// The four timestamps of one echoed tick, all in µs since the Unix epoch
+#[derive(Debug, Clone, Copy)]
+pub struct EchoTimestamps {
+    pub tick: u64,
+    pub client_send: u64,
+    pub server_recv: u64,
+    pub server_send: u64,
+    pub client_recv: u64,
+}
+
+impl EchoTimestamps {
+    /// Build from a client's own send/receive times and the server stamps in the echo
+    ///
+    /// Returns `None` if the server didn't stamp the echo.
+    pub fn from_echo(echo: &TickMessage, client_send: u64, client_recv: u64) -> Option<Self> {
+        Some(EchoTimestamps {
+            tick: echo.tick,
+            client_send,
+            server_recv: echo.server_recv_us?,
+            server_send: echo.server_send_us?,
+            client_recv,
+        })
+    }
+
+    /// Server clock minus client clock, as seen by this sample
+    pub fn offset(&self) -> i64 {
+        ((self.server_recv as i64 - self.client_send as i64) + (self.server_send as i64 - self.client_recv as i64)) / 2
+    }
+
+    /// Round trip time minus the time the message spent inside the server
+    pub fn network_delay(&self) -> i64 {
+        (self.client_recv as i64 - self.client_send as i64) - self.residence()
+    }
+
+    pub fn residence(&self) -> i64 {
+        self.server_send as i64 - self.server_recv as i64
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct OffsetEstimate {
+    /// Server clock minus client clock, in µs
+    pub offset_us: i64,
+    /// Smallest network delay seen, in µs
+    pub min_network_delay_us: i64,
+    /// Number of samples the estimate was taken from
+    pub samples_used: usize,
+}
+
+/// One round trip split into its three parts, in µs
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DelayBreakdown {
+    pub forward_us: i64,
+    pub residence_us: i64,
+    pub return_us: i64,
+}
+
+/// Collects echo timestamps over a run and splits each round trip once the offset is known
+#[derive(Default)]
+pub struct OneWayDelay {
+    // One entry per RTT sample, so breakdowns line up with the client's own sample order
+    samples: Vec<Option<EchoTimestamps>>,
+}
+
+impl OneWayDelay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the timestamps of one echo, or `None` if the server didn't stamp it
+    pub fn record(&mut self, timestamps: Option<EchoTimestamps>) {
+        self.samples.push(timestamps);
+    }
+
+    pub fn estimate_offset(&self) -> Option<OffsetEstimate> {
+        let mut filtered: Vec<(i64, i64)> = self
+            .samples
+            .iter()
+            .flatten()
+            .map(|ts| (ts.network_delay(), ts.offset()))
+            .collect();
+        if filtered.is_empty() {
+            return None;
+        }
+
+        filtered.sort_unstable_by_key(|&(delay, _)| delay);
+        let keep = ((filtered.len() as f64 * OFFSET_FILTER_FRACTION).ceil() as usize).max(1);
+        filtered.truncate(keep);
+
+        let min_network_delay_us = filtered[0].0;
+        let mut offsets: Vec<i64> = filtered.into_iter().map(|(_, offset)| offset).collect();
+        offsets.sort_unstable();
+
+        Some(OffsetEstimate {
+            offset_us: offsets[offsets.len() / 2],
+            min_network_delay_us,
+            samples_used: offsets.len(),
+        })
+    }
+
+    /// Split every recorded round trip using `offset`, in the order the samples were recorded
+    pub fn breakdown(&self, offset: &OffsetEstimate) -> Vec<Option<DelayBreakdown>> {
+        self.samples
+            .iter()
+            .map(|ts| {
+                ts.map(|ts| DelayBreakdown {
+                    forward_us: ts.server_recv as i64 - ts.client_send as i64 - offset.offset_us,
+                    residence_us: ts.residence(),
+                    return_us: ts.client_recv as i64 - ts.server_send as i64 + offset.offset_us,
+                })
+            })
+            .collect()
+    }
+
+    /// Offset estimate and forward/residence/return statistics for the run summary
+    pub fn summary(&self) -> Value {
+        let offset = match self.estimate_offset() {
+            Some(offset) => offset,
+            None => return Value::Null,
+        };
+        let breakdowns: Vec<DelayBreakdown> = self.breakdown(&offset).into_iter().flatten().collect();
+        let forward: Vec<i64> = breakdowns.iter().map(|b| b.forward_us).collect();
+        let residence: Vec<i64> = breakdowns.iter().map(|b| b.residence_us).collect();
+        let ret: Vec<i64> = breakdowns.iter().map(|b| b.return_us).collect();
+
+        json!({
+            "clock_offset_us": offset.offset_us,
+            "min_network_delay_us": offset.min_network_delay_us,
+            "offset_samples_used": offset.samples_used,
+            "stamped_samples": breakdowns.len(),
+            "forward_us": describe(forward),
+            "server_residence_us": describe(residence),
+            "return_us": describe(ret)
+        })
+    }
+}
+
+// The same HdrHistogram summary as the RTT and the server's residence time, so the
+// percentiles agree. A bad offset estimate can make one-way delays negative, which the
+// histogram can't hold, so values are recorded relative to the smallest and shifted back.
+fn describe(values: Vec<i64>) -> Value {
+    let Some(&floor) = values.iter().min() else { return Value::Null };
+    let mut histogram = LatencyHistogram::default();
+    for value in &values {
+        histogram.record(value.abs_diff(floor));
+    }
+    let mut summary = histogram.summary();
+    if let Value::Object(fields) = &mut summary {
+        for (name, field) in fields.iter_mut() {
+            *field = match (name.as_str(), field.as_u64()) {
+                ("count" | "stddev" | "significant_figures", _) => continue,
+                ("mean", _) => json!(field.as_f64().unwrap_or_default() + floor as f64),
+                (_, Some(value)) => json!(value as i64 + floor),
+                _ => continue,
+            };
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_shifts_negative_delays_back() {
+        let summary = describe(vec![-50, 0, 100]);
+        assert_eq!(summary["count"], 3);
+        assert_eq!(summary["min"], -50);
+        assert_eq!(summary["p50"], 0);
+        assert_eq!(summary["max"], 100);
+        assert!((summary["mean"].as_f64().unwrap() - 50.0 / 3.0).abs() < 1.0);
+        assert!(describe(Vec::new()).is_null());
+    }
+}
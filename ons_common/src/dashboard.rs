@@ -0,0 +1,427 @@
+// The live terminal view behind --tui. The tick loop only records into shared
+// stats; a thread of its own draws them, so a slow terminal can't delay a tick.
+use crate::metrics::SessionMetrics;
+use crate::stop::Stop;
+use crate::timeseries::TransportSnapshot;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Sparkline bins kept, one per redraw
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+const HISTORY: usize = 512;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DashboardOptions {
+    /// Show a live terminal dashboard while the run is going (needs the `tui` feature)
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Dashboard redraws per second
+    #[arg(long, default_value = "4")]
+    pub tui_refresh_hz: u32,
+
+    /// Seconds of echoes the dashboard's rolling percentiles cover
+    #[arg(long, default_value = "10")]
+    pub tui_window_secs: u64,
+}
+
+impl Default for DashboardOptions {
+    fn default() -> Self {
+        DashboardOptions { tui: false, tui_refresh_hz: 4, tui_window_secs: 10 }
+    }
+}
+
+impl DashboardOptions {
+    fn refresh(&self) -> Duration {
+        Duration::from_secs(1) / self.tui_refresh_hz.max(1)
+    }
+}
+
+/// What the dashboard shows, as the tick loop last reported it.
+///
+/// Only the last window of echoes and send errors is kept, so the percentiles
+/// follow the run as it changes rather than settling on the whole-run figure the
+/// summary reports at the end.
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+pub struct LiveStats {
+    title: String,
+    start: Instant,
+    duration: Duration,
+    window: Duration,
+    sent: u64,
+    on_time: u64,
+    late: u64,
+    outstanding: u64,
+    overruns: u64,
+    rtts: VecDeque<(Instant, u64)>,
+    last_rtt: Option<u64>,
+    jitter_us: f64,
+    schedule_errors: VecDeque<(Instant, u64)>,
+    transport: TransportSnapshot,
+    /// Mean RTT per redraw, for the sparkline
+    history: VecDeque<u64>,
+    bin: (u64, u64),
+    /// Where the same updates go for /metrics
+    metrics: SessionMetrics,
+}
+
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+impl LiveStats {
+    fn new(title: String, duration: Duration, window: Duration) -> Self {
+        LiveStats {
+            title,
+            start: Instant::now(),
+            duration,
+            window,
+            sent: 0,
+            on_time: 0,
+            late: 0,
+            outstanding: 0,
+            overruns: 0,
+            rtts: VecDeque::new(),
+            last_rtt: None,
+            jitter_us: 0.0,
+            schedule_errors: VecDeque::new(),
+            transport: TransportSnapshot::default(),
+            history: VecDeque::new(),
+            bin: (0, 0),
+            metrics: SessionMetrics::default(),
+        }
+    }
+
+    fn trim(&mut self, now: Instant) {
+        let cutoff = now.checked_sub(self.window).unwrap_or(self.start);
+        while self.rtts.front().is_some_and(|(at, _)| *at < cutoff) {
+            self.rtts.pop_front();
+        }
+        while self.schedule_errors.front().is_some_and(|(at, _)| *at < cutoff) {
+            self.schedule_errors.pop_front();
+        }
+    }
+
+    /// Close the current sparkline bin; called once per redraw
+    fn roll(&mut self) {
+        let (sum, count) = std::mem::take(&mut self.bin);
+        let mean = sum.checked_div(count).unwrap_or_else(|| self.history.back().copied().unwrap_or(0));
+        self.history.push_back(mean);
+        if self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    /// Lost so far: neither echoed nor still inside the late cut-off
+    fn lost(&self) -> u64 {
+        self.sent.saturating_sub(self.on_time + self.late + self.outstanding)
+    }
+}
+
+/// One redraw's worth of [`LiveStats`], copied out so the terminal is written without
+/// holding up the tick loop on the lock
+#[cfg(feature = "tui")]
+struct View {
+    title: String,
+    elapsed: Duration,
+    duration: Duration,
+    window: Duration,
+    sent: u64,
+    on_time: u64,
+    late: u64,
+    lost: u64,
+    outstanding: u64,
+    overruns: u64,
+    rtts: Vec<u64>,
+    last_rtt: Option<u64>,
+    jitter_us: f64,
+    schedule_errors: Vec<u64>,
+    transport: TransportSnapshot,
+    history: Vec<u64>,
+}
+
+#[cfg(feature = "tui")]
+impl View {
+    fn of(stats: &LiveStats) -> Self {
+        View {
+            title: stats.title.clone(),
+            elapsed: stats.start.elapsed(),
+            duration: stats.duration,
+            window: stats.window,
+            sent: stats.sent,
+            on_time: stats.on_time,
+            late: stats.late,
+            lost: stats.lost(),
+            outstanding: stats.outstanding,
+            overruns: stats.overruns,
+            rtts: stats.rtts.iter().map(|(_, rtt)| *rtt).collect(),
+            last_rtt: stats.last_rtt,
+            jitter_us: stats.jitter_us,
+            schedule_errors: stats.schedule_errors.iter().map(|(_, e)| *e).collect(),
+            transport: stats.transport,
+            history: stats.history.iter().copied().collect(),
+        }
+    }
+}
+
+/// Handle the tick loop reports through; cheap to clone into receive tasks.
+#[derive(Clone)]
+pub struct Live(Arc<Mutex<LiveStats>>);
+
+impl Live {
+    fn update(&self, f: impl FnOnce(&mut LiveStats)) {
+        if let Ok(mut stats) = self.0.lock() {
+            f(&mut stats);
+        }
+    }
+
+    /// A tick went out `schedule_error` after its deadline
+    pub fn sent(&self, schedule_error: Duration, transport: TransportSnapshot) {
+        let now = Instant::now();
+        self.update(|s| {
+            s.sent += 1;
+            s.schedule_errors.push_back((now, schedule_error.as_micros() as u64));
+            s.transport = transport;
+            s.trim(now);
+            s.metrics.sent();
+            s.metrics.transport(transport);
+        });
+    }
+
+    /// An echo came back in time
+    pub fn echo(&self, rtt_us: u64) {
+        let now = Instant::now();
+        self.update(|s| {
+            s.on_time += 1;
+            // RFC 3550's smoothing, applied to the change between consecutive RTTs
+            if let Some(last) = s.last_rtt {
+                s.jitter_us += (last.abs_diff(rtt_us) as f64 - s.jitter_us) / 16.0;
+            }
+            s.last_rtt = Some(rtt_us);
+            s.rtts.push_back((now, rtt_us));
+            s.bin.0 += rtt_us;
+            s.bin.1 += 1;
+            s.trim(now);
+            s.metrics.rtt(rtt_us);
+        });
+    }
+
+    /// An echo came back after the late cut-off
+    pub fn late(&self) {
+        self.update(|s| {
+            s.late += 1;
+            s.metrics.late();
+        });
+    }
+
+    /// Ticks still waiting for an echo inside the late cut-off
+    pub fn outstanding(&self, pending: usize) {
+        self.update(|s| {
+            s.outstanding = pending as u64;
+            s.metrics.outstanding(pending);
+        });
+    }
+
+    /// The send loop took longer than one tick period
+    pub fn overrun(&self) {
+        self.update(|s| {
+            s.overruns += 1;
+            s.metrics.overrun();
+        });
+    }
+}
+
+/// The live view of a running client. Redraws on its own thread, so the tick loop
+/// only pays for the [`Live`] updates; without `--tui` nothing is drawn at all, but
+/// the updates still reach /metrics once [`Dashboard::export`] is called.
+pub struct Dashboard {
+    live: Live,
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Dashboard {
+    /// Start drawing if `--tui` was given. `duration` is the planned length of the run,
+    /// for the progress bar; q or Ctrl-C requests `stop`, which the tick loop watches.
+    pub fn start(options: &DashboardOptions, title: &str, duration: Duration, stop: &Stop) -> Result<Self, Box<dyn Error>> {
+        let window = Duration::from_secs(options.tui_window_secs.max(1));
+        let live = Live(Arc::new(Mutex::new(LiveStats::new(title.to_string(), duration, window))));
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = if options.tui { Some(spawn(live.clone(), done.clone(), stop.clone(), options.refresh())?) } else { None };
+        Ok(Dashboard { live, done, thread })
+    }
+
+    pub fn live(&self) -> Live {
+        self.live.clone()
+    }
+
+    /// Pass everything the tick loop reports on to /metrics as well
+    pub fn export(&self, metrics: SessionMetrics) {
+        self.live.update(|s| s.metrics = metrics);
+    }
+
+    /// Stop drawing and give the terminal back
+    pub fn finish(mut self) {
+        self.stop_thread();
+    }
+
+    fn stop_thread(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+#[cfg(feature = "tui")]
+fn spawn(live: Live, done: Arc<AtomicBool>, stop: Stop, refresh: Duration) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    let mut terminal = ratatui::try_init()?;
+    Ok(std::thread::Builder::new().name("dashboard".into()).spawn(move || {
+        draw_loop(&mut terminal, &live, &done, &stop, refresh);
+        ratatui::restore();
+    })?)
+}
+
+#[cfg(not(feature = "tui"))]
+fn spawn(_live: Live, _done: Arc<AtomicBool>, _stop: Stop, _refresh: Duration) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    Err("The dashboard needs ons_common built with the `tui` feature".into())
+}
+
+/// Redraw until the run is over or the user asks to stop it. Raw mode turns Ctrl-C
+/// into a key press, so it is handled here like q rather than as a signal.
+#[cfg(feature = "tui")]
+fn draw_loop(terminal: &mut ratatui::DefaultTerminal, live: &Live, done: &AtomicBool, stop: &Stop, refresh: Duration) {
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    while !done.load(Ordering::Relaxed) {
+        let view = live.0.lock().ok().map(|mut stats| {
+            stats.roll();
+            stats.trim(Instant::now());
+            View::of(&stats)
+        });
+        if let Some(view) = view {
+            let _ = terminal.draw(|frame| render(frame, &view));
+        }
+        // Wait out the refresh period, but answer keys straight away
+        if event::poll(refresh).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (key.code == KeyCode::Char('q') || ctrl_c) {
+                    // Keep drawing while the tick loop winds down; finish() ends this loop
+                    stop.request();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+fn render(frame: &mut ratatui::Frame, stats: &View) {
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::style::{Color, Style};
+    use ratatui::text::Line;
+    use ratatui::widgets::{Block, Borders, Gauge, Paragraph, Sparkline};
+
+    let ms = |us: Option<f64>| us.map_or_else(|| "-".to_string(), |us| format!("{:.3} ms", us / 1e3));
+    let count = |v: Option<u64>| v.map_or_else(|| "-".to_string(), |v| v.to_string());
+    let rtts = sorted(&stats.rtts);
+    let errors = sorted(&stats.schedule_errors);
+    let window = format!("{} s", stats.window.as_secs());
+
+    let [progress, panels, spark, help] =
+        Layout::vertical([Constraint::Length(3), Constraint::Length(9), Constraint::Min(5), Constraint::Length(1)]).areas(frame.area());
+    let [rtt_area, loss_area, schedule_area, transport_area] = Layout::horizontal([Constraint::Ratio(1, 4); 4]).areas(panels);
+
+    let elapsed = stats.elapsed;
+    let ratio = (elapsed.as_secs_f64() / stats.duration.as_secs_f64().max(1e-9)).clamp(0.0, 1.0);
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::default().borders(Borders::ALL).title(stats.title.as_str()))
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .ratio(ratio)
+            .label(format!("{:.0} / {} s", elapsed.as_secs_f64(), stats.duration.as_secs())),
+        progress,
+    );
+
+    let panel = |title: String, lines: Vec<(&str, String)>| {
+        let width = lines.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        Paragraph::new(lines.into_iter().map(|(name, value)| Line::from(format!("{:<width$}  {}", name, value))).collect::<Vec<_>>())
+            .block(Block::default().borders(Borders::ALL).title(title))
+    };
+    frame.render_widget(
+        panel(format!("RTT ({})", window), vec![
+            ("last", ms(stats.last_rtt.map(|v| v as f64))),
+            ("p50", ms(quantile(&rtts, 0.5))),
+            ("p90", ms(quantile(&rtts, 0.9))),
+            ("p99", ms(quantile(&rtts, 0.99))),
+            ("p99.9", ms(quantile(&rtts, 0.999))),
+            ("max", ms(rtts.last().map(|&v| v as f64))),
+            ("jitter", ms((stats.on_time > 1).then_some(stats.jitter_us))),
+        ]),
+        rtt_area,
+    );
+    let loss_percent = if stats.sent == 0 { 0.0 } else { stats.lost as f64 / stats.sent as f64 * 100.0 };
+    frame.render_widget(
+        panel("Echoes".to_string(), vec![
+            ("sent", stats.sent.to_string()),
+            ("on time", stats.on_time.to_string()),
+            ("late", stats.late.to_string()),
+            ("lost", stats.lost.to_string()),
+            ("waiting", stats.outstanding.to_string()),
+            ("loss", format!("{:.3} %", loss_percent)),
+        ]),
+        loss_area,
+    );
+    frame.render_widget(
+        panel(format!("Schedule error ({})", window), vec![
+            ("p50", ms(quantile(&errors, 0.5))),
+            ("p99", ms(quantile(&errors, 0.99))),
+            ("max", ms(errors.last().map(|&v| v as f64))),
+            ("overruns", stats.overruns.to_string()),
+        ]),
+        schedule_area,
+    );
+    let t = &stats.transport;
+    frame.render_widget(
+        panel("Transport".to_string(), vec![
+            ("stack RTT", ms(t.stack_rtt_us.map(|v| v as f64))),
+            ("cwnd", t.cwnd_bytes.map_or_else(|| "-".to_string(), |v| format!("{} B", v))),
+            ("packets sent", count(t.sent_packets)),
+            ("packets lost", count(t.lost_packets)),
+            ("retransmits", count(t.retransmits)),
+        ]),
+        transport_area,
+    );
+
+    // Newest bins on the right, as many as fit
+    let width = spark.width.saturating_sub(2) as usize;
+    let history: Vec<u64> = stats.history.iter().skip(stats.history.len().saturating_sub(width)).copied().collect();
+    let peak = history.iter().max().copied().unwrap_or(0);
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::default().borders(Borders::ALL).title(format!("Mean RTT per redraw (peak {})", ms(Some(peak as f64)))))
+            .style(Style::default().fg(Color::Green))
+            .data(&history),
+        spark,
+    );
+    frame.render_widget(Paragraph::new("q or Ctrl-C stops the run"), help);
+}
+
+#[cfg(feature = "tui")]
+fn sorted(values: &[u64]) -> Vec<u64> {
+    let mut values = values.to_vec();
+    values.sort_unstable();
+    values
+}
+
+#[cfg(feature = "tui")]
+fn quantile(sorted: &[u64], q: f64) -> Option<f64> {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    (!sorted.is_empty()).then(|| sorted[rank.clamp(1, sorted.len()) - 1] as f64)
+}
@@ -0,0 +1,149 @@
+// How the servers echo: straight away or on their own tick, with an optional cap per
+// tick, and the summary of what the echo path did that goes next to the client's RTTs.
+use crate::histogram::{HistogramOptions, LatencyHistogram};
+use crate::metrics::SessionMetrics;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::time::Duration;
+
+/// When a server sends back what it received
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EchoMode {
+    /// Echo each message as soon as it is read, bypassing the server tick
+    Immediate,
+    /// Hold messages until the server's next tick, then echo everything received
+    Tick,
+    /// Like `tick`, but echo at most --echo-cap messages per tick; the rest wait for later ticks
+    TickCapped,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct EchoOptions {
+    /// Echo immediately, or batch echoes on the server tick as a game server would
+    #[arg(long, value_enum, default_value_t = EchoMode::Tick)]
+    pub echo_mode: EchoMode,
+
+    /// Most messages echoed per tick in tick-capped mode
+    #[arg(long, default_value = "10")]
+    pub echo_cap: usize,
+}
+
+impl Default for EchoOptions {
+    fn default() -> Self {
+        EchoOptions { echo_mode: EchoMode::Tick, echo_cap: 10 }
+    }
+}
+
+impl EchoOptions {
+    pub fn immediate(&self) -> bool {
+        self.echo_mode == EchoMode::Immediate
+    }
+
+    /// Per-tick echo limit, if the mode has one
+    pub fn cap(&self) -> Option<usize> {
+        match self.echo_mode {
+            EchoMode::TickCapped => Some(self.echo_cap.max(1)),
+            _ => None,
+        }
+    }
+
+    /// Take this tick's batch from the front of `backlog`, leaving anything over the cap queued.
+    pub fn take_tick<T>(&self, backlog: &mut VecDeque<T>) -> Vec<T> {
+        let count = self.cap().map_or(backlog.len(), |cap| cap.min(backlog.len()));
+        backlog.drain(..count).collect()
+    }
+}
+
+/// What the server's echo path did over a session, so tick quantisation on the
+/// server can be told apart from transport latency in the client's RTT.
+pub struct EchoStats {
+    mode: EchoMode,
+    cap: Option<usize>,
+    tick: Duration,
+    ticks: u64,
+    echoed: u64,
+    deferred: u64,
+    max_batch: usize,
+    max_backlog: usize,
+    overruns: u64,
+    residence: LatencyHistogram,
+    metrics: SessionMetrics,
+}
+
+impl EchoStats {
+    pub fn new(options: &EchoOptions, tick: Duration) -> Self {
+        EchoStats {
+            mode: options.echo_mode,
+            cap: options.cap(),
+            tick,
+            ticks: 0,
+            echoed: 0,
+            deferred: 0,
+            max_batch: 0,
+            max_backlog: 0,
+            overruns: 0,
+            residence: LatencyHistogram::new(&HistogramOptions::default()),
+            metrics: SessionMetrics::default(),
+        }
+    }
+
+    /// Also report ticks and echoes to the session's /metrics series
+    pub fn with_metrics(mut self, metrics: SessionMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Record one server tick: how many messages it echoed and how many were left waiting.
+    pub fn tick(&mut self, batch: usize, left: usize) {
+        self.ticks += 1;
+        self.deferred += left as u64;
+        self.max_batch = self.max_batch.max(batch);
+        self.max_backlog = self.max_backlog.max(batch + left);
+        self.metrics.queue_depth(left);
+    }
+
+    /// Record a server tick whose processing took longer than the tick period.
+    pub fn overrun(&mut self) {
+        self.overruns += 1;
+        self.metrics.overrun();
+    }
+
+    /// Record an echo from its wall-clock receive and send times, in µs.
+    pub fn echoed(&mut self, recv_us: u64, send_us: u64) {
+        self.echoed += 1;
+        self.residence.record(send_us.saturating_sub(recv_us));
+        self.metrics.echoed(recv_us, send_us);
+    }
+
+    pub fn summary(&self) -> Value {
+        json!({
+            "mode": format!("{:?}", self.mode),
+            "cap_per_tick": self.cap,
+            "tick_us": if self.mode == EchoMode::Immediate { None } else { Some(self.tick.as_micros() as u64) },
+            "ticks": self.ticks,
+            "echoed": self.echoed,
+            "deferred": self.deferred,
+            "max_batch": self.max_batch,
+            "max_backlog": self.max_backlog,
+            "overruns": self.overruns,
+            "residence_us": self.residence.summary()
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.save_with(path, Value::Null)
+    }
+
+    /// [`save`](Self::save) with more sections next to "echo", e.g. a transport's own
+    /// receive queue counters
+    pub fn save_with(&self, path: &str, extra: Value) -> Result<(), Box<dyn Error>> {
+        let mut summary = json!({ "echo": self.summary() });
+        if let (Value::Object(summary), Value::Object(extra)) = (&mut summary, extra) {
+            summary.extend(extra);
+        }
+        fs::write(path, serde_json::to_string_pretty(&summary)?)?;
+        Ok(())
+    }
+}
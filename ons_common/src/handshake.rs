@@ -0,0 +1,216 @@
+// Connection setup timing: each transport's handshake split into its phases, either
+// once at the start of a run or over many connections with --connect-bench.
+use crate::histogram::{HistogramOptions, LatencyHistogram};
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct HandshakeOptions {
+    /// Open and close this many connections, timing each handshake phase, instead of running the tick session
+    #[arg(long)]
+    pub connect_bench: Option<u32>,
+
+    /// Pause between benchmark connections, in milliseconds
+    #[arg(long, default_value = "100")]
+    pub connect_interval_ms: u64,
+
+    /// Resume the TLS session on connections after the first (QUIC sends 0-RTT data where the server accepts it)
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Where to write the handshake summary; defaults to <transport>_handshake.json, with a CSV of each connection beside it
+    #[arg(long)]
+    pub handshake_file: Option<PathBuf>,
+}
+
+impl Default for HandshakeOptions {
+    fn default() -> Self {
+        HandshakeOptions { connect_bench: None, connect_interval_ms: 100, resume: false, handshake_file: None }
+    }
+}
+
+impl HandshakeOptions {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.connect_interval_ms)
+    }
+
+    pub fn path(&self, stem: &str) -> PathBuf {
+        self.handshake_file.clone().unwrap_or_else(|| PathBuf::from(format!("{}_handshake.json", stem)))
+    }
+}
+
+/// Splits one connection attempt into named phases, each timed from the end of the one before.
+pub struct PhaseTimer {
+    start: Instant,
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl PhaseTimer {
+    pub fn start() -> Self {
+        let now = Instant::now();
+        PhaseTimer { start: now, last: now, phases: Vec::new() }
+    }
+
+    /// Close the phase that has been running since the previous mark. A phase that
+    /// has already been marked is left alone, so state callbacks that fire again
+    /// after a reconnection don't add to the breakdown.
+    pub fn mark(&mut self, phase: &'static str) {
+        if self.phases.iter().any(|(name, _)| *name == phase) {
+            return;
+        }
+        let now = Instant::now();
+        self.phases.push((phase, now - self.last));
+        self.last = now;
+    }
+
+    pub fn finish(self) -> Handshake {
+        Handshake { phases: self.phases, total: self.last - self.start, resumed: None, early_data: None }
+    }
+}
+
+/// The phase times of one completed connection.
+///
+/// `resumed` and `early_data` are `None` when the stack can't say, which is
+/// different from a full handshake (`Some(false)`).
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    pub phases: Vec<(&'static str, Duration)>,
+    pub total: Duration,
+    pub resumed: Option<bool>,
+    pub early_data: Option<bool>,
+}
+
+impl Handshake {
+    pub fn with_resumption(mut self, resumed: Option<bool>, early_data: Option<bool>) -> Self {
+        self.resumed = resumed;
+        self.early_data = early_data;
+        self
+    }
+
+    pub fn summary(&self) -> Value {
+        let phases: Map<String, Value> =
+            self.phases.iter().map(|(name, d)| (format!("{}_us", name), json!(d.as_micros() as u64))).collect();
+        json!({
+            "phases": phases,
+            "total_us": self.total.as_micros() as u64,
+            "resumed": self.resumed,
+            "early_data": self.early_data
+        })
+    }
+
+    pub fn log(&self) {
+        let phases: Vec<String> =
+            self.phases.iter().map(|(name, d)| format!("{} {:.2} ms", name, d.as_secs_f64() * 1000.0)).collect();
+        println!("Connection established in {:.2} ms ({})", self.total.as_secs_f64() * 1000.0, phases.join(", "));
+    }
+}
+
+/// Handshake phase distributions over a run of connections.
+pub struct HandshakeBench {
+    transport: &'static str,
+    histogram: HistogramOptions,
+    attempts: Vec<Result<Handshake, String>>,
+}
+
+impl HandshakeBench {
+    pub fn new(transport: &'static str, histogram: &HistogramOptions) -> Self {
+        HandshakeBench { transport, histogram: histogram.clone(), attempts: Vec::new() }
+    }
+
+    pub fn record(&mut self, attempt: Result<Handshake, String>) {
+        match &attempt {
+            Ok(handshake) => handshake.log(),
+            Err(e) => eprintln!("Connection {} failed: {}", self.attempts.len() + 1, e),
+        }
+        self.attempts.push(attempt);
+    }
+
+    fn completed(&self) -> impl Iterator<Item = &Handshake> {
+        self.attempts.iter().filter_map(|a| a.as_ref().ok())
+    }
+
+    /// Phase names in the order the transport went through them
+    fn phase_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<&'static str> = Vec::new();
+        for (name, _) in self.completed().flat_map(|h| &h.phases) {
+            if !names.contains(name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    pub fn summary(&self) -> Value {
+        let mut total = LatencyHistogram::new(&self.histogram);
+        let mut phases: Vec<(&'static str, LatencyHistogram)> =
+            self.phase_names().into_iter().map(|name| (name, LatencyHistogram::new(&self.histogram))).collect();
+        for handshake in self.completed() {
+            total.record(handshake.total.as_micros() as u64);
+            for (name, d) in &handshake.phases {
+                if let Some((_, hist)) = phases.iter_mut().find(|(n, _)| n == name) {
+                    hist.record(d.as_micros() as u64);
+                }
+            }
+        }
+        let count = |f: fn(&Handshake) -> Option<bool>, value: bool| self.completed().filter(|h| f(h) == Some(value)).count();
+        let phase_summaries: Map<String, Value> =
+            phases.iter().map(|(name, hist)| (format!("{}_us", name), hist.summary())).collect();
+
+        json!({
+            "transport": self.transport,
+            "attempts": self.attempts.len(),
+            "completed": total.len(),
+            "failed": self.attempts.iter().filter(|a| a.is_err()).count(),
+            "total_us": total.summary(),
+            "phases": phase_summaries,
+            "resumption": {
+                "resumed": count(|h| h.resumed, true),
+                "full": count(|h| h.resumed, false),
+                "unknown": self.completed().filter(|h| h.resumed.is_none()).count()
+            },
+            "early_data": {
+                "accepted": count(|h| h.early_data, true),
+                "rejected": count(|h| h.early_data, false)
+            }
+        })
+    }
+
+    /// Write the summary as JSON and one row per connection as CSV next to it.
+    pub fn save(&self, options: &HandshakeOptions) -> Result<PathBuf, Box<dyn Error>> {
+        let path = options.path(self.transport);
+        fs::write(&path, serde_json::to_string_pretty(&json!({ "handshake": self.summary() }))?)?;
+
+        let names = self.phase_names();
+        let mut writer = csv::Writer::from_path(path.with_extension("csv"))?;
+        let mut header = vec!["connection".to_string(), "total_us".to_string()];
+        header.extend(names.iter().map(|name| format!("{}_us", name)));
+        header.extend(["resumed", "early_data", "error"].map(String::from));
+        writer.write_record(&header)?;
+
+        let flag = |v: Option<bool>| v.map(|b| b.to_string()).unwrap_or_default();
+        for (i, attempt) in self.attempts.iter().enumerate() {
+            let mut row = vec![(i + 1).to_string()];
+            match attempt {
+                Ok(handshake) => {
+                    row.push(handshake.total.as_micros().to_string());
+                    for name in &names {
+                        let phase = handshake.phases.iter().find(|(n, _)| n == name);
+                        row.push(phase.map(|(_, d)| d.as_micros().to_string()).unwrap_or_default());
+                    }
+                    row.extend([flag(handshake.resumed), flag(handshake.early_data), String::new()]);
+                }
+                Err(e) => {
+                    row.extend(std::iter::repeat_n(String::new(), names.len() + 3));
+                    row.push(e.clone());
+                }
+            }
+            writer.write_record(&row)?;
+        }
+        writer.flush()?;
+        Ok(path)
+    }
+}
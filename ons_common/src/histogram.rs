@@ -0,0 +1,122 @@
+// Latency histograms for RTTs, residence times and handshake phases.
+//
+// Every summary in the results takes its percentiles from here, and the serialized
+// form lets runs be merged afterwards with HdrHistogram tooling.
+use hdrhistogram::serialization::{Deserializer, Serializer, V2DeflateSerializer};
+use hdrhistogram::Histogram;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Significant value digits kept by default (0.1% relative error)
+pub const DEFAULT_SIGFIG: u8 = 3;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct HistogramOptions {
+    /// Significant decimal digits kept by the latency histogram (1-5)
+    #[arg(long, default_value_t = DEFAULT_SIGFIG, value_parser = clap::value_parser!(u8).range(1..=5))]
+    pub histogram_sigfig: u8,
+}
+
+impl Default for HistogramOptions {
+    fn default() -> Self {
+        HistogramOptions { histogram_sigfig: DEFAULT_SIGFIG }
+    }
+}
+
+/// Latency recorder backed by an auto-resizing HdrHistogram, in microseconds.
+///
+/// Percentiles come from the histogram rather than from indexing a sorted
+/// sample vector, so they are defined for any count (including one) and have
+/// bounded relative error regardless of range.
+pub struct LatencyHistogram {
+    hist: Histogram<u64>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram::new(&HistogramOptions::default())
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new(options: &HistogramOptions) -> Self {
+        let hist = Histogram::new(options.histogram_sigfig)
+            .expect("histogram precision is validated by the argument parser");
+        LatencyHistogram { hist }
+    }
+
+    pub fn record(&mut self, micros: u64) {
+        // The histogram resizes itself, so this only fails for values it can never hold;
+        // saturating_record would clamp to the current (tiny) upper bound instead of growing
+        if self.hist.record(micros).is_err() {
+            self.hist.saturating_record(micros);
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.hist.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hist.is_empty()
+    }
+
+    pub fn histogram(&self) -> &Histogram<u64> {
+        &self.hist
+    }
+
+    /// Add another run's samples, e.g. one loaded with [`LatencyHistogram::load`].
+    pub fn merge(&mut self, other: &LatencyHistogram) -> Result<(), Box<dyn Error>> {
+        self.hist.add(&other.hist).map_err(|e| format!("{:?}", e))?;
+        Ok(())
+    }
+
+    pub fn summary(&self) -> Value {
+        if self.hist.is_empty() {
+            return Value::Null;
+        }
+        json!({
+            "count": self.hist.len(),
+            "min": self.hist.min(),
+            "max": self.hist.max(),
+            "mean": self.hist.mean(),
+            "stddev": self.hist.stdev(),
+            "p50": self.hist.value_at_quantile(0.50),
+            "p90": self.hist.value_at_quantile(0.90),
+            "p99": self.hist.value_at_quantile(0.99),
+            "p99_9": self.hist.value_at_quantile(0.999),
+            "p99_99": self.hist.value_at_quantile(0.9999),
+            "significant_figures": self.hist.sigfig()
+        })
+    }
+
+    /// Counts in fixed-width buckets from zero to the maximum, skipping empty buckets.
+    pub fn buckets(&self, width: u64) -> Vec<(u64, u64)> {
+        self.hist
+            .iter_linear(width.max(1))
+            .filter(|v| v.count_since_last_iteration() > 0)
+            .map(|v| (v.value_iterated_to() + 1 - width.max(1), v.count_since_last_iteration()))
+            .collect()
+    }
+
+    /// Write the histogram in the V2 deflate encoding understood by HdrHistogram tooling.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        V2DeflateSerializer::new()
+            .serialize(&self.hist, &mut writer)
+            .map_err(|e| format!("{:?}", e))?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let hist: Histogram<u64> = Deserializer::new()
+            .deserialize(&mut reader)
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(LatencyHistogram { hist })
+    }
+}
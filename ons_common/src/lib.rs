@@ -0,0 +1,20 @@
+// Measurement code shared by the transport benchmark clients and servers
+pub mod clock;
+pub mod dashboard;
+pub mod echo;
+pub mod handshake;
+pub mod histogram;
+pub mod manifest;
+pub mod metrics;
+pub mod outstanding;
+#[cfg(feature = "quinn")]
+pub mod quic_socket;
+pub mod receive;
+pub mod scheduler;
+pub mod sequence;
+pub mod stop;
+pub mod tcpinfo;
+pub mod timeseries;
+pub mod timestamping;
+pub mod wire;
+pub mod workload;
@@ -0,0 +1,285 @@
+// What produced a set of result files: the build, the command line, the settings and
+// the machine it ran on. Written when a run starts and again when it ends, next to the
+// results, so a CSV found months later can be traced back to how it was made.
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Socket buffer and queueing sysctls that change what the benchmarks measure
+const SYSCTLS: [&str; 11] = [
+    "net.core.rmem_default",
+    "net.core.rmem_max",
+    "net.core.wmem_default",
+    "net.core.wmem_max",
+    "net.core.netdev_max_backlog",
+    "net.core.default_qdisc",
+    "net.ipv4.tcp_rmem",
+    "net.ipv4.tcp_wmem",
+    "net.ipv4.tcp_congestion_control",
+    "net.ipv4.tcp_low_latency",
+    "net.ipv4.udp_mem",
+];
+
+/// Options whose values are secrets, written to the manifest as `<redacted>`
+const SECRET_FLAGS: [&str; 1] = ["--turn-credential"];
+const REDACTED: &str = "<redacted>";
+
+/// The offloads websockets/offloadtoggle.sh switches, as `ethtool -k` names them
+const OFFLOADS: [&str; 4] = [
+    "tcp-segmentation-offload",
+    "generic-segmentation-offload",
+    "generic-receive-offload",
+    "large-receive-offload",
+];
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ManifestOptions {
+    /// Where to write the run manifest; defaults to <name>_manifest.json
+    #[arg(long)]
+    pub manifest_file: Option<PathBuf>,
+}
+
+impl ManifestOptions {
+    pub fn path(&self, stem: &str) -> PathBuf {
+        self.manifest_file.clone().unwrap_or_else(|| PathBuf::from(format!("{}_manifest.json", stem)))
+    }
+}
+
+/// How the binary was built; fixed when ons_common was compiled
+#[derive(Debug, Clone, Serialize)]
+pub struct Build {
+    pub git_commit: Option<&'static str>,
+    /// Tracked files differed from the commit when ons_common was last built
+    pub git_dirty: Option<bool>,
+    pub profile: Option<&'static str>,
+    pub debug_assertions: bool,
+    pub rustc: Option<&'static str>,
+}
+
+impl Build {
+    pub fn current() -> Self {
+        Build {
+            git_commit: option_env!("ONS_GIT_COMMIT"),
+            git_dirty: option_env!("ONS_GIT_DIRTY").map(|dirty| dirty == "true"),
+            profile: option_env!("ONS_BUILD_PROFILE"),
+            debug_assertions: cfg!(debug_assertions),
+            rustc: option_env!("ONS_RUSTC_VERSION"),
+        }
+    }
+}
+
+/// The machine a run happened on. Anything that can't be read is left out rather
+/// than failing the run.
+#[derive(Debug, Clone, Serialize)]
+pub struct Environment {
+    pub hostname: Option<String>,
+    pub kernel_release: Option<String>,
+    pub kernel_version: Option<String>,
+    pub cpu_model: Option<String>,
+    pub cpu_count: usize,
+    pub cpu_governor: Option<String>,
+    /// `ethtool -k` offload state per non-loopback interface
+    pub offloads: BTreeMap<String, BTreeMap<String, String>>,
+    pub sysctls: BTreeMap<String, String>,
+}
+
+impl Environment {
+    pub fn capture() -> Self {
+        let cpuinfo = read_trimmed("/proc/cpuinfo").unwrap_or_default();
+        let cpu_model = cpuinfo
+            .lines()
+            .find(|line| line.starts_with("model name"))
+            .and_then(|line| line.split_once(':'))
+            .map(|(_, model)| model.trim().to_string());
+        Environment {
+            hostname: read_trimmed("/proc/sys/kernel/hostname"),
+            kernel_release: read_trimmed("/proc/sys/kernel/osrelease"),
+            kernel_version: read_trimmed("/proc/sys/kernel/version"),
+            cpu_model,
+            cpu_count: std::thread::available_parallelism().map_or(0, |n| n.get()),
+            cpu_governor: read_trimmed("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+            offloads: offloads(),
+            sysctls: SYSCTLS
+                .iter()
+                .filter_map(|name| {
+                    let value = read_trimmed(&format!("/proc/sys/{}", name.replace('.', "/")))?;
+                    // tcp_rmem and friends are tab-separated
+                    Some((name.to_string(), value.split_whitespace().collect::<Vec<_>>().join(" ")))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// One result file as it stood when the run finished
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultFile {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunManifest {
+    /// Which benchmark wrote this, e.g. "websocket_client"
+    pub binary: String,
+    pub build: Build,
+    /// The command line, with secret values redacted
+    pub args: Vec<String>,
+    /// Every parsed option, defaults included, as the binary's Args debug output (secrets redacted)
+    pub options: String,
+    /// Tick rate, duration and payload size as actually used
+    pub settings: Value,
+    pub working_dir: Option<PathBuf>,
+    pub environment: Environment,
+    pub started_unix: f64,
+    pub finished_unix: Option<f64>,
+    pub results: Vec<ResultFile>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl RunManifest {
+    pub fn start(binary: &str, args: &impl Debug, settings: Value, options: &ManifestOptions) -> Self {
+        RunManifest {
+            binary: binary.to_string(),
+            build: Build::current(),
+            args: redact_args(std::env::args()),
+            options: redact_options(format!("{:?}", args)),
+            settings,
+            working_dir: std::env::current_dir().ok(),
+            environment: Environment::capture(),
+            started_unix: unix_secs(),
+            finished_unix: None,
+            results: Vec::new(),
+            path: options.path(binary),
+        }
+    }
+
+    /// Mark the run finished and list whichever of `results` exist now.
+    pub fn finish<P: AsRef<Path>>(&mut self, results: &[P]) {
+        self.finished_unix = Some(unix_secs());
+        self.results = results
+            .iter()
+            .filter_map(|path| {
+                let path = path.as_ref();
+                let bytes = fs::metadata(path).ok()?.len();
+                Some(ResultFile { path: path.to_path_buf(), bytes })
+            })
+            .collect();
+    }
+
+    pub fn save(&self) -> io::Result<&Path> {
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(&self.path)
+    }
+}
+
+pub fn unix_secs() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+/// Replace the values of SECRET_FLAGS, given as `--flag value` or `--flag=value`
+fn redact_args(args: impl Iterator<Item = String>) -> Vec<String> {
+    let mut redacted = Vec::new();
+    let mut secret_next = false;
+    for arg in args {
+        if secret_next {
+            redacted.push(REDACTED.to_string());
+            secret_next = false;
+        } else if SECRET_FLAGS.contains(&arg.as_str()) {
+            secret_next = true;
+            redacted.push(arg);
+        } else if let Some(flag) = SECRET_FLAGS.iter().find(|flag| arg.starts_with(&format!("{}=", flag))) {
+            redacted.push(format!("{}={}", flag, REDACTED));
+        } else {
+            redacted.push(arg);
+        }
+    }
+    redacted
+}
+
+/// Replace the quoted values of SECRET_FLAGS' fields (`turn_credential: "..."`) in Args debug output
+fn redact_options(mut options: String) -> String {
+    for flag in SECRET_FLAGS {
+        let field = format!("{}: \"", flag.trim_start_matches("--").replace('-', "_"));
+        let mut from = 0;
+        while let Some(found) = options[from..].find(&field) {
+            let start = from + found + field.len();
+            // Debug escapes quotes inside the string, so the first unescaped one ends it
+            let mut escaped = false;
+            let Some(len) = options[start..].find(|c: char| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            }) else {
+                break;
+            };
+            options.replace_range(start..start + len, REDACTED);
+            from = start + REDACTED.len();
+        }
+    }
+    options
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn offloads() -> BTreeMap<String, BTreeMap<String, String>> {
+    let Ok(interfaces) = fs::read_dir("/sys/class/net") else {
+        return BTreeMap::new();
+    };
+    let mut offloads = BTreeMap::new();
+    for interface in interfaces.flatten() {
+        let name = interface.file_name().to_string_lossy().into_owned();
+        if name == "lo" {
+            continue;
+        }
+        let Ok(output) = Command::new("ethtool").args(["-k", &name]).output() else {
+            // No ethtool on this machine; nothing more to find
+            break;
+        };
+        let features: BTreeMap<String, String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.trim().split_once(": "))
+            .filter(|(feature, _)| OFFLOADS.contains(feature))
+            .map(|(feature, state)| (feature.to_string(), state.trim().to_string()))
+            .collect();
+        if !features.is_empty() {
+            offloads.insert(name, features);
+        }
+    }
+    offloads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_args_are_redacted_in_either_form() {
+        let argv = ["server", "--turn-credential", "hunter2", "--turn-username", "ons", "--turn-credential=hunter2"];
+        assert_eq!(
+            redact_args(argv.iter().map(|arg| arg.to_string())),
+            ["server", "--turn-credential", REDACTED, "--turn-username", "ons", "--turn-credential=<redacted>"]
+        );
+    }
+
+    #[test]
+    fn secret_options_are_redacted() {
+        #[allow(dead_code)]
+        #[derive(Debug)]
+        struct Ice {
+            turn_username: String,
+            turn_credential: String,
+        }
+        let options = format!("{:?}", Ice { turn_username: "ons".into(), turn_credential: "a \"quoted\" secret".into() });
+        assert_eq!(redact_options(options), r#"Ice { turn_username: "ons", turn_credential: "<redacted>" }"#);
+    }
+}
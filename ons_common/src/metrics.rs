@@ -0,0 +1,501 @@
+// A Prometheus /metrics endpoint for the clients and servers.
+//
+// Served by hand over a plain TcpListener, since the exposition format is a few lines
+// of text and the benchmarks shouldn't pull in an HTTP stack to offer it.
+use crate::histogram::LatencyHistogram;
+use crate::timeseries::TransportSnapshot;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Quantiles each latency summary reports
+const QUANTILES: [f64; 5] = [0.5, 0.9, 0.99, 0.999, 1.0];
+
+/// Ended sessions that keep series of their own; older ones are folded into one
+/// `session="ended"` series, so a server that runs for days doesn't grow without bound
+const KEPT_ENDED_SESSIONS: usize = 16;
+
+/// Session label of the series the oldest ended sessions are folded into
+const ENDED_SESSION: &str = "ended";
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct MetricsOptions {
+    /// Serve Prometheus metrics on http://ADDR/metrics while running, e.g. 127.0.0.1:9464
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+/// Which end of the benchmark a process is, which decides the metrics it exports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+/// Counters for one connection, as the echo path or the tick loop last reported them
+#[derive(Default)]
+struct SessionStats {
+    id: String,
+    open: bool,
+    received: u64,
+    echoed: u64,
+    queue_depth: u64,
+    residence: LatencyHistogram,
+    sent: u64,
+    on_time: u64,
+    late: u64,
+    outstanding: u64,
+    rtt: LatencyHistogram,
+    overruns: u64,
+    transport: TransportSnapshot,
+    /// Cumulative counters only one transport has, e.g. quinn's congestion events
+    stack: Vec<(&'static str, f64)>,
+    /// Discards and high-water mark of a receive queue in front of the echo path, if any
+    local_queue: Option<(u64, u64)>,
+}
+
+impl SessionStats {
+    /// Add an ended session's counts and latencies to this one. Gauges and the
+    /// transport's snapshot only mean something while a session is open, so they go.
+    fn fold(&mut self, ended: &SessionStats) {
+        self.received += ended.received;
+        self.echoed += ended.echoed;
+        self.sent += ended.sent;
+        self.on_time += ended.on_time;
+        self.late += ended.late;
+        self.overruns += ended.overruns;
+        // Both use the default bounds, so merging can't fail
+        let _ = self.residence.merge(&ended.residence);
+        let _ = self.rtt.merge(&ended.rtt);
+        for &(name, value) in &ended.stack {
+            match self.stack.iter_mut().find(|(existing, _)| *existing == name) {
+                Some((_, total)) => *total += value,
+                None => self.stack.push((name, value)),
+            }
+        }
+        if let Some((dropped, high_water)) = ended.local_queue {
+            let (total, most) = self.local_queue.get_or_insert((0, 0));
+            *total += dropped;
+            *most = (*most).max(high_water);
+        }
+    }
+}
+
+struct Registry {
+    process: String,
+    side: Side,
+    sessions: Mutex<Vec<Arc<Mutex<SessionStats>>>>,
+    /// Sessions ended beyond KEPT_ENDED_SESSIONS, and how many have been folded in
+    retired: Mutex<(SessionStats, u64)>,
+}
+
+impl Registry {
+    fn new(process: &str, side: Side) -> Self {
+        Registry {
+            process: process.to_string(),
+            side,
+            sessions: Mutex::new(Vec::new()),
+            retired: Mutex::new((SessionStats { id: ENDED_SESSION.to_string(), ..Default::default() }, 0)),
+        }
+    }
+
+    /// Fold the oldest ended sessions into the retired series until only
+    /// KEPT_ENDED_SESSIONS are left with series of their own
+    fn retire(&self, sessions: &mut Vec<Arc<Mutex<SessionStats>>>) {
+        let ended = |s: &Arc<Mutex<SessionStats>>| s.lock().map_or(true, |s| !s.open);
+        let mut excess = sessions.iter().filter(|s| ended(s)).count().saturating_sub(KEPT_ENDED_SESSIONS);
+        let mut retired = self.retired.lock().unwrap();
+        sessions.retain(|s| {
+            if excess == 0 || !ended(s) {
+                return true;
+            }
+            excess -= 1;
+            if let Ok(stats) = s.lock() {
+                retired.0.fold(&stats);
+                retired.1 += 1;
+            }
+            false
+        });
+    }
+}
+
+/// The process's /metrics endpoint. Without --metrics-addr nothing is served and
+/// every update is a no-op, so the echo and tick loops report unconditionally.
+#[derive(Clone, Default)]
+pub struct Metrics(Option<Arc<Registry>>);
+
+impl Metrics {
+    /// Start serving if --metrics-addr was given. `process` names the binary, e.g.
+    /// `websocket_server`, and goes on every sample as the `process` label.
+    pub fn start(options: &MetricsOptions, process: &str, side: Side) -> Result<Self, Box<dyn Error>> {
+        let Some(addr) = options.metrics_addr else { return Ok(Metrics(None)) };
+        let listener = TcpListener::bind(addr).map_err(|e| format!("could not serve metrics on {}: {}", addr, e))?;
+        let registry = Arc::new(Registry::new(process, side));
+        let served = registry.clone();
+        std::thread::Builder::new().name("metrics".into()).spawn(move || serve(listener, &served))?;
+        println!("Serving metrics on http://{}/metrics", addr);
+        Ok(Metrics(Some(registry)))
+    }
+
+    /// A new session, labelled `id` (the peer's address on the servers). It counts as
+    /// active until [`SessionMetrics::end`]; its series stay afterwards so the final
+    /// counts can still be scraped, until enough later sessions have ended that it is
+    /// folded into the `session="ended"` series.
+    pub fn session(&self, id: &str) -> SessionMetrics {
+        SessionMetrics(self.0.as_ref().map(|registry| {
+            let stats = Arc::new(Mutex::new(SessionStats { id: id.to_string(), open: true, ..Default::default() }));
+            let mut sessions = registry.sessions.lock().unwrap();
+            registry.retire(&mut sessions);
+            sessions.push(stats.clone());
+            stats
+        }))
+    }
+}
+
+/// Handle one session reports through; cheap to clone into receive and echo tasks.
+#[derive(Clone, Default)]
+pub struct SessionMetrics(Option<Arc<Mutex<SessionStats>>>);
+
+impl SessionMetrics {
+    fn update(&self, f: impl FnOnce(&mut SessionStats)) {
+        if let Some(Ok(mut stats)) = self.0.as_ref().map(|stats| stats.lock()) {
+            f(&mut stats);
+        }
+    }
+
+    /// The server read `messages` messages from the client
+    pub fn received(&self, messages: u64) {
+        self.update(|s| s.received += messages);
+    }
+
+    /// The server echoed a message, from its wall-clock receive and send times in µs
+    pub fn echoed(&self, recv_us: u64, send_us: u64) {
+        self.update(|s| {
+            s.echoed += 1;
+            s.residence.record(send_us.saturating_sub(recv_us));
+        });
+    }
+
+    /// Messages left waiting for a later server tick
+    pub fn queue_depth(&self, waiting: usize) {
+        self.update(|s| s.queue_depth = waiting as u64);
+    }
+
+    /// The client sent a tick
+    pub fn sent(&self) {
+        self.update(|s| s.sent += 1);
+    }
+
+    /// An echo came back to the client in time
+    pub fn rtt(&self, rtt_us: u64) {
+        self.update(|s| {
+            s.on_time += 1;
+            s.rtt.record(rtt_us);
+        });
+    }
+
+    /// An echo came back after the client's late cut-off
+    pub fn late(&self) {
+        self.update(|s| s.late += 1);
+    }
+
+    /// Ticks still waiting for an echo inside the late cut-off
+    pub fn outstanding(&self, pending: usize) {
+        self.update(|s| s.outstanding = pending as u64);
+    }
+
+    /// A tick took longer than the tick period
+    pub fn overrun(&self) {
+        self.update(|s| s.overruns += 1);
+    }
+
+    pub fn transport(&self, snapshot: TransportSnapshot) {
+        self.update(|s| s.transport = snapshot);
+    }
+
+    /// Cumulative counters from the transport stack that [`TransportSnapshot`] has no
+    /// field for; each is exported as `ons_<name>_total`
+    pub fn stack(&self, counters: &[(&'static str, f64)]) {
+        self.update(|s| s.stack = counters.to_vec());
+    }
+
+    /// A receive queue between the transport and the echo path: messages it discarded
+    /// for being full, and the most it held at once
+    pub fn local_queue(&self, dropped: u64, high_water: usize) {
+        self.update(|s| s.local_queue = Some((dropped, high_water as u64)));
+    }
+
+    /// The path stats quinn keeps for a QUIC connection
+    #[cfg(feature = "quinn")]
+    pub fn quic(&self, conn: &quinn::Connection) {
+        let stats = conn.stats();
+        self.transport(TransportSnapshot::quic(&stats));
+        self.stack(&[
+            ("quic_congestion_events", stats.path.congestion_events as f64),
+            ("quic_lost_bytes", stats.path.lost_bytes as f64),
+            ("quic_black_holes_detected", stats.path.black_holes_detected as f64),
+            ("quic_udp_tx_datagrams", stats.udp_tx.datagrams as f64),
+            ("quic_udp_rx_datagrams", stats.udp_rx.datagrams as f64),
+            ("quic_udp_tx_bytes", stats.udp_tx.bytes as f64),
+            ("quic_udp_rx_bytes", stats.udp_rx.bytes as f64),
+        ]);
+    }
+
+    pub fn end(&self) {
+        self.update(|s| s.open = false);
+    }
+}
+
+fn serve(listener: TcpListener, registry: &Registry) {
+    for stream in listener.incoming().flatten() {
+        // A scraper that hangs up early only loses its own response
+        let _ = respond(stream, registry);
+    }
+}
+
+fn respond(mut stream: TcpStream, registry: &Registry) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Read the headers too, so closing the socket doesn't reset the connection under the response
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path.split('?').next() {
+        Some("/metrics") => ("200 OK", registry.render()),
+        _ => ("404 Not Found", "Metrics are at /metrics\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+impl Registry {
+    /// The Prometheus text exposition format
+    fn render(&self) -> String {
+        let sessions: Vec<Arc<Mutex<SessionStats>>> = self.sessions.lock().unwrap().clone();
+        // Retired before the sessions, the order retire() takes them in
+        let retired = self.retired.lock().unwrap();
+        let guards: Vec<MutexGuard<SessionStats>> = sessions.iter().filter_map(|s| s.lock().ok()).collect();
+        let mut sessions: Vec<&SessionStats> = guards.iter().map(|s| &**s).collect();
+        if retired.1 > 0 {
+            sessions.insert(0, &retired.0);
+        }
+        let mut out = Exposition { out: String::new(), process: &self.process };
+
+        let active = sessions.iter().filter(|s| s.open).count() as f64;
+        out.family("ons_active_sessions", "gauge", "Sessions currently open", &[(None, active)]);
+        let each = |f: &dyn Fn(&SessionStats) -> f64| -> Vec<(Option<&str>, f64)> {
+            sessions.iter().map(|s| (Some(s.id.as_str()), f(s))).collect()
+        };
+        match self.side {
+            Side::Server => {
+                out.family("ons_messages_received_total", "counter", "Messages read from the client", &each(&|s| s.received as f64));
+                out.family("ons_messages_echoed_total", "counter", "Echoes sent back to the client", &each(&|s| s.echoed as f64));
+                out.family("ons_echo_queue_depth", "gauge", "Messages left waiting for a later server tick", &each(&|s| s.queue_depth as f64));
+                out.summary("ons_echo_residence_seconds", "Time from reading a message to echoing it", &sessions, |s| &s.residence);
+                out.family("ons_tick_overruns_total", "counter", "Server ticks that took longer than the tick period", &each(&|s| s.overruns as f64));
+            }
+            Side::Client => {
+                out.family("ons_ticks_sent_total", "counter", "Ticks sent to the server", &each(&|s| s.sent as f64));
+                out.family("ons_echoes_received_total", "counter", "Echoes that came back in time", &each(&|s| s.on_time as f64));
+                out.family("ons_echoes_late_total", "counter", "Echoes that came back after the late cut-off", &each(&|s| s.late as f64));
+                out.family("ons_echoes_outstanding", "gauge", "Ticks still waiting for an echo", &each(&|s| s.outstanding as f64));
+                out.summary("ons_rtt_seconds", "Round trip time of the echoes", &sessions, |s| &s.rtt);
+                out.family("ons_tick_overruns_total", "counter", "Ticks whose send loop took longer than the tick period", &each(&|s| s.overruns as f64));
+            }
+        }
+
+        // Transport counters only where the stack has them
+        let some = |f: &dyn Fn(&TransportSnapshot) -> Option<f64>| -> Vec<(Option<&str>, f64)> {
+            sessions.iter().filter_map(|s| f(&s.transport).map(|v| (Some(s.id.as_str()), v))).collect()
+        };
+        out.family("ons_transport_rtt_seconds", "gauge", "The transport's own smoothed RTT", &some(&|t| t.stack_rtt_us.map(|us| us as f64 / 1e6)));
+        out.family("ons_transport_cwnd_bytes", "gauge", "Congestion window", &some(&|t| t.cwnd_bytes.map(|v| v as f64)));
+        out.family("ons_transport_sent_packets_total", "counter", "Packets sent by the transport", &some(&|t| t.sent_packets.map(|v| v as f64)));
+        out.family("ons_transport_lost_packets_total", "counter", "Packets the transport declared lost", &some(&|t| t.lost_packets.map(|v| v as f64)));
+        out.family("ons_transport_retransmits_total", "counter", "Retransmitted segments", &some(&|t| t.retransmits.map(|v| v as f64)));
+        let queued = |f: &dyn Fn((u64, u64)) -> u64| -> Vec<(Option<&str>, f64)> {
+            sessions.iter().filter_map(|s| s.local_queue.map(|q| (Some(s.id.as_str()), f(q) as f64))).collect()
+        };
+        out.family("ons_local_queue_dropped_total", "counter", "Messages discarded because the receive queue was full", &queued(&|(dropped, _)| dropped));
+        out.family("ons_local_queue_high_water", "gauge", "Most messages waiting in the receive queue at once", &queued(&|(_, high)| high));
+        let mut stack: BTreeMap<&str, Vec<(Option<&str>, f64)>> = BTreeMap::new();
+        for s in &sessions {
+            for &(name, value) in &s.stack {
+                stack.entry(name).or_default().push((Some(s.id.as_str()), value));
+            }
+        }
+        for (name, samples) in stack {
+            out.family(&format!("ons_{}_total", name), "counter", "Counter from the transport stack", &samples);
+        }
+        out.out
+    }
+}
+
+struct Exposition<'a> {
+    out: String,
+    process: &'a str,
+}
+
+impl Exposition<'_> {
+    fn labels(&self, session: Option<&str>, quantile: Option<f64>) -> String {
+        let mut labels = format!("process=\"{}\"", escape(self.process));
+        if let Some(session) = session {
+            let _ = write!(labels, ",session=\"{}\"", escape(session));
+        }
+        if let Some(q) = quantile {
+            let _ = write!(labels, ",quantile=\"{}\"", q);
+        }
+        labels
+    }
+
+    /// One metric family; families with no samples are left out
+    fn family(&mut self, name: &str, kind: &str, help: &str, samples: &[(Option<&str>, f64)]) {
+        if samples.is_empty() {
+            return;
+        }
+        let _ = writeln!(self.out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+        for &(session, value) in samples {
+            let _ = writeln!(self.out, "{}{{{}}} {}", name, self.labels(session, None), value);
+        }
+    }
+
+    /// A latency histogram as a summary in seconds
+    fn summary(&mut self, name: &str, help: &str, sessions: &[&SessionStats], hist: impl Fn(&SessionStats) -> &LatencyHistogram) {
+        if sessions.is_empty() {
+            return;
+        }
+        let _ = writeln!(self.out, "# HELP {} {}\n# TYPE {} summary", name, help, name);
+        for s in sessions {
+            let h = hist(s).histogram();
+            if !h.is_empty() {
+                for q in QUANTILES {
+                    let _ = writeln!(self.out, "{}{{{}}} {}", name, self.labels(Some(&s.id), Some(q)), h.value_at_quantile(q) as f64 / 1e6);
+                }
+            }
+            let labels = self.labels(Some(&s.id), None);
+            let _ = writeln!(self.out, "{}_sum{{{}}} {}", name, labels, h.mean() * h.len() as f64 / 1e6);
+            let _ = writeln!(self.out, "{}_count{{{}}} {}", name, labels, h.len());
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(process: &str, side: Side) -> Metrics {
+        Metrics(Some(Arc::new(Registry::new(process, side))))
+    }
+
+    fn render(metrics: &Metrics) -> String {
+        metrics.0.as_ref().unwrap().render()
+    }
+
+    #[test]
+    fn families_have_help_and_type_and_labels_are_escaped() {
+        let metrics = metrics("client \"one\"", Side::Client);
+        let session = metrics.session("a\"b\\c\nd");
+        session.sent();
+        session.sent();
+        let text = render(&metrics);
+        assert!(text.starts_with(
+            "# HELP ons_active_sessions Sessions currently open\n\
+             # TYPE ons_active_sessions gauge\n\
+             ons_active_sessions{process=\"client \\\"one\\\"\"} 1\n"
+        ));
+        assert!(text.contains(
+            "# HELP ons_ticks_sent_total Ticks sent to the server\n\
+             # TYPE ons_ticks_sent_total counter\n\
+             ons_ticks_sent_total{process=\"client \\\"one\\\"\",session=\"a\\\"b\\\\c\\nd\"} 2\n"
+        ));
+        // Every sample line belongs to the family whose TYPE line came last
+        let mut family = "";
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                family = rest.split(' ').next().unwrap();
+            } else if !line.starts_with('#') {
+                assert!(line.starts_with(family), "{} outside {}", line, family);
+            }
+        }
+    }
+
+    #[test]
+    fn latencies_are_summaries_in_seconds() {
+        let metrics = metrics("client", Side::Client);
+        let session = metrics.session("s");
+        session.rtt(1000);
+        session.rtt(2000);
+        let text = render(&metrics);
+        assert!(text.contains("# TYPE ons_rtt_seconds summary\n"));
+        assert!(text.contains("ons_rtt_seconds{process=\"client\",session=\"s\",quantile=\"0.5\"} 0.001\n"));
+        assert!(text.contains("ons_rtt_seconds{process=\"client\",session=\"s\",quantile=\"1\"} 0.002\n"));
+        assert!(text.contains("ons_rtt_seconds_sum{process=\"client\",session=\"s\"} 0.003\n"));
+        assert!(text.contains("ons_rtt_seconds_count{process=\"client\",session=\"s\"} 2\n"));
+        assert!(text.contains("ons_echoes_received_total{process=\"client\",session=\"s\"} 2\n"));
+    }
+
+    #[test]
+    fn server_families_leave_out_what_no_session_reported() {
+        let metrics = metrics("server", Side::Server);
+        let first = metrics.session("10.0.0.1:5000");
+        metrics.session("10.0.0.2:5000").received(3);
+        first.end();
+        let text = render(&metrics);
+        assert!(text.contains("ons_active_sessions{process=\"server\"} 1\n"));
+        assert!(text.contains("ons_messages_received_total{process=\"server\",session=\"10.0.0.2:5000\"} 3\n"));
+        // Nothing echoed yet: a count but no quantiles
+        assert!(text.contains("ons_echo_residence_seconds_count{process=\"server\",session=\"10.0.0.1:5000\"} 0\n"));
+        assert!(!text.contains("quantile"));
+        assert!(!text.contains("ons_transport_") && !text.contains("ons_local_queue_"));
+        assert!(!text.contains("ons_ticks_sent_total"));
+    }
+
+    #[test]
+    fn the_oldest_ended_sessions_are_folded_together() {
+        let metrics = metrics("server", Side::Server);
+        for n in 0..KEPT_ENDED_SESSIONS + 3 {
+            let session = metrics.session(&format!("peer{}", n));
+            session.received(1);
+            session.echoed(0, 1000);
+            session.local_queue(n as u64, n);
+            session.end();
+        }
+        let open = metrics.session("open");
+        open.received(5);
+        let registry = metrics.0.as_ref().unwrap();
+        assert_eq!(registry.sessions.lock().unwrap().len(), KEPT_ENDED_SESSIONS + 1);
+
+        let text = render(&metrics);
+        assert!(text.contains("ons_messages_received_total{process=\"server\",session=\"ended\"} 3\n"));
+        assert!(text.contains("ons_echo_residence_seconds_count{process=\"server\",session=\"ended\"} 3\n"));
+        assert!(text.contains("ons_local_queue_dropped_total{process=\"server\",session=\"ended\"} 3\n"));
+        assert!(text.contains("ons_local_queue_high_water{process=\"server\",session=\"ended\"} 2\n"));
+        assert!(!text.contains("session=\"peer2\""));
+        assert!(text.contains("session=\"peer3\""));
+        assert!(text.contains("ons_active_sessions{process=\"server\"} 1\n"));
+        // Every session's messages are still counted once
+        let total: f64 = text
+            .lines()
+            .filter(|line| line.starts_with("ons_messages_received_total{"))
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<f64>().unwrap())
+            .sum();
+        assert_eq!(total, (KEPT_ENDED_SESSIONS + 3 + 5) as f64);
+    }
+}
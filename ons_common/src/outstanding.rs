@@ -0,0 +1,215 @@
+// Bookkeeping for ticks sent and not yet echoed: when to give up waiting for an
+// echo, when a late one is still recognised, and how long the client drains at the end.
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DrainOptions {
+    /// Keep reading echoes for this long after the last tick is sent, in milliseconds
+    #[arg(long, default_value = "1000")]
+    pub drain_ms: u64,
+
+    /// Echoes arriving more than this many milliseconds after their send count as late, not received
+    #[arg(long, default_value = "1000")]
+    pub late_after_ms: u64,
+
+    /// Most ticks remembered while awaiting an echo; the oldest are forgotten (and counted lost) beyond this
+    #[arg(long, default_value = "65536")]
+    pub max_outstanding: usize,
+}
+
+impl Default for DrainOptions {
+    fn default() -> Self {
+        DrainOptions { drain_ms: 1000, late_after_ms: 1000, max_outstanding: 65536 }
+    }
+}
+
+impl DrainOptions {
+    pub fn drain(&self) -> Duration {
+        Duration::from_millis(self.drain_ms)
+    }
+
+    pub fn late_after(&self) -> Duration {
+        Duration::from_millis(self.late_after_ms)
+    }
+}
+
+/// What an echo turned out to be once matched against the ticks we sent.
+pub enum Echo<T> {
+    /// Came back before the late cut-off
+    OnTime(T),
+    /// Came back, but after the late cut-off
+    Late(T),
+    /// Not (or no longer) awaited: a duplicate, or a tick forgotten to bound memory
+    Unknown,
+}
+
+/// Ticks sent but not yet echoed, keyed by sequence number.
+///
+/// Replaces an unbounded `HashMap<u64, _>` of send times: entries move to an
+/// expired set once past the late cut-off so a straggling echo can still be
+/// classified as late, and the total kept is capped so long runs over a lossy
+/// path cannot grow without limit.
+pub struct OutstandingTicks<T> {
+    pending: BTreeMap<u64, (Instant, T)>,
+    expired: BTreeMap<u64, T>,
+    late_after: Duration,
+    capacity: usize,
+    late: u64,
+    forgotten: u64,
+}
+
+impl<T> Default for OutstandingTicks<T> {
+    fn default() -> Self {
+        OutstandingTicks::new(&DrainOptions::default())
+    }
+}
+
+impl<T> OutstandingTicks<T> {
+    pub fn new(options: &DrainOptions) -> Self {
+        OutstandingTicks {
+            pending: BTreeMap::new(),
+            expired: BTreeMap::new(),
+            late_after: options.late_after(),
+            capacity: options.max_outstanding.max(1),
+            late: 0,
+            forgotten: 0,
+        }
+    }
+
+    pub fn insert(&mut self, seq: u64, value: T) {
+        self.pending.insert(seq, (Instant::now(), value));
+        self.enforce_capacity();
+    }
+
+    /// Forget a tick that was never actually sent.
+    pub fn remove(&mut self, seq: u64) -> Option<T> {
+        self.pending.remove(&seq).map(|(_, value)| value)
+    }
+
+    /// Match an echo for `seq` and classify it against the late cut-off.
+    pub fn take(&mut self, seq: u64) -> Echo<T> {
+        self.take_at(seq, Instant::now())
+    }
+
+    /// Like `take`, for an echo that arrived at `at` but is only being matched now.
+    pub fn take_at(&mut self, seq: u64, at: Instant) -> Echo<T> {
+        if let Some((sent, value)) = self.pending.remove(&seq) {
+            if at.saturating_duration_since(sent) <= self.late_after {
+                return Echo::OnTime(value);
+            }
+            self.late += 1;
+            return Echo::Late(value);
+        }
+        match self.expired.remove(&seq) {
+            Some(value) => {
+                self.late += 1;
+                Echo::Late(value)
+            }
+            None => Echo::Unknown,
+        }
+    }
+
+    /// Move ticks past the late cut-off out of the pending set. Call once per tick.
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        while let Some(entry) = self.pending.first_entry() {
+            if now.duration_since(entry.get().0) <= self.late_after {
+                break;
+            }
+            let (seq, (_, value)) = entry.remove_entry();
+            self.expired.insert(seq, value);
+        }
+        self.enforce_capacity();
+    }
+
+    fn enforce_capacity(&mut self) {
+        while self.pending.len() + self.expired.len() > self.capacity {
+            // Oldest expired ticks go first; only then do still-pending ones
+            if self.expired.pop_first().is_none() {
+                self.pending.pop_first();
+            }
+            self.forgotten += 1;
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    /// Report given the number of ticks sent and echoes accepted on time.
+    pub fn summary(&self, sent: u64, on_time: u64) -> Value {
+        json!({
+            "late_after_ms": self.late_after.as_millis() as u64,
+            "on_time": on_time,
+            "late": self.late,
+            "lost": sent.saturating_sub(on_time + self.late),
+            "outstanding_at_end": self.pending.len(),
+            "expired_unanswered": self.expired.len(),
+            "forgotten": self.forgotten
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn options(late_after_ms: u64, max_outstanding: usize) -> DrainOptions {
+        DrainOptions { drain_ms: 0, late_after_ms, max_outstanding }
+    }
+
+    #[test]
+    fn echoes_are_on_time_late_or_unknown() {
+        let mut ticks = OutstandingTicks::new(&options(1000, 16));
+        ticks.insert(0, "a");
+        ticks.insert(1, "b");
+        assert!(matches!(ticks.take_at(0, Instant::now()), Echo::OnTime("a")));
+        assert!(matches!(ticks.take_at(1, Instant::now() + Duration::from_secs(2)), Echo::Late("b")));
+        // Already matched, or never sent
+        assert!(matches!(ticks.take(1), Echo::Unknown));
+        assert!(matches!(ticks.take(7), Echo::Unknown));
+        let summary = ticks.summary(3, 1);
+        assert_eq!(summary["late"], 1);
+        assert_eq!(summary["lost"], 1);
+    }
+
+    #[test]
+    fn expired_ticks_are_still_recognised_as_late() {
+        let mut ticks = OutstandingTicks::new(&options(0, 16));
+        ticks.insert(0, ());
+        thread::sleep(Duration::from_millis(2));
+        ticks.expire();
+        assert_eq!(ticks.pending(), 0);
+        assert!(matches!(ticks.take(0), Echo::Late(())));
+        assert_eq!(ticks.late(), 1);
+    }
+
+    #[test]
+    fn capacity_forgets_expired_ticks_first() {
+        let mut ticks = OutstandingTicks::new(&options(0, 2));
+        ticks.insert(0, ());
+        thread::sleep(Duration::from_millis(2));
+        ticks.expire();
+        ticks.insert(1, ());
+        ticks.insert(2, ());
+        assert!(matches!(ticks.take(0), Echo::Unknown));
+        assert!(!matches!(ticks.take(1), Echo::Unknown));
+        assert_eq!(ticks.summary(3, 0)["forgotten"], 1);
+    }
+
+    #[test]
+    fn removed_ticks_are_not_awaited() {
+        let mut ticks = OutstandingTicks::new(&options(1000, 16));
+        ticks.insert(0, 5);
+        assert_eq!(ticks.remove(0), Some(5));
+        assert_eq!(ticks.pending(), 0);
+        assert!(matches!(ticks.take(0), Echo::Unknown));
+    }
+}
@@ -0,0 +1,132 @@
+// A UDP socket for quinn that keeps the kernel's packet timestamps, so the
+// WebTransport client can use SO_TIMESTAMPING like the DTLS one.
+use crate::timestamping::{self, StackTimestamps, TimestampingOptions};
+use crate::wire::now_micros;
+use quinn::udp::{RecvMeta, Transmit, UdpSocketState};
+use quinn::{AsyncUdpSocket, UdpPoller};
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tokio::io::Interest;
+
+/// A quinn socket that reads packets itself with `recvmsg`, so the
+/// SO_TIMESTAMPING stamps quinn's own socket discards are kept.
+///
+/// Datagrams go out one per send (no GSO) so the kernel's transmit timestamp
+/// keys stay in step with `StackTimestamps::sent`, and come in one per receive,
+/// without GRO or ECN marks.
+pub struct TimestampedUdpSocket {
+    io: tokio::net::UdpSocket,
+    state: UdpSocketState,
+    stamps: Arc<Mutex<StackTimestamps>>,
+}
+
+impl fmt::Debug for TimestampedUdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimestampedUdpSocket").field("io", &self.io).finish_non_exhaustive()
+    }
+}
+
+impl TimestampedUdpSocket {
+    /// Bind a socket with timestamping switched on. Must be called inside a Tokio runtime.
+    pub fn bind(addr: SocketAddr, options: &TimestampingOptions) -> io::Result<Self> {
+        let socket = std::net::UdpSocket::bind(addr)?;
+        options.apply(socket.as_raw_fd())?;
+        let state = UdpSocketState::new((&socket).into())?;
+        socket.set_nonblocking(true)?;
+        Ok(TimestampedUdpSocket {
+            io: tokio::net::UdpSocket::from_std(socket)?,
+            state,
+            stamps: Arc::new(Mutex::new(StackTimestamps::new(options))),
+        })
+    }
+
+    /// Per-packet userspace, kernel and NIC timestamps collected so far
+    pub fn stamps(&self) -> Arc<Mutex<StackTimestamps>> {
+        self.stamps.clone()
+    }
+}
+
+impl AsyncUdpSocket for TimestampedUdpSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(WritablePoller { socket: self, writable: None })
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let user_us = now_micros();
+        self.io.try_io(Interest::WRITABLE, || self.state.send((&self.io).into(), transmit))?;
+        let mut stamps = self.stamps.lock().unwrap();
+        stamps.sent(user_us);
+        // Transmit stamps of earlier packets have usually come back by now
+        let _ = stamps.poll_tx(self.io.as_raw_fd());
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let fd = self.io.as_raw_fd();
+        loop {
+            ready!(self.io.poll_recv_ready(cx))?;
+            let received = match self.io.try_io(Interest::READABLE, || timestamping::recv_timestamped(fd, &mut bufs[0])) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            let user_us = now_micros();
+            let Some(addr) = received.from else { continue };
+
+            let mut stamps = self.stamps.lock().unwrap();
+            stamps.received(user_us, received.stamps);
+            let _ = stamps.poll_tx(fd);
+
+            meta[0] = RecvMeta { addr, len: received.len, stride: received.len, ecn: None, dst_ip: None };
+            return Poll::Ready(Ok(1));
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.state.may_fragment()
+    }
+}
+
+type WritableFuture = Pin<Box<dyn Future<Output = io::Result<()>> + Send + Sync>>;
+
+/// Wakes a sender once the socket is writable again
+struct WritablePoller {
+    socket: Arc<TimestampedUdpSocket>,
+    writable: Option<WritableFuture>,
+}
+
+impl fmt::Debug for WritablePoller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WritablePoller").finish_non_exhaustive()
+    }
+}
+
+impl UdpPoller for WritablePoller {
+    fn poll_writable(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let writable = this.writable.get_or_insert_with(|| {
+            let socket = this.socket.clone();
+            Box::pin(async move { socket.io.writable().await })
+        });
+        let result = writable.as_mut().poll(cx);
+        if result.is_ready() {
+            this.writable = None;
+        }
+        result
+    }
+}
@@ -0,0 +1,43 @@
+// The client side of receiving echoes on a task of their own, apart from the tick loop.
+use crate::wire::now_micros;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+/// An echo as the receive task saw it, stamped the moment it came off the transport.
+///
+/// The tick loop only gets round to matching echoes once per tick, so timing them
+/// there measures the loop's cadence as much as the network. Carrying the arrival
+/// time with the data keeps the two apart.
+#[derive(Debug, Clone)]
+pub struct Arrival {
+    pub data: Vec<u8>,
+    /// Monotonic receive time, comparable with the send `Instant`
+    pub at: Instant,
+    /// Wall-clock receive time in µs since the Unix epoch
+    pub wall_us: u64,
+}
+
+impl Arrival {
+    pub fn now(data: Vec<u8>) -> Self {
+        Arrival { data, at: Instant::now(), wall_us: now_micros() }
+    }
+}
+
+/// Wait up to `timeout` for `fd` to become readable.
+pub fn wait_readable(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let mut pfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    let ret = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis().min(i32::MAX as u128) as libc::c_int) };
+    match ret {
+        -1 => {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(err)
+            }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
@@ -0,0 +1,296 @@
+// The client tick loop's clock: absolute deadlines, what to do about missed ticks,
+// real-time priority and CPU pinning, and how late each send actually went out.
+use crate::histogram::{HistogramOptions, LatencyHistogram};
+use serde_json::{json, Value};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// What to do when the loop wakes up after one or more whole tick periods have passed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissedTickPolicy {
+    /// Fire the missed ticks back to back until the schedule is caught up
+    Burst,
+    /// Restart the schedule from now, shifting every later deadline
+    Delay,
+    /// Drop the missed ticks and resume at the next deadline on the original grid
+    Skip,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SchedulerOptions {
+    /// Behaviour when the tick loop falls a whole period or more behind
+    #[arg(long, value_enum, default_value_t = MissedTickPolicy::Skip)]
+    pub missed_ticks: MissedTickPolicy,
+
+    /// Busy-spin for this many microseconds before each deadline instead of sleeping through it
+    #[arg(long, default_value = "0")]
+    pub spin_us: u64,
+
+    /// Run the tick loop thread under SCHED_FIFO with this priority (1-99, needs CAP_SYS_NICE)
+    #[arg(long)]
+    pub realtime_priority: Option<i32>,
+
+    /// Pin the tick loop thread to this CPU
+    #[arg(long)]
+    pub cpu: Option<usize>,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> Self {
+        SchedulerOptions { missed_ticks: MissedTickPolicy::Skip, spin_us: 0, realtime_priority: None, cpu: None }
+    }
+}
+
+impl SchedulerOptions {
+    /// Apply the priority and pinning options to the calling thread.
+    #[cfg(target_os = "linux")]
+    pub fn apply_to_current_thread(&self) -> io::Result<()> {
+        if let Some(cpu) = self.cpu {
+            unsafe {
+                let mut set: libc::cpu_set_t = std::mem::zeroed();
+                libc::CPU_SET(cpu, &mut set);
+                if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        if let Some(priority) = self.realtime_priority {
+            let param = libc::sched_param { sched_priority: priority };
+            if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply_to_current_thread(&self) -> io::Result<()> {
+        if self.cpu.is_some() || self.realtime_priority.is_some() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "CPU pinning and real-time priority need Linux"));
+        }
+        Ok(())
+    }
+}
+
+/// A tick handed out by the scheduler: its index and the deadline it was due at.
+#[derive(Debug, Clone, Copy)]
+pub struct TickSlot {
+    pub index: u64,
+    pub deadline: Instant,
+}
+
+/// Fixed-rate tick scheduler targeting absolute deadlines `start + n * period`.
+///
+/// Sleeping for `period - elapsed` after each tick lets every tick's processing
+/// time leak into the schedule; computing each deadline from the start instead
+/// keeps the long-run rate exact. The scheduler also measures how late each send
+/// actually happened, so its own jitter can be separated from the network's.
+pub struct TickScheduler {
+    start: Instant,
+    period: Duration,
+    next: u64,
+    fired: u64,
+    policy: MissedTickPolicy,
+    spin: Duration,
+    wakeup_error: LatencyHistogram,
+    send_error: LatencyHistogram,
+    missed: u64,
+    /// Highest tick index already counted in `missed`, so a burst doesn't count its backlog again
+    missed_through: u64,
+    skipped: u64,
+    delayed: Duration,
+}
+
+impl TickScheduler {
+    pub fn new(period: Duration, options: &SchedulerOptions) -> Self {
+        TickScheduler {
+            start: Instant::now(),
+            period,
+            next: 0,
+            fired: 0,
+            policy: options.missed_ticks,
+            spin: Duration::from_micros(options.spin_us),
+            wakeup_error: LatencyHistogram::new(&HistogramOptions::default()),
+            send_error: LatencyHistogram::new(&HistogramOptions::default()),
+            missed: 0,
+            missed_through: 0,
+            skipped: 0,
+            delayed: Duration::ZERO,
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn deadline(&self, index: u64) -> Instant {
+        self.start + Duration::from_nanos((self.period.as_nanos() * index as u128) as u64)
+    }
+
+    /// Wait for the next tick on the current thread.
+    pub fn wait_blocking(&mut self) -> TickSlot {
+        let deadline = self.deadline(self.next);
+        let wake = deadline.checked_sub(self.spin).unwrap_or(deadline);
+        let now = Instant::now();
+        if wake > now {
+            std::thread::sleep(wake - now);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+        self.advance(deadline, Instant::now())
+    }
+
+    /// Wait for the next tick on the async runtime.
+    ///
+    /// Spinning here blocks the runtime thread for up to `spin_us`, so only use it
+    /// with a dedicated (current-thread) runtime.
+    pub async fn wait(&mut self) -> TickSlot {
+        let deadline = self.deadline(self.next);
+        let wake = deadline.checked_sub(self.spin).unwrap_or(deadline);
+        tokio::time::sleep_until(wake.into()).await;
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+        self.advance(deadline, Instant::now())
+    }
+
+    fn advance(&mut self, deadline: Instant, now: Instant) -> TickSlot {
+        self.wakeup_error.record(now.duration_since(deadline).as_micros() as u64);
+        let slot = TickSlot { index: self.next, deadline };
+        self.next += 1;
+        self.fired += 1;
+
+        let behind = now.duration_since(deadline);
+        if behind >= self.period {
+            let whole = (behind.as_nanos() / self.period.as_nanos()) as u64;
+            // Ticks slot.index + 1 ..= slot.index + whole are overdue; earlier ticks of a
+            // burst have counted some of them already
+            let through = slot.index + whole;
+            self.missed += through - self.missed_through.max(slot.index).min(through);
+            self.missed_through = self.missed_through.max(through);
+            match self.policy {
+                MissedTickPolicy::Burst => {}
+                MissedTickPolicy::Delay => {
+                    // Shift the grid so the next deadline is one period from now; nothing
+                    // after this tick is overdue on the new grid
+                    let shift = now + self.period - self.deadline(self.next);
+                    self.start += shift;
+                    self.delayed += shift;
+                    self.missed_through = slot.index;
+                }
+                MissedTickPolicy::Skip => {
+                    self.next += whole;
+                    self.skipped += whole;
+                }
+            }
+        }
+        slot
+    }
+
+    /// Record that the tick's message actually left now.
+    pub fn sent(&mut self, slot: &TickSlot) {
+        self.send_error.record(Instant::now().saturating_duration_since(slot.deadline).as_micros() as u64);
+    }
+
+    pub fn summary(&self) -> Value {
+        json!({
+            "policy": format!("{:?}", self.policy),
+            "period_us": self.period.as_micros() as u64,
+            "spin_us": self.spin.as_micros() as u64,
+            "ticks": self.fired,
+            "missed_periods": self.missed,
+            "skipped_ticks": self.skipped,
+            "delayed_us": self.delayed.as_micros() as u64,
+            "wakeup_error_us": self.wakeup_error.summary(),
+            "send_error_us": self.send_error.summary()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    fn scheduler(policy: MissedTickPolicy) -> TickScheduler {
+        TickScheduler::new(PERIOD, &SchedulerOptions { missed_ticks: policy, ..Default::default() })
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    // Fire the next tick as if the loop woke at `now`
+    fn tick_at(scheduler: &mut TickScheduler, now: Instant) -> TickSlot {
+        let deadline = scheduler.deadline(scheduler.next);
+        scheduler.advance(deadline, now)
+    }
+
+    fn next_deadline(scheduler: &TickScheduler) -> Instant {
+        scheduler.deadline(scheduler.next)
+    }
+
+    #[test]
+    fn burst_counts_a_stall_once_and_catches_up_on_the_grid() {
+        let mut s = scheduler(MissedTickPolicy::Burst);
+        let start = s.start;
+        tick_at(&mut s, start + ms(0));
+        // Tick 1 was due at 10 ms; waking at 45 ms leaves ticks 2-4 overdue
+        tick_at(&mut s, start + ms(45));
+        assert_eq!(s.missed, 3);
+        for index in 2..=4 {
+            assert_eq!(tick_at(&mut s, start + ms(45)).index, index);
+        }
+        assert_eq!(s.missed, 3);
+        assert_eq!(s.skipped, 0);
+        assert_eq!(next_deadline(&s), start + ms(50));
+    }
+
+    #[test]
+    fn delay_shifts_the_grid_and_counts_later_stalls() {
+        let mut s = scheduler(MissedTickPolicy::Delay);
+        let start = s.start;
+        tick_at(&mut s, start + ms(0));
+        tick_at(&mut s, start + ms(45));
+        assert_eq!(s.missed, 3);
+        assert_eq!(next_deadline(&s), start + ms(55));
+        assert_eq!(s.delayed, ms(35));
+        tick_at(&mut s, start + ms(55));
+        assert_eq!(s.missed, 3);
+        // Tick 3 was due at 65 ms on the shifted grid
+        tick_at(&mut s, start + ms(90));
+        assert_eq!(s.missed, 5);
+        assert_eq!(next_deadline(&s), start + ms(100));
+    }
+
+    #[test]
+    fn skip_drops_overdue_ticks_and_stays_on_the_grid() {
+        let mut s = scheduler(MissedTickPolicy::Skip);
+        let start = s.start;
+        tick_at(&mut s, start + ms(0));
+        tick_at(&mut s, start + ms(45));
+        assert_eq!(s.missed, 3);
+        assert_eq!(s.skipped, 3);
+        assert_eq!(next_deadline(&s), start + ms(50));
+        assert_eq!(tick_at(&mut s, start + ms(50)).index, 5);
+        assert_eq!(s.summary()["missed_periods"], 3);
+    }
+
+    #[test]
+    fn late_wakeups_within_a_period_do_not_drift() {
+        for policy in [MissedTickPolicy::Burst, MissedTickPolicy::Delay, MissedTickPolicy::Skip] {
+            let mut s = scheduler(policy);
+            let start = s.start;
+            for n in 0..1000 {
+                // Every wakeup 9 ms late, which sleeping for the rest of the period would accumulate
+                let slot = tick_at(&mut s, start + ms(10 * n + 9));
+                assert_eq!(slot.deadline, start + ms(10 * n));
+            }
+            assert_eq!(next_deadline(&s), start + ms(10_000));
+            assert_eq!((s.missed, s.skipped, s.delayed), (0, 0, Duration::ZERO));
+            assert_eq!(s.fired, 1000);
+        }
+    }
+}
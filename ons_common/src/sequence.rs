@@ -0,0 +1,212 @@
+// Loss, reordering and delay variation from the tick sequence numbers of datagram echoes.
+//
+// Loss counts distinct sequence numbers, so duplicates can't hide it; reordering and its
+// extent follow RFC 4737, jitter RFC 3550 and packet delay variation RFC 5481.
+use crate::histogram::{HistogramOptions, LatencyHistogram};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+
+/// Loss, reordering, duplication and delay variation for a datagram run, derived
+/// from the tick sequence numbers of the echoes as they arrive.
+///
+/// Transit times are whatever the caller measures per tick (the echo RTT in the
+/// clients); jitter and PDV only depend on their differences, so a constant clock
+/// offset between sender and receiver does not matter.
+#[derive(Default)]
+pub struct DatagramMetrics {
+    seen: HashSet<u64>,
+    duplicates: u64,
+    // Running maximum sequence number after each arrival, for RFC 4737 reorder extents
+    prefix_max: Vec<u64>,
+    reorder_extents: BTreeMap<u64, u64>,
+    reordered: u64,
+    last_transit: Option<i64>,
+    jitter: f64,
+    transits: Vec<i64>,
+}
+
+impl DatagramMetrics {
+    pub fn new() -> Self {
+        DatagramMetrics::default()
+    }
+
+    /// Record an arrival of `seq` whose one-way or round-trip transit time was `transit_us`.
+    /// Returns false if `seq` had already arrived.
+    pub fn record(&mut self, seq: u64, transit_us: i64) -> bool {
+        if !self.seen.insert(seq) {
+            // Duplicates are counted but kept out of ordering and delay statistics
+            self.duplicates += 1;
+            return false;
+        }
+
+        let next_expected = self.prefix_max.last().map(|&max| max + 1).unwrap_or(0);
+        if seq < next_expected {
+            // RFC 4737 section 4.2.2: extent is the distance back to the first
+            // earlier arrival with a larger sequence number
+            let first_later = self.prefix_max.partition_point(|&max| max <= seq);
+            let extent = (self.prefix_max.len() - first_later) as u64;
+            *self.reorder_extents.entry(extent).or_insert(0) += 1;
+            self.reordered += 1;
+        }
+        let max = self.prefix_max.last().map_or(seq, |&max| max.max(seq));
+        self.prefix_max.push(max);
+
+        // RFC 3550 section 6.4.1 interarrival jitter, in arrival order
+        if let Some(last) = self.last_transit {
+            let d = (transit_us - last).abs() as f64;
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.last_transit = Some(transit_us);
+        self.transits.push(transit_us);
+        true
+    }
+
+    /// An echo whose send time is no longer tracked; only a duplicate if `seq` already arrived.
+    pub fn record_unmatched(&mut self, seq: u64) {
+        if self.seen.contains(&seq) {
+            self.duplicates += 1;
+        }
+    }
+
+    pub fn received(&self) -> u64 {
+        self.seen.len() as u64
+    }
+
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    pub fn reordered(&self) -> u64 {
+        self.reordered
+    }
+
+    /// RFC 3550 interarrival jitter estimate, in microseconds.
+    pub fn jitter_us(&self) -> f64 {
+        self.jitter
+    }
+
+    /// Lengths of consecutive runs of sequence numbers below `sent` that never arrived.
+    pub fn loss_bursts(&self, sent: u64) -> Vec<u64> {
+        let mut bursts = Vec::new();
+        let mut current = 0;
+        for seq in 0..sent {
+            if self.seen.contains(&seq) {
+                if current > 0 {
+                    bursts.push(current);
+                    current = 0;
+                }
+            } else {
+                current += 1;
+            }
+        }
+        if current > 0 {
+            bursts.push(current);
+        }
+        bursts
+    }
+
+    /// Per-run report given the number of ticks sent (sequence numbers `0..sent`).
+    pub fn summary(&self, sent: u64) -> Value {
+        let received = self.seen.iter().filter(|&&seq| seq < sent).count() as u64;
+        let lost = sent - received;
+        let percent = |n: u64| if sent > 0 { n as f64 / sent as f64 * 100.0 } else { 0.0 };
+
+        // RFC 5481 PDV: each transit relative to the smallest one observed
+        let pdv = self.transits.iter().min().map(|&min| {
+            let mut hist = LatencyHistogram::new(&HistogramOptions::default());
+            for &transit in &self.transits {
+                hist.record((transit - min) as u64);
+            }
+            hist.summary()
+        });
+
+        let bursts = self.loss_bursts(sent);
+        let mut burst_lengths: BTreeMap<u64, u64> = BTreeMap::new();
+        for &length in &bursts {
+            *burst_lengths.entry(length).or_insert(0) += 1;
+        }
+
+        json!({
+            "sent": sent,
+            "received": received,
+            "lost": lost,
+            "loss_rate_percent": percent(lost),
+            "duplicates": self.duplicates,
+            "reordered": self.reordered,
+            "reordered_percent": percent(self.reordered),
+            "max_reorder_extent": self.reorder_extents.keys().next_back().copied().unwrap_or(0),
+            "reorder_extents": self.reorder_extents.iter()
+                .map(|(extent, count)| json!({ "extent": extent, "count": count }))
+                .collect::<Vec<_>>(),
+            "jitter_us": self.jitter,
+            "pdv_us": pdv,
+            "loss_bursts": {
+                "count": bursts.len(),
+                "max_length": bursts.iter().max().copied().unwrap_or(0),
+                "mean_length": if bursts.is_empty() { 0.0 } else { lost as f64 / bursts.len() as f64 },
+                "lengths": burst_lengths.iter()
+                    .map(|(length, count)| json!({ "length": length, "count": count }))
+                    .collect::<Vec<_>>()
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loss_counts_distinct_sequence_numbers() {
+        let mut metrics = DatagramMetrics::new();
+        for seq in [0, 1, 3] {
+            assert!(metrics.record(seq, 100));
+        }
+        // A duplicate neither counts as received nor hides the loss of 2 and 4
+        assert!(!metrics.record(1, 100));
+        let summary = metrics.summary(5);
+        assert_eq!(summary["received"], 3);
+        assert_eq!(summary["lost"], 2);
+        assert_eq!(summary["duplicates"], 1);
+        assert_eq!(metrics.loss_bursts(5), vec![1, 1]);
+        assert_eq!(metrics.loss_bursts(7), vec![1, 3]);
+    }
+
+    #[test]
+    fn reorder_extent_follows_rfc_4737() {
+        let mut metrics = DatagramMetrics::new();
+        // 3 arrives one packet after 5 overtook it, 4 two packets after
+        for seq in [0, 1, 2, 5, 3, 4, 6] {
+            metrics.record(seq, 100);
+        }
+        let summary = metrics.summary(7);
+        assert_eq!(metrics.reordered(), 2);
+        assert_eq!(summary["max_reorder_extent"], 2);
+        assert_eq!(summary["reorder_extents"], json!([{ "extent": 1, "count": 1 }, { "extent": 2, "count": 1 }]));
+        assert_eq!(summary["lost"], 0);
+    }
+
+    #[test]
+    fn jitter_is_rfc_3550_smoothed() {
+        let mut metrics = DatagramMetrics::new();
+        metrics.record(0, 100);
+        metrics.record(1, 116);
+        assert_eq!(metrics.jitter_us(), 1.0);
+        metrics.record(2, 100);
+        assert_eq!(metrics.jitter_us(), 1.0 + 15.0 / 16.0);
+        // Duplicates stay out of the delay statistics
+        metrics.record(2, 5000);
+        assert_eq!(metrics.jitter_us(), 1.0 + 15.0 / 16.0);
+    }
+
+    #[test]
+    fn pdv_is_relative_to_the_smallest_transit() {
+        let mut metrics = DatagramMetrics::new();
+        for (seq, transit) in [(0, 100), (1, 150), (2, 120)] {
+            metrics.record(seq, transit);
+        }
+        let pdv = &metrics.summary(3)["pdv_us"];
+        assert_eq!(pdv["min"], 0);
+        assert_eq!(pdv["max"], 50);
+    }
+}
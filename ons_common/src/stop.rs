@@ -0,0 +1,47 @@
+// Stopping a run early: SIGINT, SIGTERM and the dashboard's q set one flag that the
+// tick and accept loops check, so an interrupted run still writes its results
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often [`Stop::wait`] looks at the flag
+const POLL: Duration = Duration::from_millis(50);
+
+/// Exit status for a second signal, as a shell reports death by SIGINT
+const FORCED_EXIT: i32 = 130;
+
+/// Set once the run should wind down; cheap to clone into tasks and threads.
+#[derive(Clone, Debug, Default)]
+pub struct Stop(Arc<AtomicBool>);
+
+impl Stop {
+    /// A flag that SIGINT and SIGTERM set. The first signal only asks the loops to
+    /// stop; a second one exits straight away, for a run stuck somewhere that never
+    /// looks at the flag.
+    pub fn on_signals() -> io::Result<Stop> {
+        let stop = Stop::default();
+        for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+            // Registered first, so it sees the flag as it was before this signal
+            signal_hook::flag::register_conditional_shutdown(signal, FORCED_EXIT, stop.0.clone())?;
+            signal_hook::flag::register(signal, stop.0.clone())?;
+        }
+        Ok(stop)
+    }
+
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once a stop is requested, for a `tokio::select!` branch
+    pub async fn wait(&self) {
+        while !self.requested() {
+            tokio::time::sleep(POLL).await;
+        }
+    }
+}
+
@@ -0,0 +1,37 @@
+// TCP_INFO snapshots for the WebSocket client and server, the one TCP transport here.
+use crate::timeseries::TransportSnapshot;
+use std::os::unix::io::RawFd;
+
+/// Read the kernel's TCP_INFO for a connected socket.
+///
+/// The caller keeps the socket alive; only the descriptor number is needed, so
+/// this works for a `TcpStream` that has since been wrapped in TLS.
+#[cfg(target_os = "linux")]
+pub fn snapshot(fd: RawFd) -> Option<TransportSnapshot> {
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    Some(TransportSnapshot {
+        stack_rtt_us: Some(info.tcpi_rtt as u64),
+        cwnd_bytes: Some(info.tcpi_snd_cwnd as u64 * info.tcpi_snd_mss as u64),
+        sent_packets: None,
+        lost_packets: Some(info.tcpi_lost as u64),
+        retransmits: Some(info.tcpi_total_retrans as u64),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn snapshot(_fd: RawFd) -> Option<TransportSnapshot> {
+    None
+}
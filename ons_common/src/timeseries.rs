@@ -0,0 +1,209 @@
+// One row per tick for plotting a run over time: when it went out, when (and whether)
+// its echo came back, and the transport's counters at that moment.
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Instant;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeSeriesFormat {
+    Csv,
+    /// Only offered when ons_common is built with the `parquet` feature
+    #[cfg_attr(not(feature = "parquet"), value(skip))]
+    Parquet,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct TimeSeriesOptions {
+    /// Per-tick time series output; defaults to <transport>_timeseries.<format>
+    #[arg(long)]
+    pub timeseries_file: Option<PathBuf>,
+
+    /// File format for the per-tick time series
+    #[arg(long, value_enum, default_value_t = TimeSeriesFormat::Csv)]
+    pub timeseries_format: TimeSeriesFormat,
+}
+
+impl TimeSeriesOptions {
+    pub fn path(&self, stem: &str) -> PathBuf {
+        self.timeseries_file.clone().unwrap_or_else(|| {
+            let ext = match self.timeseries_format {
+                TimeSeriesFormat::Csv => "csv",
+                TimeSeriesFormat::Parquet => "parquet",
+            };
+            PathBuf::from(format!("{}_timeseries.{}", stem, ext))
+        })
+    }
+}
+
+/// Transport-level counters at the moment a tick was sent. Each client fills in
+/// what its stack exposes (TCP_INFO, quinn path stats, WebRTC ICE stats).
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct TransportSnapshot {
+    pub stack_rtt_us: Option<u64>,
+    pub cwnd_bytes: Option<u64>,
+    pub sent_packets: Option<u64>,
+    pub lost_packets: Option<u64>,
+    pub retransmits: Option<u64>,
+}
+
+impl TransportSnapshot {
+    /// quinn's path stats; QUIC has no separate retransmission count, lost packets are resent as new ones
+    #[cfg(feature = "quinn")]
+    pub fn quic(stats: &quinn::ConnectionStats) -> Self {
+        TransportSnapshot {
+            stack_rtt_us: Some(stats.path.rtt.as_micros() as u64),
+            cwnd_bytes: Some(stats.path.cwnd),
+            sent_packets: Some(stats.path.sent_packets),
+            lost_packets: Some(stats.path.lost_packets),
+            retransmits: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TickRecord {
+    pub seq: u64,
+    pub send_offset_us: u64,
+    pub recv_offset_us: Option<u64>,
+    pub rtt_us: Option<u64>,
+    pub payload_bytes: u64,
+    /// The send loop took longer than one tick duration on this tick
+    pub overrun: bool,
+    /// The echo came back after the late cut-off
+    pub late: bool,
+    pub transport: TransportSnapshot,
+}
+
+/// One record per tick sent, filled in as echoes arrive. Offsets are relative
+/// to the start of the run so stalls and ramp-up can be plotted over time.
+pub struct TimeSeries {
+    start: Instant,
+    records: Vec<TickRecord>,
+    index: HashMap<u64, usize>,
+}
+
+impl Default for TimeSeries {
+    fn default() -> Self {
+        TimeSeries::new(Instant::now())
+    }
+}
+
+impl TimeSeries {
+    pub fn new(start: Instant) -> Self {
+        TimeSeries { start, records: Vec::new(), index: HashMap::new() }
+    }
+
+    pub fn sent(&mut self, seq: u64, at: Instant, payload_bytes: usize, transport: TransportSnapshot) {
+        self.index.insert(seq, self.records.len());
+        self.records.push(TickRecord {
+            seq,
+            send_offset_us: at.duration_since(self.start).as_micros() as u64,
+            recv_offset_us: None,
+            rtt_us: None,
+            payload_bytes: payload_bytes as u64,
+            overrun: false,
+            late: false,
+            transport,
+        });
+    }
+
+    /// Fill in the echo for `seq`; repeated echoes keep the first arrival.
+    pub fn received(&mut self, seq: u64, at: Instant, late: bool) {
+        if let Some(record) = self.index.get(&seq).map(|&i| &mut self.records[i]) {
+            if record.recv_offset_us.is_none() {
+                let recv = at.duration_since(self.start).as_micros() as u64;
+                record.recv_offset_us = Some(recv);
+                record.rtt_us = Some(recv.saturating_sub(record.send_offset_us));
+                record.late = late;
+            }
+        }
+    }
+
+    /// Flag the tick that was being sent when the loop overran its tick duration.
+    pub fn mark_overrun(&mut self, seq: u64) {
+        if let Some(&i) = self.index.get(&seq) {
+            self.records[i].overrun = true;
+        }
+    }
+
+    pub fn records(&self) -> &[TickRecord] {
+        &self.records
+    }
+
+    pub fn save(&self, options: &TimeSeriesOptions, stem: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let path = options.path(stem);
+        match options.timeseries_format {
+            TimeSeriesFormat::Csv => self.write_csv(&path)?,
+            TimeSeriesFormat::Parquet => self.write_parquet(&path)?,
+        }
+        Ok(path)
+    }
+
+    pub fn write_csv(&self, path: &PathBuf) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record([
+            "seq", "send_offset_us", "recv_offset_us", "rtt_us", "payload_bytes", "overrun", "late",
+            "stack_rtt_us", "cwnd_bytes", "sent_packets", "lost_packets", "retransmits",
+        ])?;
+        let opt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        for r in &self.records {
+            writer.write_record([
+                r.seq.to_string(),
+                r.send_offset_us.to_string(),
+                opt(r.recv_offset_us),
+                opt(r.rtt_us),
+                r.payload_bytes.to_string(),
+                r.overrun.to_string(),
+                r.late.to_string(),
+                opt(r.transport.stack_rtt_us),
+                opt(r.transport.cwnd_bytes),
+                opt(r.transport.sent_packets),
+                opt(r.transport.lost_packets),
+                opt(r.transport.retransmits),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet(&self, path: &PathBuf) -> Result<(), Box<dyn Error>> {
+        use arrow::array::{ArrayRef, BooleanArray, UInt64Array};
+        use arrow::record_batch::RecordBatch;
+        use parquet::arrow::ArrowWriter;
+        use std::sync::Arc;
+
+        let column = |f: &dyn Fn(&TickRecord) -> Option<u64>| -> ArrayRef {
+            Arc::new(self.records.iter().map(f).collect::<UInt64Array>())
+        };
+        let flag = |f: &dyn Fn(&TickRecord) -> bool| -> ArrayRef {
+            Arc::new(self.records.iter().map(|r| Some(f(r))).collect::<BooleanArray>())
+        };
+        let batch = RecordBatch::try_from_iter([
+            ("seq", column(&|r| Some(r.seq))),
+            ("send_offset_us", column(&|r| Some(r.send_offset_us))),
+            ("recv_offset_us", column(&|r| r.recv_offset_us)),
+            ("rtt_us", column(&|r| r.rtt_us)),
+            ("payload_bytes", column(&|r| Some(r.payload_bytes))),
+            ("overrun", flag(&|r| r.overrun)),
+            ("late", flag(&|r| r.late)),
+            ("stack_rtt_us", column(&|r| r.transport.stack_rtt_us)),
+            ("cwnd_bytes", column(&|r| r.transport.cwnd_bytes)),
+            ("sent_packets", column(&|r| r.transport.sent_packets)),
+            ("lost_packets", column(&|r| r.transport.lost_packets)),
+            ("retransmits", column(&|r| r.transport.retransmits)),
+        ])?;
+
+        let mut writer = ArrowWriter::try_new(std::fs::File::create(path)?, batch.schema(), None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    pub fn write_parquet(&self, _path: &PathBuf) -> Result<(), Box<dyn Error>> {
+        Err("Parquet output needs ons_common built with the `parquet` feature".into())
+    }
+}
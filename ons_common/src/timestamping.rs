@@ -0,0 +1,393 @@
+// Kernel and NIC packet timestamps (SO_TIMESTAMPING).
+//
+// These split a round trip into what the application loop adds and what the stack and
+// the wire take, by stamping each datagram as the kernel or the NIC sees it.
+use crate::histogram::{HistogramOptions, LatencyHistogram};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum TimestampingMode {
+    /// Userspace clock only
+    Off,
+    /// Kernel software timestamps on receive and transmit
+    Software,
+    /// Kernel software plus NIC hardware timestamps
+    Hardware,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct TimestampingOptions {
+    /// SO_TIMESTAMPING on UDP-based transports, to split stack time from network time (Linux only)
+    #[arg(long, value_enum, default_value_t = TimestampingMode::Off)]
+    pub timestamping: TimestampingMode,
+
+    /// In hardware mode, switch on NIC timestamping for this interface (needs CAP_NET_ADMIN)
+    #[arg(long, required_if_eq("timestamping", "hardware"))]
+    pub timestamping_interface: Option<String>,
+
+    /// Per-packet userspace/kernel/NIC timestamps; defaults to <transport>_timestamps.csv
+    #[arg(long)]
+    pub timestamps_file: Option<PathBuf>,
+}
+
+impl Default for TimestampingOptions {
+    fn default() -> Self {
+        TimestampingOptions { timestamping: TimestampingMode::Off, timestamping_interface: None, timestamps_file: None }
+    }
+}
+
+impl TimestampingOptions {
+    pub fn enabled(&self) -> bool {
+        self.timestamping != TimestampingMode::Off
+    }
+
+    pub fn path(&self, stem: &str) -> PathBuf {
+        self.timestamps_file.clone().unwrap_or_else(|| PathBuf::from(format!("{}_timestamps.csv", stem)))
+    }
+
+    /// Turn on timestamping for a UDP socket, and for the NIC in hardware mode.
+    ///
+    /// Transmit timestamps are numbered per datagram from the moment this is
+    /// called (SOF_TIMESTAMPING_OPT_ID), so call it before the first send.
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self, fd: RawFd) -> io::Result<()> {
+        let mut flags = libc::SOF_TIMESTAMPING_SOFTWARE
+            | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+            | libc::SOF_TIMESTAMPING_TX_SOFTWARE
+            | libc::SOF_TIMESTAMPING_OPT_ID
+            | libc::SOF_TIMESTAMPING_OPT_TSONLY;
+        match self.timestamping {
+            TimestampingMode::Off => return Ok(()),
+            TimestampingMode::Software => {}
+            TimestampingMode::Hardware => {
+                // Without it the NIC never stamps anything and the run records no hardware times
+                let Some(interface) = &self.timestamping_interface else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--timestamping hardware needs --timestamping-interface",
+                    ));
+                };
+                enable_nic_timestamps(fd, interface)?;
+                flags |= libc::SOF_TIMESTAMPING_RAW_HARDWARE
+                    | libc::SOF_TIMESTAMPING_RX_HARDWARE
+                    | libc::SOF_TIMESTAMPING_TX_HARDWARE;
+            }
+        }
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_TIMESTAMPING,
+                &flags as *const libc::c_uint as *const libc::c_void,
+                std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self, _fd: RawFd) -> io::Result<()> {
+        if self.enabled() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "SO_TIMESTAMPING needs Linux"));
+        }
+        Ok(())
+    }
+}
+
+/// Ask the driver to timestamp every packet in and out (SIOCSHWTSTAMP).
+#[cfg(target_os = "linux")]
+fn enable_nic_timestamps(fd: RawFd, interface: &str) -> io::Result<()> {
+    let mut config = libc::hwtstamp_config {
+        flags: 0,
+        tx_type: libc::HWTSTAMP_TX_ON as libc::c_int,
+        rx_filter: libc::HWTSTAMP_FILTER_ALL as libc::c_int,
+    };
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    if interface.len() >= request.ifr_name.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "interface name too long"));
+    }
+    for (dst, src) in request.ifr_name.iter_mut().zip(interface.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    request.ifr_ifru.ifru_data = &mut config as *mut libc::hwtstamp_config as *mut libc::c_char;
+    if unsafe { libc::ioctl(fd, libc::SIOCSHWTSTAMP as _, &mut request) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Kernel and NIC timestamps for one packet, in µs.
+///
+/// Software stamps are CLOCK_REALTIME, so they compare directly with
+/// `wire::now_micros()`. Hardware stamps come from the NIC's clock and only
+/// line up with the system clock when it is kept in sync (e.g. by phc2sys).
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct KernelStamps {
+    pub software_us: Option<u64>,
+    pub hardware_us: Option<u64>,
+}
+
+impl KernelStamps {
+    fn merge(&mut self, other: KernelStamps) {
+        self.software_us = self.software_us.or(other.software_us);
+        self.hardware_us = self.hardware_us.or(other.hardware_us);
+    }
+}
+
+/// A datagram read with `recv_timestamped`.
+#[derive(Debug, Clone, Copy)]
+pub struct Received {
+    pub len: usize,
+    pub from: Option<SocketAddr>,
+    pub stamps: KernelStamps,
+}
+
+#[cfg(target_os = "linux")]
+fn timespec_us(ts: &libc::timespec) -> Option<u64> {
+    if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+        return None;
+    }
+    Some(ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1_000)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in);
+            let ip = std::net::Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Some(SocketAddr::new(ip.into(), u16::from_be(addr.sin_port)))
+        }
+        libc::AF_INET6 => {
+            let addr = &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6);
+            let ip = std::net::Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            Some(SocketAddr::new(ip.into(), u16::from_be(addr.sin6_port)))
+        }
+        _ => None,
+    }
+}
+
+/// Walk the control messages of `msg`, collecting SCM_TIMESTAMPING and, for
+/// error-queue reads, the OPT_ID key of the transmitted packet.
+#[cfg(target_os = "linux")]
+unsafe fn parse_control(msg: &libc::msghdr) -> (KernelStamps, Option<u32>) {
+    let mut stamps = KernelStamps::default();
+    let mut id = None;
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        let (level, kind) = ((*cmsg).cmsg_level, (*cmsg).cmsg_type);
+        if level == libc::SOL_SOCKET && kind == libc::SCM_TIMESTAMPING {
+            // ts[0] is the software stamp; ts[1] is unused and ts[2] is the raw hardware stamp
+            let ts = libc::CMSG_DATA(cmsg) as *const libc::timespec;
+            stamps.software_us = timespec_us(&std::ptr::read_unaligned(ts));
+            stamps.hardware_us = timespec_us(&std::ptr::read_unaligned(ts.add(2)));
+        } else if (level == libc::SOL_IP && kind == libc::IP_RECVERR)
+            || (level == libc::SOL_IPV6 && kind == libc::IPV6_RECVERR)
+        {
+            let err = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err);
+            if err.ee_errno == libc::ENOMSG as u32 && err.ee_origin == libc::SO_EE_ORIGIN_TIMESTAMPING {
+                id = Some(err.ee_data);
+            }
+        }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    (stamps, id)
+}
+
+/// `recvmsg` on a datagram socket, also returning any receive timestamps the kernel attached.
+#[cfg(target_os = "linux")]
+pub fn recv_timestamped(fd: RawFd, buf: &mut [u8]) -> io::Result<Received> {
+    // Room for one SCM_TIMESTAMPING message: three timespecs plus the header
+    let mut control = [0u64; 16];
+    let mut from: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut from as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let received = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (stamps, _) = unsafe { parse_control(&msg) };
+    let from = if msg.msg_namelen > 0 { unsafe { socket_addr(&from) } } else { None };
+    Ok(Received { len: received as usize, from, stamps })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn recv_timestamped(fd: RawFd, buf: &mut [u8]) -> io::Result<Received> {
+    let received = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Received { len: received as usize, from: None, stamps: KernelStamps::default() })
+}
+
+/// Drain the socket's error queue of transmit timestamps, keyed by datagram number.
+///
+/// Software and hardware stamps for the same datagram arrive as separate
+/// entries, so one key can appear twice.
+#[cfg(target_os = "linux")]
+pub fn read_tx_timestamps(fd: RawFd) -> io::Result<Vec<(u32, KernelStamps)>> {
+    let mut stamps = Vec::new();
+    loop {
+        let mut control = [0u64; 32];
+        let mut data = [0u8; 64];
+        let mut iov = libc::iovec { iov_base: data.as_mut_ptr() as *mut libc::c_void, iov_len: data.len() };
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        if unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Ok(stamps);
+            }
+            return Err(err);
+        }
+        if let (stamp, Some(id)) = unsafe { parse_control(&msg) } {
+            stamps.push((id, stamp));
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_tx_timestamps(_fd: RawFd) -> io::Result<Vec<(u32, KernelStamps)>> {
+    Ok(Vec::new())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketStamps {
+    pub direction: &'static str,
+    /// Datagram number on transmit, matching the kernel's OPT_ID key
+    pub id: Option<u32>,
+    pub user_us: u64,
+    pub kernel_us: Option<u64>,
+    pub nic_us: Option<u64>,
+}
+
+/// Datagrams kept waiting for their transmit timestamps before being given up on
+const MAX_PENDING_TX: usize = 4096;
+
+/// Userspace, kernel and NIC timestamps for every datagram a socket sent or
+/// received, to show how much of the RTT is spent in the host's own stack.
+#[derive(Debug)]
+pub struct StackTimestamps {
+    mode: TimestampingMode,
+    next_id: u32,
+    pending_tx: BTreeMap<u32, usize>,
+    packets: Vec<PacketStamps>,
+}
+
+impl StackTimestamps {
+    pub fn new(options: &TimestampingOptions) -> Self {
+        StackTimestamps { mode: options.timestamping, next_id: 0, pending_tx: BTreeMap::new(), packets: Vec::new() }
+    }
+
+    /// Record a datagram handed to the kernel at `user_us`. Call once per successful send.
+    pub fn sent(&mut self, user_us: u64) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending_tx.insert(id, self.packets.len());
+        self.packets.push(PacketStamps { direction: "tx", id: Some(id), user_us, kernel_us: None, nic_us: None });
+        while self.pending_tx.len() > MAX_PENDING_TX {
+            self.pending_tx.pop_first();
+        }
+    }
+
+    pub fn tx_stamped(&mut self, id: u32, stamps: KernelStamps) {
+        if let Some(&index) = self.pending_tx.get(&id) {
+            let packet = &mut self.packets[index];
+            let mut merged = KernelStamps { software_us: packet.kernel_us, hardware_us: packet.nic_us };
+            merged.merge(stamps);
+            packet.kernel_us = merged.software_us;
+            packet.nic_us = merged.hardware_us;
+            let complete = match self.mode {
+                TimestampingMode::Hardware => merged.software_us.is_some() && merged.hardware_us.is_some(),
+                _ => merged.software_us.is_some(),
+            };
+            if complete {
+                self.pending_tx.remove(&id);
+            }
+        }
+    }
+
+    /// Collect whatever transmit timestamps have come back on `fd`'s error queue.
+    pub fn poll_tx(&mut self, fd: RawFd) -> io::Result<()> {
+        for (id, stamps) in read_tx_timestamps(fd)? {
+            self.tx_stamped(id, stamps);
+        }
+        Ok(())
+    }
+
+    /// Record a datagram read from the socket at `user_us`.
+    pub fn received(&mut self, user_us: u64, stamps: KernelStamps) {
+        self.packets.push(PacketStamps {
+            direction: "rx",
+            id: None,
+            user_us,
+            kernel_us: stamps.software_us,
+            nic_us: stamps.hardware_us,
+        });
+    }
+
+    fn histogram(&self, direction: &str, gap: impl Fn(&PacketStamps) -> Option<u64>) -> Value {
+        let mut histogram = LatencyHistogram::new(&HistogramOptions::default());
+        for packet in self.packets.iter().filter(|p| p.direction == direction) {
+            if let Some(us) = gap(packet) {
+                histogram.record(us);
+            }
+        }
+        histogram.summary()
+    }
+
+    pub fn summary(&self) -> Value {
+        let count = |direction: &str| self.packets.iter().filter(|p| p.direction == direction).count();
+        let stamped = |direction: &str, f: fn(&PacketStamps) -> bool| {
+            self.packets.iter().filter(|p| p.direction == direction && f(p)).count()
+        };
+        json!({
+            "mode": self.mode,
+            "tx": {
+                "packets": count("tx"),
+                "kernel_stamped": stamped("tx", |p| p.kernel_us.is_some()),
+                "nic_stamped": stamped("tx", |p| p.nic_us.is_some()),
+                "user_to_kernel_us": self.histogram("tx", |p| p.kernel_us.map(|k| k.saturating_sub(p.user_us))),
+                "kernel_to_nic_us": self.histogram("tx", |p| Some(p.nic_us?.saturating_sub(p.kernel_us?)))
+            },
+            "rx": {
+                "packets": count("rx"),
+                "kernel_stamped": stamped("rx", |p| p.kernel_us.is_some()),
+                "nic_stamped": stamped("rx", |p| p.nic_us.is_some()),
+                "nic_to_kernel_us": self.histogram("rx", |p| Some(p.kernel_us?.saturating_sub(p.nic_us?))),
+                "kernel_to_user_us": self.histogram("rx", |p| p.kernel_us.map(|k| p.user_us.saturating_sub(k)))
+            }
+        })
+    }
+
+    pub fn save(&self, options: &TimestampingOptions, stem: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let path = options.path(stem);
+        let mut writer = csv::Writer::from_path(&path)?;
+        for packet in &self.packets {
+            writer.serialize(packet)?;
+        }
+        writer.flush()?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,100 @@
+// The tick message exchanged by every client and server.
+//
+// Clients send `{"tick": <n>, "timestamp": <µs>}`. Servers echo it back and add their own
+// wall-clock receive and send times, so the client can split the round trip into forward
+// delay, server residence time and return delay.
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TickMessage {
+    pub tick: u64,
+    pub timestamp: u64,
+    /// Server wall-clock time the tick arrived, in µs since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_recv_us: Option<u64>,
+    /// Server wall-clock time the echo was handed to the transport, in µs since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_send_us: Option<u64>,
+}
+
+impl TickMessage {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+/// Current wall-clock time in microseconds since the Unix epoch
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Add server receive and send timestamps to an echoed tick message
+///
+/// Anything that isn't a single JSON object is echoed unchanged, so the client still
+/// gets its RTT sample.
+pub fn stamp_echo(data: &[u8], server_recv_us: u64, server_send_us: u64) -> Vec<u8> {
+    match serde_json::from_slice::<Value>(data) {
+        Ok(Value::Object(mut map)) => {
+            map.insert("server_recv_us".to_string(), server_recv_us.into());
+            map.insert("server_send_us".to_string(), server_send_us.into());
+            serde_json::to_vec(&Value::Object(map)).unwrap_or_else(|_| data.to_vec())
+        }
+        _ => data.to_vec(),
+    }
+}
+
+/// `stamp_echo` for text-framed transports
+pub fn stamp_echo_text(text: &str, server_recv_us: u64, server_send_us: u64) -> String {
+    // serde_json only ever writes UTF-8, and non-JSON input is returned unchanged
+    String::from_utf8(stamp_echo(text.as_bytes(), server_recv_us, server_send_us)).unwrap_or_else(|_| text.to_string())
+}
+
+/// Terminate a message for a stream transport, where reads don't keep message
+/// boundaries. Tick messages are compact JSON, so they never contain a raw newline.
+pub fn frame_line(message: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(message.len() + 1);
+    framed.extend_from_slice(message);
+    framed.push(b'\n');
+    framed
+}
+
+/// Reassembles [`frame_line`] messages from a stream, where one read can hold part of
+/// a message or several of them.
+#[derive(Debug, Default)]
+pub struct LineFramer {
+    partial: Vec<u8>,
+}
+
+impl LineFramer {
+    /// Add what a read returned and take every message it completed, newlines stripped
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.partial.extend_from_slice(data);
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else { return Vec::new() };
+        let rest = self.partial.split_off(end + 1);
+        let complete = std::mem::replace(&mut self.partial, rest);
+        complete.split(|&b| b == b'\n').filter(|line| !line.is_empty()).map(<[u8]>::to_vec).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framer_splits_and_joins_reads() {
+        let mut framer = LineFramer::default();
+        let mut stream = frame_line(br#"{"tick":1}"#);
+        stream.extend(frame_line(br#"{"tick":2}"#));
+        stream.extend(frame_line(br#"{"tick":3}"#));
+        let (first, second) = stream.split_at(15);
+        assert_eq!(framer.push(first), vec![br#"{"tick":1}"#.to_vec()]);
+        assert_eq!(framer.push(second), vec![br#"{"tick":2}"#.to_vec(), br#"{"tick":3}"#.to_vec()]);
+        assert!(framer.push(b"{\"ti").is_empty());
+        assert_eq!(framer.push(b"ck\":4}\n"), vec![br#"{"tick":4}"#.to_vec()]);
+    }
+}
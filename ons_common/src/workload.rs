@@ -0,0 +1,56 @@
+// What a client sends: how often, for how long, and how big each tick message is.
+//
+// Each binary keeps its own defaults (the WebRTC client has always run at 32 Hz for
+// 60 s, the others at 128 Hz for 180 s), so the options only override them.
+use std::time::Duration;
+use serde_json::{json, Value};
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct WorkloadOptions {
+    /// Ticks sent per second
+    #[arg(long)]
+    pub tick_rate: Option<u64>,
+
+    /// How long to keep sending ticks, in seconds
+    #[arg(long)]
+    pub simulation_duration_secs: Option<u64>,
+
+    /// Pad each tick message to at least this many bytes; 0 sends the bare message
+    #[arg(long, default_value = "0")]
+    pub payload_bytes: usize,
+}
+
+impl WorkloadOptions {
+    pub fn tick_rate(&self, default: u64) -> u64 {
+        self.tick_rate.unwrap_or(default).max(1)
+    }
+
+    pub fn tick_duration(&self, default_rate: u64) -> Duration {
+        Duration::from_micros(1_000_000 / self.tick_rate(default_rate))
+    }
+
+    pub fn duration(&self, default_secs: u64) -> Duration {
+        Duration::from_secs(self.simulation_duration_secs.unwrap_or(default_secs))
+    }
+
+    /// The tick message, with a `pad` field filling it out to `--payload-bytes`.
+    /// Servers echo unknown fields unchanged, so the echo grows by the same amount.
+    pub fn message(&self, tick: u64, timestamp: u128) -> String {
+        let bare = format!(r#"{{"tick":{},"timestamp":{}}}"#, tick, timestamp);
+        // `,"pad":""` adds 9 bytes before any padding goes in
+        const PAD_OVERHEAD: usize = 9;
+        if self.payload_bytes < bare.len() + PAD_OVERHEAD {
+            return bare;
+        }
+        let pad = "x".repeat(self.payload_bytes - bare.len() - PAD_OVERHEAD);
+        format!(r#"{{"tick":{},"timestamp":{},"pad":"{}"}}"#, tick, timestamp, pad)
+    }
+
+    pub fn summary(&self, default_rate: u64, default_secs: u64) -> Value {
+        json!({
+            "tick_rate": self.tick_rate(default_rate),
+            "simulation_duration_secs": self.duration(default_secs).as_secs(),
+            "payload_bytes": self.payload_bytes,
+        })
+    }
+}
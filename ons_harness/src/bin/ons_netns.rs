@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use clap::{Parser, Subcommand};
+use ons_harness::netns::Network;
+use ons_harness::runner::{interrupt_flag, run_scenario};
+use ons_harness::scenario::Scenario;
+
+/// Run a benchmark between two network namespaces joined by a shaped veth pair.
+///
+/// The scenario file (TOML, or YAML by extension) gives the topology, netem/tbf shaping
+/// per direction in netem_proxy's spec format, and the server and client commands.
+/// Needs root, iproute2 and tc; ethtool is used if present.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Build the topology, run the server then the client, and tear everything down
+    Run {
+        scenario: PathBuf,
+
+        /// Logs, the run summary and, by default, the benchmarks' own result files go
+        /// here; `{output_dir}` in commands points at it
+        #[arg(long, default_value = "netns_run")]
+        output_dir: PathBuf,
+    },
+    /// Build and shape the topology and hold it until Ctrl-C, for running things by hand
+    /// with `ip netns exec`
+    Up { scenario: PathBuf },
+    /// Remove namespaces left behind by an interrupted run
+    Down { scenario: PathBuf },
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    match args.command {
+        Command::Run { scenario, output_dir } => {
+            let scenario = Scenario::load(&scenario)?;
+            println!("Scenario {}: up {}, down {}", scenario.name, scenario.shaping.up, scenario.shaping.down);
+            let interrupted = interrupt_flag()?;
+            let summary = run_scenario(&scenario, &output_dir, &interrupted)?;
+            if summary["timed_out"] == true {
+                return Err(format!("client was stopped at the timeout, so its results are partial; see {}",
+                    output_dir.join("client.log").display()).into());
+            }
+            if summary["client_ok"] != true {
+                return Err(format!("client failed; see {}", output_dir.join("client.log").display()).into());
+            }
+            Ok(())
+        }
+        Command::Up { scenario } => {
+            let scenario = Scenario::load(&scenario)?;
+            let interrupted = interrupt_flag()?;
+            let network = Network::create(&scenario.topology)?;
+            for command in scenario.shaping.apply(&network)? {
+                println!("{}", command);
+            }
+            let t = &network.topology;
+            println!("Server at {} in {}, client at {} in {}", t.server_ip(), t.server_ns, t.client_ip(), t.client_ns);
+            println!("Ctrl-C to tear down");
+            while !interrupted.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(100));
+            }
+            Ok(())
+        }
+        Command::Down { scenario } => {
+            let scenario = Scenario::load(&scenario)?;
+            for ns in [&scenario.topology.client_ns, &scenario.topology.server_ns] {
+                let _ = ons_harness::netns::run(&["ip", "netns", "del", ns]);
+            }
+            Ok(())
+        }
+    }
+}
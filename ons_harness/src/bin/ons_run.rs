@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+use clap::Parser;
+use ons_harness::manifest::{Manifest, RunRecord, RunStatus};
+use ons_harness::matrix::Matrix;
+use ons_harness::runner::{interrupt_flag, run_scenario, unix_secs};
+
+/// Run a whole measurement matrix: every impairment profile, transport, tick rate and
+/// payload size in the matrix file, repeated, each in fresh network namespaces.
+///
+/// Results go in one directory per run under the output directory, with manifest.json
+/// at the top recording what ran. Needs root, like ons-netns.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Matrix file, TOML or YAML by extension
+    matrix: PathBuf,
+
+    /// Overrides the matrix file's output_dir
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// List the runs and the time they would take without running anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let mut matrix = Matrix::load(&args.matrix)?;
+    if let Some(output_dir) = &args.output_dir {
+        matrix.output_dir = output_dir.clone();
+    }
+    let cells = matrix.cells();
+    let estimate = matrix.estimated_secs();
+    println!(
+        "Matrix {}: {} runs, about {}h{:02}m, into {}",
+        matrix.name,
+        cells.len(),
+        estimate / 3600,
+        estimate % 3600 / 60,
+        matrix.output_dir.display()
+    );
+    if args.dry_run {
+        for cell in &cells {
+            let scenario = matrix.scenario(cell);
+            println!("{}: up {}, down {}", cell.dir().display(), scenario.shaping.up, scenario.shaping.down);
+            println!("  server: {}", scenario.server.command.join(" "));
+            println!("  client: {}", scenario.client.command.join(" "));
+        }
+        return Ok(());
+    }
+
+    fs::create_dir_all(&matrix.output_dir)?;
+    // Keep the matrix file itself next to the results it produced
+    let copy = matrix.output_dir.join(args.matrix.file_name().ok_or("matrix path has no file name")?);
+    fs::copy(&args.matrix, &copy)?;
+
+    let interrupted = interrupt_flag()?;
+    let mut manifest = Manifest::new(&args.matrix, &matrix, unix_secs());
+    manifest.save(&matrix.output_dir)?;
+    let matrix_started = Instant::now();
+
+    for (index, cell) in cells.into_iter().enumerate() {
+        if interrupted.load(Ordering::SeqCst) {
+            break;
+        }
+        println!("\n=== Run {}/{}: {} ===", index + 1, manifest.planned_runs, cell.dir().display());
+        let scenario = matrix.scenario(&cell);
+        let started = unix_secs();
+        let result = run_scenario(&scenario, &matrix.output_dir.join(cell.dir()), &interrupted);
+        let (status, summary) = match result {
+            Ok(summary) if summary["interrupted"] == true => (RunStatus::Interrupted, Some(summary)),
+            Ok(summary) if summary["timed_out"] == true => (RunStatus::TimedOut, Some(summary)),
+            Ok(summary) if summary["client_ok"] == true => (RunStatus::Completed, Some(summary)),
+            Ok(summary) => (RunStatus::ClientFailed, Some(summary)),
+            Err(e) => {
+                eprintln!("Run failed: {}", e);
+                (RunStatus::Error(e.to_string()), None)
+            }
+        };
+        manifest.runs.push(RunRecord::new(cell, status, summary.as_ref(), started, unix_secs()));
+        manifest.save(&matrix.output_dir)?;
+
+        let cooldown_end = Instant::now() + Duration::from_secs(matrix.cooldown_secs);
+        while Instant::now() < cooldown_end && !interrupted.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    manifest.finished_unix = Some(unix_secs());
+    let path = manifest.save(&matrix.output_dir)?;
+    let completed = manifest.runs.iter().filter(|run| matches!(run.status, RunStatus::Completed)).count();
+    println!(
+        "\n{} of {} runs completed in {:.0} minutes; manifest saved to {}",
+        completed,
+        manifest.planned_runs,
+        matrix_started.elapsed().as_secs_f64() / 60.0,
+        path.display()
+    );
+    Ok(())
+}
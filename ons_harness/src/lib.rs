@@ -0,0 +1,7 @@
+pub mod manifest;
+pub mod matrix;
+pub mod netns;
+pub mod process;
+pub mod runner;
+pub mod scenario;
+pub mod shaping;
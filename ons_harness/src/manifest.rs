@@ -0,0 +1,93 @@
+// manifest.json at the top of a matrix's output directory: what was planned and,
+// run by run, exactly what was executed and how it ended. Rewritten after every run,
+// so an interrupted matrix still says how far it got.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use serde_json::Value;
+use ons_common::manifest::{Build, Environment};
+use crate::matrix::{Cell, Matrix};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// The client ran to the end
+    Completed,
+    /// The client was still running at the timeout and was stopped, so its results are partial
+    TimedOut,
+    /// The client exited with an error
+    ClientFailed,
+    /// The run couldn't be set up or torn down
+    Error(String),
+    /// Ctrl-C arrived during the run
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunRecord {
+    #[serde(flatten)]
+    pub cell: Cell,
+    /// Relative to the manifest
+    pub dir: PathBuf,
+    pub status: RunStatus,
+    pub server_command: Value,
+    pub client_command: Value,
+    pub tc_commands: Value,
+    pub started_unix: f64,
+    pub finished_unix: f64,
+}
+
+impl RunRecord {
+    /// Pull what the manifest keeps out of the run's netns_summary.json
+    pub fn new(cell: Cell, status: RunStatus, summary: Option<&Value>, started_unix: f64, finished_unix: f64) -> Self {
+        let field = |name: &str| summary.map_or(Value::Null, |summary| summary[name].clone());
+        RunRecord {
+            dir: cell.dir(),
+            cell,
+            status,
+            server_command: field("server_command"),
+            client_command: field("client_command"),
+            tc_commands: field("tc_commands"),
+            started_unix,
+            finished_unix,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Manifest {
+    pub matrix_file: PathBuf,
+    pub matrix: Matrix,
+    pub planned_runs: usize,
+    /// The harness's own build; each client and server also writes a manifest into its run directory
+    pub build: Build,
+    /// The host side of the machine; the per-run manifests cover the namespaces' interfaces
+    pub environment: Environment,
+    pub started_unix: f64,
+    pub finished_unix: Option<f64>,
+    pub runs: Vec<RunRecord>,
+}
+
+impl Manifest {
+    pub fn new(matrix_file: &Path, matrix: &Matrix, started_unix: f64) -> Self {
+        Manifest {
+            matrix_file: matrix_file.to_path_buf(),
+            matrix: matrix.clone(),
+            planned_runs: matrix.cells().len(),
+            build: Build::current(),
+            environment: Environment::capture(),
+            started_unix,
+            finished_unix: None,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, output_dir: &Path) -> io::Result<PathBuf> {
+        let path = output_dir.join(MANIFEST_FILE);
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,277 @@
+// A measurement matrix: every combination of impairment profile, transport, tick rate
+// and payload size, repeated, with each combination run as its own netns scenario.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::netns::Topology;
+use crate::process::ProcessSpec;
+use crate::scenario::{self, Scenario};
+use crate::shaping::Shaping;
+
+/// How to start one transport's server and client. The runner appends `--tick-rate` to
+/// the server and `--tick-rate`, `--simulation-duration-secs` and `--payload-bytes` to
+/// the client, so these carry only what stays the same across the matrix.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransportSpec {
+    pub server: ProcessSpec,
+    pub client: ProcessSpec,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Matrix {
+    pub name: String,
+    /// Each run gets `<profile>/<transport>/tick<rate>_payload<bytes>/rep<n>` under here
+    #[serde(default = "default_output_dir")]
+    pub output_dir: PathBuf,
+    pub transports: BTreeMap<String, TransportSpec>,
+    /// Named shaping profiles; a single unshaped "baseline" if none are given
+    #[serde(default = "default_impairments")]
+    pub impairments: BTreeMap<String, Shaping>,
+    #[serde(default = "default_tick_rates")]
+    pub tick_rates: Vec<u64>,
+    #[serde(default = "default_payload_bytes")]
+    pub payload_bytes: Vec<usize>,
+    #[serde(default = "default_repetitions")]
+    pub repetitions: u32,
+    /// How long each client sends ticks for
+    #[serde(default = "default_duration_secs")]
+    pub duration_secs: u64,
+    /// Extra time past the duration, for connecting and draining, before a client is stopped
+    #[serde(default = "default_timeout_slack_secs")]
+    pub timeout_slack_secs: u64,
+    /// Pause between runs, so sockets in TIME_WAIT and queues from one run don't reach the next
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+    #[serde(default = "default_server_start_ms")]
+    pub server_start_ms: u64,
+    #[serde(default)]
+    pub topology: Topology,
+}
+
+fn default_output_dir() -> PathBuf {
+    PathBuf::from("measurements/matrix")
+}
+
+fn default_impairments() -> BTreeMap<String, Shaping> {
+    BTreeMap::from([("baseline".to_string(), Shaping::default())])
+}
+
+fn default_tick_rates() -> Vec<u64> {
+    vec![128]
+}
+
+fn default_payload_bytes() -> Vec<usize> {
+    vec![0]
+}
+
+fn default_repetitions() -> u32 {
+    1
+}
+
+fn default_duration_secs() -> u64 {
+    180
+}
+
+fn default_timeout_slack_secs() -> u64 {
+    60
+}
+
+fn default_cooldown_secs() -> u64 {
+    5
+}
+
+fn default_server_start_ms() -> u64 {
+    1000
+}
+
+/// One run of the matrix
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Cell {
+    pub profile: String,
+    pub transport: String,
+    pub tick_rate: u64,
+    pub payload_bytes: usize,
+    /// Counted from 1
+    pub repetition: u32,
+}
+
+impl Cell {
+    /// Where this run's results go, relative to the matrix output directory
+    pub fn dir(&self) -> PathBuf {
+        Path::new(&self.profile)
+            .join(&self.transport)
+            .join(format!("tick{}_payload{}", self.tick_rate, self.payload_bytes))
+            .join(format!("rep{}", self.repetition))
+    }
+}
+
+impl Matrix {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let matrix: Matrix = scenario::load(path)?;
+        if matrix.transports.is_empty() {
+            return Err(format!("{} lists no transports", path.display()));
+        }
+        if matrix.tick_rates.is_empty() || matrix.payload_bytes.is_empty() || matrix.impairments.is_empty() {
+            return Err(format!("{} has an empty tick_rates, payload_bytes or impairments list", path.display()));
+        }
+        // Catch profiles netem can't apply before the first run rather than partway through
+        let topology = &matrix.topology;
+        for (name, shaping) in &matrix.impairments {
+            for (dev, impairment) in [(&topology.client_dev, &shaping.up), (&topology.server_dev, &shaping.down)] {
+                shaping.commands(dev, impairment).map_err(|e| format!("impairment profile {}: {}", name, e))?;
+            }
+        }
+        Ok(matrix)
+    }
+
+    /// Every run, repetitions outermost so slow drift on the machine is spread across
+    /// configurations instead of landing on whichever ran last.
+    pub fn cells(&self) -> Vec<Cell> {
+        let mut cells = Vec::new();
+        for repetition in 1..=self.repetitions {
+            for profile in self.impairments.keys() {
+                for transport in self.transports.keys() {
+                    for &tick_rate in &self.tick_rates {
+                        for &payload_bytes in &self.payload_bytes {
+                            cells.push(Cell {
+                                profile: profile.clone(),
+                                transport: transport.clone(),
+                                tick_rate,
+                                payload_bytes,
+                                repetition,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    pub fn scenario(&self, cell: &Cell) -> Scenario {
+        let transport = &self.transports[&cell.transport];
+        let mut server = transport.server.clone();
+        server.command.extend(["--tick-rate".to_string(), cell.tick_rate.to_string()]);
+        let mut client = transport.client.clone();
+        client.command.extend([
+            "--tick-rate".to_string(),
+            cell.tick_rate.to_string(),
+            "--simulation-duration-secs".to_string(),
+            self.duration_secs.to_string(),
+            "--payload-bytes".to_string(),
+            cell.payload_bytes.to_string(),
+        ]);
+        Scenario {
+            name: format!("{}/{}", self.name, cell.dir().display()),
+            topology: self.topology.clone(),
+            shaping: self.impairments[&cell.profile].clone(),
+            server,
+            client,
+            server_start_ms: self.server_start_ms,
+            timeout_secs: Some(self.duration_secs + self.timeout_slack_secs),
+        }
+    }
+
+    /// Rough wall-clock time for the whole matrix
+    pub fn estimated_secs(&self) -> u64 {
+        let per_run = self.duration_secs + self.cooldown_secs + self.server_start_ms / 1000;
+        self.cells().len() as u64 * per_run
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const MATRIX: &str = r#"
+name = "test"
+tick_rates = [128, 32]
+payload_bytes = [0, 100]
+repetitions = 2
+duration_secs = 60
+
+[impairments.lossy]
+up = "loss=1%"
+
+[impairments.slow]
+down = "delay=20ms"
+
+[transports.websocket]
+server = { command = ["server", "--summary-file", "{output_dir}/summary.json"] }
+client = { command = ["client", "--url", "wss://{server_ip}:4043"] }
+
+[transports.udp]
+server = { command = ["udp_server"] }
+client = { command = ["udp_client"] }
+"#;
+
+    fn matrix() -> Matrix {
+        toml::from_str(MATRIX).unwrap()
+    }
+
+    // Load `text` from a file of its own, as ons-run would
+    fn load(name: &str, text: &str) -> Result<Matrix, String> {
+        let path = std::env::temp_dir().join(format!("ons_harness_{}_{}.toml", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        let matrix = Matrix::load(&path);
+        fs::remove_file(&path).unwrap();
+        matrix
+    }
+
+    #[test]
+    fn cells_cover_every_combination_with_repetitions_outermost() {
+        let cells = matrix().cells();
+        assert_eq!(cells.len(), 2 * 2 * 2 * 2 * 2);
+        let first: Vec<_> = cells[..3].iter().map(|c| c.dir().display().to_string()).collect();
+        assert_eq!(first, ["lossy/udp/tick128_payload0/rep1", "lossy/udp/tick128_payload100/rep1", "lossy/udp/tick32_payload0/rep1"]);
+        // Every configuration runs once before any runs a second time
+        assert!(cells[..16].iter().all(|c| c.repetition == 1));
+        assert!(cells[16..].iter().all(|c| c.repetition == 2));
+        assert_eq!(cells[16].dir(), Path::new("lossy/udp/tick128_payload0/rep2"));
+    }
+
+    #[test]
+    fn scenarios_append_the_cell_to_each_command() {
+        let matrix = matrix();
+        let cell = Cell { profile: "slow".to_string(), transport: "websocket".to_string(), tick_rate: 32, payload_bytes: 100, repetition: 2 };
+        let scenario = matrix.scenario(&cell);
+        assert_eq!(scenario.name, "test/slow/websocket/tick32_payload100/rep2");
+        assert_eq!(scenario.server.command, ["server", "--summary-file", "{output_dir}/summary.json", "--tick-rate", "32"]);
+        assert_eq!(
+            scenario.client.command,
+            ["client", "--url", "wss://{server_ip}:4043", "--tick-rate", "32", "--simulation-duration-secs", "60", "--payload-bytes", "100"]
+        );
+        assert_eq!(scenario.shaping, matrix.impairments["slow"]);
+        assert_eq!(scenario.timeout_secs, Some(60 + default_timeout_slack_secs()));
+    }
+
+    #[test]
+    fn load_accepts_a_complete_matrix() {
+        assert_eq!(load("complete", MATRIX).unwrap(), matrix());
+    }
+
+    #[test]
+    fn load_rejects_empty_lists_and_unknown_fields() {
+        let no_transports = MATRIX.split("[transports.websocket]").next().unwrap().to_string() + "[transports]\n";
+        assert!(load("no_transports", &no_transports).unwrap_err().contains("lists no transports"));
+        let no_rates = MATRIX.replace("tick_rates = [128, 32]", "tick_rates = []");
+        assert!(load("no_rates", &no_rates).unwrap_err().contains("empty tick_rates"));
+        let typo = MATRIX.replace("repetitions", "repetition");
+        assert!(load("typo", &typo).unwrap_err().contains("unknown field"));
+        let bad_spec = MATRIX.replace("loss=1%", "loss=lots");
+        assert!(load("bad_spec", &bad_spec).unwrap_err().contains("invalid"));
+    }
+
+    #[test]
+    fn load_rejects_profiles_netem_cannot_apply() {
+        let samples = std::env::temp_dir().join(format!("ons_harness_{}_samples.csv", std::process::id()));
+        fs::write(&samples, "1.5\n2.5\n").unwrap();
+        let proxy_only = MATRIX.replace("loss=1%", &format!("delay-samples={}", samples.display()));
+        let error = load("proxy_only", &proxy_only).unwrap_err();
+        fs::remove_file(&samples).unwrap();
+        assert!(error.starts_with("impairment profile lossy:"), "{}", error);
+    }
+}
@@ -0,0 +1,152 @@
+// A client and a server network namespace joined by a veth pair, so both ends of a
+// benchmark go through the real kernel stack on one machine.
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Topology {
+    pub client_ns: String,
+    pub server_ns: String,
+    /// veth end in the client namespace; its egress is the client-to-server direction
+    pub client_dev: String,
+    /// veth end in the server namespace; its egress is the server-to-client direction
+    pub server_dev: String,
+    /// With prefix length, e.g. 10.77.0.1/24
+    pub client_addr: String,
+    pub server_addr: String,
+    /// Name the client namespace resolves to the server address, for certificates
+    /// issued to a name rather than an address; `{server_name}` in commands
+    pub server_name: Option<String>,
+    pub mtu: Option<u32>,
+    /// Turn off TSO, GSO and GRO on both ends, so netem sees wire-sized packets
+    /// rather than 64 KB aggregates (what websockets/offloadtoggle.sh does on the NIC)
+    pub disable_offloads: bool,
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Topology {
+            client_ns: "ons_client".to_string(),
+            server_ns: "ons_server".to_string(),
+            client_dev: "ons_c0".to_string(),
+            server_dev: "ons_s0".to_string(),
+            client_addr: "10.77.0.1/24".to_string(),
+            server_addr: "10.77.0.2/24".to_string(),
+            server_name: None,
+            mtu: None,
+            disable_offloads: true,
+        }
+    }
+}
+
+impl Topology {
+    pub fn client_ip(&self) -> &str {
+        self.client_addr.split('/').next().unwrap_or(&self.client_addr)
+    }
+
+    pub fn server_ip(&self) -> &str {
+        self.server_addr.split('/').next().unwrap_or(&self.server_addr)
+    }
+
+    /// What the client should connect to: the server name if there is one, else its address
+    pub fn server_name(&self) -> &str {
+        self.server_name.as_deref().unwrap_or_else(|| self.server_ip())
+    }
+}
+
+// `ip netns exec` bind-mounts files in /etc/netns/<ns> over their /etc counterparts
+fn hosts_file(ns: &str) -> PathBuf {
+    PathBuf::from("/etc/netns").join(ns).join("hosts")
+}
+
+/// The namespaces and veth pair while they exist. Dropping it deletes the namespaces,
+/// which takes the veth pair and any processes' network with them.
+pub struct Network {
+    pub topology: Topology,
+}
+
+impl Network {
+    /// Build the topology, first removing anything left over from an interrupted run.
+    pub fn create(topology: &Topology) -> io::Result<Self> {
+        remove(topology);
+        let network = Network { topology: topology.clone() };
+        let t = &network.topology;
+        run(&["ip", "netns", "add", &t.client_ns])?;
+        run(&["ip", "netns", "add", &t.server_ns])?;
+        run(&["ip", "link", "add", &t.client_dev, "type", "veth", "peer", "name", &t.server_dev])?;
+        run(&["ip", "link", "set", &t.client_dev, "netns", &t.client_ns])?;
+        run(&["ip", "link", "set", &t.server_dev, "netns", &t.server_ns])?;
+
+        for (ns, dev, addr) in [(&t.client_ns, &t.client_dev, &t.client_addr), (&t.server_ns, &t.server_dev, &t.server_addr)] {
+            run(&["ip", "-n", ns, "addr", "add", addr, "dev", dev])?;
+            if let Some(mtu) = t.mtu {
+                run(&["ip", "-n", ns, "link", "set", dev, "mtu", &mtu.to_string()])?;
+            }
+            run(&["ip", "-n", ns, "link", "set", dev, "up"])?;
+            run(&["ip", "-n", ns, "link", "set", "lo", "up"])?;
+            if t.disable_offloads {
+                // veth has no real offload hardware; this only stops the stack aggregating
+                if let Err(e) = run(&["ip", "netns", "exec", ns, "ethtool", "-K", dev, "tso", "off", "gso", "off", "gro", "off"]) {
+                    eprintln!("Could not disable offloads on {}: {}", dev, e);
+                }
+            }
+        }
+        if let Some(name) = &t.server_name {
+            let hosts = hosts_file(&t.client_ns);
+            fs::create_dir_all(hosts.parent().unwrap())?;
+            fs::write(&hosts, format!("127.0.0.1 localhost\n{} {}\n", t.server_ip(), name))?;
+        }
+        println!(
+            "Namespaces up: {} ({} {}) <-> {} ({} {})",
+            t.client_ns, t.client_dev, t.client_addr, t.server_ns, t.server_dev, t.server_addr
+        );
+        Ok(network)
+    }
+
+    /// Run a command inside one of the namespaces and return what it printed.
+    pub fn output_in(&self, ns: &str, args: &[&str]) -> io::Result<String> {
+        let output = Command::new("ip").args(["netns", "exec", ns]).args(args).output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "`{}` in {} failed: {}",
+                args.join(" "),
+                ns,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        remove(&self.topology);
+        println!("Namespaces {} and {} removed", self.topology.client_ns, self.topology.server_ns);
+    }
+}
+
+/// Delete the namespaces if they exist. Deleting either end's namespace removes the pair.
+fn remove(topology: &Topology) {
+    for ns in [&topology.client_ns, &topology.server_ns] {
+        let _ = Command::new("ip").args(["netns", "del", ns]).stderr(std::process::Stdio::null()).status();
+    }
+    if topology.server_name.is_some() {
+        let hosts = hosts_file(&topology.client_ns);
+        let _ = fs::remove_file(&hosts);
+        // Only if nothing else was put there
+        let _ = fs::remove_dir(hosts.parent().unwrap());
+    }
+}
+
+/// Run a command to completion, failing if it exits unsuccessfully.
+pub fn run(args: &[&str]) -> io::Result<()> {
+    let status = Command::new(args[0]).args(&args[1..]).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("`{}` failed with {}", args.join(" "), status)));
+    }
+    Ok(())
+}
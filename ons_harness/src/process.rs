@@ -0,0 +1,101 @@
+// Benchmark servers and clients launched inside a namespace, with their output in log files.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+/// A command to run, e.g. `["../websocket_rust/target/release/server"]`.
+///
+/// `{server_ip}`, `{server_name}`, `{client_ip}` and `{output_dir}` in arguments and
+/// `dir` are replaced before running. Prefer built binaries to `cargo run`: the
+/// namespaces are created as root, and cargo would build as root inside the timed run.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessSpec {
+    pub command: Vec<String>,
+    /// Working directory, where the benchmarks write their fixed-name result files;
+    /// defaults to the output directory. A relative program path is taken from where
+    /// the harness was started, not from here.
+    #[serde(default)]
+    pub dir: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl ProcessSpec {
+    pub fn args(&self, vars: &[(&str, &str)]) -> Vec<String> {
+        self.command.iter().map(|arg| substitute(arg, vars)).collect()
+    }
+
+    /// Start the command in namespace `ns`, with stdout and stderr going to `log`.
+    pub fn spawn(&self, ns: &str, vars: &[(&str, &str)], default_dir: &Path, log: &Path) -> io::Result<Child> {
+        let mut args = self.args(vars);
+        let Some(program) = args.first_mut() else {
+            return Err(io::Error::other("empty command"));
+        };
+        if program.contains('/') && Path::new(program).is_relative() {
+            *program = std::env::current_dir()?.join(&*program).to_string_lossy().into_owned();
+        }
+        let out = File::create(log)?;
+        let mut command = Command::new("ip");
+        // ip execs the command, so the child's pid is the benchmark's own
+        command.args(["netns", "exec", ns]).args(&args).envs(&self.env);
+        command.stdin(Stdio::null()).stdout(out.try_clone()?).stderr(out);
+        let dir = self.dir.as_ref().map_or_else(|| default_dir.to_path_buf(), |dir| PathBuf::from(substitute(dir, vars)));
+        command.current_dir(dir);
+        println!("[{}] {} (output in {})", ns, args.join(" "), log.display());
+        command.spawn()
+    }
+}
+
+fn substitute(value: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter().fold(value.to_string(), |value, (name, var)| value.replace(&format!("{{{}}}", name), var))
+}
+
+/// Ask a process to exit with SIGINT, on which the benchmark binaries stop their tick
+/// loop and save their results, and kill it if it hasn't exited after `grace`.
+pub fn stop(child: &mut Child, grace: Duration) -> io::Result<ExitStatus> {
+    if let Some(status) = child.try_wait()? {
+        return Ok(status);
+    }
+    Command::new("kill").args(["-INT", &child.id().to_string()]).status()?;
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    eprintln!("Process {} ignored SIGINT for {:?}; killing it", child.id(), grace);
+    child.kill()?;
+    child.wait()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_replaced_wherever_they_appear() {
+        let vars = [("server_ip", "10.77.0.2"), ("server_name", "signallite.io"), ("output_dir", "/tmp/run")];
+        assert_eq!(substitute("wss://{server_name}:4043", &vars), "wss://signallite.io:4043");
+        assert_eq!(substitute("{server_ip}:4444", &vars), "10.77.0.2:4444");
+        assert_eq!(substitute("{output_dir}/{server_ip}.json", &vars), "/tmp/run/10.77.0.2.json");
+        // Unknown placeholders and other braces are left alone
+        assert_eq!(substitute("{client_ip} {x}", &vars), "{client_ip} {x}");
+    }
+
+    #[test]
+    fn args_substitute_every_argument() {
+        let spec = ProcessSpec {
+            command: vec!["client".to_string(), "--server".to_string(), "{server_ip}:4444".to_string()],
+            dir: None,
+            env: BTreeMap::new(),
+        };
+        assert_eq!(spec.args(&[("server_ip", "10.77.0.2")]), ["client", "--server", "10.77.0.2:4444"]);
+    }
+}
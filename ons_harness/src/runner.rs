@@ -0,0 +1,105 @@
+// One scenario run: build the topology, start the server, run the client to the end,
+// and record what happened in netns_summary.json.
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde_json::{json, Value};
+use crate::netns::Network;
+use crate::process::stop;
+use crate::scenario::Scenario;
+use crate::shaping::Shaping;
+
+/// How long a server or client gets to exit after SIGINT before it is killed
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+pub const SUMMARY_FILE: &str = "netns_summary.json";
+
+pub fn unix_secs() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+/// A flag set on Ctrl-C, so a run can stop its processes and tear down cleanly.
+pub fn interrupt_flag() -> Result<Arc<AtomicBool>, ctrlc::Error> {
+    let flag = Arc::new(AtomicBool::new(false));
+    let handler = flag.clone();
+    ctrlc::set_handler(move || handler.store(true, Ordering::SeqCst))?;
+    Ok(flag)
+}
+
+/// Run `scenario` with its logs and results in `output_dir`, returning the summary it
+/// saved there. Stops early, still tearing down, once `interrupted` is set.
+pub fn run_scenario(scenario: &Scenario, output_dir: &Path, interrupted: &AtomicBool) -> Result<Value, Box<dyn Error>> {
+    fs::create_dir_all(output_dir)?;
+    let output_dir = output_dir.canonicalize()?;
+    let started = unix_secs();
+
+    let network = Network::create(&scenario.topology)?;
+    let tc_commands = scenario.shaping.apply(&network)?;
+    let t = &network.topology;
+    let output = output_dir.to_string_lossy();
+    let vars = [
+        ("server_ip", t.server_ip()),
+        ("server_name", t.server_name()),
+        ("client_ip", t.client_ip()),
+        ("output_dir", output.as_ref()),
+    ];
+
+    let mut server = scenario.server.spawn(&t.server_ns, &vars, &output_dir, &output_dir.join("server.log"))?;
+    thread::sleep(Duration::from_millis(scenario.server_start_ms));
+    if let Some(status) = server.try_wait()? {
+        return Err(format!("server exited early with {}; see {}", status, output_dir.join("server.log").display()).into());
+    }
+
+    let mut client = match scenario.client.spawn(&t.client_ns, &vars, &output_dir, &output_dir.join("client.log")) {
+        Ok(client) => client,
+        Err(e) => {
+            stop(&mut server, STOP_GRACE)?;
+            return Err(e.into());
+        }
+    };
+    let client_started = Instant::now();
+    let timeout = scenario.timeout_secs.map(Duration::from_secs);
+    let mut timed_out = false;
+    while client.try_wait()?.is_none() {
+        if interrupted.load(Ordering::SeqCst) {
+            println!("Interrupted; stopping the client");
+            break;
+        }
+        if let Some(timeout) = timeout.filter(|timeout| client_started.elapsed() >= *timeout) {
+            println!("Client still running after {:?}; stopping it", timeout);
+            timed_out = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let client_status = stop(&mut client, STOP_GRACE)?;
+    let server_status = stop(&mut server, STOP_GRACE)?;
+    println!("Client exited with {}, server with {}", client_status, server_status);
+
+    let summary = json!({
+        "scenario": scenario,
+        "server_command": scenario.server.args(&vars),
+        "client_command": scenario.client.args(&vars),
+        "tc_commands": tc_commands,
+        "qdisc_stats": Shaping::stats(&network)?,
+        "client_exit": client_status.code(),
+        "server_exit": server_status.code(),
+        // A client stopped at the timeout saves what it has and exits cleanly, but its
+        // results only cover part of the run
+        "client_ok": client_status.success() && !timed_out,
+        "timed_out": timed_out,
+        "interrupted": interrupted.load(Ordering::SeqCst),
+        "client_secs": client_started.elapsed().as_secs_f64(),
+        "started_unix": started,
+        "finished_unix": unix_secs(),
+    });
+    drop(network);
+    let summary_file = output_dir.join(SUMMARY_FILE);
+    fs::write(&summary_file, serde_json::to_string_pretty(&summary)?)?;
+    println!("Summary saved to {}", summary_file.display());
+    Ok(summary)
+}
@@ -0,0 +1,46 @@
+// A scenario file: the topology, its shaping, and the server and client to run.
+use std::fs;
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::netns::Topology;
+use crate::process::ProcessSpec;
+use crate::shaping::Shaping;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    #[serde(default)]
+    pub topology: Topology,
+    #[serde(default)]
+    pub shaping: Shaping,
+    pub server: ProcessSpec,
+    pub client: ProcessSpec,
+    /// Time the server gets to start listening before the client is launched
+    #[serde(default = "default_server_start_ms")]
+    pub server_start_ms: u64,
+    /// Stop the client if it is still running after this long
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+fn default_server_start_ms() -> u64 {
+    1000
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        load(path)
+    }
+}
+
+/// Read TOML, or YAML if the file ends in .yaml or .yml.
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let parsed = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(|e| e.to_string()),
+        _ => toml::from_str(&text).map_err(|e| e.to_string()),
+    };
+    parsed.map_err(|e| format!("invalid {}: {}", path.display(), e))
+}
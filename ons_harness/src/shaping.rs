@@ -0,0 +1,144 @@
+// tc shaping for each veth end: netem for loss, delay, reordering and duplication,
+// with tbf underneath it for the bottleneck rate. Impairments are written in
+// netem_proxy's spec format, so the same profile works through the proxy or here.
+use std::io;
+use netem_proxy::impairment::Impairment;
+use serde::{Deserialize, Serialize};
+use crate::netns::{run, Network};
+
+/// Default queueing latency before tbf drops, as tc's own examples use
+const TBF_LATENCY: &str = "50ms";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Shaping {
+    /// Client-to-server impairments, e.g. "loss=0.1%,delay=20ms,rate=10mbit"
+    #[serde(with = "spec")]
+    pub up: Impairment,
+    /// Server-to-client impairments
+    #[serde(with = "spec")]
+    pub down: Impairment,
+    /// tbf bucket size, e.g. "32kb"; defaults to 5ms at the rate, and at least two packets
+    pub tbf_burst: Option<String>,
+    /// How long packets may wait in tbf before being dropped; defaults to 50ms
+    pub tbf_latency: Option<String>,
+}
+
+impl Shaping {
+    /// The tc commands shaping one direction's egress device, without the namespace prefix.
+    pub fn commands(&self, dev: &str, impairment: &Impairment) -> Result<Vec<Vec<String>>, String> {
+        let mut commands = Vec::new();
+        let rate = impairment.rate_bps;
+        let netem = *impairment != Impairment { rate_bps: rate, ..Impairment::default() };
+        let qdisc = |rest: &[&str]| -> Vec<String> {
+            ["tc", "qdisc", "add", "dev", dev].iter().chain(rest).map(|s| s.to_string()).collect()
+        };
+        if netem {
+            let mut command = qdisc(&["root", "handle", "1:", "netem"]);
+            command.extend(impairment.netem_args()?);
+            commands.push(command);
+        }
+        if let Some(rate) = rate {
+            let parent: &[&str] = if netem { &["parent", "1:1", "handle", "10:"] } else { &["root", "handle", "10:"] };
+            let burst = self.tbf_burst.clone().unwrap_or_else(|| format!("{}b", (rate / 8 / 200).max(3028)));
+            let latency = self.tbf_latency.as_deref().unwrap_or(TBF_LATENCY);
+            let mut command = qdisc(parent);
+            command.extend(["tbf", "rate", &format!("{}bit", rate), "burst", &burst, "latency", latency].map(String::from));
+            commands.push(command);
+        }
+        Ok(commands)
+    }
+
+    /// Shape both veth ends and return the commands run, for the run summary.
+    pub fn apply(&self, network: &Network) -> io::Result<Vec<String>> {
+        let t = &network.topology;
+        let mut applied = Vec::new();
+        for (ns, dev, impairment) in [(&t.client_ns, &t.client_dev, &self.up), (&t.server_ns, &t.server_dev, &self.down)] {
+            for command in self.commands(dev, impairment).map_err(io::Error::other)? {
+                let mut args = vec!["ip", "netns", "exec", ns];
+                args.extend(command.iter().map(String::as_str));
+                run(&args)?;
+                applied.push(args.join(" "));
+            }
+        }
+        Ok(applied)
+    }
+
+    /// `tc -s qdisc show` for both ends, with netem and tbf's drop and backlog counters.
+    pub fn stats(network: &Network) -> io::Result<serde_json::Value> {
+        let t = &network.topology;
+        let show = |ns: &str, dev: &str| network.output_in(ns, &["tc", "-s", "qdisc", "show", "dev", dev]);
+        Ok(serde_json::json!({
+            "up": show(&t.client_ns, &t.client_dev)?,
+            "down": show(&t.server_ns, &t.server_dev)?,
+        }))
+    }
+}
+
+/// Impairments as their spec strings, so scenario files and summaries stay readable
+pub mod spec {
+    use netem_proxy::impairment::Impairment;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(impairment: &Impairment, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(impairment)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Impairment, D::Error> {
+        let spec = String::deserialize(deserializer)?;
+        spec.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(shaping: &Shaping, dev: &str, spec: &str) -> Vec<String> {
+        let impairment: Impairment = spec.parse().unwrap();
+        shaping.commands(dev, &impairment).unwrap().iter().map(|command| command.join(" ")).collect()
+    }
+
+    #[test]
+    fn netem_impairments_share_one_root_qdisc() {
+        assert_eq!(
+            commands(&Shaping::default(), "ons_c0", "loss=1%,delay=4ms,jitter=1ms,distribution=pareto"),
+            ["tc qdisc add dev ons_c0 root handle 1: netem limit 1000 delay 4ms 1ms distribution pareto loss random 1%"]
+        );
+        assert_eq!(
+            commands(&Shaping::default(), "ons_s0", "gemodel=1%/25%,duplicate=2%,limit=50"),
+            ["tc qdisc add dev ons_s0 root handle 1: netem limit 50 loss gemodel 1% 25% 100% 0% duplicate 2%"]
+        );
+    }
+
+    #[test]
+    fn a_rate_alone_is_a_root_tbf() {
+        // 5ms at 10mbit is 6250 bytes
+        assert_eq!(
+            commands(&Shaping::default(), "ons_s0", "rate=10mbit"),
+            ["tc qdisc add dev ons_s0 root handle 10: tbf rate 10000000bit burst 6250b latency 50ms"]
+        );
+        // but never less than two full-size packets
+        assert_eq!(
+            commands(&Shaping::default(), "ons_s0", "rate=1mbit"),
+            ["tc qdisc add dev ons_s0 root handle 10: tbf rate 1000000bit burst 3028b latency 50ms"]
+        );
+    }
+
+    #[test]
+    fn a_rate_with_netem_goes_underneath_it() {
+        let shaping = Shaping { tbf_burst: Some("32kb".to_string()), tbf_latency: Some("20ms".to_string()), ..Shaping::default() };
+        assert_eq!(
+            commands(&shaping, "ons_c0", "delay=10ms,rate=20mbit"),
+            [
+                "tc qdisc add dev ons_c0 root handle 1: netem limit 1000 delay 10ms",
+                "tc qdisc add dev ons_c0 parent 1:1 handle 10: tbf rate 20000000bit burst 32kb latency 20ms",
+            ]
+        );
+    }
+
+    #[test]
+    fn no_impairment_adds_no_qdisc() {
+        assert!(commands(&Shaping::default(), "ons_c0", "none").is_empty());
+    }
+}
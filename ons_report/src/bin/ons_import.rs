@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use clap::Parser;
+use ons_report::legacy::{self, Session};
+
+/// Import the output of the Python clients into the result layout the Rust clients write.
+///
+/// Each input is a log in the format of sessions.txt (sections titled like "WEBSOCKETS NO
+/// NAGLE, WITH OFFLOAD:", sessions marked "#N") or the captured output of a single run of
+/// webtransport/client.py or websockets/websocket_client.py. Every session becomes
+/// <output-dir>/<implementation>/<transport>/<nagle>/<offload>/repN holding a
+/// <transport>_summary.json, so ons-report reads it like any other run. The Python clients
+/// only print the mean RTT unless they were run with --rtt-file; give that file here to
+/// import the per-echo RTTs too.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    #[arg(long, default_value = "legacy")]
+    output_dir: PathBuf,
+
+    /// The client's --rtt-file, for an input holding a single session
+    #[arg(long)]
+    rtt_file: Option<PathBuf>,
+
+    /// Implementation name for sessions whose command line (and so interpreter) wasn't recorded
+    #[arg(long, default_value = "python")]
+    implementation: String,
+
+    /// Whether NIC offloads were on, for sessions whose section title doesn't say
+    #[arg(long)]
+    offload: Option<bool>,
+
+    /// Whether Nagle's algorithm was on, for WebSocket sessions that don't say
+    #[arg(long)]
+    nagle: Option<bool>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let mut imported: Vec<(PathBuf, Session)> = Vec::new();
+    for input in &args.inputs {
+        let text = fs::read_to_string(input).map_err(|e| format!("could not read {}: {}", input.display(), e))?;
+        let sessions = legacy::parse(&text);
+        if sessions.is_empty() {
+            return Err(format!("no Python client output in {}", input.display()).into());
+        }
+        imported.extend(sessions.into_iter().map(|session| (input.clone(), session)));
+    }
+    let rtts = match &args.rtt_file {
+        Some(_) if imported.len() > 1 => {
+            return Err(format!("--rtt-file needs a single session, found {}", imported.len()).into());
+        }
+        Some(path) => Some(legacy::read_rtt_file(path)?),
+        None => None,
+    };
+
+    let mut written = HashSet::new();
+    for (source, mut session) in imported {
+        session.offload = session.offload.or(args.offload);
+        if session.transport == Some("websocket") {
+            session.nagle = session.nagle.or(args.nagle);
+        }
+        let Some(transport) = session.transport else {
+            eprintln!("Skipping a session in {}: could not tell which client produced it", source.display());
+            continue;
+        };
+        let implementation = session.interpreter.clone().unwrap_or_else(|| args.implementation.clone());
+        let mut variant_dir = args.output_dir.join(implementation).join(transport);
+        variant_dir.extend(session.variant());
+        let dir = repetition_dir(&variant_dir, session.number, &written);
+        let path = legacy::write_run(&session, rtts.as_deref(), &source, &dir)?;
+        println!(
+            "{} {}#{}: {} messages, mean RTT {} ms -> {}",
+            source.display(),
+            session.section.as_deref().map_or(String::new(), |s| format!("{} ", s)),
+            session.number.map_or("-".to_string(), |n| n.to_string()),
+            session.messages.map_or("?".to_string(), |n| n.to_string()),
+            session.average_rtt_ms.map_or("?".to_string(), |ms| ms.to_string()),
+            path.display()
+        );
+        written.insert(dir);
+    }
+    println!("Compare with: ons-report {} <rust results>", args.output_dir.display());
+    Ok(())
+}
+
+/// repN for the session's own number, unless this import already wrote it (two sections
+/// with the same settings), or the first free one for a session without a number
+fn repetition_dir(variant_dir: &Path, number: Option<u32>, written: &HashSet<PathBuf>) -> PathBuf {
+    let dir = |n: u32| variant_dir.join(format!("rep{}", n));
+    if let Some(n) = number.filter(|&n| !written.contains(&dir(n))) {
+        return dir(n);
+    }
+    (1..).map(dir).find(|d| !written.contains(d) && !d.exists()).expect("some repetition number is free")
+}
@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+use clap::Parser;
+use ons_report::report::{self, ReportOptions, REPORT_FILE};
+use ons_report::runs;
+
+/// Compare benchmark runs across transports, configurations and repetitions.
+///
+/// Each input is searched for run directories (ones holding a client's
+/// <transport>_summary.json and <transport>_measurements.csv). Runs whose paths differ
+/// only in a repN directory are repetitions of one configuration, so an ons-run matrix
+/// directory can be given as it is. The report is written as report.md and report.json,
+/// with the plots as SVG beside them.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Directories to search, or LABEL=DIR to put LABEL in front of the names of the runs found under DIR
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    #[arg(long, default_value = "report")]
+    output_dir: PathBuf,
+
+    #[arg(long, default_value = "Transport comparison")]
+    title: String,
+
+    /// Compare every configuration against this one; by default each pair whose names differ in one part is compared
+    #[arg(long)]
+    baseline: Option<String>,
+
+    /// Bootstrap iterations for the p99 intervals; 0 skips them
+    #[arg(long, default_value = "1000")]
+    bootstrap: usize,
+
+    /// Seed for the bootstrap, so a report can be regenerated exactly
+    #[arg(long, default_value = "1")]
+    seed: u64,
+
+    /// Width of the time bins in the latency-over-time plots, in milliseconds
+    #[arg(long, default_value = "1000")]
+    bin_ms: u64,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let dirs = runs::discover(&args.inputs)?;
+    let mut loaded = Vec::new();
+    for dir in &dirs {
+        let run = dir.load()?;
+        println!("{}: {} ({} echoes)", run.group, run.dir.display(), run.rtts_us.len());
+        loaded.push(run);
+    }
+
+    let options = ReportOptions {
+        title: args.title,
+        baseline: args.baseline,
+        bootstrap: args.bootstrap,
+        seed: args.seed,
+        bin_ms: args.bin_ms.max(1),
+    };
+    let report = report::build(&loaded, &options, &args.output_dir)?;
+    println!(
+        "{} configurations, {} comparisons; report written to {}",
+        report.groups.len(),
+        report.comparisons.len(),
+        args.output_dir.join(REPORT_FILE).display()
+    );
+    Ok(())
+}
@@ -0,0 +1,334 @@
+// The output of the original Python clients (webtransport/client.py and
+// websockets/websocket_client.py), either pasted into a log like sessions.txt or
+// captured from one run, turned into the result files the Rust clients write so
+// ons-report can put both implementations side by side.
+//
+// A log is free text: sections start after a line of '=' with a title such as
+// "WEBSOCKETS NO NAGLE, WITH OFFLOAD:", sessions start at "#N", and each session is
+// the shell command (when it was pasted) followed by what the client printed.
+use std::fs;
+use std::path::{Path, PathBuf};
+use ons_common::histogram::LatencyHistogram;
+use serde_json::{json, Value};
+
+/// Smoothing factor of the EWMA the Python clients print
+pub const EWMA_ALPHA: f64 = 0.1;
+
+/// One run of a Python client
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// Title of the section of the log the session is in, without the colon
+    pub section: Option<String>,
+    /// The N of "#N"
+    pub number: Option<u32>,
+    pub command: Option<String>,
+    /// The interpreter the client ran under, e.g. `pypy3.10`
+    pub interpreter: Option<String>,
+    pub transport: Option<&'static str>,
+    pub nagle: Option<bool>,
+    /// Whether NIC offloads (TSO/GSO/GRO) were on, which only the section titles say
+    pub offload: Option<bool>,
+    pub messages: Option<u64>,
+    pub messages_per_second: Option<f64>,
+    pub average_rtt_ms: Option<f64>,
+    pub ewma_rtt_ms: Option<f64>,
+}
+
+impl Session {
+    fn has_results(&self) -> bool {
+        self.messages.is_some() || self.average_rtt_ms.is_some()
+    }
+
+    fn new(section: &Option<String>, number: Option<u32>) -> Self {
+        Session { section: section.clone(), number, ..Default::default() }
+    }
+
+    /// Fill in what the client didn't print from the command line and the section title
+    fn complete(mut self) -> Self {
+        if let Some(command) = &self.command {
+            if let Some(first) = command.split_whitespace().next().filter(|first| !first.ends_with(".py")) {
+                self.interpreter = Some(first.rsplit('/').next().unwrap_or(first).to_string());
+            }
+            if self.transport.is_none() {
+                self.transport = script_transport(command);
+            }
+            if self.transport == Some("websocket") && self.nagle.is_none() {
+                self.nagle = Some(!command.split_whitespace().any(|arg| arg == "--disable-nagling"));
+            }
+        }
+        if let Some(title) = self.section.as_deref().map(str::to_uppercase) {
+            if self.transport.is_none() {
+                if title.contains("WEBTRANSPORT") {
+                    self.transport = Some("webtransport");
+                } else if title.contains("WEBSOCKET") {
+                    self.transport = Some("websocket");
+                }
+            }
+            if self.nagle.is_none() {
+                self.nagle = title_flag(&title, "NAGLE");
+            }
+            if self.offload.is_none() {
+                self.offload = title_flag(&title, "OFFLOAD");
+            }
+        }
+        self
+    }
+
+    /// Directories for the socket options the session ran with, one per option, e.g.
+    /// `no_nagle/offload`, so runs that differ in one option differ in one path component
+    pub fn variant(&self) -> Vec<&'static str> {
+        let mut parts = Vec::new();
+        match self.nagle {
+            Some(true) => parts.push("nagle"),
+            Some(false) => parts.push("no_nagle"),
+            None => (),
+        }
+        match self.offload {
+            Some(true) => parts.push("offload"),
+            Some(false) => parts.push("no_offload"),
+            None => (),
+        }
+        if parts.is_empty() {
+            parts.push("default");
+        }
+        parts
+    }
+
+    /// The session in the shape of a Rust client's summary. With the per-echo RTTs the
+    /// percentiles are real; without them only the count and mean are known.
+    pub fn summary(&self, rtts: Option<&LatencyHistogram>, source: &Path) -> Value {
+        let rtt = match rtts {
+            Some(hist) if !hist.is_empty() => hist.summary(),
+            _ => json!({
+                "count": self.messages,
+                "mean": self.average_rtt_ms.map(|ms| ms * 1e3),
+            }),
+        };
+        json!({
+            "sample_count": rtt["count"],
+            "workload": {
+                // The Python clients send the next message when the last one is echoed
+                "tick_rate": null,
+                "closed_loop": true,
+                "messages": self.messages,
+                "messages_per_second": self.messages_per_second,
+            },
+            "metrics": { "rtt": rtt },
+            "legacy": {
+                "source": source,
+                "section": self.section,
+                "session": self.number,
+                "command": self.command,
+                "interpreter": self.interpreter,
+                "nagle": self.nagle,
+                "offload": self.offload,
+                "average_rtt_ms": self.average_rtt_ms,
+                "ewma_rtt_ms": self.ewma_rtt_ms,
+                "ewma_alpha": EWMA_ALPHA,
+            },
+        })
+    }
+}
+
+/// Which client a command line ran
+fn script_transport(command: &str) -> Option<&'static str> {
+    let script = command.split_whitespace().find(|arg| arg.ends_with(".py"))?;
+    let script = script.rsplit('/').next().unwrap_or(script);
+    match script {
+        "websocket_client.py" => Some("websocket"),
+        "client.py" => Some("webtransport"),
+        _ => None,
+    }
+}
+
+/// "NO NAGLE" / "WITHOUT NAGLE" -> false, "WITH NAGLE" / "NAGLE" -> true
+fn title_flag(title: &str, name: &str) -> Option<bool> {
+    if !title.contains(name) {
+        return None;
+    }
+    Some(!(title.contains(&format!("NO {}", name)) || title.contains(&format!("WITHOUT {}", name))))
+}
+
+fn value<T: std::str::FromStr>(line: &str, prefix: &str) -> Option<T> {
+    line.strip_prefix(prefix)?.trim().trim_end_matches("ms").trim().parse().ok()
+}
+
+/// Every session in a log that got as far as printing results. Text that is neither a
+/// command, a client's output nor a marker (stray prompts, notes) is skipped.
+pub fn parse(text: &str) -> Vec<Session> {
+    let mut sessions = Vec::new();
+    let mut section: Option<String> = None;
+    let mut expect_title = false;
+    let mut current: Option<Session> = None;
+    let mut finish = |current: &mut Option<Session>| {
+        if let Some(session) = current.take().filter(Session::has_results) {
+            sessions.push(session.complete());
+        }
+    };
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            continue;
+        }
+        if line.len() >= 3 && line.chars().all(|c| c == '=') {
+            finish(&mut current);
+            expect_title = true;
+            continue;
+        }
+        if std::mem::take(&mut expect_title) {
+            if let Some(title) = line.strip_suffix(':') {
+                section = Some(title.trim().to_string());
+                continue;
+            }
+        }
+        if let Some(number) = line.strip_prefix('#').and_then(|n| n.trim().parse().ok()) {
+            finish(&mut current);
+            current = Some(Session::new(&section, Some(number)));
+            continue;
+        }
+
+        // The rest belongs to a session; output without a "#N" before it starts one
+        if current.as_ref().is_some_and(|s| s.has_results() && (line.contains(".py") || line.starts_with("Total messages sent:"))) {
+            finish(&mut current);
+        }
+        let session = current.get_or_insert_with(|| Session::new(&section, None));
+        if script_transport(line).is_some() {
+            // Drop the shell prompt in front of the command
+            let command = line.split_once("# ").map_or(line, |(_, command)| command);
+            session.command = Some(command.trim().to_string());
+        } else if line.starts_with("WebTransport session established") {
+            session.transport = Some("webtransport");
+        } else if line.starts_with("Nagle's algorithm enabled") {
+            session.transport = Some("websocket");
+            session.nagle = Some(true);
+        } else if line.starts_with("Nagle's algorithm disabled") {
+            session.transport = Some("websocket");
+            session.nagle = Some(false);
+        } else if let Some(messages) = value(line, "Total messages sent:") {
+            session.messages = Some(messages);
+        } else if let Some(rate) = value(line, "Messages per second:") {
+            session.messages_per_second = Some(rate);
+        } else if let Some(rtt) = value(line, "Average RTT:") {
+            session.average_rtt_ms = Some(rtt);
+        } else if let Some(rtt) = value(line, "EWMA RTT:") {
+            session.ewma_rtt_ms = Some(rtt);
+        }
+    }
+    finish(&mut current);
+
+    // A session pasted without its command line ran under the same interpreter as the
+    // others in its section, or failing that the rest of the log
+    for i in 0..sessions.len() {
+        if sessions[i].interpreter.is_none() {
+            let section = &sessions[i].section;
+            let same_section = sessions.iter().filter(|s| &s.section == section).find_map(|s| s.interpreter.clone());
+            sessions[i].interpreter = same_section.or_else(|| sessions.iter().find_map(|s| s.interpreter.clone()));
+        }
+    }
+    sessions
+}
+
+/// The `rtt` column of a Python client's --rtt-file, in microseconds
+pub fn read_rtt_file(path: &Path) -> Result<Vec<f64>, String> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let headers = reader.headers().map_err(|e| format!("invalid CSV {}: {}", path.display(), e))?;
+    let column = headers.iter().position(|h| h == "rtt").ok_or_else(|| format!("no rtt column in {}", path.display()))?;
+    let mut rtts = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| format!("invalid CSV {}: {}", path.display(), e))?;
+        if let Some(rtt) = record.get(column).and_then(|v| v.trim().parse().ok()) {
+            rtts.push(rtt);
+        }
+    }
+    Ok(rtts)
+}
+
+/// Write a session as a run directory: `<transport>_summary.json`, plus
+/// `<transport>_measurements.csv` when there are per-echo RTTs.
+pub fn write_run(session: &Session, rtts_us: Option<&[f64]>, source: &Path, dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let transport = session.transport.ok_or("could not tell which client produced the session")?;
+    fs::create_dir_all(dir)?;
+    let hist = rtts_us.map(|rtts| {
+        let mut hist = LatencyHistogram::default();
+        for &rtt in rtts {
+            hist.record(rtt.round() as u64);
+        }
+        hist
+    });
+    if let Some(rtts) = rtts_us {
+        let mut writer = csv::Writer::from_path(dir.join(format!("{}_measurements.csv", transport)))?;
+        writer.write_record(["rtt"])?;
+        for rtt in rtts {
+            writer.write_record([format!("{}", rtt.round() as u64)])?;
+        }
+        writer.flush()?;
+    }
+    let path = dir.join(format!("{}_summary.json", transport));
+    fs::write(&path, serde_json::to_string_pretty(&session.summary(hist.as_ref(), source))?)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/sessions.txt");
+
+    #[test]
+    fn fixture_sessions_are_parsed_and_the_crashed_one_skipped() {
+        let sessions = parse(FIXTURE);
+        // The second WebTransport session died before printing anything
+        assert_eq!(sessions.len(), 4);
+
+        let webtransport = &sessions[0];
+        assert_eq!(webtransport.section.as_deref(), Some("WEBTRANSPORT"));
+        assert_eq!(webtransport.number, Some(1));
+        assert_eq!(webtransport.transport, Some("webtransport"));
+        assert_eq!(webtransport.interpreter.as_deref(), Some("pypy3.10"));
+        assert_eq!(webtransport.messages, Some(10000));
+        assert_eq!(webtransport.messages_per_second, Some(753.46));
+        assert_eq!(webtransport.average_rtt_ms, Some(1.13));
+        assert_eq!(webtransport.ewma_rtt_ms, Some(0.71));
+        assert_eq!((webtransport.nagle, webtransport.offload), (None, None));
+        assert_eq!(webtransport.variant(), ["default"]);
+
+        let websocket = &sessions[1];
+        assert_eq!(websocket.section.as_deref(), Some("WEBSOCKETS NO NAGLE, WITHOUT OFFLOAD"));
+        assert_eq!(websocket.transport, Some("websocket"));
+        assert_eq!(websocket.interpreter.as_deref(), Some("python3"));
+        assert!(websocket.command.as_deref().is_some_and(|c| c.starts_with("python3 websocket_client.py")));
+        assert_eq!((websocket.nagle, websocket.offload), (Some(false), Some(false)));
+        assert_eq!(websocket.variant(), ["no_nagle", "no_offload"]);
+    }
+
+    #[test]
+    fn malformed_lines_leave_fields_empty_and_settings_come_from_the_section() {
+        let sessions = parse(FIXTURE);
+        // Pasted without its command: transport, options and interpreter from the section
+        let pasted = &sessions[2];
+        assert_eq!(pasted.number, Some(2));
+        assert_eq!(pasted.command, None);
+        assert_eq!(pasted.transport, Some("websocket"));
+        assert_eq!(pasted.interpreter.as_deref(), Some("python3"));
+        assert_eq!((pasted.nagle, pasted.offload), (Some(false), Some(false)));
+        assert_eq!(pasted.messages, Some(100000));
+        assert_eq!(pasted.messages_per_second, None);
+        assert_eq!(pasted.average_rtt_ms, Some(0.38));
+        assert_eq!(pasted.ewma_rtt_ms, None);
+
+        // A second set of results without a "#N" is another session
+        let unnumbered = &sessions[3];
+        assert_eq!(unnumbered.number, None);
+        assert_eq!(unnumbered.messages, Some(50000));
+        assert_eq!(unnumbered.average_rtt_ms, Some(0.52));
+        assert_eq!(unnumbered.section, pasted.section);
+    }
+
+    #[test]
+    fn variants_differ_in_one_component_per_option() {
+        let session = |nagle, offload| Session { nagle, offload, ..Default::default() };
+        assert_eq!(session(Some(true), Some(true)).variant(), ["nagle", "offload"]);
+        assert_eq!(session(Some(false), Some(true)).variant(), ["no_nagle", "offload"]);
+        assert_eq!(session(None, Some(false)).variant(), ["no_offload"]);
+    }
+}
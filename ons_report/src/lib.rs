@@ -0,0 +1,7 @@
+// Comparing the results of many benchmark runs: loading them, the statistics, the plots
+// and the report that ties them together, plus importing the Python clients' output.
+pub mod legacy;
+pub mod plot;
+pub mod report;
+pub mod runs;
+pub mod stats;
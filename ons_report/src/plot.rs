@@ -0,0 +1,168 @@
+// SVG plots for the report: RTT CDFs across configurations, the tail on a log
+// scale, and RTT over the course of each run.
+use std::path::Path;
+use plotters::prelude::*;
+use crate::runs::TickPoint;
+use crate::stats::quantile;
+
+/// Points drawn per CDF curve; the samples are thinned to this many quantiles
+const CDF_POINTS: usize = 2000;
+const SIZE: (u32, u32) = (1024, 640);
+
+type PlotResult = Result<(), Box<dyn std::error::Error>>;
+
+/// One configuration's pooled RTTs, sorted
+pub struct Curve<'a> {
+    pub label: &'a str,
+    pub sorted_us: &'a [u64],
+}
+
+/// RTT CDF per configuration, cut off at the largest p99.9 so the bodies stay readable.
+pub fn cdf(path: &Path, curves: &[Curve]) -> PlotResult {
+    let x_min = lowest(curves, 0.001);
+    let x_max = curves.iter().map(|c| quantile(c.sorted_us, 0.999)).filter(|v| !v.is_nan()).fold(1.0, f64::max) / 1e3 * 1.05;
+    let root = SVGBackend::new(path, SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption("RTT CDF", ("sans-serif", 22))
+        .margin(15)
+        .x_label_area_size(45)
+        .y_label_area_size(55)
+        .build_cartesian_2d(x_min..x_max, 0f64..1f64)?;
+    chart.configure_mesh().x_desc("RTT (ms)").y_desc("Fraction of echoes").draw()?;
+    for (i, curve) in curves.iter().enumerate() {
+        let n = curve.sorted_us.len();
+        if n == 0 {
+            continue;
+        }
+        let color = Palette99::pick(i).to_rgba();
+        let points = (0..=CDF_POINTS).map(|k| {
+            let q = k as f64 / CDF_POINTS as f64;
+            (quantile(curve.sorted_us, q) / 1e3, q)
+        });
+        chart
+            .draw_series(LineSeries::new(points.filter(|(x, _)| (x_min..=x_max).contains(x)), color.stroke_width(2)))?
+            .label(curve.label)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2)));
+    }
+    chart.configure_series_labels().position(SeriesLabelPosition::LowerRight).background_style(WHITE.mix(0.8)).border_style(BLACK).draw()?;
+    root.present()?;
+    Ok(())
+}
+
+/// Complementary CDF on a log scale, which is where the tails differ.
+pub fn tail(path: &Path, curves: &[Curve]) -> PlotResult {
+    let x_min = lowest(curves, 0.5);
+    let x_max = curves.iter().filter_map(|c| c.sorted_us.last()).max().copied().unwrap_or(1) as f64 / 1e3 * 1.05;
+    let largest = curves.iter().map(|c| c.sorted_us.len()).max().unwrap_or(1).max(1);
+    let y_min = (1.0 / largest as f64).min(0.1);
+    let root = SVGBackend::new(path, SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption("RTT tail (1 - CDF)", ("sans-serif", 22))
+        .margin(15)
+        .x_label_area_size(45)
+        .y_label_area_size(65)
+        .build_cartesian_2d(x_min..x_max, (y_min..1f64).log_scale())?;
+    chart.configure_mesh().x_desc("RTT (ms)").y_desc("Fraction of echoes above").y_label_formatter(&|y| format!("{:e}", y)).draw()?;
+    for (i, curve) in curves.iter().enumerate() {
+        let n = curve.sorted_us.len();
+        if n == 0 {
+            continue;
+        }
+        let color = Palette99::pick(i).to_rgba();
+        // One point per distinct value in the tail, thinned quantiles below it
+        let step = (n / CDF_POINTS).max(1);
+        let points = (0..n)
+            .filter(move |&k| k % step == 0 || k + CDF_POINTS / 10 >= n)
+            .map(|k| (curve.sorted_us[k] as f64 / 1e3, (n - k) as f64 / n as f64))
+            .filter(|(x, _)| *x >= x_min);
+        chart
+            .draw_series(LineSeries::new(points, color.stroke_width(2)))?
+            .label(curve.label)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2)));
+    }
+    chart.configure_series_labels().position(SeriesLabelPosition::UpperRight).background_style(WHITE.mix(0.8)).border_style(BLACK).draw()?;
+    root.present()?;
+    Ok(())
+}
+
+/// Start of the x axis in ms: just below the smallest quantile `q` of any curve, so a
+/// long fixed path delay doesn't squash the curves against the right-hand side
+fn lowest(curves: &[Curve], q: f64) -> f64 {
+    let low = curves.iter().map(|c| quantile(c.sorted_us, q)).filter(|v| !v.is_nan()).fold(f64::INFINITY, f64::min);
+    if low.is_finite() { (low / 1e3 * 0.95).floor() } else { 0.0 }
+}
+
+/// One repetition's ticks, for the latency-over-time plot
+pub struct Timeline<'a> {
+    pub label: String,
+    pub points: &'a [TickPoint],
+}
+
+/// Median and p99 RTT per time bin for each repetition of one configuration, with
+/// lost echoes counted per bin underneath.
+pub fn timeline(path: &Path, title: &str, timelines: &[Timeline], bin_us: u64) -> PlotResult {
+    let bin_us = bin_us.max(1);
+    let binned: Vec<Vec<(f64, f64, f64, usize)>> = timelines.iter().map(|t| bins(t.points, bin_us)).collect();
+    let x_max = binned.iter().flatten().map(|b| b.0).fold(1.0, f64::max) + bin_us as f64 / 1e6;
+    let y_max = binned.iter().flatten().map(|b| b.2).fold(1.0, f64::max) * 1.05;
+    let lost_max = binned.iter().flatten().map(|b| b.3).max().unwrap_or(0).max(1);
+
+    let root = SVGBackend::new(path, (SIZE.0, SIZE.1 + 200)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let (upper, lower) = root.split_vertically(SIZE.1);
+    let mut chart = ChartBuilder::on(&upper)
+        .caption(title, ("sans-serif", 22))
+        .margin(15)
+        .x_label_area_size(35)
+        .y_label_area_size(55)
+        .build_cartesian_2d(0f64..x_max, 0f64..y_max)?;
+    chart.configure_mesh().y_desc(format!("RTT per {} ms (ms)", bin_us / 1000)).draw()?;
+    for (i, (timeline, bins)) in timelines.iter().zip(&binned).enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart
+            .draw_series(LineSeries::new(bins.iter().filter(|b| !b.1.is_nan()).map(|b| (b.0, b.1)), color.stroke_width(2)))?
+            .label(format!("{} p50", timeline.label))
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2)));
+        let faint = color.mix(0.45);
+        chart
+            .draw_series(LineSeries::new(bins.iter().filter(|b| !b.2.is_nan()).map(|b| (b.0, b.2)), faint.stroke_width(1)))?
+            .label(format!("{} p99", timeline.label))
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], faint.stroke_width(1)));
+    }
+    chart.configure_series_labels().position(SeriesLabelPosition::UpperRight).background_style(WHITE.mix(0.8)).border_style(BLACK).draw()?;
+
+    let mut losses = ChartBuilder::on(&lower)
+        .margin(15)
+        .x_label_area_size(40)
+        .y_label_area_size(55)
+        .build_cartesian_2d(0f64..x_max, 0f64..lost_max as f64 * 1.1)?;
+    losses.configure_mesh().x_desc("Time since start (s)").y_desc("Lost echoes").draw()?;
+    for (i, bins) in binned.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        losses.draw_series(LineSeries::new(bins.iter().map(|b| (b.0, b.3 as f64)), color.stroke_width(1)))?;
+    }
+    root.present()?;
+    Ok(())
+}
+
+/// (bin start in seconds, p50 ms, p99 ms, lost echoes) per bin of send time
+fn bins(points: &[TickPoint], bin_us: u64) -> Vec<(f64, f64, f64, usize)> {
+    let Some(last) = points.iter().map(|p| p.send_offset_us).max() else { return Vec::new() };
+    let mut bins: Vec<(Vec<u64>, usize)> = vec![(Vec::new(), 0); (last / bin_us + 1) as usize];
+    for point in points {
+        let bin = &mut bins[(point.send_offset_us / bin_us) as usize];
+        match point.rtt_us {
+            Some(rtt) => bin.0.push(rtt),
+            None => bin.1 += 1,
+        }
+    }
+    bins.into_iter()
+        .enumerate()
+        .map(|(i, (mut rtts, lost))| {
+            rtts.sort_unstable();
+            (i as f64 * bin_us as f64 / 1e6, quantile(&rtts, 0.5) / 1e3, quantile(&rtts, 0.99) / 1e3, lost)
+        })
+        .collect()
+}
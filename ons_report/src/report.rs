@@ -0,0 +1,434 @@
+// The report itself: per-configuration percentile, loss and jitter tables, the
+// pairwise comparisons, and report.md/report.json with the plots beside them.
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Serialize;
+use serde_json::Value;
+use crate::plot::{self, Curve, Timeline};
+use crate::runs::Run;
+use crate::stats::{self, MannWhitney};
+
+pub const REPORT_FILE: &str = "report.md";
+pub const JSON_FILE: &str = "report.json";
+
+/// How the comparisons are drawn and resampled
+#[derive(Debug, Clone)]
+pub struct ReportOptions {
+    pub title: String,
+    /// Compare every configuration against this one instead of against its neighbours
+    pub baseline: Option<String>,
+    pub bootstrap: usize,
+    pub seed: u64,
+    pub bin_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Percentiles {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub p99_9: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    fn of(sorted: &[u64]) -> Self {
+        Percentiles {
+            mean: sample_mean(sorted),
+            p50: stats::quantile(sorted, 0.5),
+            p90: stats::quantile(sorted, 0.9),
+            p99: stats::quantile(sorted, 0.99),
+            p99_9: stats::quantile(sorted, 0.999),
+            max: sorted.last().map_or(f64::NAN, |&v| v as f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    pub dir: PathBuf,
+    pub repetition: Option<u32>,
+    pub git_commit: Option<String>,
+    pub samples: usize,
+    /// From the summary when the run has no per-echo RTTs (imported Python runs)
+    pub mean_us: f64,
+    pub p50_us: f64,
+    pub p99_us: f64,
+    /// Ticks whose echo never came back or came back after the late cut-off
+    pub sent: Option<u64>,
+    pub lost: Option<u64>,
+    pub late: Option<u64>,
+    /// Loss the datagram clients see from sequence numbers
+    pub datagram_loss_percent: Option<f64>,
+    pub reordered_percent: Option<f64>,
+    pub ipdv_us: Option<f64>,
+    /// RFC 3550 interarrival jitter, from the datagram clients
+    pub datagram_jitter_us: Option<f64>,
+    pub pdv_p99_us: Option<f64>,
+}
+
+impl RunReport {
+    fn new(run: &Run, sorted: &[u64]) -> Self {
+        let echoes = &run.summary["echoes"];
+        let datagram = &run.summary["datagram"];
+        let count = |name: &str| echoes[name].as_u64();
+        let (on_time, late, lost) = (count("on_time"), count("late"), count("lost"));
+        RunReport {
+            dir: run.dir.clone(),
+            repetition: run.repetition,
+            git_commit: run.git_commit.clone(),
+            samples: sorted.len(),
+            mean_us: if sorted.is_empty() { run.summary["metrics"]["rtt"]["mean"].as_f64().unwrap_or(f64::NAN) } else { sample_mean(sorted) },
+            p50_us: stats::quantile(sorted, 0.5),
+            p99_us: stats::quantile(sorted, 0.99),
+            sent: on_time.zip(late).zip(lost).map(|((on_time, late), lost)| on_time + late + lost),
+            lost,
+            late,
+            datagram_loss_percent: datagram["loss_rate_percent"].as_f64(),
+            reordered_percent: datagram["reordered_percent"].as_f64(),
+            ipdv_us: stats::mean_ipdv(&run.rtts_us),
+            datagram_jitter_us: datagram["jitter_us"].as_f64(),
+            pdv_p99_us: datagram["pdv_us"]["p99"].as_f64(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupReport {
+    pub group: String,
+    pub transport: String,
+    pub workload: Value,
+    pub samples: usize,
+    /// Over every echo of every repetition, in microseconds
+    pub rtt_us: Percentiles,
+    pub p99_ci_us: Option<(f64, f64)>,
+    /// Mean and standard deviation of the repetitions' own p99s
+    pub p99_per_run_mean_us: f64,
+    pub p99_per_run_std_us: f64,
+    pub runs: Vec<RunReport>,
+}
+
+impl GroupReport {
+    fn total(&self, field: impl Fn(&RunReport) -> Option<u64>) -> Option<u64> {
+        self.runs.iter().map(field).sum()
+    }
+
+    fn mean(&self, field: impl Fn(&RunReport) -> Option<f64>) -> Option<f64> {
+        let values: Vec<f64> = self.runs.iter().filter_map(field).collect();
+        (!values.is_empty()).then(|| stats::mean(&values))
+    }
+
+    pub fn echo_loss_percent(&self) -> Option<f64> {
+        let sent = self.total(|r| r.sent)?;
+        Some(if sent == 0 { 0.0 } else { self.total(|r| r.lost)? as f64 / sent as f64 * 100.0 })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub a: String,
+    pub b: String,
+    /// b minus a, in microseconds
+    pub mean_diff_us: f64,
+    pub p50_diff_us: f64,
+    pub p99_diff_us: f64,
+    pub p99_diff_ci_us: Option<(f64, f64)>,
+    /// Over every echo
+    pub samples: Option<MannWhitney>,
+    /// Over the repetitions' p99s, which is what says the difference holds up from run to run
+    pub repetitions: Option<MannWhitney>,
+    /// "p99", or "mean" when a side has runs with only a mean RTT
+    pub repetition_statistic: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub title: String,
+    pub groups: Vec<GroupReport>,
+    pub comparisons: Vec<Comparison>,
+    pub bootstrap_iterations: usize,
+    pub seed: u64,
+    pub plots: Vec<PathBuf>,
+}
+
+/// A configuration's repetitions with their RTTs sorted
+struct Group<'a> {
+    name: String,
+    runs: Vec<(&'a Run, Vec<u64>)>,
+    pooled: Vec<u64>,
+}
+
+impl Group<'_> {
+    fn slices(&self) -> Vec<&[u64]> {
+        self.runs.iter().map(|(_, sorted)| sorted.as_slice()).collect()
+    }
+}
+
+/// Work out every table and comparison, draw the plots into `output_dir` and write the report there.
+pub fn build(runs: &[Run], options: &ReportOptions, output_dir: &Path) -> Result<Report, Box<dyn std::error::Error>> {
+    fs::create_dir_all(output_dir)?;
+    let mut rng = StdRng::seed_from_u64(options.seed);
+
+    let mut groups: Vec<Group> = Vec::new();
+    for run in runs {
+        let mut sorted = run.rtts_us.clone();
+        sorted.sort_unstable();
+        match groups.iter_mut().find(|g| g.name == run.group) {
+            Some(group) => group.runs.push((run, sorted)),
+            None => groups.push(Group { name: run.group.clone(), runs: vec![(run, sorted)], pooled: Vec::new() }),
+        }
+    }
+    for group in &mut groups {
+        group.pooled = group.runs.iter().flat_map(|(_, sorted)| sorted.iter().copied()).collect();
+        group.pooled.sort_unstable();
+    }
+    if let Some(baseline) = &options.baseline {
+        if !groups.iter().any(|g| &g.name == baseline) {
+            let names: Vec<&str> = groups.iter().map(|g| g.name.as_str()).collect();
+            return Err(format!("no configuration named '{}' (found {})", baseline, names.join(", ")).into());
+        }
+    }
+
+    let group_reports: Vec<GroupReport> = groups
+        .iter()
+        .map(|group| {
+            let runs: Vec<RunReport> = group.runs.iter().map(|(run, sorted)| RunReport::new(run, sorted)).collect();
+            let p99s: Vec<f64> = runs.iter().map(|r| r.p99_us).filter(|v| !v.is_nan()).collect();
+            let mut rtt_us = Percentiles::of(&group.pooled);
+            if group.pooled.is_empty() {
+                // Summary-only runs: weight each run's mean by its echo count
+                let weighted: Vec<(f64, f64)> = group
+                    .runs
+                    .iter()
+                    .zip(&runs)
+                    .filter(|(_, r)| !r.mean_us.is_nan())
+                    .map(|((run, _), r)| (r.mean_us, run.summary["metrics"]["rtt"]["count"].as_f64().unwrap_or(1.0)))
+                    .collect();
+                let count: f64 = weighted.iter().map(|(_, n)| n).sum();
+                if count > 0.0 {
+                    rtt_us.mean = weighted.iter().map(|(mean, n)| mean * n).sum::<f64>() / count;
+                }
+            }
+            GroupReport {
+                group: group.name.clone(),
+                transport: group.runs[0].0.transport.to_string(),
+                workload: group.runs[0].0.summary["workload"].clone(),
+                samples: group.pooled.len(),
+                rtt_us,
+                p99_ci_us: stats::bootstrap_quantile(&group.slices(), 0.99, options.bootstrap, &mut rng),
+                p99_per_run_mean_us: if p99s.is_empty() { f64::NAN } else { stats::mean(&p99s) },
+                p99_per_run_std_us: stats::std_dev(&p99s),
+                runs,
+            }
+        })
+        .collect();
+
+    let comparisons = pairs(&groups, options.baseline.as_deref())
+        .into_iter()
+        .map(|(a, b)| compare(&groups[a], &groups[b], &group_reports[a], &group_reports[b], options.bootstrap, &mut rng))
+        .collect();
+
+    let mut plots = Vec::new();
+    let curves: Vec<Curve> = groups.iter().map(|g| Curve { label: &g.name, sorted_us: &g.pooled }).collect();
+    plots.push(PathBuf::from("cdf.svg"));
+    plot::cdf(&output_dir.join("cdf.svg"), &curves)?;
+    plots.push(PathBuf::from("tail.svg"));
+    plot::tail(&output_dir.join("tail.svg"), &curves)?;
+    fs::create_dir_all(output_dir.join("timeline"))?;
+    for group in groups.iter().filter(|g| g.runs.iter().any(|(run, _)| !run.timeline.is_empty())) {
+        let timelines: Vec<Timeline> = group
+            .runs
+            .iter()
+            .enumerate()
+            .map(|(i, (run, _))| Timeline {
+                label: run.repetition.map_or_else(|| format!("run {}", i + 1), |n| format!("rep{}", n)),
+                points: &run.timeline,
+            })
+            .collect();
+        let file = PathBuf::from("timeline").join(format!("{}.svg", group.name.replace(['/', ' '], "_")));
+        plot::timeline(&output_dir.join(&file), &group.name, &timelines, options.bin_ms * 1000)?;
+        plots.push(file);
+    }
+
+    let report = Report {
+        title: options.title.clone(),
+        groups: group_reports,
+        comparisons,
+        bootstrap_iterations: options.bootstrap,
+        seed: options.seed,
+        plots,
+    };
+    fs::write(output_dir.join(JSON_FILE), serde_json::to_string_pretty(&report)?)?;
+    fs::write(output_dir.join(REPORT_FILE), report.markdown())?;
+    Ok(report)
+}
+
+/// Which configurations to compare: each against the baseline, or else every pair
+/// whose names differ in one part only (the transport, or the impairment profile...).
+fn pairs(groups: &[Group], baseline: Option<&str>) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for a in 0..groups.len() {
+        for b in a + 1..groups.len() {
+            let (na, nb) = (&groups[a].name, &groups[b].name);
+            match baseline {
+                Some(baseline) if na == baseline => pairs.push((a, b)),
+                Some(baseline) if nb == baseline => pairs.push((b, a)),
+                Some(_) => (),
+                None => {
+                    let (pa, pb): (Vec<&str>, Vec<&str>) = (na.split('/').collect(), nb.split('/').collect());
+                    if pa.len() == pb.len() && pa.iter().zip(&pb).filter(|(x, y)| x != y).count() == 1 {
+                        pairs.push((a, b));
+                    }
+                }
+            }
+        }
+    }
+    pairs
+}
+
+fn compare(a: &Group, b: &Group, ra: &GroupReport, rb: &GroupReport, bootstrap: usize, rng: &mut StdRng) -> Comparison {
+    let as_f64 = |sorted: &[u64]| sorted.iter().map(|&v| v as f64).collect::<Vec<_>>();
+    // Runs imported from the Python clients may only have a mean, so fall back to
+    // comparing means unless every run on both sides has a p99
+    let has_p99 = |r: &GroupReport| r.runs.iter().all(|run| !run.p99_us.is_nan());
+    let repetition_statistic = if has_p99(ra) && has_p99(rb) { "p99" } else { "mean" };
+    let per_run = |r: &GroupReport| {
+        r.runs
+            .iter()
+            .map(|run| if repetition_statistic == "p99" { run.p99_us } else { run.mean_us })
+            .filter(|v| !v.is_nan())
+            .collect::<Vec<_>>()
+    };
+    Comparison {
+        a: a.name.clone(),
+        b: b.name.clone(),
+        mean_diff_us: rb.rtt_us.mean - ra.rtt_us.mean,
+        p50_diff_us: rb.rtt_us.p50 - ra.rtt_us.p50,
+        p99_diff_us: rb.rtt_us.p99 - ra.rtt_us.p99,
+        p99_diff_ci_us: stats::bootstrap_quantile_diff(&a.slices(), &b.slices(), 0.99, bootstrap, rng),
+        samples: stats::mann_whitney(&as_f64(&a.pooled), &as_f64(&b.pooled)),
+        repetitions: stats::mann_whitney(&per_run(ra), &per_run(rb)),
+        repetition_statistic,
+    }
+}
+
+fn sample_mean(values: &[u64]) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    values.iter().sum::<u64>() as f64 / values.len() as f64
+}
+
+fn ms(us: f64) -> String {
+    if us.is_nan() {
+        "-".to_string()
+    } else {
+        format!("{:.3}", us / 1e3)
+    }
+}
+
+fn opt(value: Option<f64>, format: impl Fn(f64) -> String) -> String {
+    value.filter(|v| !v.is_nan()).map_or_else(|| "-".to_string(), format)
+}
+
+fn interval(value: Option<(f64, f64)>) -> String {
+    match value {
+        Some((lo, hi)) if !lo.is_nan() => format!("{} – {}", ms(lo), ms(hi)),
+        _ => "-".to_string(),
+    }
+}
+
+fn p_value(test: &Option<MannWhitney>) -> String {
+    match test {
+        Some(t) if t.p_value < 1e-4 => format!("<0.0001{}", if t.exact { " (exact)" } else { "" }),
+        Some(t) => format!("{:.4}{}", t.p_value, if t.exact { " (exact)" } else { "" }),
+        None => "-".to_string(),
+    }
+}
+
+impl Report {
+    pub fn markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(md, "# {}\n", self.title);
+        let _ = writeln!(md, "RTTs in milliseconds. Percentiles pool every echo of every repetition; \
+            the p99 interval is a {}-iteration bootstrap that resamples repetitions, then echoes within them.\n",
+            self.bootstrap_iterations);
+
+        let _ = writeln!(md, "## Latency\n");
+        let _ = writeln!(md, "| Configuration | Runs | Echoes | Mean | p50 | p90 | p99 | p99 95% CI | p99 per run | p99.9 | Max |");
+        let _ = writeln!(md, "|---|---|---|---|---|---|---|---|---|---|---|");
+        for g in &self.groups {
+            let per_run = if g.p99_per_run_mean_us.is_nan() {
+                "-".to_string()
+            } else {
+                format!("{} ± {}", ms(g.p99_per_run_mean_us), ms(g.p99_per_run_std_us))
+            };
+            let _ = writeln!(md, "| {} | {} | {} | {} | {} | {} | {} | {} | {} | {} | {} |",
+                g.group, g.runs.len(), g.samples, ms(g.rtt_us.mean), ms(g.rtt_us.p50), ms(g.rtt_us.p90),
+                ms(g.rtt_us.p99), interval(g.p99_ci_us), per_run, ms(g.rtt_us.p99_9), ms(g.rtt_us.max));
+        }
+
+        let _ = writeln!(md, "\n## Loss\n");
+        let _ = writeln!(md, "Echo loss counts ticks whose echo never arrived; late echoes arrived after the cut-off. \
+            Datagram loss and reordering come from sequence numbers and only the datagram clients report them.\n");
+        let _ = writeln!(md, "| Configuration | Ticks sent | Echoes lost | Echo loss % | Late | Datagram loss % | Reordered % |");
+        let _ = writeln!(md, "|---|---|---|---|---|---|---|");
+        for g in &self.groups {
+            let count = |v: Option<u64>| v.map_or_else(|| "-".to_string(), |v| v.to_string());
+            let _ = writeln!(md, "| {} | {} | {} | {} | {} | {} | {} |",
+                g.group, count(g.total(|r| r.sent)), count(g.total(|r| r.lost)),
+                opt(g.echo_loss_percent(), |v| format!("{:.3}", v)), count(g.total(|r| r.late)),
+                opt(g.mean(|r| r.datagram_loss_percent), |v| format!("{:.3}", v)),
+                opt(g.mean(|r| r.reordered_percent), |v| format!("{:.3}", v)));
+        }
+
+        let _ = writeln!(md, "\n## Jitter\n");
+        let _ = writeln!(md, "IPDV is the mean change between consecutive RTTs, for every transport. \
+            Interarrival jitter (RFC 3550) and the PDV p99 are one-way and datagram clients only. Means over repetitions.\n");
+        let _ = writeln!(md, "| Configuration | RTT IPDV | Interarrival jitter | PDV p99 |");
+        let _ = writeln!(md, "|---|---|---|---|");
+        for g in &self.groups {
+            let _ = writeln!(md, "| {} | {} | {} | {} |", g.group,
+                opt(g.mean(|r| r.ipdv_us), ms), opt(g.mean(|r| r.datagram_jitter_us), ms), opt(g.mean(|r| r.pdv_p99_us), ms));
+        }
+
+        if !self.comparisons.is_empty() {
+            let _ = writeln!(md, "\n## Comparisons\n");
+            let _ = writeln!(md, "Differences are B minus A. The echo test is a Mann-Whitney U over every echo, \
+                with P(A < B) its effect size; with tens of thousands of echoes it finds tiny differences, so read it \
+                with the interval. The repetition test runs the same test over each repetition's p99 (its mean \
+                when a side has runs imported with only a mean RTT), and can only reach significance with enough \
+                repetitions (at least four each for p < 0.05).\n");
+            let _ = writeln!(md, "| A | B | ΔMean | Δp50 | Δp99 | Δp99 95% CI | Echoes p | P(A < B) | Repetitions p |");
+            let _ = writeln!(md, "|---|---|---|---|---|---|---|---|---|");
+            for c in &self.comparisons {
+                let _ = writeln!(md, "| {} | {} | {} | {} | {} | {} | {} | {} | {}{} |",
+                    c.a, c.b, ms(c.mean_diff_us), ms(c.p50_diff_us), ms(c.p99_diff_us),
+                    interval(c.p99_diff_ci_us),
+                    p_value(&c.samples), opt(c.samples.as_ref().map(|t| t.prob_less), |v| format!("{:.3}", v)),
+                    p_value(&c.repetitions), if c.repetition_statistic == "p99" { "" } else { ", on means" });
+            }
+        }
+
+        let _ = writeln!(md, "\n## Plots\n");
+        for plot in &self.plots {
+            let _ = writeln!(md, "![{}]({})\n", plot.display(), plot.display());
+        }
+
+        let _ = writeln!(md, "## Runs\n");
+        let _ = writeln!(md, "| Configuration | Directory | Commit | Echoes | Mean | p50 | p99 |");
+        let _ = writeln!(md, "|---|---|---|---|---|---|---|");
+        for g in &self.groups {
+            for r in &g.runs {
+                let commit = r.git_commit.as_deref().map_or("-", |c| &c[..c.len().min(10)]);
+                let _ = writeln!(md, "| {} | {} | {} | {} | {} | {} | {} |",
+                    g.group, r.dir.display(), commit, r.samples, ms(r.mean_us), ms(r.p50_us), ms(r.p99_us));
+            }
+        }
+        md
+    }
+}
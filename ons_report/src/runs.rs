@@ -0,0 +1,201 @@
+// Finding runs on disk. A run is a directory holding one client's
+// <transport>_summary.json and <transport>_measurements.csv, and, when the client
+// wrote them, <transport>_timeseries.csv and <transport>_manifest.json. Runs whose
+// paths differ only in a repN component are repetitions of the same configuration.
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use serde_json::Value;
+
+/// File stems the clients write their results under
+pub const TRANSPORTS: [&str; 4] = ["websocket", "udp", "webtransport", "webrtc"];
+
+/// Where a run's results live, before they are loaded
+#[derive(Debug, Clone)]
+pub struct RunDir {
+    pub dir: PathBuf,
+    pub transport: &'static str,
+    /// The configuration this run is a repetition of, e.g. `baseline/websocket/tick128_payload0`
+    pub group: String,
+    pub repetition: Option<u32>,
+}
+
+/// One tick as the time series recorded it
+#[derive(Debug, Clone, Copy)]
+pub struct TickPoint {
+    pub send_offset_us: u64,
+    /// None if the echo never came back
+    pub rtt_us: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub dir: PathBuf,
+    pub transport: &'static str,
+    pub group: String,
+    pub repetition: Option<u32>,
+    pub summary: Value,
+    /// Every echo's RTT in send order
+    pub rtts_us: Vec<u64>,
+    pub timeline: Vec<TickPoint>,
+    /// From the run manifest, when there is one
+    pub git_commit: Option<String>,
+}
+
+/// Find every run under each input. An input is a directory, or `LABEL=DIR` to put
+/// LABEL in front of the group names of the runs under DIR.
+pub fn discover(inputs: &[String]) -> Result<Vec<RunDir>, String> {
+    let mut runs = Vec::new();
+    for input in inputs {
+        let (label, root) = match input.split_once('=') {
+            Some((label, root)) => (Some(label.to_string()), PathBuf::from(root)),
+            None => (None, PathBuf::from(input)),
+        };
+        if !root.is_dir() {
+            return Err(format!("{} is not a directory", root.display()));
+        }
+        let before = runs.len();
+        walk(&root, &root, label.as_deref(), &mut runs)?;
+        if runs.len() == before {
+            return Err(format!("no runs under {} (looked for <transport>_summary.json)", root.display()));
+        }
+    }
+    runs.sort_by(|a: &RunDir, b| (&a.group, a.repetition, &a.dir).cmp(&(&b.group, b.repetition, &b.dir)));
+    Ok(runs)
+}
+
+fn walk(root: &Path, dir: &Path, label: Option<&str>, runs: &mut Vec<RunDir>) -> Result<(), String> {
+    for transport in TRANSPORTS {
+        if dir.join(format!("{}_summary.json", transport)).is_file() {
+            runs.push(run_dir(root, dir, label, transport));
+        }
+    }
+    let entries = fs::read_dir(dir).map_err(|e| format!("could not read {}: {}", dir.display(), e))?;
+    let mut children: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).filter(|path| path.is_dir()).collect();
+    children.sort();
+    for child in children {
+        if child.file_name().is_some_and(|name| name == "target") {
+            continue;
+        }
+        walk(root, &child, label, runs)?;
+    }
+    Ok(())
+}
+
+fn run_dir(root: &Path, dir: &Path, label: Option<&str>, transport: &'static str) -> RunDir {
+    let mut parts: Vec<String> = label.map(str::to_string).into_iter().collect();
+    let mut repetition = None;
+    let relative = dir.strip_prefix(root).unwrap_or(dir);
+    let mut named = false;
+    for component in relative.components() {
+        if let Component::Normal(name) = component {
+            let name = name.to_string_lossy();
+            match repetition_number(&name) {
+                Some(n) => repetition = Some(n),
+                None => {
+                    parts.push(name.into_owned());
+                    named = true;
+                }
+            }
+        }
+    }
+    // A run directory given directly is named after itself, or its parent if it is a repN
+    if !named && label.is_none() {
+        let mut root = root.canonicalize().ok();
+        while let Some(dir) = root {
+            let Some(name) = dir.file_name().map(|n| n.to_string_lossy().into_owned()) else { break };
+            match repetition_number(&name) {
+                Some(n) => repetition = repetition.or(Some(n)),
+                None => {
+                    parts.push(name);
+                    break;
+                }
+            }
+            root = dir.parent().map(Path::to_path_buf);
+        }
+    }
+    if !parts.iter().any(|part| part == transport) {
+        parts.push(transport.to_string());
+    }
+    RunDir { dir: dir.to_path_buf(), transport, group: parts.join("/"), repetition }
+}
+
+/// `rep3` -> 3
+fn repetition_number(name: &str) -> Option<u32> {
+    name.strip_prefix("rep")?.parse().ok()
+}
+
+impl RunDir {
+    pub fn load(&self) -> Result<Run, String> {
+        let summary_path = self.dir.join(format!("{}_summary.json", self.transport));
+        let summary: Value = read_json(&summary_path)?;
+        let rtts_us = read_rtts(&self.dir.join(format!("{}_measurements.csv", self.transport)))?;
+        let timeseries = self.dir.join(format!("{}_timeseries.csv", self.transport));
+        let timeline = if timeseries.is_file() {
+            read_timeline(&timeseries)?
+        } else {
+            // Without a time series, space the echoes out at the tick rate, or at the
+            // message rate of a closed-loop run imported from the Python clients
+            let workload = &summary["workload"];
+            let rate = workload["tick_rate"]
+                .as_f64()
+                .or(summary["tick_rate"].as_f64())
+                .or(workload["messages_per_second"].as_f64())
+                .filter(|rate| *rate > 0.0)
+                .unwrap_or(128.0);
+            rtts_us
+                .iter()
+                .enumerate()
+                .map(|(i, &rtt)| TickPoint { send_offset_us: (i as f64 * 1e6 / rate) as u64, rtt_us: Some(rtt) })
+                .collect()
+        };
+        let manifest = self.dir.join(format!("{}_manifest.json", self.transport));
+        let git_commit = read_json(&manifest).ok().and_then(|m| m["build"]["git_commit"].as_str().map(str::to_string));
+        Ok(Run {
+            dir: self.dir.clone(),
+            transport: self.transport,
+            group: self.group.clone(),
+            repetition: self.repetition,
+            summary,
+            rtts_us,
+            timeline,
+            git_commit,
+        })
+    }
+}
+
+fn read_json(path: &Path) -> Result<Value, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| format!("invalid JSON in {}: {}", path.display(), e))
+}
+
+/// The `rtt` column, in microseconds. A missing file means the run collected no echoes.
+fn read_rtts(path: &Path) -> Result<Vec<u64>, String> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let mut reader = csv::Reader::from_path(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let headers = reader.headers().map_err(|e| format!("invalid CSV {}: {}", path.display(), e))?;
+    let column = headers.iter().position(|h| h == "rtt").ok_or_else(|| format!("no rtt column in {}", path.display()))?;
+    let mut rtts = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| format!("invalid CSV {}: {}", path.display(), e))?;
+        if let Some(rtt) = record.get(column).and_then(|v| v.trim().parse::<f64>().ok()) {
+            rtts.push(rtt.round() as u64);
+        }
+    }
+    Ok(rtts)
+}
+
+fn read_timeline(path: &Path) -> Result<Vec<TickPoint>, String> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let headers = reader.headers().map_err(|e| format!("invalid CSV {}: {}", path.display(), e))?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name).ok_or_else(|| format!("no {} column in {}", name, path.display()));
+    let (send, rtt) = (column("send_offset_us")?, column("rtt_us")?);
+    let mut points = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| format!("invalid CSV {}: {}", path.display(), e))?;
+        let Some(send_offset_us) = record.get(send).and_then(|v| v.parse().ok()) else { continue };
+        points.push(TickPoint { send_offset_us, rtt_us: record.get(rtt).and_then(|v| v.parse().ok()) });
+    }
+    Ok(points)
+}
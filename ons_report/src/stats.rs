@@ -0,0 +1,302 @@
+// Percentiles, jitter and the tests used to decide whether two configurations differ.
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::Serialize;
+
+/// Above this many values in total, or with ties, the U test uses the normal approximation
+const EXACT_LIMIT: usize = 20;
+
+/// Nearest-rank quantile of sorted values
+pub fn quantile(sorted: &[u64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1] as f64
+}
+
+/// The same quantile, without sorting everything
+pub fn quantile_unsorted(values: &mut [u64], q: f64) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let rank = (q * values.len() as f64).ceil() as usize;
+    let index = rank.clamp(1, values.len()) - 1;
+    *values.select_nth_unstable(index).1 as f64
+}
+
+pub fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Sample standard deviation; zero for fewer than two values
+pub fn std_dev(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let m = mean(values);
+    (values.iter().map(|v| (v - m).powi(2)).sum::<f64>() / (values.len() - 1) as f64).sqrt()
+}
+
+/// Mean difference between consecutive RTTs (RFC 5481 IPDV, taken on the round trip).
+/// Works for every transport, unlike the datagram jitter, which only the datagram clients report.
+pub fn mean_ipdv(rtts: &[u64]) -> Option<f64> {
+    if rtts.len() < 2 {
+        return None;
+    }
+    let total: u64 = rtts.windows(2).map(|w| w[0].abs_diff(w[1])).sum();
+    Some(total as f64 / (rtts.len() - 1) as f64)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MannWhitney {
+    /// U for the first sample: pairs where it is larger, ties counting half
+    pub u: f64,
+    /// Two-sided
+    pub p_value: f64,
+    /// Chance a value from the first sample is below one from the second, ties counting half
+    pub prob_less: f64,
+    pub exact: bool,
+}
+
+/// Mann-Whitney U test of whether `a` and `b` come from the same distribution.
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> Option<MannWhitney> {
+    let (n1, n2) = (a.len(), b.len());
+    if n1 == 0 || n2 == 0 {
+        return None;
+    }
+    let mut all: Vec<(f64, bool)> = a.iter().map(|&v| (v, true)).chain(b.iter().map(|&v| (v, false))).collect();
+    all.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    // Average ranks over ties, and the tie correction for the variance
+    let n = all.len();
+    let (mut rank_sum, mut tie_term, mut ties) = (0.0, 0.0, false);
+    let mut i = 0;
+    while i < n {
+        let mut j = i;
+        while j + 1 < n && all[j + 1].0 == all[i].0 {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        let count = (j - i + 1) as f64;
+        if count > 1.0 {
+            ties = true;
+            tie_term += count.powi(3) - count;
+        }
+        rank_sum += rank * all[i..=j].iter().filter(|(_, first)| *first).count() as f64;
+        i = j + 1;
+    }
+
+    let (n1f, n2f) = (n1 as f64, n2 as f64);
+    let u = rank_sum - n1f * (n1f + 1.0) / 2.0;
+    let pairs = n1f * n2f;
+    let (p_value, exact) = if !ties && n <= EXACT_LIMIT {
+        (exact_p(n1, n2, u.round() as usize), true)
+    } else {
+        let nf = n as f64;
+        let variance = pairs / 12.0 * ((nf + 1.0) - tie_term / (nf * (nf - 1.0)));
+        if variance <= 0.0 {
+            (1.0, false)
+        } else {
+            // Continuity correction towards the mean
+            let z = ((u - pairs / 2.0).abs() - 0.5).max(0.0) / variance.sqrt();
+            ((2.0 * normal_sf(z)).min(1.0), false)
+        }
+    };
+    Some(MannWhitney { u, p_value, prob_less: 1.0 - u / pairs, exact })
+}
+
+/// Two-sided p-value of U from the exact null distribution, counting the
+/// orderings of n1 + n2 values that give each U.
+fn exact_p(n1: usize, n2: usize, u: usize) -> f64 {
+    let max_u = n1 * n2;
+    // counts[i][j][u]: orderings of i values from the first sample and j from the second with that U
+    let mut counts = vec![vec![vec![0f64; max_u + 1]; n2 + 1]; n1 + 1];
+    for i in 0..=n1 {
+        for j in 0..=n2 {
+            if i == 0 || j == 0 {
+                counts[i][j][0] = 1.0;
+                continue;
+            }
+            for k in 0..=i * j {
+                // The largest value is from the first sample (beating all j) or from the second
+                let from_first = if k >= j { counts[i - 1][j][k - j] } else { 0.0 };
+                counts[i][j][k] = from_first + counts[i][j - 1][k];
+            }
+        }
+    }
+    let distribution = &counts[n1][n2];
+    let total: f64 = distribution.iter().sum();
+    let lower: f64 = distribution[..=u].iter().sum();
+    let upper: f64 = distribution[u..].iter().sum();
+    (2.0 * lower.min(upper) / total).min(1.0)
+}
+
+/// Upper tail of the standard normal, from erfc (Numerical Recipes' erfcc, error below 1.2e-7)
+fn normal_sf(z: f64) -> f64 {
+    let x = z / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let poly = -x * x - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let erfc = t * poly.exp();
+    let erfc = if x >= 0.0 { erfc } else { 2.0 - erfc };
+    erfc / 2.0
+}
+
+/// Resample a configuration the way it was measured: pick repetitions with
+/// replacement, then echoes with replacement within each, and take quantile `q`.
+fn resampled_quantile(runs: &[&[u64]], q: f64, rng: &mut StdRng, buffer: &mut Vec<u64>) -> f64 {
+    buffer.clear();
+    for _ in 0..runs.len() {
+        let run = runs[rng.gen_range(0..runs.len())];
+        if run.is_empty() {
+            continue;
+        }
+        buffer.extend((0..run.len()).map(|_| run[rng.gen_range(0..run.len())]));
+    }
+    quantile_unsorted(buffer, q)
+}
+
+/// 95% bootstrap interval for quantile `q` of one configuration
+pub fn bootstrap_quantile(runs: &[&[u64]], q: f64, iterations: usize, rng: &mut StdRng) -> Option<(f64, f64)> {
+    if iterations == 0 || runs.iter().all(|run| run.is_empty()) {
+        return None;
+    }
+    let mut buffer = Vec::new();
+    let estimates: Vec<f64> = (0..iterations).map(|_| resampled_quantile(runs, q, rng, &mut buffer)).collect();
+    Some(interval(estimates))
+}
+
+/// 95% bootstrap interval for quantile `q` of `b` minus that of `a`
+pub fn bootstrap_quantile_diff(a: &[&[u64]], b: &[&[u64]], q: f64, iterations: usize, rng: &mut StdRng) -> Option<(f64, f64)> {
+    if iterations == 0 || a.iter().all(|run| run.is_empty()) || b.iter().all(|run| run.is_empty()) {
+        return None;
+    }
+    let mut buffer = Vec::new();
+    let estimates: Vec<f64> = (0..iterations)
+        .map(|_| {
+            let qa = resampled_quantile(a, q, rng, &mut buffer);
+            resampled_quantile(b, q, rng, &mut buffer) - qa
+        })
+        .collect();
+    Some(interval(estimates))
+}
+
+/// Percentile interval: the 2.5th and 97.5th of the bootstrap estimates
+fn interval(mut estimates: Vec<f64>) -> (f64, f64) {
+    estimates.retain(|v| !v.is_nan());
+    if estimates.is_empty() {
+        return (f64::NAN, f64::NAN);
+    }
+    estimates.sort_by(|a, b| a.total_cmp(b));
+    let at = |q: f64| estimates[((q * (estimates.len() - 1) as f64).round() as usize).min(estimates.len() - 1)];
+    (at(0.025), at(0.975))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() < tolerance, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn nearest_rank_quantiles() {
+        let values: Vec<u64> = (1..=10).collect();
+        assert_eq!(quantile(&values, 0.0), 1.0);
+        assert_eq!(quantile(&values, 0.5), 5.0);
+        assert_eq!(quantile(&values, 0.9), 9.0);
+        assert_eq!(quantile(&values, 0.99), 10.0);
+        let mut shuffled = vec![7, 3, 10, 1, 9, 2, 8, 4, 6, 5];
+        assert_eq!(quantile_unsorted(&mut shuffled, 0.9), 9.0);
+        assert!(quantile(&[], 0.5).is_nan());
+    }
+
+    #[test]
+    fn exact_u_test_matches_scipy() {
+        // scipy.stats.mannwhitneyu(males, females, method="exact"), from its documentation
+        let result = mann_whitney(&[19.0, 22.0, 16.0, 29.0, 24.0], &[20.0, 11.0, 17.0, 12.0]).unwrap();
+        assert!(result.exact);
+        assert_eq!(result.u, 17.0);
+        close(result.p_value, 1.0 / 9.0, 1e-12);
+
+        // Complete separation: the one ordering in C(6,3) = 20, and in C(8,4) = 70, either way round
+        close(mann_whitney(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]).unwrap().p_value, 0.1, 1e-12);
+        close(mann_whitney(&[5.0, 6.0, 7.0, 8.0], &[1.0, 2.0, 3.0, 4.0]).unwrap().p_value, 2.0 / 70.0, 1e-12);
+
+        // Unequal sizes: U = 2 of 20, 8 of the 126 orderings at or beyond it on each side
+        let result = mann_whitney(&[3.0, 1.0, 4.0, 1.5], &[2.0, 7.0, 6.0, 5.0, 8.0]).unwrap();
+        assert_eq!(result.u, 2.0);
+        close(result.p_value, 8.0 / 126.0, 1e-12);
+        close(result.prob_less, 0.9, 1e-12);
+    }
+
+    #[test]
+    fn exact_null_distribution_is_symmetric() {
+        for u in 0..=12 {
+            close(exact_p(3, 4, u), exact_p(3, 4, 12 - u), 1e-12);
+        }
+        // The middle of the distribution is as unremarkable as it gets
+        assert_eq!(exact_p(3, 4, 6), 1.0);
+    }
+
+    #[test]
+    fn tied_ranks_use_the_corrected_normal_approximation() {
+        // Ties in 2, 3 and 5; scipy's asymptotic method with use_continuity=True
+        let result = mann_whitney(&[1.0, 2.0, 2.0, 3.0, 3.0, 5.0], &[2.0, 3.0, 4.0, 4.0, 5.0, 6.0, 7.0]).unwrap();
+        assert!(!result.exact);
+        assert_eq!(result.u, 8.5);
+        close(result.p_value, 0.08215265384607158, 1e-6);
+    }
+
+    #[test]
+    fn large_samples_use_the_normal_approximation() {
+        let a: Vec<f64> = (0..30).step_by(2).map(|v| v as f64).collect();
+        let b: Vec<f64> = (0..30).step_by(2).map(|v| v as f64 + 7.5).collect();
+        let result = mann_whitney(&a, &b).unwrap();
+        assert!(!result.exact);
+        assert_eq!(result.u, 66.0);
+        close(result.p_value, 0.05639236422683164, 1e-6);
+        assert!(mann_whitney(&a, &[]).is_none());
+    }
+
+    #[test]
+    fn identical_values_are_no_evidence_of_a_difference() {
+        let result = mann_whitney(&[4.0; 15], &[4.0; 15]).unwrap();
+        assert_eq!(result.p_value, 1.0);
+        assert_eq!(result.prob_less, 0.5);
+    }
+
+    #[test]
+    fn bootstrap_resamples_repetitions_not_just_echoes() {
+        let mut rng = StdRng::seed_from_u64(3);
+        // Every echo agrees within a repetition, so only picking repetitions can move the median
+        let (fast, slow) = (vec![1000; 200], vec![5000; 200]);
+        let (low, high) = bootstrap_quantile(&[&fast, &slow], 0.5, 2000, &mut rng).unwrap();
+        assert_eq!((low, high), (1000.0, 5000.0));
+
+        let steady = vec![1000; 200];
+        assert_eq!(bootstrap_quantile(&[&steady, &steady], 0.5, 500, &mut rng), Some((1000.0, 1000.0)));
+        assert_eq!(bootstrap_quantile(&[&[]], 0.5, 500, &mut rng), None);
+        assert_eq!(bootstrap_quantile(&[&steady], 0.5, 0, &mut rng), None);
+    }
+
+    #[test]
+    fn bootstrap_difference_is_b_minus_a() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let (a, b) = (vec![1000; 100], vec![1250; 100]);
+        assert_eq!(bootstrap_quantile_diff(&[&a, &a], &[&b, &b], 0.99, 500, &mut rng), Some((250.0, 250.0)));
+
+        // Run-to-run spread in b widens the interval around the true difference
+        let (b_low, b_high) = (vec![1100; 100], vec![1400; 100]);
+        let (low, high) = bootstrap_quantile_diff(&[&a, &a], &[&b_low, &b_high], 0.5, 2000, &mut rng).unwrap();
+        assert_eq!((low, high), (100.0, 400.0));
+        assert_eq!(bootstrap_quantile_diff(&[&a], &[&[]], 0.5, 500, &mut rng), None);
+    }
+}
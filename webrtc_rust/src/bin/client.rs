@@ -1,45 +1,189 @@
 use std::sync::Arc;
 use webrtc::api::media_engine::MediaEngine;
 use webrtc::api::interceptor_registry::register_default_interceptors;
-use webrtc::api::APIBuilder;
+use webrtc::api::{APIBuilder, API};
 use webrtc::interceptor::registry::Registry;
-use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::Error;
 use std::io::stdin;
 use std::time::{Instant, Duration};
-use tokio::sync::{Mutex, Notify, mpsc};
+use tokio::sync::{Mutex, Notify};
 use tokio::time::sleep;
-use webrtc::data_channel::data_channel_message::DataChannelMessage;
 use webrtc::data_channel::RTCDataChannel;
 use webrtc::api::setting_engine::SettingEngine;
 use bytes::Bytes;
 use webrtc::dtls_transport::dtls_role::DTLSRole;
+use webrtc::peer_connection::RTCPeerConnection;
 use serde_json::{json, Value};
-// Remove unused import
 use csv::Writer;
-use std::collections::HashMap;
+use clap::Parser;
+use ons_common::clock::{DelayBreakdown, EchoTimestamps, OneWayDelay};
+use ons_common::dashboard::{Dashboard, DashboardOptions, Live};
+use ons_common::metrics::{Metrics, MetricsOptions, Side};
+use ons_common::handshake::{Handshake, HandshakeBench, HandshakeOptions};
+use ons_common::histogram::{HistogramOptions, LatencyHistogram};
+use ons_common::manifest::{ManifestOptions, RunManifest};
+use ons_common::outstanding::{DrainOptions, Echo, OutstandingTicks};
+use ons_common::scheduler::{SchedulerOptions, TickScheduler};
+use ons_common::stop::Stop;
+use ons_common::timeseries::{TimeSeries, TimeSeriesOptions};
+use ons_common::wire::now_micros;
+use ons_common::sequence::DatagramMetrics;
+use ons_common::wire::TickMessage;
+use ons_common::workload::WorkloadOptions;
+use webrtc_rust::handshake::PhaseMarks;
+use webrtc_rust::ice::IceOptions;
+use webrtc_rust::queue::{DirectHandler, Inbound, InboundQueue, QueueOptions};
+use webrtc_rust::rtp::{self, RtpTickSender};
+use webrtc_rust::sctp::{self, BackpressuredSender, SctpOptions};
+use webrtc_rust::signal::Signal;
+use webrtc_rust::stats::{self, LatestSample, StatsRecorder};
+use webrtc_rust::transport::{TickSender, TickTransport};
 
-// Constants for tick simulation
-const CLIENT_TICK_RATE: u64 = 32; // Reduced to 60 Hz to prevent connection overload
-const SIMULATION_DURATION_SECS: u64 = 60; // 3 minutes
-const BUFFER_SIZE: usize = 10000; // Buffer size for data channels
+// Defaults for --tick-rate and --simulation-duration-secs
+const CLIENT_TICK_RATE: u64 = 32;
+const SIMULATION_DURATION_SECS: u64 = 60;
+
+// Longest a benchmark connection may take before it counts as failed; past ICE's failed timeout
+const BENCH_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(flatten)]
+    workload: WorkloadOptions,
+
+    /// Carry ticks over the SCTP data channel or as RTP packets on a media track
+    #[arg(long, value_enum, default_value_t = TickTransport::DataChannel)]
+    transport: TickTransport,
+
+    /// Write periodic ICE/SCTP/data channel stats samples to this CSV file
+    #[arg(long, default_value = "webrtc_stats.csv")]
+    stats_file: String,
+
+    /// Interval between stats samples, in milliseconds
+    #[arg(long, default_value = "1000")]
+    stats_interval_ms: u64,
+
+    #[command(flatten)]
+    ice: IceOptions,
+
+    #[command(flatten)]
+    sctp: SctpOptions,
+
+    #[command(flatten)]
+    queue: QueueOptions,
+
+    /// Write the serialized RTT histogram here so runs can be merged later
+    #[arg(long, default_value = "webrtc_rtt.hdr")]
+    histogram_file: String,
+
+    #[command(flatten)]
+    histogram: HistogramOptions,
+
+    #[command(flatten)]
+    drain: DrainOptions,
+
+    #[command(flatten)]
+    timeseries: TimeSeriesOptions,
+
+    #[command(flatten)]
+    scheduler: SchedulerOptions,
+
+    #[command(flatten)]
+    handshake: HandshakeOptions,
+
+    /// Take the server's offer and send our answer over TCP to this address, instead of pasting them
+    #[arg(long)]
+    signal_addr: Option<std::net::SocketAddr>,
+
+    #[command(flatten)]
+    manifest: ManifestOptions,
+
+    #[command(flatten)]
+    dashboard: DashboardOptions,
+
+    #[command(flatten)]
+    metrics: MetricsOptions,
+}
+
+// Echo bookkeeping; shared with the data channel callback when messages are handled directly
+#[derive(Default)]
+struct RttTracker {
+    sent_ticks: OutstandingTicks<(Instant, u64)>,
+    rtt_samples: Vec<u128>,
+    rtt_histogram: LatencyHistogram,
+    recorded_ticks: Vec<u64>,
+    one_way_delay: OneWayDelay,
+    datagram_metrics: DatagramMetrics,
+    timeseries: TimeSeries,
+    live: Option<Live>,
+}
+
+impl RttTracker {
+    fn handle(&mut self, msg: Inbound) {
+        if let Ok(value) = serde_json::from_slice::<Value>(&msg.data) {
+            if let (Some(tick), Some(_timestamp)) = (value["tick"].as_u64(), value["timestamp"].as_u64()) {
+                // Receive time was taken in the callback, before any queueing delay
+                let received_at = msg.at;
+                match self.sent_ticks.take_at(tick, received_at) {
+                    Echo::OnTime((sent_time, sent_wall)) => {
+                        let rtt = received_at.saturating_duration_since(sent_time).as_micros();
+                        self.datagram_metrics.record(tick, rtt as i64);
+                        self.timeseries.received(tick, received_at, false);
+                        self.rtt_samples.push(rtt);
+                        self.rtt_histogram.record(rtt as u64);
+                        self.recorded_ticks.push(tick);
+                        let stamps = TickMessage::parse(&msg.data).and_then(|echo| {
+                            EchoTimestamps::from_echo(&echo, sent_wall, msg.received_at_micros)
+                        });
+                        self.one_way_delay.record(stamps);
+                        if let Some(live) = &self.live {
+                            live.echo(rtt as u64);
+                        }
+                        log::trace!("Received tick {}, RTT: {} μs", tick, rtt);
+                    }
+                    Echo::Late((sent_time, _)) => {
+                        // Arrived, so not lost, but kept out of the RTT statistics
+                        let rtt = received_at.saturating_duration_since(sent_time).as_micros();
+                        self.datagram_metrics.record(tick, rtt as i64);
+                        self.timeseries.received(tick, received_at, true);
+                        if let Some(live) = &self.live {
+                            live.late();
+                        }
+                        log::trace!("Received tick {} late, after {} μs", tick, rtt);
+                    }
+                    // The channel is unreliable, so a repeated tick is a duplicate rather than a new sample
+                    Echo::Unknown => self.datagram_metrics.record_unmatched(tick),
+                }
+            }
+        }
+    }
+}
 
 fn save_measurements(
     rtt_samples: &[u128],
     ticks: &[u64],
+    breakdowns: &[Option<DelayBreakdown>],
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Save raw measurements in CSV format
     let mut writer = Writer::from_path("webrtc_measurements.csv")?;
     
     // Write headers
-    writer.write_record(&["tick", "rtt"])?;
+    writer.write_record(&["tick", "rtt", "forward_us", "residence_us", "return_us"])?;
     
-    // Write the data rows
+    // Write the data rows, with the one-way breakdown when the server stamped the echo
     for i in 0..rtt_samples.len() {
+        let (forward, residence, ret) = match &breakdowns[i] {
+            Some(b) => (b.forward_us.to_string(), b.residence_us.to_string(), b.return_us.to_string()),
+            None => (String::new(), String::new(), String::new()),
+        };
         writer.write_record(&[
             ticks[i].to_string(),
             rtt_samples[i].to_string(),
+            forward,
+            residence,
+            ret,
         ])?;
     }
     
@@ -47,15 +191,21 @@ fn save_measurements(
     Ok(())
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+// A WebRTC API with our ICE, SCTP and codec settings, answering with `dtls_role`
+fn build_api(args: &Args, ice: &IceOptions, transport: TickTransport, dtls_role: DTLSRole) -> Result<API, Error> {
     let mut m = MediaEngine::default();
-    m.register_default_codecs()?;
+    if transport == TickTransport::Rtp {
+        // Only the tick codec, so its payload type can't collide with a default codec
+        rtp::register_tick_codec(&mut m)?;
+    } else {
+        m.register_default_codecs()?;
+    }
 
     let mut s = SettingEngine::default();
-    s.set_lite(true);
+    ice.apply(&mut s);
+    args.sctp.apply(&mut s);
     s.disable_media_engine_copy(true);
-    let _ = s.set_answering_dtls_role(DTLSRole::Client);
+    let _ = s.set_answering_dtls_role(dtls_role);
     // Set ICE timeouts for better reliability
     s.set_ice_timeouts(
         Some(Duration::from_secs(10)), // disconnected_timeout
@@ -70,38 +220,195 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let registry = Registry::new();
     let registry = register_default_interceptors(registry, &mut m)?;
 
-    let api = APIBuilder::new()
+    Ok(APIBuilder::new()
         .with_media_engine(m)
         .with_interceptor_registry(registry)
         .with_setting_engine(s)
-        .build();
+        .build())
+}
+
+// Open and close `runs` data channel connections, timing ICE, DTLS, the SCTP association
+// and the channel open. There's no one to paste SDP between runs, so the offering side is
+// a peer in this process set up like the server, and the SDP is handed over in memory.
+async fn connect_bench(args: &Args, runs: u32, stop: &Stop) -> Result<(), Box<dyn std::error::Error>> {
+    if args.handshake.resume {
+        println!("--resume has no effect on WebRTC: webrtc-rs keeps no DTLS session cache");
+    }
+    // Only one end may be ICE-lite, so the offering peer always runs full ICE
+    let offerer_ice = IceOptions { ice_lite: false, ..args.ice.clone() };
+    let mut bench = HandshakeBench::new("webrtc", &args.histogram);
+    for _ in 0..runs {
+        if stop.requested() {
+            break;
+        }
+        bench.record(bench_connection(args, &offerer_ice).await.map_err(|e| e.to_string()));
+        sleep(args.handshake.interval()).await;
+    }
+
+    let path = bench.save(&args.handshake)?;
+    println!("Handshake summary saved to {}", path.display());
+    Ok(())
+}
+
+// One benchmark connection between a fresh offering and answering peer
+async fn bench_connection(args: &Args, offerer_ice: &IceOptions) -> Result<Handshake, Error> {
+    let offerer = build_api(args, offerer_ice, TickTransport::DataChannel, DTLSRole::Server)?
+        .new_peer_connection(offerer_ice.configuration())
+        .await?;
+    let answerer = build_api(args, &args.ice, TickTransport::DataChannel, DTLSRole::Client)?
+        .new_peer_connection(args.ice.configuration())
+        .await?;
+    let handshake = match tokio::time::timeout(BENCH_CONNECT_TIMEOUT, bench_handshake(&offerer, &answerer)).await {
+        Ok(handshake) => handshake,
+        Err(_) => Err(Error::new(format!("not connected after {:?}", BENCH_CONNECT_TIMEOUT))),
+    };
+    // Closed whether or not it worked, so a failed attempt doesn't hold its ports into the next
+    let _ = answerer.close().await;
+    let _ = offerer.close().await;
+    handshake
+}
+
+// Exchange SDP in memory and time the answering peer's phases, from the moment the
+// offerer has the answer and both ends can run their connectivity checks
+async fn bench_handshake(offerer: &RTCPeerConnection, answerer: &RTCPeerConnection) -> Result<Handshake, Error> {
+    let phases = PhaseMarks::default();
+    phases.watch(answerer);
+    let open = Arc::new(Notify::new());
+    let open_phases = phases.clone();
+    let open_notify = Arc::clone(&open);
+    answerer.on_data_channel(Box::new(move |d: Arc<RTCDataChannel>| {
+        let open_phases = open_phases.clone();
+        let open_notify = Arc::clone(&open_notify);
+        Box::pin(async move {
+            d.on_open(Box::new(move || {
+                open_phases.data_channel_open();
+                open_notify.notify_one();
+                Box::pin(async {})
+            }));
+        })
+    }));
+
+    offerer.create_data_channel("data", Some(sctp::tick_channel_init())).await?;
+    let offer = offerer.create_offer(None).await?;
+    offerer.set_local_description(offer).await?;
+    let mut gather_complete = offerer.gathering_complete_promise().await;
+    gather_complete.recv().await;
+    let offer = offerer.local_description().await.ok_or(Error::new("Failed to get local description".to_string()))?;
+
+    answerer.set_remote_description(offer).await?;
+    let answer = answerer.create_answer(None).await?;
+    answerer.set_local_description(answer).await?;
+    let mut gather_complete = answerer.gathering_complete_promise().await;
+    gather_complete.recv().await;
+    let answer = answerer.local_description().await.ok_or(Error::new("Failed to get local description".to_string()))?;
+
+    // Started as the answer goes in, so a quick ICE check can't land before the clock does
+    phases.start();
+    offerer.set_remote_description(answer).await?;
+    open.notified().await;
+    // DTLS has no session cache here, so every handshake is a full one
+    Ok(phases.finish().with_resumption(Some(false), None))
+}
 
-    let config = RTCConfiguration::default();
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    // Per-tick lines are at trace level: RUST_LOG=client=trace brings them back
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("warn"));
+    args.scheduler.apply_to_current_thread()?;
+    // Stop early on Ctrl-C, keeping what was measured
+    let stop = Stop::on_signals()?;
+    let mut manifest = RunManifest::start("webrtc", &args, args.workload.summary(CLIENT_TICK_RATE, SIMULATION_DURATION_SECS), &args.manifest);
+    println!("Run manifest saved to {}", manifest.save()?.display());
+
+    if let Some(runs) = args.handshake.connect_bench {
+        connect_bench(&args, runs, &stop).await?;
+        let handshake_path = args.handshake.path("webrtc");
+        manifest.finish(&[handshake_path.with_extension("csv"), handshake_path]);
+        manifest.save()?;
+        return Ok(());
+    }
+
+    let api = build_api(&args, &args.ice, args.transport, DTLSRole::Client)?;
+    let config = args.ice.configuration();
     let peer_connection = Arc::new(api.new_peer_connection(config).await?);
+
+    // Sample the stack's own RTT and counters alongside the tick measurements
+    let recorder = StatsRecorder::create(&args.stats_file)?;
+    let latest_stats: LatestSample = Arc::new(std::sync::Mutex::new(None));
+    tokio::spawn(stats::run_sampler(
+        Arc::clone(&peer_connection),
+        recorder,
+        Duration::from_millis(args.stats_interval_ms),
+        Some(Arc::clone(&latest_stats)),
+    ));
     
+    // Echo handling; in RTP mode echoes arrive on the server's track, so hook it up before negotiating
+    let tracker = Arc::new(std::sync::Mutex::new(RttTracker {
+        sent_ticks: OutstandingTicks::new(&args.drain),
+        rtt_histogram: LatencyHistogram::new(&args.histogram),
+        ..Default::default()
+    }));
+    let queue = Arc::new(InboundQueue::new(&args.queue));
+    let tracker_direct = Arc::clone(&tracker);
+    let direct: DirectHandler = Arc::new(move |msg: Inbound| {
+        tracker_direct.lock().unwrap().handle(msg);
+        Box::pin(async {})
+    });
+    if args.transport == TickTransport::Rtp {
+        rtp::forward_remote_track(&peer_connection, Arc::clone(&queue), Some(Arc::clone(&direct)));
+    }
+
+    // Handshake phases, timed from our answer going to the server, which is when both ends
+    // can run their connectivity checks. Over --signal-addr the server applies it on arrival;
+    // pasted by hand, the ICE phase also holds however long the paste takes.
+    let phases = PhaseMarks::default();
+    phases.watch(&peer_connection);
+
     let data_channel_mutex = Arc::new(Mutex::new(None::<Arc<RTCDataChannel>>));
     let notify = Arc::new(Notify::new());
 
     let pc = Arc::clone(&peer_connection);
     let dc_mutex_clone = Arc::clone(&data_channel_mutex);
     let notify_clone = Arc::clone(&notify);
+    let dc_phases = phases.clone();
 
     pc.on_data_channel(Box::new(move |d: Arc<RTCDataChannel>| {
         let dc_mutex_inner = Arc::clone(&dc_mutex_clone);
         let notify_inner = Arc::clone(&notify_clone);
+        let open_phases = dc_phases.clone();
         Box::pin(async move {
+            d.on_open(Box::new(move || {
+                open_phases.data_channel_open();
+                notify_inner.notify_one();
+                Box::pin(async {})
+            }));
             println!("Data channel '{}' opened", d.label());
             *dc_mutex_inner.lock().await = Some(Arc::clone(&d));
-            notify_inner.notify_one();
         })
     }));
 
-    println!("Enter the SDP offer from the server:");
-    let mut remote_sdp = String::new();
-    stdin().read_line(&mut remote_sdp)?;
-    let remote_desc: RTCSessionDescription = serde_json::from_str(&remote_sdp.trim())?;
+    let mut signal = match args.signal_addr {
+        Some(addr) => Some(Signal::connect(addr).await?),
+        None => None,
+    };
+    let remote_desc: RTCSessionDescription = match &mut signal {
+        Some(signal) => signal.recv().await?,
+        None => {
+            println!("Enter the SDP offer from the server:");
+            let mut remote_sdp = String::new();
+            stdin().read_line(&mut remote_sdp)?;
+            serde_json::from_str(&remote_sdp.trim())?
+        }
+    };
     peer_connection.set_remote_description(remote_desc).await?;
 
+    // Answer the server's tick track with one of our own in the forward direction
+    let rtp_sender = match args.transport {
+        TickTransport::Rtp => Some(RtpTickSender::add_to(&peer_connection, "client-ticks").await?),
+        TickTransport::DataChannel => None,
+    };
+
     let answer = peer_connection.create_answer(None).await?;
     peer_connection.set_local_description(answer).await?;
 
@@ -112,41 +419,57 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .local_description()
         .await
         .ok_or(Error::new("Failed to get local description".to_string()))?;
-    let sdp = serde_json::to_string(&local_desc)?;
-    println!("Paste this SDP answer to the server:\n{}", sdp);
+    phases.start();
+    match &mut signal {
+        Some(signal) => signal.send(&local_desc).await?,
+        None => {
+            let sdp = serde_json::to_string(&local_desc)?;
+            println!("Paste this SDP answer to the server:\n{}", sdp);
+        }
+    }
 
     notify.notified().await;
+    // DTLS has no session cache here, so every handshake is a full one
+    let handshake = phases.finish().with_resumption(Some(false), None);
+    handshake.log();
     
     let dc_option = data_channel_mutex.lock().await;
     let dc = dc_option.as_ref().unwrap().clone();
+    let sender = match rtp_sender {
+        Some(rtp_sender) => TickSender::Rtp(rtp_sender),
+        None => {
+            queue.attach(&dc, Some(direct));
+            TickSender::DataChannel(BackpressuredSender::new(Arc::clone(&dc), &args.sctp).await)
+        }
+    };
 
-    let (tx, mut rx) = mpsc::channel::<Bytes>(BUFFER_SIZE);
-
-    let tx_clone = tx.clone();
-    dc.on_message(Box::new(move |msg: DataChannelMessage| {
-        let tx_inner = tx_clone.clone();
-        Box::pin(async move {
-            let _ = tx_inner.try_send(msg.data);  // Non-blocking send
-        })
-    }));
-
-    // Calculate tick duration in microseconds
-    let tick_duration_micros = 1_000_000 / CLIENT_TICK_RATE;
-    let tick_duration = Duration::from_micros(tick_duration_micros);
+    let tick_rate = args.workload.tick_rate(CLIENT_TICK_RATE);
+    let tick_duration = args.workload.tick_duration(CLIENT_TICK_RATE);
+    let simulation_duration = args.workload.duration(SIMULATION_DURATION_SECS);
+    let mut scheduler = TickScheduler::new(tick_duration, &args.scheduler);
     
-    // Store sent tick timestamps
-    let mut sent_ticks = HashMap::new();
-    let mut rtt_samples = Vec::with_capacity(SIMULATION_DURATION_SECS as usize * CLIENT_TICK_RATE as usize);
-    let mut recorded_ticks = Vec::with_capacity(SIMULATION_DURATION_SECS as usize * CLIENT_TICK_RATE as usize);
+    {
+        let mut tracker = tracker.lock().unwrap();
+        let expected = (simulation_duration.as_secs() * tick_rate) as usize;
+        tracker.rtt_samples.reserve(expected);
+        tracker.recorded_ticks.reserve(expected);
+        tracker.timeseries = TimeSeries::new(Instant::now());
+    }
     
     // Initialize current tick counter
     let mut current_tick: u64 = 0;
     
     // Calculate end time
-    let simulation_end_time = Instant::now() + Duration::from_secs(SIMULATION_DURATION_SECS);
+    let simulation_end_time = Instant::now() + simulation_duration;
+    let drain_end_time = simulation_end_time + args.drain.drain();
     
     println!("Starting tick-based simulation at {} ticks/sec for {} seconds...", 
-        CLIENT_TICK_RATE, SIMULATION_DURATION_SECS);
+        tick_rate, simulation_duration.as_secs());
+    let dashboard = Dashboard::start(&args.dashboard, &format!("WebRTC {:?}", args.transport), simulation_duration, &stop)?;
+    let session = Metrics::start(&args.metrics, "webrtc_client", Side::Client)?.session("peer");
+    dashboard.export(session.clone());
+    let live = dashboard.live();
+    tracker.lock().unwrap().live = Some(live.clone());
 
     // Monitor the connection state
     let pc_monitor = Arc::clone(&peer_connection);
@@ -159,72 +482,75 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("NOTE: WebRTC connection may have failed/disconnected. Data will resume when reconnected.");
             }
 
-            // If connected, log ICE transport information for XDP filtering
-            if state == webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState::Connected {
-                // Log local candidates for XDP filtering
-                let stats = pc_monitor.get_stats().await;
-                println!("WebRTC LOCAL CONNECTION INFO FOR XDP FILTERING:");
-                println!("Stats: {:?}", stats);
-                
-                // When you see this output, look for port numbers in the stats
-                // that you can target with your XDP filter
-            }
-            
             sleep(Duration::from_millis(1000)).await;
         }
     });
 
     // Simulation loop
-    while Instant::now() < simulation_end_time {
+    while Instant::now() < drain_end_time {
+        if stop.requested() {
+            println!("Stopping early after {} ticks", current_tick);
+            break;
+        }
+        let slot = scheduler.wait().await;
         let tick_start = Instant::now();
         
-        // Process incoming messages
-        while let Ok(data) = rx.try_recv() {
-            if let Ok(value) = serde_json::from_slice::<Value>(&data) {
-                if let (Some(tick), Some(_timestamp)) = (value["tick"].as_u64(), value["timestamp"].as_u64()) {
-                    if let Some(&sent_time) = sent_ticks.get(&tick) {
-                        // Get current time in microseconds since program start
-                        let now = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_micros();
-                        
-                        let rtt = now - sent_time;
-                        rtt_samples.push(rtt);
-                        recorded_ticks.push(tick);
-                        
-                        // Print RTT for every tick
-                        println!("Received tick {}, RTT: {} μs", tick, rtt);
-                    }
-                }
+        // Process incoming messages (a no-op in direct mode, where the callback handles them)
+        let pending = {
+            let mut tracker = tracker.lock().unwrap();
+            for msg in queue.drain() {
+                tracker.handle(msg);
+            }
+            tracker.sent_ticks.expire();
+            tracker.sent_ticks.pending()
+        };
+        live.outstanding(pending);
+
+        // After the last send, keep reading until the drain period ends or nothing is outstanding
+        if Instant::now() >= simulation_end_time {
+            if pending == 0 {
+                break;
             }
+            continue;
         }
         
         // Send current tick
-        // Use system time for consistent timestamp handling
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-            
-        let message = json!({
-            "tick": current_tick,
-            "timestamp": now
-        }).to_string();
+        let now = now_micros();
+        let message = args.workload.message(current_tick, now as u128);
         
         let message_bytes = Bytes::from(message.into_bytes());
         
         // Check data channel state before sending
-        if dc.ready_state() == webrtc::data_channel::data_channel_state::RTCDataChannelState::Open {
-            if let Err(e) = dc.send(&message_bytes).await {
-                println!("Error sending tick {}: {}", current_tick, e);
-            } else {
-                // Store sent tick time
-                sent_ticks.insert(current_tick, now);
+        if sender.is_open() {
+            // Store sent tick time first, in case the echo is handled directly in the callback
+            let snapshot = latest_stats.lock().unwrap().as_ref().map(|sample| {
+                session.stack(&sample.counters());
+                sample.snapshot()
+            });
+            {
+                let mut tracker = tracker.lock().unwrap();
+                tracker.sent_ticks.insert(current_tick, (Instant::now(), now));
+                tracker.timeseries.sent(current_tick, Instant::now(), message_bytes.len(), snapshot.unwrap_or_default());
+            }
+
+            // Wait at most one tick for SCTP to drain before giving up on this tick
+            match sender.send(&message_bytes, tick_duration).await {
+                Ok(true) => {
+                    scheduler.sent(&slot);
+                    live.sent(slot.deadline.elapsed(), snapshot.unwrap_or_default());
+                }
+                Ok(false) => {
+                    tracker.lock().unwrap().sent_ticks.remove(current_tick);
+                    log::debug!("Skipped tick {}: data channel still above buffered amount threshold", current_tick);
+                }
+                Err(e) => {
+                    tracker.lock().unwrap().sent_ticks.remove(current_tick);
+                    log::warn!("Error sending tick {}: {}", current_tick, e);
+                }
             }
         } else {
             // Don't increment tick counter when connection is down
-            println!("Data channel not open, state: {}", dc.ready_state());
+            log::warn!("Data channel not open, state: {}", dc.ready_state());
             // Give a bit more time for reconnection
             sleep(Duration::from_millis(100)).await;
             continue;
@@ -238,42 +564,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             tokio::task::yield_now().await;
         }
         
-        // Sleep until next tick
+        // The scheduler waits for the next absolute deadline; just report ticks that overran it
         let elapsed = tick_start.elapsed();
-        if elapsed < tick_duration {
-            sleep(tick_duration - elapsed).await;
-        } else {
-            println!("Warning: Tick {} processing took longer than tick duration: {:?}", 
+        if elapsed >= tick_duration {
+            log::debug!("Tick {} processing took longer than tick duration: {:?}",
                 current_tick - 1, elapsed);
+            tracker.lock().unwrap().timeseries.mark_overrun(current_tick - 1);
+            live.overrun();
         }
     }
 
+    dashboard.finish();
     println!("\nSimulation completed!");
+
+    let RttTracker {
+        sent_ticks, rtt_samples, rtt_histogram, recorded_ticks, one_way_delay, datagram_metrics, timeseries, live: _,
+    } = std::mem::take(&mut *tracker.lock().unwrap());
     
     if !rtt_samples.is_empty() {
-        // Calculate statistics
-        let calculate_stats = |times: &Vec<u128>| {
-            let mut sorted = times.clone();
-            sorted.sort_unstable();
-            let min = sorted[0];
-            let max = sorted[sorted.len() - 1];
-            let avg = times.iter().sum::<u128>() as f64 / times.len() as f64;
-            let p50 = sorted[times.len() / 2];
-            let p95 = sorted[(times.len() as f64 * 0.95) as usize];
-            let p99 = sorted[(times.len() as f64 * 0.99) as usize];
-            (min, max, avg, p50, p95, p99)
-        };
+        let rtt_stats = rtt_histogram.summary();
+        let stat = |key: &str| rtt_stats[key].clone();
 
-        let (min_rtt, max_rtt, avg_rtt, p50_rtt, p95_rtt, p99_rtt) = calculate_stats(&rtt_samples);
-        
         println!("\nRTT Statistics:");
         println!("  Total samples: {}", rtt_samples.len());
-        println!("  Min: {} µs", min_rtt);
-        println!("  Max: {} µs", max_rtt);
-        println!("  Average: {:.2} µs", avg_rtt);
-        println!("  50th percentile: {} µs", p50_rtt);
-        println!("  95th percentile: {} µs", p95_rtt);
-        println!("  99th percentile: {} µs", p99_rtt);
+        println!("  Min: {} µs", stat("min"));
+        println!("  Max: {} µs", stat("max"));
+        println!("  Mean: {:.2} µs (stddev {:.2})", stat("mean").as_f64().unwrap_or(0.0),
+            stat("stddev").as_f64().unwrap_or(0.0));
+        println!("  50th percentile: {} µs", stat("p50"));
+        println!("  90th percentile: {} µs", stat("p90"));
+        println!("  99th percentile: {} µs", stat("p99"));
+        println!("  99.9th percentile: {} µs", stat("p99_9"));
+        println!("  99.99th percentile: {} µs", stat("p99_99"));
 
         // Calculate message loss
         let expected_messages = current_tick;
@@ -288,62 +610,105 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("  Messages received: {}", received_messages);
         println!("  Message loss rate: {:.2}%", loss_rate);
 
-        // Print distribution of RTTs in millisecond buckets
-        println!("\nRTT Distribution (1ms buckets):");
-        let mut buckets = vec![0; 100]; // 0-100ms in 1ms increments
-        for &rtt in &rtt_samples {
-            let bucket = (rtt / 1000) as usize; // Convert to milliseconds
-            if bucket < buckets.len() {
-                buckets[bucket] += 1;
-            }
+        // Echoes discarded by our own inbound queue are not network loss
+        let local_queue_drops = queue.dropped();
+        let late_echoes = sent_ticks.late();
+        let transport_loss = expected_messages.saturating_sub(received_messages + local_queue_drops + late_echoes);
+        println!("  Dropped in local queue: {}", local_queue_drops);
+        println!("  Late (after {} ms): {}", args.drain.late_after_ms, late_echoes);
+        println!("  Lost in transport: {}", transport_loss);
+        println!("  Reordered: {}, duplicates: {}, jitter: {:.1} µs", datagram_metrics.reordered(),
+            datagram_metrics.duplicates(), datagram_metrics.jitter_us());
+        let transport_loss_rate = if expected_messages > 0 {
+            transport_loss as f64 / expected_messages as f64 * 100.0
+        } else {
+            0.0
+        };
+        if let Some(backpressure) = sender.backpressure() {
+            println!("  Backpressure stalls: {} ({} ticks skipped, {:?} waiting)",
+                backpressure.stalls(), backpressure.skipped(), backpressure.stall_time());
         }
-        
-        for (i, count) in buckets.iter().enumerate() {
-            if *count > 0 {  // Only print non-empty buckets
-                println!("{}-{}ms: {} samples", i, i+1, count);
-            }
+
+        // Print distribution of RTTs in millisecond buckets, read back from the histogram so
+        // nothing above the old 100 ms cap goes missing
+        println!("\nRTT Distribution (1ms buckets):");
+        let buckets = rtt_histogram.buckets(1000);
+        for &(start, count) in &buckets {
+            println!("{}-{}ms: {} samples", start / 1000, start / 1000 + 1, count);
         }
 
         // Save raw data to CSV
-        save_measurements(&rtt_samples, &recorded_ticks)?;
+        let breakdowns = match one_way_delay.estimate_offset() {
+            Some(offset) => {
+                println!("  Estimated server clock offset: {} µs", offset.offset_us);
+                one_way_delay.breakdown(&offset)
+            }
+            None => vec![None; rtt_samples.len()],
+        };
+        save_measurements(&rtt_samples, &recorded_ticks, &breakdowns)?;
 
         // Save summary statistics to JSON
         let summary = json!({
-            "tick_rate": CLIENT_TICK_RATE,
+            "tick_rate": tick_rate,
+            "workload": args.workload.summary(CLIENT_TICK_RATE, SIMULATION_DURATION_SECS),
             "sample_count": rtt_samples.len(),
             "messages_sent": expected_messages,
             "messages_received": received_messages,
             "loss_rate_percent": loss_rate,
-            "distribution_ms": buckets.iter().enumerate()
-                .filter(|(_, &count)| count > 0)
-                .map(|(i, &count)| {
+            "local_queue_drops": local_queue_drops,
+            "transport_loss": transport_loss,
+            "transport_loss_rate_percent": transport_loss_rate,
+            "queue": queue.summary(),
+            "transport": format!("{:?}", args.transport),
+            "backpressure": sender.backpressure().map(|backpressure| json!({
+                "stalls": backpressure.stalls(),
+                "skipped_ticks": backpressure.skipped(),
+                "stall_time_micros": backpressure.stall_time().as_micros()
+            })),
+            "distribution_ms": buckets.iter()
+                .map(|&(start, count)| {
                     json!({
-                        "bucket": format!("{}-{}", i, i+1),
+                        "bucket": format!("{}-{}", start / 1000, start / 1000 + 1),
                         "count": count
                     })
                 })
                 .collect::<Vec<_>>(),
-            "rtt_micros": {
-                "min": min_rtt,
-                "max": max_rtt,
-                "avg": avg_rtt,
-                "p50": p50_rtt,
-                "p95": p95_rtt,
-                "p99": p99_rtt
-            }
+            "rtt_micros": rtt_stats,
+            "one_way_delay": one_way_delay.summary(),
+            "datagram": datagram_metrics.summary(expected_messages),
+            "echoes": sent_ticks.summary(expected_messages, received_messages),
+            "scheduler": scheduler.summary(),
+            "handshake": handshake.summary()
         });
 
         std::fs::write(
             "webrtc_summary.json",
             serde_json::to_string_pretty(&summary)?
         )?;
+        rtt_histogram.save(&args.histogram_file)?;
         
         println!("\nMeasurements saved to webrtc_measurements.csv");
         println!("Summary saved to webrtc_summary.json");
+        println!("RTT histogram saved to {}", args.histogram_file);
     } else {
         println!("No RTT samples collected during simulation!");
     }
 
+    // Last, so a failure here can't cost the RTT measurements and summary above
+    match timeseries.save(&args.timeseries, "webrtc") {
+        Ok(path) => println!("Per-tick time series saved to {}", path.display()),
+        Err(e) => eprintln!("Failed to save the per-tick time series: {}", e),
+    }
+
+    manifest.finish(&[
+        "webrtc_measurements.csv".into(),
+        "webrtc_summary.json".into(),
+        args.histogram_file.clone().into(),
+        args.stats_file.clone().into(),
+        args.timeseries.path("webrtc"),
+    ]);
+    manifest.save()?;
+
     println!("Client disconnected.");
     Ok(())
 }
\ No newline at end of file
@@ -1,36 +1,167 @@
 // server.rs
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 use webrtc::api::media_engine::MediaEngine;
 use webrtc::api::interceptor_registry::register_default_interceptors;
 use webrtc::api::APIBuilder;
 use webrtc::interceptor::registry::Registry;
-use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::Error;
 use std::io::stdin;
 use std::time::{Instant, Duration};
 use tokio::time::sleep;
-use webrtc::data_channel::data_channel_message::DataChannelMessage;
 use webrtc::api::setting_engine::SettingEngine;
-use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
 use webrtc::dtls_transport::dtls_role::DTLSRole;
-use tokio::sync::{mpsc, Mutex};
 use bytes::Bytes;
-use serde_json::{json, Value};
-use std::collections::VecDeque;
+use serde_json::json;
 use std::sync::atomic::{AtomicBool, Ordering};
+use clap::Parser;
+use ons_common::echo::{EchoMode, EchoOptions, EchoStats};
+use ons_common::manifest::{ManifestOptions, RunManifest};
+use ons_common::metrics::{Metrics, MetricsOptions, Side};
+use ons_common::scheduler::{SchedulerOptions, TickScheduler};
+use ons_common::stop::Stop;
+use ons_common::wire::{now_micros, stamp_echo, TickMessage};
+use webrtc_rust::ice::{self, IceOptions};
+use webrtc_rust::queue::{DirectHandler, Inbound, InboundQueue, QueueOptions, QueueStrategy};
+use webrtc_rust::rtp::{self, RtpTickSender};
+use webrtc_rust::sctp::{self, BackpressuredSender, SctpOptions};
+use webrtc_rust::signal::Signal;
+use webrtc_rust::stats::{self, LatestSample, StatsRecorder};
+use webrtc_rust::transport::{TickSender, TickTransport};
 
 // Constants for tick simulation
-const SERVER_TICK_RATE: u64 = 32; // Ticks per second - reduced to prevent connection overload
-const BUFFER_SIZE: usize = 10000; // Buffer size for data channels
+const SERVER_TICK_RATE: u64 = 32; // Default ticks per second, override with --tick-rate
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Server ticks per second
+    #[arg(long, default_value_t = SERVER_TICK_RATE)]
+    tick_rate: u64,
+
+    /// Carry ticks over the SCTP data channel or as RTP packets on a media track
+    #[arg(long, value_enum, default_value_t = TickTransport::DataChannel)]
+    transport: TickTransport,
+
+    /// Write periodic ICE/SCTP/data channel stats samples to this CSV file
+    #[arg(long, default_value = "webrtc_server_stats.csv")]
+    stats_file: String,
+
+    /// Interval between stats samples, in milliseconds
+    #[arg(long, default_value = "1000")]
+    stats_interval_ms: u64,
+
+    #[command(flatten)]
+    ice: IceOptions,
+
+    #[command(flatten)]
+    sctp: SctpOptions,
+
+    #[command(flatten)]
+    queue: QueueOptions,
+
+    #[command(flatten)]
+    echo: EchoOptions,
+
+    #[command(flatten)]
+    scheduler: SchedulerOptions,
+
+    /// Write the echo mode, server residence times and receive queue counters here, refreshed every second
+    #[arg(long, default_value = "webrtc_server_summary.json")]
+    summary_file: String,
+
+    /// Start an in-process TURN server on this address (e.g. 127.0.0.1:3478)
+    #[arg(long)]
+    embedded_turn: Option<std::net::SocketAddr>,
+
+    /// Address the embedded TURN server allocates relay ports on
+    #[arg(long, default_value = "127.0.0.1")]
+    turn_relay_ip: std::net::IpAddr,
+
+    /// Hand the offer to the client and take its answer over TCP on this address, instead of pasting them
+    #[arg(long)]
+    signal_addr: Option<std::net::SocketAddr>,
+
+    #[command(flatten)]
+    manifest: ManifestOptions,
+
+    #[command(flatten)]
+    metrics: MetricsOptions,
+}
+
+// Echo a tick message back as it came, padding and all, plus our receive and send times
+async fn echo(sender: &TickSender, msg: &Inbound, max_wait: Duration, stats: &Mutex<EchoStats>) {
+    let tick = TickMessage::parse(&msg.data).map_or_else(|| "?".to_string(), |message| message.tick.to_string());
+    log::trace!("Server received tick {}", tick);
+
+    let recv_us = msg.received_at_micros;
+    let mut send_us = 0;
+
+    // Check transport state before sending
+    if sender.is_open() {
+        // Stamped once backpressure lets the echo out, so any wait counts as server residence
+        let sent = sender.send_built(max_wait, || {
+            send_us = now_micros();
+            Bytes::from(stamp_echo(&msg.data, recv_us, send_us))
+        });
+        match sent.await {
+            Ok(true) => {
+                stats.lock().unwrap().echoed(recv_us, send_us);
+                log::trace!("Server sent response for tick {}", tick)
+            }
+            Ok(false) => println!("Dropped response for tick {}: data channel still above buffered amount threshold",
+                tick),
+            Err(e) => println!("Error sending response for tick {}: {}", tick, e),
+        }
+    } else {
+        println!("Transport not open, dropping response for tick {}", tick);
+    }
+}
+
+// Write the summary and mark the manifest finished as of now
+fn save_summary(stats: &Mutex<EchoStats>, queue: &InboundQueue, manifest: &mut RunManifest, args: &Args) {
+    if let Err(e) = stats.lock().unwrap().save_with(&args.summary_file, json!({ "queue": queue.summary() })) {
+        println!("Failed to save server summary: {}", e);
+    }
+    manifest.finish(&[&args.summary_file, &args.stats_file]);
+    if let Err(e) = manifest.save() {
+        println!("Failed to update run manifest: {}", e);
+    }
+}
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = Args::parse();
+    // Per-tick lines are at trace level: RUST_LOG=server=trace brings them back
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("warn"));
+    args.scheduler.apply_to_current_thread()?;
+    // SIGINT ends the tick loop, after a last summary is written
+    let stop = Stop::on_signals()?;
+    // Echoing from the data channel callback is what immediate mode means here, so the two go together
+    if args.echo.immediate() {
+        args.queue.queue_strategy = QueueStrategy::Direct;
+    } else if args.queue.queue_strategy == QueueStrategy::Direct {
+        args.echo.echo_mode = EchoMode::Immediate;
+    }
+    println!("Echo mode: {:?}", args.echo.echo_mode);
+    let mut manifest = RunManifest::start("webrtc_server", &args, json!({ "tick_rate": args.tick_rate }), &args.manifest);
+    println!("Run manifest saved to {}", manifest.save()?.display());
+    let metrics = Metrics::start(&args.metrics, "webrtc_server", Side::Server)?;
+    // One peer connection per server, so one session
+    let session = metrics.session("peer");
+
     let mut m = MediaEngine::default();
-    m.register_default_codecs()?;
+    if args.transport == TickTransport::Rtp {
+        // Only the tick codec, so its payload type can't collide with a default codec
+        rtp::register_tick_codec(&mut m)?;
+    } else {
+        m.register_default_codecs()?;
+    }
 
     let mut s = SettingEngine::default();
-    s.set_lite(true);
+    args.ice.apply(&mut s);
+    args.sctp.apply(&mut s);
     s.disable_media_engine_copy(true);
     let _ = s.set_answering_dtls_role(DTLSRole::Server);
     // Set ICE timeouts for better reliability
@@ -53,25 +184,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_setting_engine(s)
         .build();
 
-    let config = RTCConfiguration::default();
+    // Keep the embedded TURN server alive for as long as the peer connection runs
+    let _turn_server = match args.embedded_turn {
+        Some(listen) => Some(
+            ice::start_embedded_turn(listen, args.turn_relay_ip, &args.ice.turn_username, &args.ice.turn_credential)
+                .await?,
+        ),
+        None => None,
+    };
+
+    // Without explicit ICE servers, point this end at its own embedded relay
+    let mut ice_options = args.ice.clone();
+    if let Some(listen) = args.embedded_turn {
+        if ice_options.ice_servers.is_empty() {
+            ice_options.ice_servers.push(format!("turn:{}?transport=udp", listen));
+        }
+    }
+    let config = ice_options.configuration();
 
     let peer_connection = Arc::new(api.new_peer_connection(config).await?);
 
-    let mut data_channel_init = RTCDataChannelInit::default();
-    data_channel_init.ordered = Some(false);
-    data_channel_init.max_retransmits = Some(0);
-    data_channel_init.protocol = Some("binary".to_string());
+    // Sample the stack's own RTT and counters for comparison with the client's tick RTT
+    let recorder = StatsRecorder::create(&args.stats_file)?;
+    let latest_stats: LatestSample = Arc::new(Mutex::new(None));
+    tokio::spawn(stats::run_sampler(
+        Arc::clone(&peer_connection),
+        recorder,
+        Duration::from_millis(args.stats_interval_ms),
+        Some(Arc::clone(&latest_stats)),
+    ));
 
-    let data_channel = peer_connection.create_data_channel("data", Some(data_channel_init)).await?;
-    
-    // Create a message queue to store incoming messages
-    let message_queue = Arc::new(Mutex::new(VecDeque::new()));
+    let data_channel = peer_connection.create_data_channel("data", Some(sctp::tick_channel_init())).await?;
+    // Echoes go back on our own track in RTP mode, so it has to exist before the offer
+    let sender = Arc::new(match args.transport {
+        TickTransport::Rtp => TickSender::Rtp(RtpTickSender::add_to(&peer_connection, "server-ticks").await?),
+        TickTransport::DataChannel => {
+            TickSender::DataChannel(BackpressuredSender::new(Arc::clone(&data_channel), &args.sctp).await)
+        }
+    });
+
+    // Calculate tick duration in microseconds
+    let tick_duration_micros = 1_000_000 / args.tick_rate.max(1);
+    let tick_duration = Duration::from_micros(tick_duration_micros);
     
-    // Flag to indicate if the server should start ticking (set to true after first message)
+    // Flag to indicate if the server should be ticking (cleared when the connection fails)
     let start_ticking = Arc::new(AtomicBool::new(false));
-    
-    // Create channels for message passing
-    let (tx, mut rx) = mpsc::channel::<Bytes>(BUFFER_SIZE);
 
     let dc = Arc::clone(&data_channel);
     dc.on_open(Box::new(move || {
@@ -79,15 +236,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Box::pin(async {})
     }));
 
-    // Handle incoming messages
-    let tx_clone = tx.clone();
-    let dc_for_message = Arc::clone(&data_channel);
-    dc_for_message.on_message(Box::new(move |msg: DataChannelMessage| {
-        let tx_inner = tx_clone.clone();
+    // Handle incoming messages: queue them for the tick loop, or echo straight from the callback
+    let echo_stats = Arc::new(Mutex::new(EchoStats::new(&args.echo, tick_duration).with_metrics(session.clone())));
+    let message_queue = Arc::new(InboundQueue::new(&args.queue));
+    let direct_sender = Arc::clone(&sender);
+    let direct_stats = Arc::clone(&echo_stats);
+    let direct: DirectHandler = Arc::new(move |msg: Inbound| {
+        let sender = Arc::clone(&direct_sender);
+        let stats = Arc::clone(&direct_stats);
         Box::pin(async move {
-            let _ = tx_inner.try_send(msg.data);  // Non-blocking send
+            echo(&sender, &msg, tick_duration, &stats).await;
         })
-    }));
+    });
+    match args.transport {
+        TickTransport::Rtp => rtp::forward_remote_track(&peer_connection, Arc::clone(&message_queue), Some(direct)),
+        TickTransport::DataChannel => message_queue.attach(&data_channel, Some(direct)),
+    }
 
     peer_connection.on_ice_connection_state_change(Box::new(|s| {
         println!("ICE Connection State has changed: {}", s);
@@ -104,39 +268,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .local_description()
         .await
         .ok_or(Error::new("Failed to get local description".to_string()))?;
-    let sdp = serde_json::to_string(&local_desc)?;
-    println!("Paste this SDP offer to the client:\n{}", sdp);
+    let remote_desc: RTCSessionDescription = match args.signal_addr {
+        Some(addr) => {
+            let mut signal = Signal::accept(addr).await?;
+            signal.send(&local_desc).await?;
+            signal.recv().await?
+        }
+        None => {
+            let sdp = serde_json::to_string(&local_desc)?;
+            println!("Paste this SDP offer to the client:\n{}", sdp);
 
-    println!("Enter the SDP answer from the client:");
-    let mut remote_sdp = String::new();
-    stdin().read_line(&mut remote_sdp)?;
-    let remote_desc: RTCSessionDescription = serde_json::from_str(&remote_sdp.trim())?;
+            println!("Enter the SDP answer from the client:");
+            let mut remote_sdp = String::new();
+            stdin().read_line(&mut remote_sdp)?;
+            serde_json::from_str(&remote_sdp.trim())?
+        }
+    };
     peer_connection.set_remote_description(remote_desc).await?;
 
     println!("Server running, waiting for first message to start tick simulation...");
 
-    // Create queue for storing incoming messages
-    let msg_queue = Arc::clone(&message_queue);
-    let tick_flag = Arc::clone(&start_ticking);
-    
-    // Message processor task
-    tokio::spawn(async move {
-        while let Some(data) = rx.recv().await {
-            let mut queue = msg_queue.lock().await;
-            queue.push_back(data);
-            
-            // Set start_ticking to true after receiving first message
-            if !tick_flag.load(Ordering::SeqCst) {
-                tick_flag.store(true, Ordering::SeqCst);
-                println!("First message received, starting tick simulation!");
-            }
-        }
-    });
-
-    // Calculate tick duration in microseconds
-    let tick_duration_micros = 1_000_000 / SERVER_TICK_RATE;
-    let tick_duration = Duration::from_micros(tick_duration_micros);
-    
     // Monitor connection state
     let pc_monitor = Arc::clone(&peer_connection);
     let monitor_tick_flag = Arc::clone(&start_ticking);
@@ -155,57 +306,61 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("Connection failed. Will wait for new message to restart tick simulation.");
             }
 
-            // If connected, log ICE transport information for XDP filtering
-            if state == webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState::Connected {
-                // Log local candidates for XDP filtering
-                let stats = pc_monitor.get_stats().await;
-                println!("WebRTC LOCAL CONNECTION INFO FOR XDP FILTERING:");
-                println!("Stats: {:?}", stats);
-                
-                // When you see this output, look for port numbers in the stats
-                // that you can target with your XDP filter
-            }
-            
             sleep(Duration::from_millis(1000)).await;
         }
     });
     
-    // Data channel for the tick loop
-    let tick_dc = Arc::clone(&data_channel);
     
     // Wait for first message before starting tick simulation
-    while !start_ticking.load(Ordering::SeqCst) {
+    while message_queue.received() == 0 {
+        if stop.requested() {
+            return Ok(());
+        }
         sleep(Duration::from_millis(10)).await;
     }
+    start_ticking.store(true, Ordering::SeqCst);
+    println!("First message received, starting tick simulation!");
     
-    println!("Tick simulation started at {} ticks/sec", SERVER_TICK_RATE);
+    println!("Tick simulation started at {} ticks/sec", args.tick_rate);
     
     let mut consecutive_empty_ticks = 0;
+    let mut backlog = VecDeque::new();
+    let mut last_saved = Instant::now();
+    let mut counted = 0;
     
-    // Tick loop
-    loop {
+    // Tick loop, on absolute deadlines so processing time doesn't drift the schedule
+    let mut scheduler = TickScheduler::new(tick_duration, &args.scheduler);
+    while !stop.requested() {
+        scheduler.wait().await;
         let tick_start = Instant::now();
         
         // Check if we should still be ticking
         if !start_ticking.load(Ordering::SeqCst) {
             // Wait for first message again
             println!("Waiting for client to reconnect and send first message...");
-            while !start_ticking.load(Ordering::SeqCst) {
+            let seen = message_queue.received();
+            while message_queue.received() == seen && !stop.requested() {
                 sleep(Duration::from_millis(100)).await;
             }
+            start_ticking.store(true, Ordering::SeqCst);
             println!("Client reconnected! Resuming tick simulation.");
             consecutive_empty_ticks = 0;
+            // The wait isn't missed ticks; start a fresh schedule
+            scheduler = TickScheduler::new(tick_duration, &args.scheduler);
         }
         
-        // Process all queued messages
-        let messages_to_process = {
-            let mut queue = message_queue.lock().await;
-            let msgs = queue.drain(..).collect::<Vec<_>>();
-            msgs
-        };
+        // Take this tick's share of the queued messages (all of them unless the echo mode caps it)
+        backlog.extend(message_queue.drain());
+        let received = message_queue.received();
+        session.received(received - counted);
+        counted = received;
+        let messages_to_process = args.echo.take_tick(&mut backlog);
+        if !args.echo.immediate() {
+            echo_stats.lock().unwrap().tick(messages_to_process.len(), backlog.len());
+        }
         
-        // Track empty ticks for health monitoring
-        if messages_to_process.is_empty() {
+        // Track empty ticks for health monitoring (in direct mode the tick loop never sees messages)
+        if messages_to_process.is_empty() && message_queue.strategy() != QueueStrategy::Direct {
             consecutive_empty_ticks += 1;
             
             // If we haven't received anything for a while, log it (but keep going)
@@ -217,44 +372,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         
         // Process each message
-        for data in messages_to_process {
-            if let Ok(value) = serde_json::from_slice::<Value>(&data) {
-                if let (Some(tick), Some(timestamp)) = (value["tick"].as_u64(), value["timestamp"].as_u64()) {
-                    // Print received tick
-                    println!("Server received tick {} with timestamp {}", tick, timestamp);
-                    
-                    // Echo the message back with the same tick number and timestamp
-                    let response = json!({
-                        "tick": tick,
-                        "timestamp": timestamp
-                    }).to_string();
-                    
-                    let response_bytes = Bytes::from(response.into_bytes());
-                    
-                    // Check data channel state before sending
-                    if data_channel.ready_state() == webrtc::data_channel::data_channel_state::RTCDataChannelState::Open {
-                        if let Err(e) = tick_dc.send(&response_bytes).await {
-                            println!("Error sending response for tick {}: {}", tick, e);
-                        } else {
-                            println!("Server sent response for tick {}", tick);
-                        }
-                    } else {
-                        println!("Data channel not open, state: {}", data_channel.ready_state());
-                    }
-                }
+        for msg in messages_to_process {
+            echo(&sender, &msg, tick_duration, &echo_stats).await;
+        }
+
+        // The server runs until it is killed, so keep the summary on disk current
+        if last_saved.elapsed() >= Duration::from_secs(1) {
+            save_summary(&echo_stats, &message_queue, &mut manifest, &args);
+            if let Some(sample) = latest_stats.lock().unwrap().as_ref() {
+                session.transport(sample.snapshot());
+                session.stack(&sample.counters());
             }
+            session.local_queue(message_queue.dropped(), message_queue.high_water());
+            last_saved = Instant::now();
         }
         
-        // Calculate time to sleep until next tick
+        // The scheduler waits for the next deadline; just report ticks that overran it
         let elapsed = tick_start.elapsed();
-        if elapsed < tick_duration {
-            sleep(tick_duration - elapsed).await;
-        } else {
-            // If processing took longer than the tick duration, log a warning
+        if elapsed > tick_duration {
+            echo_stats.lock().unwrap().overrun();
             println!("Warning: Tick processing took longer than tick duration: {:?}", elapsed);
         }
-        
-        // Yield periodically to prevent blocking
-        tokio::task::yield_now().await;
     }
+
+    println!("Stopping; saving the final summary");
+    save_summary(&echo_stats, &message_queue, &mut manifest, &args);
+    Ok(())
 }
@@ -0,0 +1,89 @@
+// Handshake phase timing for a peer connection, for the client's tick run and --connect-bench.
+//
+// The phases follow the stack: ICE connectivity checks, the DTLS handshake (the peer
+// connection reports connected once it's done), the SCTP association over DTLS, and the
+// DCEP exchange that opens the data channel.
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ons_common::handshake::{Handshake, PhaseTimer};
+use tokio::time::sleep;
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::sctp_transport::sctp_transport_state::RTCSctpTransportState;
+use webrtc::sctp_transport::RTCSctpTransport;
+
+/// How often to look for the SCTP association, which webrtc-rs has no callback for
+const SCTP_POLL: Duration = Duration::from_millis(1);
+
+/// Phase marks for one peer connection, shared with its state callbacks
+#[derive(Clone)]
+pub struct PhaseMarks(Arc<Mutex<PhaseTimer>>);
+
+impl Default for PhaseMarks {
+    fn default() -> Self {
+        PhaseMarks(Arc::new(Mutex::new(PhaseTimer::start())))
+    }
+}
+
+impl PhaseMarks {
+    /// Restart the clock. Call it once the remote description is applied, which is
+    /// when ICE can begin.
+    pub fn start(&self) {
+        *self.0.lock().unwrap() = PhaseTimer::start();
+    }
+
+    pub fn mark(&self, phase: &'static str) {
+        self.0.lock().unwrap().mark(phase);
+    }
+
+    /// Mark ICE and DTLS from the peer connection's state changes, and the SCTP
+    /// association once it's up. This takes over both state change handlers.
+    pub fn watch(&self, pc: &RTCPeerConnection) {
+        let ice = self.clone();
+        pc.on_ice_connection_state_change(Box::new(move |state: RTCIceConnectionState| {
+            if state == RTCIceConnectionState::Connected {
+                ice.mark("ice");
+            }
+            Box::pin(async {})
+        }));
+        let dtls = self.clone();
+        let sctp = pc.sctp();
+        pc.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+            if state == RTCPeerConnectionState::Connected {
+                dtls.mark("dtls");
+                // The association starts once DTLS is up
+                tokio::spawn(wait_for_association(Arc::clone(&sctp), dtls.clone()));
+            }
+            Box::pin(async {})
+        }));
+    }
+
+    /// Mark the data channel open once DCEP has finished. The association is up by
+    /// then, so if the poll hasn't seen it yet it's marked here, with the DCEP phase
+    /// taking none of its time.
+    pub fn data_channel_open(&self) {
+        let mut timer = self.0.lock().unwrap();
+        timer.mark("sctp");
+        timer.mark("data_channel_open");
+    }
+
+    /// The phases marked so far; the clock restarts for any later connection
+    pub fn finish(&self) -> Handshake {
+        std::mem::replace(&mut *self.0.lock().unwrap(), PhaseTimer::start()).finish()
+    }
+}
+
+// Mark "sctp" when the association comes up, to within SCTP_POLL
+async fn wait_for_association(sctp: Arc<RTCSctpTransport>, marks: PhaseMarks) {
+    loop {
+        match sctp.state() {
+            RTCSctpTransportState::Connected => {
+                marks.mark("sctp");
+                return;
+            }
+            RTCSctpTransportState::Closed => return,
+            _ => sleep(SCTP_POLL).await,
+        }
+    }
+}
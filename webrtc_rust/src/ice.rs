@@ -0,0 +1,142 @@
+// ICE configuration shared by the client and server, plus an optional in-process TURN relay.
+//
+// Both ends used to run ICE-lite with no ICE servers, which only ever exercised direct host
+// candidates. These options let a run use STUN, full ICE, and a TURN relay path instead.
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::policy::ice_transport_policy::RTCIceTransportPolicy;
+use webrtc::turn::auth::{generate_auth_key, AuthHandler};
+use webrtc::turn::relay::relay_static::RelayAddressGeneratorStatic;
+use webrtc::turn::server::config::{ConnConfig, ServerConfig};
+use webrtc::turn::server::Server;
+use webrtc::util::vnet::net::Net;
+
+const TURN_REALM: &str = "ons";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct IceOptions {
+    /// ICE server URL (stun:host:port or turn:host:port), may be repeated
+    #[arg(long = "ice-server")]
+    pub ice_servers: Vec<String>,
+
+    /// Username presented to TURN servers
+    #[arg(long, default_value = "ons")]
+    pub turn_username: String,
+
+    /// Credential presented to TURN servers
+    #[arg(long, default_value = "ons")]
+    pub turn_credential: String,
+
+    /// Run this end as ICE-lite. Only one side may be lite; the other must run full ICE
+    #[arg(long)]
+    pub ice_lite: bool,
+
+    /// Only use relay candidates, forcing all traffic through the TURN server
+    #[arg(long)]
+    pub relay_only: bool,
+
+    /// Gather host candidates on loopback interfaces, for runs on a single machine
+    #[arg(long)]
+    pub include_loopback: bool,
+}
+
+impl IceOptions {
+    pub fn apply(&self, s: &mut SettingEngine) {
+        s.set_lite(self.ice_lite);
+        s.set_include_loopback_candidate(self.include_loopback);
+    }
+
+    pub fn configuration(&self) -> RTCConfiguration {
+        let ice_servers = self
+            .ice_servers
+            .iter()
+            .map(|url| {
+                // Only TURN servers take credentials; STUN binding requests are unauthenticated
+                if url.starts_with("turn:") || url.starts_with("turns:") {
+                    RTCIceServer {
+                        urls: vec![url.clone()],
+                        username: self.turn_username.clone(),
+                        credential: self.turn_credential.clone(),
+                        ..Default::default()
+                    }
+                } else {
+                    RTCIceServer {
+                        urls: vec![url.clone()],
+                        ..Default::default()
+                    }
+                }
+            })
+            .collect();
+
+        RTCConfiguration {
+            ice_servers,
+            ice_transport_policy: if self.relay_only {
+                RTCIceTransportPolicy::Relay
+            } else {
+                RTCIceTransportPolicy::All
+            },
+            ..Default::default()
+        }
+    }
+}
+
+struct StaticAuthHandler {
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl AuthHandler for StaticAuthHandler {
+    fn auth_handle(
+        &self,
+        username: &str,
+        _realm: &str,
+        _src_addr: SocketAddr,
+    ) -> Result<Vec<u8>, webrtc::turn::Error> {
+        // The turn crate has no dedicated "unknown user" error; any error rejects the request
+        self.keys
+            .get(username)
+            .cloned()
+            .ok_or(webrtc::turn::Error::ErrFakeErr)
+    }
+}
+
+/// Start a TURN server inside this process
+///
+/// Allocations are relayed from `relay_ip`, so on a single machine the whole relay path
+/// stays on loopback and no outside service is involved. The returned server must be kept
+/// alive for the duration of the run.
+pub async fn start_embedded_turn(
+    listen: SocketAddr,
+    relay_ip: IpAddr,
+    username: &str,
+    credential: &str,
+) -> Result<Server, Box<dyn std::error::Error>> {
+    let conn = Arc::new(UdpSocket::bind(listen).await?);
+    println!("Embedded TURN server listening on {}, relaying from {}", conn.local_addr()?, relay_ip);
+
+    let mut keys = HashMap::new();
+    keys.insert(username.to_string(), generate_auth_key(username, TURN_REALM, credential));
+
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: relay_ip,
+                address: relay_ip.to_string(),
+                net: Arc::new(Net::new(None)),
+            }),
+        }],
+        realm: TURN_REALM.to_string(),
+        auth_handler: Arc::new(StaticAuthHandler { keys }),
+        channel_bind_timeout: Duration::from_secs(0),
+        alloc_close_notify: None,
+    })
+    .await?;
+
+    Ok(server)
+}
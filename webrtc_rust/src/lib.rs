@@ -0,0 +1,9 @@
+// Helpers shared by the WebRTC client and server binaries
+pub mod handshake;
+pub mod ice;
+pub mod queue;
+pub mod rtp;
+pub mod sctp;
+pub mod signal;
+pub mod stats;
+pub mod transport;
@@ -0,0 +1,147 @@
+// Hand-off of inbound data channel messages from the `on_message` callback to the tick loop.
+//
+// Both binaries used to `try_send` into a 10000-slot mpsc channel and ignore the result, so
+// a full channel silently discarded echoes that then showed up as network loss. The queue
+// here counts every local discard so it can be reported separately from transport loss.
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use bytes::Bytes;
+use ons_common::wire::now_micros;
+use serde_json::{json, Value};
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueStrategy {
+    /// Bounded queue; when full the oldest message is discarded and counted
+    DropOldest,
+    /// Unbounded queue; nothing is discarded locally
+    Unbounded,
+    /// Handle each message inside the `on_message` callback, without queueing
+    Direct,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct QueueOptions {
+    /// How inbound messages are handed from the data channel callback to the tick loop
+    #[arg(long, value_enum, default_value_t = QueueStrategy::DropOldest)]
+    pub queue_strategy: QueueStrategy,
+
+    /// Capacity of the drop-oldest queue, in messages
+    #[arg(long, default_value = "10000")]
+    pub queue_capacity: usize,
+}
+
+/// A message as it came off the data channel
+pub struct Inbound {
+    pub data: Bytes,
+    /// Monotonic receive time, taken in the callback; comparable with the send `Instant`
+    pub at: Instant,
+    /// Wall-clock receive time in microseconds since the Unix epoch, taken in the callback
+    pub received_at_micros: u64,
+}
+
+/// Handler invoked from the callback when running with `QueueStrategy::Direct`
+pub type DirectHandler = Arc<dyn Fn(Inbound) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+pub struct InboundQueue {
+    strategy: QueueStrategy,
+    capacity: usize,
+    messages: Mutex<VecDeque<Inbound>>,
+    received: AtomicU64,
+    dropped: AtomicU64,
+    high_water: AtomicUsize,
+}
+
+impl InboundQueue {
+    pub fn new(options: &QueueOptions) -> Self {
+        InboundQueue {
+            strategy: options.queue_strategy,
+            capacity: options.queue_capacity.max(1),
+            messages: Mutex::new(VecDeque::new()),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            high_water: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn strategy(&self) -> QueueStrategy {
+        self.strategy
+    }
+
+    /// Queue a message, discarding the oldest one if a drop-oldest queue is full
+    pub fn push(&self, message: Inbound) {
+        let mut messages = self.messages.lock().unwrap();
+        if self.strategy == QueueStrategy::DropOldest && messages.len() >= self.capacity {
+            messages.pop_front();
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            // Log the first drop and then every 1000th so a stalled consumer doesn't flood stdout
+            if dropped == 1 || dropped % 1000 == 0 {
+                println!("Inbound queue full ({} messages), {} dropped so far", self.capacity, dropped);
+            }
+        }
+        messages.push_back(message);
+        self.high_water.fetch_max(messages.len(), Ordering::Relaxed);
+    }
+
+    /// Take every queued message
+    pub fn drain(&self) -> Vec<Inbound> {
+        self.messages.lock().unwrap().drain(..).collect()
+    }
+
+    /// Messages delivered by the data channel, whether or not they were later dropped
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Messages discarded locally because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Largest number of messages waiting at once
+    pub fn high_water(&self) -> usize {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    /// Strategy and counters, for the run summary
+    pub fn summary(&self) -> Value {
+        json!({
+            "strategy": format!("{:?}", self.strategy),
+            "capacity": self.capacity,
+            "received": self.received(),
+            "dropped": self.dropped(),
+            "high_water": self.high_water()
+        })
+    }
+
+    /// Accept a message from any source: queue it, or run `direct` on it in direct mode
+    ///
+    /// `direct` must be provided for `QueueStrategy::Direct` and is ignored otherwise.
+    pub fn deliver(&self, data: Bytes, direct: Option<&DirectHandler>) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let inbound = Inbound {
+            data,
+            at: Instant::now(),
+            received_at_micros: now_micros(),
+        };
+        self.received.fetch_add(1, Ordering::Relaxed);
+        match (self.strategy, direct) {
+            (QueueStrategy::Direct, Some(handler)) => handler(inbound),
+            (QueueStrategy::Direct, None) => panic!("direct queue strategy needs a handler"),
+            _ => {
+                self.push(inbound);
+                Box::pin(async {})
+            }
+        }
+    }
+
+    /// Install the data channel's `on_message` callback according to the queue strategy
+    pub fn attach(self: &Arc<Self>, dc: &RTCDataChannel, direct: Option<DirectHandler>) {
+        let queue = Arc::clone(self);
+        dc.on_message(Box::new(move |msg: DataChannelMessage| queue.deliver(msg.data, direct.as_ref())));
+    }
+}
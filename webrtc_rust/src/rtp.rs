@@ -0,0 +1,115 @@
+// Tick transport over RTP on a custom-codec track, as an alternative to the SCTP data channel.
+//
+// Each tick payload (the same JSON the data channel carries) is sent as one RTP packet on
+// a local track; the far end echoes it back on its own track in the reverse direction.
+// Packets go through the registered interceptors and SRTP on the existing DTLS transport.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Instant;
+use bytes::Bytes;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::rtp::header::Header;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType};
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
+use webrtc::track::track_remote::TrackRemote;
+use crate::queue::{DirectHandler, InboundQueue};
+
+/// Codec the tick track is negotiated with. Both ends register only this codec in RTP mode
+pub const TICK_MIME_TYPE: &str = "video/x-ons-tick";
+pub const TICK_PAYLOAD_TYPE: u8 = 96;
+const TICK_CLOCK_RATE: u32 = 90_000;
+
+fn tick_codec_capability() -> RTCRtpCodecCapability {
+    RTCRtpCodecCapability {
+        mime_type: TICK_MIME_TYPE.to_string(),
+        clock_rate: TICK_CLOCK_RATE,
+        channels: 0,
+        sdp_fmtp_line: String::new(),
+        // No NACK/PLI feedback: lost ticks stay lost, like the unreliable data channel
+        rtcp_feedback: vec![],
+    }
+}
+
+pub fn register_tick_codec(m: &mut MediaEngine) -> Result<(), webrtc::Error> {
+    m.register_codec(
+        RTCRtpCodecParameters {
+            capability: tick_codec_capability(),
+            payload_type: TICK_PAYLOAD_TYPE,
+            ..Default::default()
+        },
+        RTPCodecType::Video,
+    )
+}
+
+/// Sends tick payloads as RTP packets on a local track
+pub struct RtpTickSender {
+    track: Arc<TrackLocalStaticRTP>,
+    sequence_number: AtomicU16,
+    start: Instant,
+}
+
+impl RtpTickSender {
+    /// Create the tick track and add it to the peer connection
+    ///
+    /// Must be called before the offer or answer is created so the track is negotiated.
+    pub async fn add_to(pc: &RTCPeerConnection, track_id: &str) -> Result<Self, webrtc::Error> {
+        let track = Arc::new(TrackLocalStaticRTP::new(
+            tick_codec_capability(),
+            track_id.to_string(),
+            "ons-tick".to_string(),
+        ));
+        let rtp_sender = pc
+            .add_track(Arc::clone(&track) as Arc<dyn TrackLocal + Send + Sync>)
+            .await?;
+
+        // Incoming RTCP has to be read for the interceptors to process it
+        tokio::spawn(async move {
+            let mut rtcp_buf = vec![0u8; 1500];
+            while rtp_sender.read(&mut rtcp_buf).await.is_ok() {}
+        });
+
+        Ok(RtpTickSender {
+            track,
+            sequence_number: AtomicU16::new(0),
+            start: Instant::now(),
+        })
+    }
+
+    pub async fn send(&self, payload: &Bytes) -> Result<(), webrtc::Error> {
+        let elapsed = self.start.elapsed();
+        let packet = Packet {
+            header: Header {
+                version: 2,
+                marker: true,
+                payload_type: TICK_PAYLOAD_TYPE,
+                sequence_number: self.sequence_number.fetch_add(1, Ordering::Relaxed),
+                timestamp: (elapsed.as_micros() * TICK_CLOCK_RATE as u128 / 1_000_000) as u32,
+                // SSRC is rewritten by the track for each binding
+                ..Default::default()
+            },
+            payload: payload.clone(),
+        };
+        self.track.write_rtp(&packet).await?;
+        Ok(())
+    }
+}
+
+/// Deliver the payload of every RTP packet on the remote tick track to `queue`
+pub fn forward_remote_track(pc: &RTCPeerConnection, queue: Arc<InboundQueue>, direct: Option<DirectHandler>) {
+    pc.on_track(Box::new(move |track: Arc<TrackRemote>, _receiver, _transceiver| {
+        let queue = Arc::clone(&queue);
+        let direct = direct.clone();
+        Box::pin(async move {
+            println!("Remote track '{}' started ({})", track.id(), track.codec().capability.mime_type);
+            tokio::spawn(async move {
+                while let Ok((packet, _)) = track.read_rtp().await {
+                    queue.deliver(packet.payload, direct.as_ref()).await;
+                }
+                println!("Remote track '{}' ended", track.id());
+            });
+        })
+    }));
+}
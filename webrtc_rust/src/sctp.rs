@@ -0,0 +1,147 @@
+// SCTP transport tuning and send-side backpressure for the data channel.
+//
+// Both binaries used to run at 32 Hz "to prevent connection overload" and pushed every
+// message straight into `send()`. These options expose the SCTP knobs webrtc-rs surfaces
+// and hold back sends while the channel's buffered amount is above a high watermark.
+//
+// webrtc-rs 0.11 only makes RTO.max configurable; RTO.initial (3 s) and RTO.min (1 s) are
+// fixed at the RFC 4960 defaults inside the association.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use tokio::sync::Notify;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::data_channel::RTCDataChannel;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SctpOptions {
+    /// SCTP receive window, in bytes (webrtc-rs default: 1 MiB)
+    #[arg(long)]
+    pub sctp_max_receive_buffer: Option<u32>,
+
+    /// Largest message the SCTP association will send, in bytes
+    #[arg(long)]
+    pub sctp_max_message_size: Option<u32>,
+
+    /// Upper bound on the SCTP retransmission timeout, in milliseconds
+    #[arg(long)]
+    pub sctp_rto_max_ms: Option<u64>,
+
+    /// Resume sending once the data channel's buffered amount drops below this many bytes
+    #[arg(long, default_value = "65536")]
+    pub buffered_amount_low_threshold: usize,
+
+    /// Hold back sends while more than this many bytes are buffered on the data channel
+    #[arg(long, default_value = "1048576")]
+    pub buffered_amount_high_threshold: usize,
+}
+
+impl SctpOptions {
+    pub fn apply(&self, s: &mut SettingEngine) {
+        if let Some(size) = self.sctp_max_receive_buffer {
+            s.set_sctp_max_receive_buffer_size(size);
+        }
+        if let Some(size) = self.sctp_max_message_size {
+            s.set_sctp_max_message_size(size);
+        }
+        if let Some(ms) = self.sctp_rto_max_ms {
+            s.set_sctp_rto_max(Duration::from_millis(ms));
+        }
+    }
+}
+
+/// The tick channel the server opens: unordered and never retransmitted, like a game's UDP
+pub fn tick_channel_init() -> RTCDataChannelInit {
+    RTCDataChannelInit {
+        ordered: Some(false),
+        max_retransmits: Some(0),
+        protocol: Some("binary".to_string()),
+        ..Default::default()
+    }
+}
+
+/// Data channel sender that waits for `on_buffered_amount_low` instead of overfilling SCTP
+pub struct BackpressuredSender {
+    dc: Arc<RTCDataChannel>,
+    low: Arc<Notify>,
+    high_threshold: usize,
+    stalls: AtomicU64,
+    stall_micros: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl BackpressuredSender {
+    pub async fn new(dc: Arc<RTCDataChannel>, options: &SctpOptions) -> Self {
+        let low = Arc::new(Notify::new());
+        let low_clone = Arc::clone(&low);
+        dc.set_buffered_amount_low_threshold(options.buffered_amount_low_threshold).await;
+        dc.on_buffered_amount_low(Box::new(move || {
+            // notify_one stores a permit, so a wakeup that races the check in send() is kept
+            low_clone.notify_one();
+            Box::pin(async {})
+        }))
+        .await;
+
+        BackpressuredSender {
+            dc,
+            low,
+            high_threshold: options.buffered_amount_high_threshold,
+            stalls: AtomicU64::new(0),
+            stall_micros: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /// Send `data`, waiting at most `max_wait` for the buffered amount to drain
+    ///
+    /// Returns `Ok(false)` if the channel was still above the high watermark after
+    /// `max_wait` and the message was not sent.
+    pub async fn send(&self, data: &Bytes, max_wait: Duration) -> Result<bool, webrtc::Error> {
+        if !self.wait_for_room(max_wait).await {
+            return Ok(false);
+        }
+        self.dc.send(data).await?;
+        Ok(true)
+    }
+
+    /// Wait at most `max_wait` for the buffered amount to drop below the high watermark.
+    /// False, with the message counted as skipped, if it didn't.
+    pub async fn wait_for_room(&self, max_wait: Duration) -> bool {
+        if self.dc.buffered_amount().await > self.high_threshold {
+            self.stalls.fetch_add(1, Ordering::Relaxed);
+            let stall_start = Instant::now();
+            let deadline = stall_start + max_wait;
+            while self.dc.buffered_amount().await > self.high_threshold {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() || tokio::time::timeout(remaining, self.low.notified()).await.is_err() {
+                    self.stall_micros.fetch_add(stall_start.elapsed().as_micros() as u64, Ordering::Relaxed);
+                    self.skipped.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+            }
+            self.stall_micros.fetch_add(stall_start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        }
+        true
+    }
+
+    pub fn data_channel(&self) -> &Arc<RTCDataChannel> {
+        &self.dc
+    }
+
+    /// Number of sends that found the channel above the high watermark
+    pub fn stalls(&self) -> u64 {
+        self.stalls.load(Ordering::Relaxed)
+    }
+
+    /// Total time spent waiting for the buffered amount to drain
+    pub fn stall_time(&self) -> Duration {
+        Duration::from_micros(self.stall_micros.load(Ordering::Relaxed))
+    }
+
+    /// Number of messages not sent because the channel never drained in time
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,55 @@
+// SDP exchange over TCP, one JSON session description per line.
+//
+// Offers and answers are otherwise pasted between terminals, which puts however long
+// that takes into the handshake timing and needs someone at the keyboard for every run.
+use std::error::Error;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+pub struct Signal {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Signal {
+    /// Wait for the other end to connect; the server side
+    pub async fn accept(addr: SocketAddr) -> std::io::Result<Signal> {
+        let listener = TcpListener::bind(addr).await?;
+        println!("Waiting for the client's signalling connection on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept().await?;
+        println!("Signalling with {}", peer);
+        Ok(Signal::from(stream))
+    }
+
+    /// Connect to an end waiting in [`Signal::accept`]; the client side
+    pub async fn connect(addr: SocketAddr) -> std::io::Result<Signal> {
+        Ok(Signal::from(TcpStream::connect(addr).await?))
+    }
+
+    pub async fn send(&mut self, description: &RTCSessionDescription) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_string(description)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    pub async fn recv(&mut self) -> Result<RTCSessionDescription, Box<dyn Error>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err("signalling connection closed before a session description arrived".into());
+        }
+        Ok(serde_json::from_str(line.trim())?)
+    }
+}
+
+impl From<TcpStream> for Signal {
+    fn from(stream: TcpStream) -> Self {
+        // Descriptions are small and waited on, so don't let Nagle hold them back
+        let _ = stream.set_nodelay(true);
+        let (reader, writer) = stream.into_split();
+        Signal { reader: BufReader::new(reader), writer }
+    }
+}
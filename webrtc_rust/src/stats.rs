@@ -0,0 +1,220 @@
+// Periodic sampling of the WebRTC stack's own ICE/DTLS/SCTP statistics.
+//
+// The connection monitors used to dump `{:?}` of the whole `get_stats()` report every
+// second. This module pulls out the handful of values we actually compare against the
+// application-level tick RTT and writes them as one CSV row per sample.
+use std::fs::File;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use csv::Writer;
+use ons_common::timeseries::TransportSnapshot;
+use serde::Serialize;
+use tokio::time::sleep;
+use webrtc::ice::candidate::CandidatePairState;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::stats::{ICECandidatePairStats, StatsReport, StatsReportType};
+
+/// One row of the stats time series
+///
+/// Counters are cumulative since the peer connection was created, so rates can be
+/// derived by differencing consecutive rows.
+///
+/// The SCTP association's smoothed RTT, windows, MTU and unacknowledged chunk count are
+/// what webrtc-rs puts in its transport stats. It keeps the retransmission and RTO
+/// counters private, so `sctp_retransmissions` is written as "unavailable" rather than
+/// left out or zeroed. With `max_retransmits = 0` on our unordered channel there should
+/// be no DATA retransmissions anyway (abandoned chunks are skipped with FORWARD-TSN).
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct StatsSample {
+    pub elapsed_ms: u128,
+    pub local_candidate: String,
+    pub remote_candidate: String,
+    pub current_rtt_us: f64,
+    pub total_rtt_us: f64,
+    pub stun_responses_received: u64,
+    pub packets_sent: u32,
+    pub packets_received: u32,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub sctp_bytes_sent: usize,
+    pub sctp_bytes_received: usize,
+    pub sctp_srtt_us: f64,
+    pub sctp_cwnd_bytes: u32,
+    pub sctp_rwnd_bytes: u32,
+    pub sctp_mtu: u32,
+    pub sctp_unacked_chunks: u32,
+    pub sctp_retransmissions: &'static str,
+    pub dc_messages_sent: usize,
+    pub dc_messages_received: usize,
+    pub dc_bytes_sent: usize,
+    pub dc_bytes_received: usize,
+}
+
+/// Written in place of counters the stack doesn't expose
+pub const UNAVAILABLE: &str = "unavailable";
+
+/// Most recent sample, shared with the tick loop for its per-tick time series
+pub type LatestSample = Arc<std::sync::Mutex<Option<StatsSample>>>;
+
+impl StatsSample {
+    /// The ICE-level values that go alongside each tick record
+    ///
+    /// webrtc-rs has no loss or retransmission count, so only the STUN RTT, packet count
+    /// and the SCTP association's congestion window are filled in.
+    pub fn snapshot(&self) -> TransportSnapshot {
+        TransportSnapshot {
+            stack_rtt_us: Some(self.current_rtt_us as u64),
+            cwnd_bytes: Some(self.sctp_cwnd_bytes as u64),
+            sent_packets: Some(self.packets_sent as u64),
+            ..Default::default()
+        }
+    }
+
+    /// The cumulative counters, named for /metrics
+    ///
+    /// The SCTP association state is a gauge rather than a counter, so it stays in the
+    /// CSV; its congestion window reaches /metrics through [`Self::snapshot`].
+    pub fn counters(&self) -> Vec<(&'static str, f64)> {
+        vec![
+            ("ice_packets_sent", self.packets_sent as f64),
+            ("ice_packets_received", self.packets_received as f64),
+            ("ice_bytes_sent", self.bytes_sent as f64),
+            ("ice_bytes_received", self.bytes_received as f64),
+            ("stun_responses_received", self.stun_responses_received as f64),
+            ("sctp_bytes_sent", self.sctp_bytes_sent as f64),
+            ("sctp_bytes_received", self.sctp_bytes_received as f64),
+            ("data_channel_messages_sent", self.dc_messages_sent as f64),
+            ("data_channel_messages_received", self.dc_messages_received as f64),
+            ("data_channel_bytes_sent", self.dc_bytes_sent as f64),
+            ("data_channel_bytes_received", self.dc_bytes_received as f64),
+        ]
+    }
+
+    /// Extract a sample from a full stats report
+    ///
+    /// Returns `None` until ICE has a succeeded candidate pair to report on.
+    pub fn from_report(elapsed: Duration, report: &StatsReport) -> Option<Self> {
+        let pair = selected_pair(report)?;
+        let mut sample = StatsSample {
+            elapsed_ms: elapsed.as_millis(),
+            local_candidate: candidate_address(report, &pair.local_candidate_id),
+            remote_candidate: candidate_address(report, &pair.remote_candidate_id),
+            // webrtc-rs reports round trip times in seconds
+            current_rtt_us: pair.current_round_trip_time * 1_000_000.0,
+            total_rtt_us: pair.total_round_trip_time * 1_000_000.0,
+            stun_responses_received: pair.responses_received,
+            packets_sent: pair.packets_sent,
+            packets_received: pair.packets_received,
+            bytes_sent: pair.bytes_sent,
+            bytes_received: pair.bytes_received,
+            sctp_retransmissions: UNAVAILABLE,
+            ..Default::default()
+        };
+
+        for stats in report.reports.values() {
+            match stats {
+                StatsReportType::SCTPTransport(sctp) => {
+                    sample.sctp_bytes_sent = sctp.bytes_sent;
+                    sample.sctp_bytes_received = sctp.bytes_received;
+                    // Also in seconds, like the candidate pair RTTs
+                    sample.sctp_srtt_us = sctp.smoothed_round_trip_time * 1_000_000.0;
+                    sample.sctp_cwnd_bytes = sctp.congestion_window;
+                    sample.sctp_rwnd_bytes = sctp.receiver_window;
+                    sample.sctp_mtu = sctp.mtu;
+                    sample.sctp_unacked_chunks = sctp.una_data;
+                }
+                StatsReportType::DataChannel(dc) => {
+                    sample.dc_messages_sent += dc.messages_sent;
+                    sample.dc_messages_received += dc.messages_received;
+                    sample.dc_bytes_sent += dc.bytes_sent;
+                    sample.dc_bytes_received += dc.bytes_received;
+                }
+                _ => {}
+            }
+        }
+
+        Some(sample)
+    }
+}
+
+// Prefer the nominated pair; fall back to whichever succeeded pair carried the most traffic
+fn selected_pair(report: &StatsReport) -> Option<&ICECandidatePairStats> {
+    report
+        .reports
+        .values()
+        .filter_map(|stats| match stats {
+            StatsReportType::CandidatePair(pair) if pair.state == CandidatePairState::Succeeded => Some(pair),
+            _ => None,
+        })
+        .max_by_key(|pair| (pair.nominated, pair.bytes_sent + pair.bytes_received))
+}
+
+fn candidate_address(report: &StatsReport, id: &str) -> String {
+    match report.reports.get(id) {
+        Some(StatsReportType::LocalCandidate(c)) | Some(StatsReportType::RemoteCandidate(c)) => {
+            format!("{}:{} ({})", c.ip, c.port, c.candidate_type)
+        }
+        _ => id.to_string(),
+    }
+}
+
+/// Writes stats samples to a CSV file, one row per sample
+pub struct StatsRecorder {
+    writer: Writer<File>,
+    start: Instant,
+}
+
+impl StatsRecorder {
+    pub fn create(path: &str) -> csv::Result<Self> {
+        Ok(StatsRecorder {
+            writer: Writer::from_path(path)?,
+            start: Instant::now(),
+        })
+    }
+
+    /// Sample the peer connection and append a row, returning the sample if one was taken
+    pub async fn record(&mut self, pc: &RTCPeerConnection) -> csv::Result<Option<StatsSample>> {
+        let report = pc.get_stats().await;
+        let sample = match StatsSample::from_report(self.start.elapsed(), &report) {
+            Some(sample) => sample,
+            None => return Ok(None),
+        };
+        self.writer.serialize(&sample)?;
+        self.writer.flush()?;
+        Ok(Some(sample))
+    }
+}
+
+/// Sample stats every `interval` while the peer connection is connected
+///
+/// The selected candidate pair is printed whenever it changes, so the ports can still be
+/// handed to the XDP filter without reading through the raw report.
+pub async fn run_sampler(
+    pc: Arc<RTCPeerConnection>,
+    mut recorder: StatsRecorder,
+    interval: Duration,
+    latest: Option<LatestSample>,
+) {
+    let mut last_pair = String::new();
+    loop {
+        if pc.connection_state() == RTCPeerConnectionState::Connected {
+            match recorder.record(&pc).await {
+                Ok(Some(sample)) => {
+                    let pair = format!("{} <-> {}", sample.local_candidate, sample.remote_candidate);
+                    if pair != last_pair {
+                        println!("Selected candidate pair: {}", pair);
+                        last_pair = pair;
+                    }
+                    if let Some(latest) = &latest {
+                        *latest.lock().unwrap() = Some(sample);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => println!("Error recording WebRTC stats: {}", e),
+            }
+        }
+
+        sleep(interval).await;
+    }
+}
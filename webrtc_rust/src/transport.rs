@@ -0,0 +1,62 @@
+// Selection between the SCTP data channel and the RTP track for carrying ticks
+use std::time::Duration;
+use bytes::Bytes;
+use webrtc::data_channel::data_channel_state::RTCDataChannelState;
+use crate::rtp::RtpTickSender;
+use crate::sctp::BackpressuredSender;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickTransport {
+    /// Unordered, unreliable SCTP data channel
+    DataChannel,
+    /// RTP packets on a custom-codec media track
+    Rtp,
+}
+
+pub enum TickSender {
+    DataChannel(BackpressuredSender),
+    Rtp(RtpTickSender),
+}
+
+impl TickSender {
+    /// Send one tick payload. Returns `Ok(false)` if backpressure made us give up on it
+    pub async fn send(&self, data: &Bytes, max_wait: Duration) -> Result<bool, webrtc::Error> {
+        match self {
+            TickSender::DataChannel(sender) => sender.send(data, max_wait).await,
+            TickSender::Rtp(sender) => sender.send(data).await.map(|_| true),
+        }
+    }
+
+    /// Like [`send`](Self::send), but only builds the payload once backpressure lets it
+    /// go, so a timestamp taken inside `build` doesn't leave the wait out
+    pub async fn send_built(&self, max_wait: Duration, build: impl FnOnce() -> Bytes) -> Result<bool, webrtc::Error> {
+        match self {
+            TickSender::DataChannel(sender) => {
+                if !sender.wait_for_room(max_wait).await {
+                    return Ok(false);
+                }
+                sender.data_channel().send(&build()).await.map(|_| true)
+            }
+            TickSender::Rtp(sender) => sender.send(&build()).await.map(|_| true),
+        }
+    }
+
+    /// Whether the underlying transport can currently carry ticks
+    ///
+    /// RTP has no open/closed state of its own; writes before the track is bound are
+    /// silently discarded by webrtc-rs and show up as loss.
+    pub fn is_open(&self) -> bool {
+        match self {
+            TickSender::DataChannel(sender) => sender.data_channel().ready_state() == RTCDataChannelState::Open,
+            TickSender::Rtp(_) => true,
+        }
+    }
+
+    /// Backpressure counters, which only exist for the data channel
+    pub fn backpressure(&self) -> Option<&BackpressuredSender> {
+        match self {
+            TickSender::DataChannel(sender) => Some(sender),
+            TickSender::Rtp(_) => None,
+        }
+    }
+}
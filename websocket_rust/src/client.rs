@@ -1,58 +1,111 @@
 use futures_util::{StreamExt, SinkExt};
 use tokio::net::TcpStream;
-use tokio_tungstenite::{client_async, tungstenite::protocol::Message};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{client_async, tungstenite::protocol::Message, WebSocketStream};
 use tokio_native_tls::native_tls::{TlsConnector as NativeTlsConnector};
-use tokio_native_tls::TlsConnector;
+use tokio_native_tls::{TlsConnector, TlsStream};
 use url::Url;
-use std::time::{Instant, Duration};
-use std::collections::HashMap;
-use tokio::time::interval;
-use std::fs::File;
-use std::io::{Write, Read};
+use std::time::Instant;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::os::unix::io::{AsRawFd, RawFd};
 use csv::Writer;
 use serde_json::{json, Value};
+use clap::Parser;
+use ons_common::clock::{DelayBreakdown, EchoTimestamps, OneWayDelay};
+use ons_common::dashboard::{Dashboard, DashboardOptions};
+use ons_common::handshake::{Handshake, HandshakeBench, HandshakeOptions, PhaseTimer};
+use ons_common::histogram::{HistogramOptions, LatencyHistogram};
+use ons_common::manifest::{ManifestOptions, RunManifest};
+use ons_common::metrics::{Metrics, MetricsOptions, Side};
+use ons_common::outstanding::{DrainOptions, Echo, OutstandingTicks};
+use ons_common::receive::Arrival;
+use ons_common::scheduler::{SchedulerOptions, TickScheduler};
+use ons_common::stop::Stop;
+use ons_common::tcpinfo;
+use ons_common::timeseries::{TimeSeries, TimeSeriesOptions};
+use ons_common::wire::{now_micros, TickMessage};
+use ons_common::workload::WorkloadOptions;
 
-// Define tick rate constants
-const TICK_RATE: u32 = 128; // ticks per second
-const TICK_DURATION_MICROS: u64 = 1_000_000 / TICK_RATE as u64;
+// Defaults for --tick-rate and --simulation-duration-secs
+const TICK_RATE: u64 = 128; // ticks per second
 const SIMULATION_DURATION_SECS: u64 = 180; // 3 minutes
 
-fn save_measurements(rtt_samples: &[u128]) -> Result<(), Box<dyn std::error::Error>> {
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Server to connect to
+    #[arg(long, default_value = "wss://sculpter.dev:4043")]
+    url: Url,
+
+    /// Trust the certificate at this path, encoded as PEM
+    #[arg(long, default_value = "/users/dorlando/ons/websocket_rust/sculpter_cert.pem")]
+    tls_cert: PathBuf,
+
+    /// Write the serialized RTT histogram here so runs can be merged later
+    #[arg(long, default_value = "websocket_rtt.hdr")]
+    histogram_file: String,
+
+    #[command(flatten)]
+    histogram: HistogramOptions,
+
+    #[command(flatten)]
+    drain: DrainOptions,
+
+    #[command(flatten)]
+    timeseries: TimeSeriesOptions,
+
+    #[command(flatten)]
+    scheduler: SchedulerOptions,
+
+    #[command(flatten)]
+    handshake: HandshakeOptions,
+
+    #[command(flatten)]
+    workload: WorkloadOptions,
+
+    #[command(flatten)]
+    manifest: ManifestOptions,
+
+    #[command(flatten)]
+    dashboard: DashboardOptions,
+
+    #[command(flatten)]
+    metrics: MetricsOptions,
+}
+
+fn save_measurements(rtt_samples: &[u128], breakdowns: &[Option<DelayBreakdown>]) -> Result<(), Box<dyn std::error::Error>> {
     // Save raw measurements in CSV format
     let mut writer = Writer::from_path("websocket_measurements.csv")?;
     
     // Write headers
-    writer.write_record(&["rtt"])?;
+    writer.write_record(&["rtt", "forward_us", "residence_us", "return_us"])?;
     
-    // Write the RTT data
-    for rtt in rtt_samples {
-        writer.write_record(&[rtt.to_string()])?;
+    // Write the RTT data, with its one-way breakdown when the server stamped the echo
+    for (rtt, breakdown) in rtt_samples.iter().zip(breakdowns) {
+        let (forward, residence, ret) = match breakdown {
+            Some(b) => (b.forward_us.to_string(), b.residence_us.to_string(), b.return_us.to_string()),
+            None => (String::new(), String::new(), String::new()),
+        };
+        writer.write_record(&[rtt.to_string(), forward, residence, ret])?;
     }
     writer.flush()?;
     Ok(())
 }
 
-fn save_summary(rtt_samples: &[u128]) -> Result<(), Box<dyn std::error::Error>> {
-    // Calculate statistics
-    let mut sorted = rtt_samples.to_vec();
-    sorted.sort_unstable();
-
-    let avg = rtt_samples.iter().sum::<u128>() as f64 / rtt_samples.len() as f64;
-    let p50 = sorted[rtt_samples.len() / 2];
-    let p95 = sorted[(rtt_samples.len() as f64 * 0.95) as usize];
-    let p99 = sorted[(rtt_samples.len() as f64 * 0.99) as usize];
-
+fn save_summary(rtt: &LatencyHistogram, workload: Value, one_way_delay: Value, echoes: Value, scheduler: Value, handshake: Value) -> Result<(), Box<dyn std::error::Error>> {
     // Save summary to JSON
     let summary = json!({
-        "sample_count": rtt_samples.len(),
+        "sample_count": rtt.len(),
+        "workload": workload,
         "metrics": {
-            "rtt": {
-                "avg": avg,
-                "p50": p50,
-                "p95": p95,
-                "p99": p99
-            }
-        }
+            "rtt": rtt.summary()
+        },
+        "one_way_delay": one_way_delay,
+        "echoes": echoes,
+        "scheduler": scheduler,
+        "handshake": handshake
     });
 
     let mut file = File::create("websocket_summary.json")?;
@@ -60,121 +113,230 @@ fn save_summary(rtt_samples: &[u128]) -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
+type WsStream = WebSocketStream<TlsStream<TcpStream>>;
+
+/// Open a connection, timing the TCP connect, TLS handshake and WebSocket upgrade separately.
+/// Also returns the TCP socket's fd for TCP_INFO snapshots, since the stream itself moves into the TLS layer.
+async fn connect(url: &Url, tls_connector: &TlsConnector) -> Result<(WsStream, RawFd, Handshake), Box<dyn std::error::Error>> {
+    let domain = url.host_str().ok_or("No host found in URL")?;
+    let mut timer = PhaseTimer::start();
+
+    let tcp_stream = TcpStream::connect((domain, url.port_or_known_default().unwrap())).await?;
+    timer.mark("tcp_connect");
+    let tcp_fd = tcp_stream.as_raw_fd();
+
+    let tls_stream = tls_connector.connect(domain, tcp_stream).await?;
+    timer.mark("tls");
+
+    let (ws_stream, _) = client_async(url.clone(), tls_stream).await?;
+    timer.mark("websocket_upgrade");
+
+    // native-tls has no session cache, so every handshake is a full one
+    Ok((ws_stream, tcp_fd, timer.finish().with_resumption(Some(false), None)))
+}
+
+/// Open and close `runs` connections and save the handshake phase distributions.
+async fn connect_bench(url: &Url, tls_connector: &TlsConnector, args: &Args, runs: u32, stop: &Stop) -> Result<(), Box<dyn std::error::Error>> {
+    if args.handshake.resume {
+        println!("native-tls cannot resume TLS sessions; every connection will do a full handshake");
+    }
+    let mut bench = HandshakeBench::new("websocket", &args.histogram);
+    for _ in 0..runs {
+        if stop.requested() {
+            break;
+        }
+        match connect(url, tls_connector).await {
+            Ok((mut ws_stream, _, handshake)) => {
+                bench.record(Ok(handshake));
+                let _ = ws_stream.close(None).await;
+            }
+            Err(e) => bench.record(Err(e.to_string())),
+        }
+        tokio::time::sleep(args.handshake.interval()).await;
+    }
+    let path = bench.save(&args.handshake)?;
+    println!("Handshake summary saved to {}", path.display());
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    //let url = Url::parse("wss://spock.cs.colgate.edu:4043").unwrap();
-    let url = Url::parse("wss://sculpter.dev:4043").unwrap();
+    let args = Args::parse();
+    // Per-tick lines are at trace level: RUST_LOG=trace brings them back
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    args.scheduler.apply_to_current_thread()?;
+    // Ctrl-C (or the harness's SIGINT) ends the run early but still saves everything below
+    let stop = Stop::on_signals()?;
+    let mut manifest = RunManifest::start("websocket", &args, args.workload.summary(TICK_RATE, SIMULATION_DURATION_SECS), &args.manifest);
+    println!("Run manifest saved to {}", manifest.save()?.display());
+    let url = args.url.clone();
     println!("Connecting to {}", url);
-    let mut cert_file = File::open("/users/dorlando/ons/websocket_rust/sculpter_cert.pem").unwrap();
-    //let mut cert_file = File::open("/users/dorlando/ons/websocket_rust/signallite_cert.pem").unwrap();
-    //let mut cert_file = File::open("/users/dorlando/ons/certs/cert1.pem").unwrap();
-    let mut cert = vec![];
-    cert_file.read_to_end(&mut cert).unwrap();
+    let cert = fs::read(&args.tls_cert)
+        .map_err(|e| format!("could not read certificate {}: {}", args.tls_cert.display(), e))?;
     let mut builder = NativeTlsConnector::builder();
     builder.danger_accept_invalid_certs(true); // Disable cert validation (not for production!)
-    builder.add_root_certificate(tokio_native_tls::native_tls::Certificate::from_pem(&cert).unwrap());
-    let tls_connector = TlsConnector::from(builder.build().unwrap());
-
-    let domain = url.host_str().expect("No host found in URL");
-    let connection_start = Instant::now();
-
-    let tcp_stream = TcpStream::connect(format!("{}:{}", domain, url.port_or_known_default().unwrap()))
-        .await
-        .expect("Failed to connect to TCP");
-    let tls_stream = tls_connector
-        .connect(domain, tcp_stream)
-        .await
-        .expect("Failed to perform TLS handshake");
-
-    let (ws_stream, _) = client_async(url, tls_stream)
-        .await
-        .expect("Failed to establish WebSocket connection");
+    builder.add_root_certificate(tokio_native_tls::native_tls::Certificate::from_pem(&cert)?);
+    let tls_connector = TlsConnector::from(builder.build()?);
 
-    let connection_end = Instant::now();
+    if let Some(runs) = args.handshake.connect_bench {
+        connect_bench(&url, &tls_connector, &args, runs, &stop).await?;
+        let handshake_path = args.handshake.path("websocket");
+        manifest.finish(&[handshake_path.with_extension("csv"), handshake_path]);
+        manifest.save()?;
+        return Ok(());
+    }
 
-    // Calculate connection establishment time
-    let connection_duration = connection_end.duration_since(connection_start);
-    println!(
-        "Connection established in {} ms",
-        connection_duration.as_millis()
-    );
+    let (ws_stream, tcp_fd, handshake) = connect(&url, &tls_connector).await
+        .expect("Failed to establish WebSocket connection");
+    handshake.log();
 
     println!("Connected to the server");
 
-    // Create a tick interval
-    let tick_duration = Duration::from_micros(TICK_DURATION_MICROS);
-    println!("Tick duration: {} µs", TICK_DURATION_MICROS);
-    let mut tick_interval = interval(tick_duration);
+    // Create the tick scheduler
+    let tick_duration = args.workload.tick_duration(TICK_RATE);
+    println!("Tick duration: {} µs", tick_duration.as_micros());
+    let mut scheduler = TickScheduler::new(tick_duration, &args.scheduler);
 
     // Split WebSocket stream for concurrent reading and writing
     let (mut write, mut read) = ws_stream.split();
-    
+
+    // Read echoes on their own task and timestamp them on arrival, so the RTT
+    // reflects the network rather than how often the tick loop polls
+    let (arrival_tx, mut arrivals) = mpsc::unbounded_channel::<Arrival>();
+    let receiver = tokio::spawn(async move {
+        while let Some(message) = read.next().await {
+            match message {
+                Ok(Message::Text(text)) => {
+                    if arrival_tx.send(Arrival::now(text.into_bytes())).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => continue, // Ignore non-text messages
+                Err(e) => {
+                    eprintln!("Error reading from WebSocket: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+
     // Setup simulation state tracking
     let simulation_start = Instant::now();
-    let simulation_end = simulation_start + Duration::from_secs(SIMULATION_DURATION_SECS);
+    let mut timeseries = TimeSeries::new(simulation_start);
+    let simulation_duration = args.workload.duration(SIMULATION_DURATION_SECS);
+    let simulation_end = simulation_start + simulation_duration;
+    let drain_end = simulation_end + args.drain.drain();
     let mut tick_count: u64 = 0;
-    let mut sent_timestamps: HashMap<u64, Instant> = HashMap::new();
+    // Monotonic send time for the RTT, wall-clock send time for the one-way breakdown
+    let mut sent_timestamps: OutstandingTicks<(Instant, u64)> = OutstandingTicks::new(&args.drain);
     let mut rtt_samples: Vec<u128> = Vec::new();
+    let mut rtt_histogram = LatencyHistogram::new(&args.histogram);
+    let mut one_way_delay = OneWayDelay::new();
 
     // Start the simulation tick loop
-    println!("Starting tick-based simulation for {} seconds", SIMULATION_DURATION_SECS);
-    
-    while Instant::now() < simulation_end {
-        // Wait for the next tick interval
-        tick_interval.tick().await;
-        
-        // Prepare the tick message with the tick number and a precise timestamp
-        let timestamp = simulation_start.elapsed().as_micros();
-        let message = format!(r#"{{"tick":{},"timestamp":{}}}"#, tick_count, timestamp);
-        sent_timestamps.insert(tick_count, Instant::now());
-        
-        // Send the tick message
-        match write.send(Message::Text(message)).await {
-            Ok(_) => println!("Sent tick {} at {} µs", tick_count, timestamp),
-            Err(e) => {
-                eprintln!("Error sending tick message: {:?}", e);
-                break;
+    println!("Starting tick-based simulation for {} seconds", simulation_duration.as_secs());
+    let dashboard = Dashboard::start(&args.dashboard, &format!("WebSocket {}", url), simulation_duration, &stop)?;
+    dashboard.export(Metrics::start(&args.metrics, "websocket_client", Side::Client)?.session(url.as_str()));
+    let live = dashboard.live();
+
+    while Instant::now() < drain_end {
+        if stop.requested() {
+            println!("Stopping early after {} ticks", tick_count);
+            break;
+        }
+        // Wait for the next tick deadline
+        let slot = scheduler.wait().await;
+        let tick_start = Instant::now();
+        let mut sent_seq = None;
+        sent_timestamps.expire();
+
+        // After the last send, keep reading until the drain period ends or nothing is outstanding
+        if Instant::now() < simulation_end {
+            // Prepare the tick message with the tick number and a precise timestamp
+            let timestamp = simulation_start.elapsed().as_micros();
+            let message = args.workload.message(tick_count, timestamp);
+            sent_timestamps.insert(tick_count, (Instant::now(), now_micros()));
+            let snapshot = tcpinfo::snapshot(tcp_fd).unwrap_or_default();
+            timeseries.sent(tick_count, Instant::now(), message.len(), snapshot);
+            sent_seq = Some(tick_count);
+
+            // Send the tick message
+            match write.send(Message::Text(message)).await {
+                Ok(_) => {
+                    scheduler.sent(&slot);
+                    live.sent(slot.deadline.elapsed(), snapshot);
+                    log::trace!("Sent tick {} at {} µs", tick_count, timestamp)
+                }
+                Err(e) => {
+                    eprintln!("Error sending tick message: {:?}", e);
+                    break;
+                }
             }
+            tick_count += 1;
+        } else if sent_timestamps.pending() == 0 {
+            break;
         }
-        tick_count += 1;
         
-        // Process any incoming responses (using a timeout to avoid blocking)
-        while let Ok(Some(message)) = tokio::time::timeout(
-            Duration::from_millis(1), 
-            read.next()
-        ).await {
-            match message {
-                Ok(msg) if msg.is_text() => {
-                    let received_str = msg.into_text()?;
-                    // Parse the JSON message to extract the tick number
-                    if let Ok(parsed) = serde_json::from_str::<Value>(&received_str) {
-                        if let Some(tick_val) = parsed.get("tick") {
-                            if let Some(tick) = tick_val.as_u64() {
-                                if let Some(sent_time) = sent_timestamps.remove(&tick) {
-                                    let rtt = Instant::now().duration_since(sent_time);
-                                    rtt_samples.push(rtt.as_micros());
-                                    println!("Tick {}: Received echo, RTT: {} µs", tick, rtt.as_micros());
-                                }
+        // Match the echoes the receive task has timestamped since the last tick
+        while let Ok(arrival) = arrivals.try_recv() {
+            // Parse the JSON message to extract the tick number
+            if let Ok(parsed) = serde_json::from_slice::<Value>(&arrival.data) {
+                if let Some(tick_val) = parsed.get("tick") {
+                    if let Some(tick) = tick_val.as_u64() {
+                        match sent_timestamps.take_at(tick, arrival.at) {
+                            Echo::OnTime((sent_time, sent_wall)) => {
+                                let rtt = arrival.at.saturating_duration_since(sent_time);
+                                timeseries.received(tick, arrival.at, false);
+                                rtt_samples.push(rtt.as_micros());
+                                rtt_histogram.record(rtt.as_micros() as u64);
+                                let stamps = TickMessage::parse(&arrival.data)
+                                    .and_then(|echo| EchoTimestamps::from_echo(&echo, sent_wall, arrival.wall_us));
+                                one_way_delay.record(stamps);
+                                live.echo(rtt.as_micros() as u64);
+                                log::trace!("Tick {}: Received echo, RTT: {} µs", tick, rtt.as_micros());
+                            }
+                            Echo::Late((sent_time, _)) => {
+                                timeseries.received(tick, arrival.at, true);
+                                live.late();
+                                log::trace!("Tick {}: Late echo after {} µs", tick,
+                                    arrival.at.saturating_duration_since(sent_time).as_micros());
                             }
+                            Echo::Unknown => (),
                         }
                     }
-                },
-                Ok(_) => continue, // Ignore non-text messages
-                Err(e) => {
-                    eprintln!("Error reading from WebSocket: {:?}", e);
-                    break;
                 }
             }
         }
+        live.outstanding(sent_timestamps.pending());
+
+        // Flag ticks where sending and matching the echoes overran the tick duration
+        if let Some(seq) = sent_seq {
+            if tick_start.elapsed() > tick_duration {
+                timeseries.mark_overrun(seq);
+                live.overrun();
+            }
+        }
     }
 
-    println!("Simulation complete after {} seconds", SIMULATION_DURATION_SECS);
+    receiver.abort();
+    dashboard.finish();
+    println!("Simulation complete after {} seconds", simulation_duration.as_secs());
 
     // After simulation, save and summarize the RTT data
     if !rtt_samples.is_empty() {
         println!("Saving RTT data...");
-        save_measurements(&rtt_samples)?;
-        save_summary(&rtt_samples)?;
+        let breakdowns = match one_way_delay.estimate_offset() {
+            Some(offset) => {
+                println!("Estimated server clock offset: {} µs", offset.offset_us);
+                one_way_delay.breakdown(&offset)
+            }
+            None => vec![None; rtt_samples.len()],
+        };
+        save_measurements(&rtt_samples, &breakdowns)?;
+        save_summary(&rtt_histogram, args.workload.summary(TICK_RATE, SIMULATION_DURATION_SECS), one_way_delay.summary(),
+            sent_timestamps.summary(tick_count, rtt_samples.len() as u64), scheduler.summary(),
+            handshake.summary())?;
+        rtt_histogram.save(&args.histogram_file)?;
 
         let total_rtt: u128 = rtt_samples.iter().sum();
         let average_rtt = total_rtt as f64 / rtt_samples.len() as f64;
@@ -184,6 +346,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("No RTT data collected.");
     }
 
+    // Last, so a failure here can't cost the RTT measurements and summary above
+    match timeseries.save(&args.timeseries, "websocket") {
+        Ok(path) => println!("Per-tick time series saved to {}", path.display()),
+        Err(e) => eprintln!("Failed to save the per-tick time series: {}", e),
+    }
+
+    manifest.finish(&[
+        "websocket_measurements.csv".into(),
+        "websocket_summary.json".into(),
+        args.histogram_file.clone().into(),
+        args.timeseries.path("websocket"),
+    ]);
+    manifest.save()?;
+
     println!("Client disconnected.");
     Ok(())
 }
@@ -4,50 +4,109 @@ use tokio_native_tls::TlsAcceptor;
 use tokio_native_tls::native_tls::{Identity, TlsAcceptor as NativeTlsAcceptor};
 use tokio_tungstenite::{accept_async, WebSocketStream};
 use tokio_tungstenite::tungstenite::protocol::Message;
-use tokio::time::{interval, Duration};
-use std::fs::File;
-use std::io::Read;
+use tokio::time::{interval, Duration, Instant};
+use std::fs;
+use std::path::PathBuf;
 use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
-use serde_json::{Value, from_str};
+use tokio::task::JoinSet;
+use serde_json::{json, Value, from_str};
 use tokio_native_tls::TlsStream;
 use tokio::net::TcpStream;
+use clap::Parser;
+use ons_common::echo::{EchoOptions, EchoStats};
+use ons_common::manifest::{ManifestOptions, RunManifest};
+use ons_common::metrics::{Metrics, MetricsOptions, Side};
+use ons_common::stop::Stop;
+use ons_common::tcpinfo;
+use ons_common::wire::{now_micros, stamp_echo_text};
 
 // Define tick rate constants
-const TICK_RATE: u32 = 128; // ticks per second
-const TICK_DURATION_MICROS: u64 = 1_000_000 / TICK_RATE as u64;
+const TICK_RATE: u64 = 128; // default ticks per second
 
 // Type alias for WebSocket stream
 type WsStream = WebSocketStream<TlsStream<TcpStream>>;
 
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(flatten)]
+    echo: EchoOptions,
+
+    /// Server ticks per second, which tick-batched echoes are sent on
+    #[arg(long, default_value_t = TICK_RATE)]
+    tick_rate: u64,
+
+    /// Use the certificate at this path, encoded as PEM
+    #[arg(long, default_value = "/etc/letsencrypt/live/sculpter.dev/cert.pem")]
+    tls_cert: PathBuf,
+
+    /// Use the PKCS#8 private key at this path, encoded as PEM
+    #[arg(long, default_value = "/etc/letsencrypt/live/sculpter.dev/privkey.pem")]
+    tls_key: PathBuf,
+
+    /// Write the echo mode and server residence times here when each connection closes
+    #[arg(long, default_value = "websocket_server_summary.json")]
+    summary_file: String,
+
+    #[command(flatten)]
+    manifest: ManifestOptions,
+
+    #[command(flatten)]
+    metrics: MetricsOptions,
+}
+
+impl Args {
+    fn tick_duration(&self) -> Duration {
+        Duration::from_micros(1_000_000 / self.tick_rate.max(1))
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Arc::new(Args::parse());
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    // On SIGINT, stop accepting and let open connections write their summaries first
+    let stop = Stop::on_signals()?;
+    let manifest = RunManifest::start("websocket_server", &args, json!({ "tick_rate": args.tick_rate }), &args.manifest);
+    println!("Run manifest saved to {}", manifest.save()?.display());
+    let manifest = Arc::new(Mutex::new(manifest));
+    let metrics = Metrics::start(&args.metrics, "websocket_server", Side::Server)?;
     let addr = "0.0.0.0:4043".to_string();
-    //let mut cert_file = File::open("/users/dorlando/ons/certs/cert1.pem")?;
-    // let mut cert_file = File::open("/etc/haproxy/certs/signallite_cert.pem").unwrap();
-    let mut cert_file = File::open("/etc/letsencrypt/live/sculpter.dev/cert.pem").unwrap();
-    let mut cert = vec![];
-    cert_file.read_to_end(&mut cert).unwrap();
-    //let mut key_file = File::open("/users/dorlando/ons/certs/privkey1.pem")?;
-    // let mut key_file = File::open("/etc/haproxy/certs/signallite_key.pem").unwrap();
-    let mut key_file = File::open("/etc/letsencrypt/live/sculpter.dev/privkey.pem").unwrap();
-    let mut key = vec![];
-    key_file.read_to_end(&mut key).unwrap();
+    let read = |path: &PathBuf| fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e));
+    let cert = read(&args.tls_cert)?;
+    let key = read(&args.tls_key)?;
     
     let identity = Identity::from_pkcs8(&cert, &key)?;
     let tls_acceptor = NativeTlsAcceptor::builder(identity).build()?;
     let tls_acceptor = TlsAcceptor::from(tls_acceptor);
     
     println!("WebSocket server starting on {}", addr);
-    println!("Using tick rate of {} ticks per second ({}µs per tick)", TICK_RATE, TICK_DURATION_MICROS);
+    println!("Using tick rate of {} ticks per second ({}µs per tick)", args.tick_rate, args.tick_duration().as_micros());
+    println!("Echo mode: {:?}", args.echo.echo_mode);
     
     let listener = TcpListener::bind(&addr).await.expect("Failed to bind");
     println!("Server listening on {}", addr);
     
-    while let Ok((stream, _)) = listener.accept().await {
+    let mut connections = JoinSet::new();
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(_) => break,
+            },
+            _ = stop.wait() => break,
+        };
+        while connections.try_join_next().is_some() {}
         let tls_acceptor = tls_acceptor.clone();
-        tokio::spawn(async move {
+        let args = args.clone();
+        let manifest = manifest.clone();
+        let metrics = metrics.clone();
+        let stop = stop.clone();
+        connections.spawn(async move {
             let peer: SocketAddr = match stream.peer_addr() {
                 Ok(addr) => addr,
                 Err(e) => {
@@ -56,6 +115,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             };
             println!("New connection from: {}", peer);
+            // TCP_INFO only needs the descriptor, which outlives the TLS and WebSocket wrappers
+            let fd = stream.as_raw_fd();
             
             // Perform TLS handshake
             let tls_stream = match tls_acceptor.accept(stream).await {
@@ -77,21 +138,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Connection established with {}", peer);
             
             // Setup tick-based processing for this client
-            handle_client(ws_stream, peer).await;
+            handle_client(ws_stream, peer, fd, &args, &manifest, &metrics, &stop).await;
         });
     }
+    while connections.join_next().await.is_some() {}
     
     Ok(())
 }
 
-async fn handle_client(ws_stream: WsStream, peer: SocketAddr) {
+async fn handle_client(ws_stream: WsStream, peer: SocketAddr, fd: RawFd, args: &Args, manifest: &Mutex<RunManifest>, metrics: &Metrics, stop: &Stop) {
     // Split the WebSocket stream
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
     
     // Wait for the first message before starting the tick loop
     println!("Waiting for first message from client {}", peer);
-    let first_message = match ws_receiver.next().await {
+    let session = metrics.session(&peer.to_string());
+    let stats = Arc::new(Mutex::new(EchoStats::new(&args.echo, args.tick_duration()).with_metrics(session.clone())));
+    let first = tokio::select! {
+        message = ws_receiver.next() => message,
+        _ = stop.wait() => return,
+    };
+    let first_message = match first {
         Some(Ok(message)) => {
+            let recv_us = now_micros();
+            session.received(1);
             if let Ok(text) = message.into_text() {
                 // Try to parse the message as JSON to get the tick number for logging
                 if let Ok(parsed) = from_str::<Value>(&text) {
@@ -103,10 +173,13 @@ async fn handle_client(ws_stream: WsStream, peer: SocketAddr) {
                 }
                 
                 // Echo the first message back immediately
-                if let Err(e) = ws_sender.send(Message::Text(text.clone())).await {
+                let send_us = now_micros();
+                let echo = stamp_echo_text(&text, recv_us, send_us);
+                if let Err(e) = ws_sender.send(Message::Text(echo)).await {
                     eprintln!("Error sending first message to {}: {}", peer, e);
                     return;
                 }
+                stats.lock().unwrap().echoed(recv_us, send_us);
                 
                 Some(text)
             } else {
@@ -125,27 +198,26 @@ async fn handle_client(ws_stream: WsStream, peer: SocketAddr) {
     };
     
     // After receiving the first message, set up the message processing channel
-    let (tx, mut rx) = mpsc::channel::<String>(100);
-    
-    // Shared message queue for tick processing
-    let message_queue = Arc::new(Mutex::new(Vec::<String>::new()));
-    let message_queue_clone = message_queue.clone();
+    // Messages carry the time they arrived, so the echo can report the server's hold time
+    let (tx, mut rx) = mpsc::channel::<(String, u64)>(100);
     
-    // Receiver task: process incoming WebSocket messages and add them to the queue
+    // Receiver task: process incoming WebSocket messages and hand them to the echo task
+    let received = session.clone();
     let receiver_task = tokio::spawn(async move {
         while let Some(message_result) = ws_receiver.next().await {
             match message_result {
                 Ok(message) => {
+                    let recv_us = now_micros();
+                    received.received(1);
                     if let Ok(text) = message.into_text() {
                         // Try to parse the message as JSON to get the tick number for logging
                         if let Ok(parsed) = from_str::<Value>(&text) {
                             if let Some(tick) = parsed.get("tick").and_then(|t| t.as_u64()) {
-                                println!("Received tick {} from {}", tick, peer);
+                                log::trace!("Received tick {} from {}", tick, peer);
                             }
                         }
                         
-                        // Send the message to be processed in the next tick
-                        if let Err(e) = tx.send(text).await {
+                        if let Err(e) = tx.send((text, recv_us)).await {
                             eprintln!("Failed to send message to processing queue: {}", e);
                             break;
                         }
@@ -160,39 +232,48 @@ async fn handle_client(ws_stream: WsStream, peer: SocketAddr) {
         println!("Client {} disconnected", peer);
     });
     
-    // Message processor: collect messages from the channel into the queue
-    let processor_task = tokio::spawn(async move {
-        while let Some(message) = rx.recv().await {
-            let mut queue = message_queue_clone.lock().unwrap();
-            queue.push(message);
-        }
-    });
-    
-    // Tick task: process messages on each tick
-    let tick_task = tokio::spawn(async move {
-        let tick_duration = Duration::from_micros(TICK_DURATION_MICROS);
+    // Echo task: echo each message as it arrives, or batch them on the server tick
+    let echo = args.echo.clone();
+    let echo_stats = stats.clone();
+    let tick_duration = args.tick_duration();
+    let transport = session.clone();
+    let echo_task = tokio::spawn(async move {
         let mut tick_interval = interval(tick_duration);
+        let mut backlog = VecDeque::new();
         
         loop {
-            // Wait for the next tick
-            tick_interval.tick().await;
-            
-            // Process all messages in the queue
-            let messages_to_process = {
-                let mut queue = message_queue.lock().unwrap();
-                let messages = queue.clone();
-                queue.clear();
-                messages
+            let tick_start;
+            let messages_to_process = if echo.immediate() {
+                match rx.recv().await {
+                    Some(message) => {
+                        tick_start = Instant::now();
+                        vec![message]
+                    }
+                    None => return,
+                }
+            } else {
+                // Wait for the next tick, then take this tick's share of the queued messages
+                tick_interval.tick().await;
+                tick_start = Instant::now();
+                while let Ok(message) = rx.try_recv() {
+                    backlog.push_back(message);
+                }
+                let batch = echo.take_tick(&mut backlog);
+                echo_stats.lock().unwrap().tick(batch.len(), backlog.len());
+                batch
             };
             
             // Echo each message back
-            for message in messages_to_process {
-                match ws_sender.send(Message::Text(message.clone())).await {
+            for (message, recv_us) in messages_to_process {
+                let send_us = now_micros();
+                let echo = stamp_echo_text(&message, recv_us, send_us);
+                match ws_sender.send(Message::Text(echo)).await {
                     Ok(_) => {
+                        echo_stats.lock().unwrap().echoed(recv_us, send_us);
                         // Try to parse the message as JSON to get the tick number for logging
                         if let Ok(parsed) = from_str::<Value>(&message) {
                             if let Some(tick) = parsed.get("tick").and_then(|t| t.as_u64()) {
-                                println!("Echoed tick {} to {}", tick, peer);
+                                log::trace!("Echoed tick {} to {}", tick, peer);
                             }
                         }
                     },
@@ -202,15 +283,34 @@ async fn handle_client(ws_stream: WsStream, peer: SocketAddr) {
                     }
                 }
             }
+            if let Some(snapshot) = tcpinfo::snapshot(fd) {
+                transport.transport(snapshot);
+            }
+
+            if !echo.immediate() && tick_start.elapsed() > tick_duration {
+                echo_stats.lock().unwrap().overrun();
+                log::debug!("Tick for {} took {:?}, longer than the tick period", peer, tick_start.elapsed());
+            }
         }
     });
     
     // Wait for any task to complete (which means the connection is closing)
     tokio::select! {
         _ = receiver_task => println!("Receiver task for {} completed", peer),
-        _ = processor_task => println!("Processor task for {} completed", peer),
-        _ = tick_task => println!("Tick task for {} completed", peer),
+        _ = echo_task => println!("Echo task for {} completed", peer),
+        _ = stop.wait() => println!("Stopping; closing connection with {}", peer),
     }
+    session.end();
     
+    if let Err(e) = stats.lock().unwrap().save(&args.summary_file) {
+        eprintln!("Failed to save server summary: {}", e);
+    }
+    // The server runs until killed, so "finished" is the last time a summary was written
+    let mut manifest = manifest.lock().unwrap();
+    manifest.finish(&[&args.summary_file]);
+    if let Err(e) = manifest.save() {
+        eprintln!("Failed to update run manifest: {}", e);
+    }
+
     println!("Connection with {} closed", peer);
 }
\ No newline at end of file
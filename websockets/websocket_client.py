@@ -38,7 +38,15 @@ class Statistics:
             print(f"Average RTT: {average_rtt:.2f} ms")
             print(f"EWMA RTT: {self.get_ewma_rtt():.2f} ms")
 
-async def run(uri, message_limit, ssl_context, disable_nagling):
+    def save_rtts(self, path):
+        # One row per echo in microseconds, the rtt column the Rust clients write
+        with open(path, "w") as f:
+            f.write("rtt\n")
+            for rtt_ms in self.rtt_samples:
+                f.write(f"{rtt_ms * 1000:.1f}\n")
+        print(f"RTT samples saved to {path}")
+
+async def run(uri, message_limit, ssl_context, disable_nagling, rtt_file=None):
     async with websockets.connect(uri, ssl=ssl_context) as websocket:
         if disable_nagling:
             transport = websocket.transport
@@ -70,6 +78,8 @@ async def run(uri, message_limit, ssl_context, disable_nagling):
             statistics.record_rtt(rtt_ms)
             statistics.record_message()
         statistics.print_statistics()
+        if rtt_file:
+            statistics.save_rtts(rtt_file)
 
 if __name__ == '__main__':
     parser = argparse.ArgumentParser(description="Simple WebSocket Client with Nagle's Algorithm Option")
@@ -85,6 +95,9 @@ if __name__ == '__main__':
     parser.add_argument(
         '--disable-nagling', action='store_true', help="Disable Nagle's algorithm (enable TCP_NODELAY)"
     )
+    parser.add_argument(
+        '--rtt-file', type=str, default=None, help="Write every RTT (microseconds) to this CSV, for ons-import"
+    )
     args = parser.parse_args()
     if args.no_ssl:
         uri = f"ws://{args.host}:{args.port}"
@@ -95,4 +108,4 @@ if __name__ == '__main__':
         if args.insecure:
             ssl_context.check_hostname = False
             ssl_context.verify_mode = ssl.CERT_NONE
-    asyncio.run(run(uri, args.count, ssl_context, args.disable_nagling))
+    asyncio.run(run(uri, args.count, ssl_context, args.disable_nagling, args.rtt_file))
@@ -45,8 +45,16 @@ class Statistics:
             print(f"Average RTT: {average_rtt:.2f} ms")
             print(f"EWMA RTT: {self.get_ewma_rtt():.2f} ms")
 
+    def save_rtts(self, path):
+        # One row per echo in microseconds, the rtt column the Rust clients write
+        with open(path, "w") as f:
+            f.write("rtt\n")
+            for rtt_ms in self.rtt_samples:
+                f.write(f"{rtt_ms * 1000:.1f}\n")
+        print(f"RTT samples saved to {path}")
+
 class SimpleWebTransportClientProtocol(QuicConnectionProtocol):
-    def __init__(self, message_limit, *args, **kwargs):
+    def __init__(self, message_limit, rtt_file, *args, **kwargs):
         super().__init__(*args, **kwargs)
         self._http = None
         self._session_id = None
@@ -54,6 +62,7 @@ class SimpleWebTransportClientProtocol(QuicConnectionProtocol):
         self._datagram_received = asyncio.Event()
         self._counter = 0
         self._message_limit = message_limit
+        self._rtt_file = rtt_file
         self._statistics = Statistics()
         self._send_timestamps = {}
 
@@ -97,9 +106,11 @@ class SimpleWebTransportClientProtocol(QuicConnectionProtocol):
             await self._datagram_received.wait()
             self._datagram_received.clear()
         self._statistics.print_statistics()
+        if self._rtt_file:
+            self._statistics.save_rtts(self._rtt_file)
         self._quic.close()
 
-async def run(host, port, message_limit):
+async def run(host, port, message_limit, rtt_file=None):
     configuration = QuicConfiguration(
         is_client=True,
         alpn_protocols=H3_ALPN,
@@ -111,7 +122,7 @@ async def run(host, port, message_limit):
         host,
         port,
         configuration=configuration,
-        create_protocol=lambda *args, **kwargs: SimpleWebTransportClientProtocol(message_limit, *args, **kwargs),
+        create_protocol=lambda *args, **kwargs: SimpleWebTransportClientProtocol(message_limit, rtt_file, *args, **kwargs),
         session_ticket_handler=None,
     ) as client:
         http = H3Connection(client._quic, enable_webtransport=True)
@@ -134,5 +145,6 @@ if __name__ == "__main__":
     parser.add_argument("host", type=str, help="Server hostname or IP")
     parser.add_argument("port", type=int, help="Server port")
     parser.add_argument("--count", type=int, default=10, help="Number of messages to send")
+    parser.add_argument("--rtt-file", type=str, default=None, help="Write every RTT (microseconds) to this CSV, for ons-import")
     args = parser.parse_args()
-    asyncio.run(run(args.host, args.port, args.count))
\ No newline at end of file
+    asyncio.run(run(args.host, args.port, args.count, args.rtt_file))
\ No newline at end of file
@@ -1,15 +1,33 @@
-use std::{fs, io, path, time::{Instant, Duration}, collections::HashMap};
+use std::{fs, io, path, time::{Instant, Duration}};
+use std::net::SocketAddr;
+use std::sync::Arc;
 use anyhow::Context;
 use clap::Parser;
 use rustls::pki_types::CertificateDer;
 use url::Url;
-use tokio::time::sleep;
 use serde_json::json;
 use csv::Writer;
 use std::io::Write;
+use tokio::sync::mpsc;
 use web_transport_quinn;
 use rustls;
 use bytes::Bytes;
+use ons_common::clock::{DelayBreakdown, EchoTimestamps, OneWayDelay};
+use ons_common::dashboard::{Dashboard, DashboardOptions};
+use ons_common::handshake::{Handshake, HandshakeBench, HandshakeOptions, PhaseTimer};
+use ons_common::histogram::{HistogramOptions, LatencyHistogram};
+use ons_common::manifest::{ManifestOptions, RunManifest};
+use ons_common::metrics::{Metrics, MetricsOptions, Side};
+use ons_common::outstanding::{DrainOptions, Echo, OutstandingTicks};
+use ons_common::quic_socket::TimestampedUdpSocket;
+use ons_common::receive::Arrival;
+use ons_common::scheduler::{SchedulerOptions, TickScheduler};
+use ons_common::stop::Stop;
+use ons_common::timeseries::{TimeSeries, TimeSeriesOptions, TransportSnapshot};
+use ons_common::timestamping::TimestampingOptions;
+use ons_common::sequence::DatagramMetrics;
+use ons_common::wire::{frame_line, now_micros, LineFramer, TickMessage};
+use ons_common::workload::WorkloadOptions;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -21,20 +39,59 @@ struct Args {
     #[arg(long)]
     pub tls_cert: path::PathBuf,
 
-    #[arg(long, default_value = "180")]
-    simulation_duration_secs: u64,
-    
     /// Whether to use datagrams instead of streams
     #[arg(long, default_value = "true")]
     use_datagrams: bool,
+
+    /// Write the serialized RTT histogram here so runs can be merged later
+    #[arg(long, default_value = "webtransport_rtt.hdr")]
+    histogram_file: String,
+
+    #[command(flatten)]
+    histogram: HistogramOptions,
+
+    #[command(flatten)]
+    drain: DrainOptions,
+
+    #[command(flatten)]
+    timeseries: TimeSeriesOptions,
+
+    #[command(flatten)]
+    scheduler: SchedulerOptions,
+
+    #[command(flatten)]
+    timestamping: TimestampingOptions,
+
+    #[command(flatten)]
+    handshake: HandshakeOptions,
+
+    #[command(flatten)]
+    workload: WorkloadOptions,
+
+    #[command(flatten)]
+    manifest: ManifestOptions,
+
+    #[command(flatten)]
+    dashboard: DashboardOptions,
+
+    #[command(flatten)]
+    metrics: MetricsOptions,
 }
 
-fn save_measurements(rtt_samples: &[u128], dropped_ticks: u64, total_ticks: u64) -> Result<(), Box<dyn std::error::Error>> {
+// Defaults for --tick-rate and --simulation-duration-secs
+const TICK_RATE: u64 = 128;
+const SIMULATION_DURATION_SECS: u64 = 180;
+
+fn save_measurements(rtt_samples: &[u128], breakdowns: &[Option<DelayBreakdown>], dropped_ticks: u64, total_ticks: u64) -> Result<(), Box<dyn std::error::Error>> {
     let mut writer = Writer::from_path("webtransport_measurements.csv")?;
-    writer.write_record(&["rtt"])?;
+    writer.write_record(&["rtt", "forward_us", "residence_us", "return_us"])?;
     
-    for rtt in rtt_samples {
-        writer.write_record(&[rtt.to_string()])?;
+    for (rtt, breakdown) in rtt_samples.iter().zip(breakdowns) {
+        let (forward, residence, ret) = match breakdown {
+            Some(b) => (b.forward_us.to_string(), b.residence_us.to_string(), b.return_us.to_string()),
+            None => (String::new(), String::new(), String::new()),
+        };
+        writer.write_record(&[rtt.to_string(), forward, residence, ret])?;
     }
     writer.flush()?;
     
@@ -56,25 +113,28 @@ fn save_measurements(rtt_samples: &[u128], dropped_ticks: u64, total_ticks: u64)
     Ok(())
 }
 
-fn save_summary(rtt_samples: &[u128]) -> Result<(), Box<dyn std::error::Error>> {
-    let mut sorted = rtt_samples.to_vec();
-    sorted.sort_unstable();
-
-    let avg = rtt_samples.iter().sum::<u128>() as f64 / rtt_samples.len() as f64;
-    let p50 = sorted[rtt_samples.len() / 2];
-    let p95 = sorted[(rtt_samples.len() as f64 * 0.95) as usize];
-    let p99 = sorted[(rtt_samples.len() as f64 * 0.99) as usize];
-
+fn save_summary(
+    rtt: &LatencyHistogram,
+    workload: serde_json::Value,
+    one_way_delay: serde_json::Value,
+    datagram: serde_json::Value,
+    echoes: serde_json::Value,
+    scheduler: serde_json::Value,
+    stack_timestamps: serde_json::Value,
+    handshake: serde_json::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
     let summary = json!({
-        "sample_count": rtt_samples.len(),
+        "sample_count": rtt.len(),
+        "workload": workload,
         "metrics": {
-            "rtt": {
-                "avg": avg,
-                "p50": p50,
-                "p95": p95,
-                "p99": p99
-            }
-        }
+            "rtt": rtt.summary()
+        },
+        "one_way_delay": one_way_delay,
+        "datagram": datagram,
+        "echoes": echoes,
+        "scheduler": scheduler,
+        "stack_timestamps": stack_timestamps,
+        "handshake": handshake
     });
 
     let mut file = fs::File::create("webtransport_summary.json")?;
@@ -82,12 +142,176 @@ fn save_summary(rtt_samples: &[u128]) -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
+// TLS config for HTTP/3. Session tickets live in the config, so connections made with
+// the same one can resume; with `resume` off, resumption is disabled so every handshake is full.
+fn client_config(chain: Vec<CertificateDer<'static>>, resume: bool) -> anyhow::Result<quinn::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in chain {
+        roots.add(cert)?;
+    }
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    // WebTransport runs over HTTP/3
+    crypto.alpn_protocols = vec![b"h3".to_vec()];
+    if resume {
+        crypto.enable_early_data = true;
+    } else {
+        crypto.resumption = rustls::client::Resumption::disabled();
+    }
+    let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?;
+    Ok(quinn::ClientConfig::new(Arc::new(crypto)))
+}
+
+// A client endpoint on quinn's own socket, or on a timestamping one so kernel timestamps survive
+fn client_endpoint(socket: Option<Arc<TimestampedUdpSocket>>) -> anyhow::Result<quinn::Endpoint> {
+    let Some(socket) = socket else {
+        return Ok(quinn::Endpoint::client("[::]:0".parse()?)?);
+    };
+    Ok(quinn::Endpoint::new_with_abstract_socket(
+        quinn::EndpointConfig::default(),
+        None,
+        socket,
+        Arc::new(quinn::TokioRuntime),
+    )?)
+}
+
+// Open the QUIC connection. With `early_data`, hand it back straight away as a 0-RTT
+// connection if a ticket from an earlier connection allows it.
+async fn quic_connect(
+    endpoint: &quinn::Endpoint,
+    config: &quinn::ClientConfig,
+    addr: SocketAddr,
+    url: &Url,
+    early_data: bool,
+) -> anyhow::Result<(quinn::Connection, Option<quinn::ZeroRttAccepted>)> {
+    let host = url.host_str().context("no host in url")?;
+    let connecting = endpoint.connect_with(config.clone(), addr, host)?;
+    if !early_data {
+        return Ok((connecting.await?, None));
+    }
+    match connecting.into_0rtt() {
+        Ok((conn, accepted)) => Ok((conn, Some(accepted))),
+        Err(connecting) => Ok((connecting.await?, None)),
+    }
+}
+
+// Whether the connection resumed and whether its 0-RTT data was accepted. quinn only
+// reports the latter, so a resumed handshake whose early data was refused shows as unknown.
+async fn resumption(requested: bool, accepted: Option<quinn::ZeroRttAccepted>) -> (Option<bool>, Option<bool>) {
+    match accepted {
+        Some(accepted) => {
+            let accepted = accepted.await;
+            (accepted.then_some(true), Some(accepted))
+        }
+        None if requested => (None, Some(false)),
+        None => (Some(false), None),
+    }
+}
+
+// The client for the tick run: web_transport_quinn's own, unless kernel timestamps need
+// the session on our socket. Resumption doesn't matter for a single connection.
+fn session_client(
+    socket: Option<Arc<TimestampedUdpSocket>>,
+    chain: Vec<CertificateDer<'static>>,
+) -> anyhow::Result<web_transport_quinn::Client> {
+    match socket {
+        Some(socket) => Ok(web_transport_quinn::Client::new(client_endpoint(Some(socket))?, client_config(chain, false)?)),
+        None => Ok(web_transport_quinn::ClientBuilder::new().with_server_certificates(chain)?),
+    }
+}
+
+// Connect a session for the tick run. Client::connect does the QUIC handshake, the
+// SETTINGS exchange and the CONNECT request in one call, so here they're timed together;
+// --connect-bench times them separately.
+async fn connect_session(
+    client: &web_transport_quinn::Client,
+    url: &Url,
+) -> anyhow::Result<(web_transport_quinn::Session, Handshake)> {
+    let mut timer = PhaseTimer::start();
+    let session = client.connect(url).await?;
+    timer.mark("webtransport_session");
+    Ok((session, timer.finish().with_resumption(Some(false), None)))
+}
+
+// Open and close `runs` connections, timing the QUIC handshake, the HTTP/3 SETTINGS
+// exchange and the WebTransport CONNECT separately, and save the distributions.
+async fn connect_bench(
+    endpoint: &quinn::Endpoint,
+    config: &quinn::ClientConfig,
+    addr: SocketAddr,
+    args: &Args,
+    runs: u32,
+    stop: &Stop,
+) -> anyhow::Result<()> {
+    let resume = args.handshake.resume;
+    let mut bench = HandshakeBench::new("webtransport", &args.histogram);
+    for _ in 0..runs {
+        if stop.requested() {
+            break;
+        }
+        let attempt = async {
+            let mut timer = PhaseTimer::start();
+            // With 0-RTT the connection comes back before the handshake finishes, and
+            // SETTINGS and CONNECT go out as early data
+            let (conn, accepted) = quic_connect(endpoint, config, addr, &args.url, resume).await?;
+            timer.mark("quic_handshake");
+            web_transport_quinn::Settings::connect(&conn).await?;
+            timer.mark("h3_settings");
+            web_transport_quinn::Connect::open(&conn, &args.url).await?;
+            timer.mark("webtransport_connect");
+            let (resumed, early_data) = resumption(resume, accepted).await;
+            conn.close(0u32.into(), b"done");
+            anyhow::Ok(timer.finish().with_resumption(resumed, early_data))
+        }
+        .await;
+        bench.record(attempt.map_err(|e| e.to_string()));
+        tokio::time::sleep(args.handshake.interval()).await;
+    }
+    // Let the last close reach the server before the endpoint goes away
+    endpoint.wait_idle().await;
+
+    let path = bench.save(&args.handshake).map_err(|e| anyhow::anyhow!("{}", e))?;
+    log::info!("Handshake summary saved to {}", path.display());
+    Ok(())
+}
+
+// Save the per-packet stack timestamps and summarise them, if timestamping was on
+fn stack_timestamps(socket: &Option<Arc<TimestampedUdpSocket>>, options: &TimestampingOptions) -> serde_json::Value {
+    let Some(socket) = socket else { return serde_json::Value::Null };
+    let stamps = socket.stamps();
+    let stamps = stamps.lock().unwrap();
+    match stamps.save(options, "webtransport") {
+        Ok(path) => log::info!("Per-packet stack timestamps saved to {}", path.display()),
+        Err(e) => log::error!("Failed to save stack timestamps: {}", e),
+    }
+    stamps.summary()
+}
+
+// Split each RTT sample into one-way delays, once the server clock offset is known
+fn one_way_breakdowns(one_way_delay: &OneWayDelay, sample_count: usize) -> Vec<Option<DelayBreakdown>> {
+    match one_way_delay.estimate_offset() {
+        Some(offset) => {
+            log::info!("Estimated server clock offset: {} µs", offset.offset_us);
+            one_way_delay.breakdown(&offset)
+        }
+        None => vec![None; sample_count],
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let env = env_logger::Env::default().default_filter_or("info");
     env_logger::init_from_env(env);
 
     let args = Args::parse();
+    args.scheduler.apply_to_current_thread()?;
+    // A first Ctrl-C stops the tick loop early; the results so far are still saved
+    let stop = Stop::on_signals()?;
+    let mut manifest = RunManifest::start("webtransport", &args, args.workload.summary(TICK_RATE, SIMULATION_DURATION_SECS), &args.manifest);
+    log::info!("Run manifest saved to {}", manifest.save()?.display());
 
     // Read the PEM certificate chain
     let chain = fs::File::open(&args.tls_cert).context("failed to open cert file")?;
@@ -99,97 +323,192 @@ async fn main() -> anyhow::Result<()> {
 
     anyhow::ensure!(!chain.is_empty(), "could not find certificate");
 
-    // Create a new client with default settings
-    let client = web_transport_quinn::ClientBuilder::new().with_server_certificates(chain)?;
+    // Our own socket only when kernel timestamps are wanted; quinn's otherwise
+    let timestamped_socket = if args.timestamping.enabled() {
+        Some(Arc::new(TimestampedUdpSocket::bind("[::]:0".parse()?, &args.timestamping)?))
+    } else {
+        None
+    };
     log::info!("connecting to {}", args.url);
-    
-    let connection_start = Instant::now();
-    let session = client.connect(&args.url).await?;
-    let connection_duration = connection_start.elapsed();
-    log::info!("connected in {} ms", connection_duration.as_millis());
-
-    // Define tick rate and compute tick duration with microsecond precision
-    const TICK_RATE: u32 = 128; // 120 ticks per second
-    let tick_duration = Duration::from_micros(1_000_000 / TICK_RATE as u64);
+
+    if let Some(runs) = args.handshake.connect_bench {
+        // Timing each phase needs quinn's connection itself, and --resume a TLS
+        // config whose session tickets outlive each connection, so build both here
+        let endpoint = client_endpoint(timestamped_socket.clone())?;
+        let config = client_config(chain, args.handshake.resume)?;
+        let host = args.url.host_str().context("no host in url")?;
+        let addr = tokio::net::lookup_host((host, args.url.port().unwrap_or(443)))
+            .await?
+            .next()
+            .context("could not resolve server address")?;
+        connect_bench(&endpoint, &config, addr, &args, runs, &stop).await?;
+        let handshake_path = args.handshake.path("webtransport");
+        manifest.finish(&[handshake_path.with_extension("csv"), handshake_path]);
+        manifest.save()?;
+        return Ok(());
+    }
+
+    let client = session_client(timestamped_socket.clone(), chain)?;
+    let (session, handshake) = connect_session(&client, &args.url).await?;
+    handshake.log();
+
+    let tick_duration = args.workload.tick_duration(TICK_RATE);
     log::info!("Tick duration: {} µs", tick_duration.as_micros());
+    let mut scheduler = TickScheduler::new(tick_duration, &args.scheduler);
 
-    // Set simulation duration
-    let simulation_duration = Duration::from_secs(args.simulation_duration_secs);
+    let simulation_duration = args.workload.duration(SIMULATION_DURATION_SECS);
     let simulation_start = Instant::now();
     let simulation_end = simulation_start + simulation_duration;
+    let drain_end = simulation_end + args.drain.drain();
+    let mut timeseries = TimeSeries::new(simulation_start);
 
     let mut tick_count: u64 = 0;
-    let mut sent_timestamps: HashMap<u64, Instant> = HashMap::new();
+    // Monotonic send time for the RTT, wall-clock send time for the one-way breakdown
+    let mut sent_timestamps: OutstandingTicks<(Instant, u64)> = OutstandingTicks::new(&args.drain);
     let mut rtt_samples: Vec<u128> = Vec::new();
-    
+    let mut rtt_histogram = LatencyHistogram::new(&args.histogram);
+    let mut one_way_delay = OneWayDelay::new();
+    let mode = if args.use_datagrams { "datagrams" } else { "streams" };
+    let dashboard = Dashboard::start(&args.dashboard, &format!("WebTransport {} {}", mode, args.url), simulation_duration, &stop)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    let metrics = Metrics::start(&args.metrics, "webtransport_client", Side::Client).map_err(|e| anyhow::anyhow!("{}", e))?;
+    dashboard.export(metrics.session(args.url.as_str()));
+    let live = dashboard.live();
+
     if args.use_datagrams {
         // Using datagram extension
         let max_datagram_size = session.max_datagram_size();
         log::info!("Using WebTransport datagrams (max size: {} bytes)", max_datagram_size);
+        if args.workload.payload_bytes > max_datagram_size {
+            return Err(anyhow::anyhow!(
+                "--payload-bytes {} is over the path's datagram limit of {} bytes; use streams for larger ticks",
+                args.workload.payload_bytes, max_datagram_size));
+        }
+        let mut datagram_metrics = DatagramMetrics::new();
+
+        // Read echoes on their own task and timestamp them on arrival, so the RTT
+        // reflects the network rather than how often the tick loop polls
+        let (arrival_tx, mut arrivals) = mpsc::unbounded_channel::<Arrival>();
+        let reader = session.clone();
+        let receiver = tokio::spawn(async move {
+            loop {
+                match reader.read_datagram().await {
+                    Ok(datagram) => {
+                        if arrival_tx.send(Arrival::now(datagram.to_vec())).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        log::debug!("Datagram receive ended: {:?}", e);
+                        break;
+                    }
+                }
+            }
+        });
         
         // Run the simulation tick loop with datagrams
-        while Instant::now() < simulation_end {
+        while Instant::now() < drain_end {
+            if stop.requested() {
+                log::info!("Stopping early after {} ticks", tick_count);
+                break;
+            }
+            let slot = scheduler.wait().await;
             let tick_start = Instant::now();
+            sent_timestamps.expire();
             
-            // Process any incoming echoed datagram messages (non-blocking)
-            loop {
-                match tokio::time::timeout(Duration::from_millis(1), session.read_datagram()).await {
-                    Ok(Ok(datagram)) => {
-                        if let Ok(received_str) = std::str::from_utf8(&datagram) {
-                            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(received_str) {
-                                if let Some(tick_val) = parsed.get("tick") {
-                                    if let Some(tick) = tick_val.as_u64() {
-                                        if let Some(sent_time) = sent_timestamps.remove(&tick) {
-                                            let rtt = Instant::now().duration_since(sent_time);
-                                            rtt_samples.push(rtt.as_micros());
-                                            log::info!("Tick {}: Received datagram echo, RTT: {} µs", tick, rtt.as_micros());
-                                        }
-                                    }
+            // Match the echoes the receive task has timestamped since the last tick
+            while let Ok(arrival) = arrivals.try_recv() {
+                if let Ok(parsed) = serde_json::from_slice::<serde_json::Value>(&arrival.data) {
+                    if let Some(tick_val) = parsed.get("tick") {
+                        if let Some(tick) = tick_val.as_u64() {
+                            let received_at = arrival.at;
+                            match sent_timestamps.take_at(tick, received_at) {
+                                Echo::OnTime((sent_time, sent_wall)) => {
+                                    let rtt = received_at.saturating_duration_since(sent_time);
+                                    timeseries.received(tick, received_at, false);
+                                    rtt_samples.push(rtt.as_micros());
+                                    rtt_histogram.record(rtt.as_micros() as u64);
+                                    datagram_metrics.record(tick, rtt.as_micros() as i64);
+                                    let stamps = TickMessage::parse(&arrival.data)
+                                        .and_then(|echo| EchoTimestamps::from_echo(&echo, sent_wall, arrival.wall_us));
+                                    one_way_delay.record(stamps);
+                                    live.echo(rtt.as_micros() as u64);
+                                    log::trace!("Tick {}: Received datagram echo, RTT: {} µs", tick, rtt.as_micros());
                                 }
+                                Echo::Late((sent_time, _)) => {
+                                    // Arrived, so not lost, but kept out of the RTT statistics
+                                    let rtt = received_at.saturating_duration_since(sent_time);
+                                    timeseries.received(tick, received_at, true);
+                                    datagram_metrics.record(tick, rtt.as_micros() as i64);
+                                    live.late();
+                                    log::trace!("Tick {}: Late datagram echo after {} µs", tick, rtt.as_micros());
+                                }
+                                Echo::Unknown => datagram_metrics.record_unmatched(tick),
                             }
                         }
-                    },
-                    // Break on timeout, error, or no more data
-                    Ok(Err(e)) => {
-                        log::debug!("No more datagrams or error: {:?}", e);
-                        break;
-                    },
-                    Err(_) => break, // Timeout occurred
+                    }
                 }
             }
             
-            // Prepare the tick message with the tick number and precise timestamp
-            let timestamp = Instant::now().duration_since(simulation_start).as_micros();
-            let message = format!(r#"{{"tick":{},"timestamp":{}}}"#, tick_count, timestamp);
-            sent_timestamps.insert(tick_count, Instant::now());
-            
-            // Send the tick message as a datagram
-            match session.send_datagram(Bytes::from(message)) {
-                Ok(_) => log::info!("Sent tick {} datagram at {} µs", tick_count, timestamp),
-                Err(e) => {
-                    log::error!("Error sending datagram: {:?}", e);
-                    // Unlike streams, we continue even if a datagram send fails
+            // After the last send, keep reading until the drain period ends or nothing is outstanding
+            if Instant::now() < simulation_end {
+                // Prepare the tick message with the tick number and precise timestamp
+                let timestamp = Instant::now().duration_since(simulation_start).as_micros();
+                let message = args.workload.message(tick_count, timestamp);
+                sent_timestamps.insert(tick_count, (Instant::now(), now_micros()));
+                let snapshot = TransportSnapshot::quic(&session.stats());
+                timeseries.sent(tick_count, Instant::now(), message.len(), snapshot);
+
+                // Send the tick message as a datagram
+                match session.send_datagram(Bytes::from(message)) {
+                    Ok(_) => {
+                        scheduler.sent(&slot);
+                        live.sent(slot.deadline.elapsed(), snapshot);
+                        log::trace!("Sent tick {} datagram at {} µs", tick_count, timestamp)
+                    }
+                    Err(e) => {
+                        log::error!("Error sending datagram: {:?}", e);
+                        // Unlike streams, we continue even if a datagram send fails
+                    }
                 }
+                tick_count += 1;
+            } else if sent_timestamps.pending() == 0 {
+                break;
             }
-            tick_count += 1;
+            live.outstanding(sent_timestamps.pending());
             
-            // Sleep until the next tick boundary
-            let elapsed = tick_start.elapsed();
-            if elapsed < tick_duration {
-                sleep(tick_duration - elapsed).await;
+            // The scheduler waits for the next absolute deadline; just flag ticks that overran it
+            if tick_start.elapsed() >= tick_duration && tick_count > 0 && Instant::now() < simulation_end {
+                timeseries.mark_overrun(tick_count - 1);
+                live.overrun();
             }
         }
+        receiver.abort();
+        dashboard.finish();
         
-        // Calculate and report dropped packets (ticks without responses)
-        let dropped_ticks = tick_count - rtt_samples.len() as u64;
+        // Calculate and report dropped packets (ticks without any response, late ones excluded)
+        let dropped_ticks = tick_count.saturating_sub(rtt_samples.len() as u64 + sent_timestamps.late());
         
         if !rtt_samples.is_empty() {
             log::info!("Saving RTT data...");
-            save_measurements(&rtt_samples, dropped_ticks, tick_count).expect("Failed to save measurements");
-            save_summary(&rtt_samples).expect("Failed to save summary");
+            let breakdowns = one_way_breakdowns(&one_way_delay, rtt_samples.len());
+            save_measurements(&rtt_samples, &breakdowns, dropped_ticks, tick_count).expect("Failed to save measurements");
+            save_summary(
+                &rtt_histogram,
+                args.workload.summary(TICK_RATE, SIMULATION_DURATION_SECS),
+                one_way_delay.summary(),
+                datagram_metrics.summary(tick_count),
+                sent_timestamps.summary(tick_count, rtt_samples.len() as u64),
+                scheduler.summary(),
+                stack_timestamps(&timestamped_socket, &args.timestamping),
+                handshake.summary(),
+            ).expect("Failed to save summary");
+            rtt_histogram.save(&args.histogram_file).expect("Failed to save RTT histogram");
 
             let average_rtt = rtt_samples.iter().sum::<u128>() as f64 / rtt_samples.len() as f64;
             log::info!("Total ticks sent: {}, received responses: {}", tick_count, rtt_samples.len());
+            log::info!("Reordered: {}, duplicates: {}, jitter: {:.1} µs",
+                datagram_metrics.reordered(), datagram_metrics.duplicates(), datagram_metrics.jitter_us());
             log::info!("Average RTT: {:.2} µs", average_rtt);
         } else {
             log::info!("No RTT data collected.");
@@ -200,69 +519,126 @@ async fn main() -> anyhow::Result<()> {
         
         // Open a single bidirectional stream for all messages
         let (mut send, mut recv) = session.open_bi().await?;
-        let mut buf = vec![0u8; 1024];
+
+        // Read echoes on their own task and timestamp them on arrival. A read can hold
+        // part of an echo or several, so they are split on the newline framing.
+        let (arrival_tx, mut arrivals) = mpsc::unbounded_channel::<Arrival>();
+        let receiver = tokio::spawn(async move {
+            let mut buf = vec![0u8; 64 * 1024];
+            let mut framer = LineFramer::default();
+            'read: loop {
+                match recv.read(&mut buf).await {
+                    Ok(Some(size)) => {
+                        for echo in framer.push(&buf[..size]) {
+                            if arrival_tx.send(Arrival::now(echo)).is_err() {
+                                break 'read;
+                            }
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::error!("Error reading from stream: {:?}", e);
+                        break;
+                    }
+                }
+            }
+        });
         
         // Run the simulation tick loop
-        while Instant::now() < simulation_end {
+        while Instant::now() < drain_end {
+            if stop.requested() {
+                log::info!("Stopping early after {} ticks", tick_count);
+                break;
+            }
+            let slot = scheduler.wait().await;
             let tick_start = Instant::now();
+            sent_timestamps.expire();
             
-            // Process any incoming echoed messages (non-blocking)
-            loop {
-                match tokio::time::timeout(Duration::from_millis(1), recv.read(&mut buf)).await {
-                    Ok(Ok(Some(size))) => {
-                        if let Ok(received_str) = std::str::from_utf8(&buf[..size]) {
-                            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(received_str) {
-                                if let Some(tick_val) = parsed.get("tick") {
-                                    if let Some(tick) = tick_val.as_u64() {
-                                        if let Some(sent_time) = sent_timestamps.remove(&tick) {
-                                            let rtt = Instant::now().duration_since(sent_time);
-                                            rtt_samples.push(rtt.as_micros());
-                                            log::info!("Tick {}: Received echo, RTT: {} µs", tick, rtt.as_micros());
-                                        }
-                                    }
+            // Match the echoes the receive task has timestamped since the last tick
+            while let Ok(arrival) = arrivals.try_recv() {
+                if let Ok(parsed) = serde_json::from_slice::<serde_json::Value>(&arrival.data) {
+                    if let Some(tick_val) = parsed.get("tick") {
+                        if let Some(tick) = tick_val.as_u64() {
+                            let received_at = arrival.at;
+                            match sent_timestamps.take_at(tick, received_at) {
+                                Echo::OnTime((sent_time, sent_wall)) => {
+                                    let rtt = received_at.saturating_duration_since(sent_time);
+                                    timeseries.received(tick, received_at, false);
+                                    rtt_samples.push(rtt.as_micros());
+                                    rtt_histogram.record(rtt.as_micros() as u64);
+                                    let stamps = TickMessage::parse(&arrival.data)
+                                        .and_then(|echo| EchoTimestamps::from_echo(&echo, sent_wall, arrival.wall_us));
+                                    one_way_delay.record(stamps);
+                                    live.echo(rtt.as_micros() as u64);
+                                    log::trace!("Tick {}: Received echo, RTT: {} µs", tick, rtt.as_micros());
+                                }
+                                Echo::Late((sent_time, _)) => {
+                                    timeseries.received(tick, received_at, true);
+                                    live.late();
+                                    log::trace!("Tick {}: Late echo after {} µs", tick,
+                                        received_at.saturating_duration_since(sent_time).as_micros());
                                 }
+                                Echo::Unknown => (),
                             }
                         }
-                    },
-                    // Break on timeout or no more data
-                    Ok(Ok(None)) => break,
-                    Ok(Err(e)) => {
-                        log::error!("Error reading from stream: {:?}", e);
-                        break;
-                    },
-                    Err(_) => break, // Timeout occurred
+                    }
                 }
             }
             
-            // Prepare the tick message with the tick number and precise timestamp
-            let timestamp = Instant::now().duration_since(simulation_start).as_micros();
-            let message = format!(r#"{{"tick":{},"timestamp":{}}}"#, tick_count, timestamp);
-            sent_timestamps.insert(tick_count, Instant::now());
-            
-            // Send the tick message
-            match send.write_all(message.as_bytes()).await {
-                Ok(_) => log::info!("Sent tick {} at {} µs", tick_count, timestamp),
-                Err(e) => {
-                    log::error!("Error sending tick message: {:?}", e);
-                    break;
+            // After the last send, keep reading until the drain period ends or nothing is outstanding
+            if Instant::now() < simulation_end {
+                // Prepare the tick message with the tick number and precise timestamp
+                let timestamp = Instant::now().duration_since(simulation_start).as_micros();
+                let message = args.workload.message(tick_count, timestamp);
+                sent_timestamps.insert(tick_count, (Instant::now(), now_micros()));
+                let snapshot = TransportSnapshot::quic(&session.stats());
+                timeseries.sent(tick_count, Instant::now(), message.len(), snapshot);
+
+                // Send the tick message
+                match send.write_all(&frame_line(message.as_bytes())).await {
+                    Ok(_) => {
+                        scheduler.sent(&slot);
+                        live.sent(slot.deadline.elapsed(), snapshot);
+                        log::trace!("Sent tick {} at {} µs", tick_count, timestamp)
+                    }
+                    Err(e) => {
+                        log::error!("Error sending tick message: {:?}", e);
+                        break;
+                    }
                 }
+                tick_count += 1;
+            } else if sent_timestamps.pending() == 0 {
+                break;
             }
-            tick_count += 1;
+            live.outstanding(sent_timestamps.pending());
             
-            // Sleep until the next tick boundary
-            let elapsed = tick_start.elapsed();
-            if elapsed < tick_duration {
-                sleep(tick_duration - elapsed).await;
+            // The scheduler waits for the next absolute deadline; just flag ticks that overran it
+            if tick_start.elapsed() >= tick_duration && tick_count > 0 && Instant::now() < simulation_end {
+                timeseries.mark_overrun(tick_count - 1);
+                live.overrun();
             }
         }
 
         // Close the stream after all messages are sent
         send.finish()?;
+        receiver.abort();
+        dashboard.finish();
 
         if !rtt_samples.is_empty() {
             log::info!("Saving RTT data...");
-            save_measurements(&rtt_samples, 0, tick_count).expect("Failed to save measurements");
-            save_summary(&rtt_samples).expect("Failed to save summary");
+            let breakdowns = one_way_breakdowns(&one_way_delay, rtt_samples.len());
+            save_measurements(&rtt_samples, &breakdowns, 0, tick_count).expect("Failed to save measurements");
+            save_summary(
+                &rtt_histogram,
+                args.workload.summary(TICK_RATE, SIMULATION_DURATION_SECS),
+                one_way_delay.summary(),
+                serde_json::Value::Null,
+                sent_timestamps.summary(tick_count, rtt_samples.len() as u64),
+                scheduler.summary(),
+                stack_timestamps(&timestamped_socket, &args.timestamping),
+                handshake.summary(),
+            ).expect("Failed to save summary");
+            rtt_histogram.save(&args.histogram_file).expect("Failed to save RTT histogram");
 
             let average_rtt = rtt_samples.iter().sum::<u128>() as f64 / rtt_samples.len() as f64;
             log::info!("Total ticks sent (with RTT measured): {}", rtt_samples.len());
@@ -272,6 +648,22 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
-    log::info!("Client simulation complete after {} seconds.", args.simulation_duration_secs);
+    // Last, so a failure here can't cost the RTT measurements and summary above
+    match timeseries.save(&args.timeseries, "webtransport") {
+        Ok(path) => log::info!("Per-tick time series saved to {}", path.display()),
+        Err(e) => log::error!("Failed to save the per-tick time series: {}", e),
+    }
+
+    manifest.finish(&[
+        "webtransport_measurements.csv".into(),
+        "webtransport_packet_loss.json".into(),
+        "webtransport_summary.json".into(),
+        args.histogram_file.clone().into(),
+        args.timestamping.path("webtransport"),
+        args.timeseries.path("webtransport"),
+    ]);
+    manifest.save()?;
+
+    log::info!("Client simulation complete after {} seconds.", simulation_duration.as_secs());
     Ok(())
 }
\ No newline at end of file
@@ -1,20 +1,23 @@
 use std::{fs, io, path, time::{Duration, Instant}};
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
+use std::collections::VecDeque;
 use anyhow::Context;
 use clap::Parser;
-use rustls::pki_types::CertificateDer;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use web_transport_quinn::Session;
 use tokio::time::interval;
 use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinSet;
 use std::sync::Arc;
 use serde_json::Value;
 use bytes::Bytes;
+use ons_common::echo::{EchoOptions, EchoStats};
+use ons_common::manifest::{ManifestOptions, RunManifest};
+use ons_common::metrics::{Metrics, MetricsOptions, SessionMetrics, Side};
+use ons_common::stop::Stop;
+use ons_common::wire::{frame_line, now_micros, stamp_echo, LineFramer};
 
 // Define tick rate constants
-const TICK_RATE: u32 = 128; // ticks per second, matching client
-const TICK_DURATION_MICROS: u64 = 1_000_000 / TICK_RATE as u64;
-const MAX_DATAGRAMS_PER_TICK: usize = 10; // Limit datagrams processed per tick to avoid overload
+const TICK_RATE: u64 = 128; // default ticks per second, matching client
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -30,12 +33,47 @@ struct Args {
     #[arg(long)]
     pub tls_key: path::PathBuf,
 
-    #[arg(long, default_value = "rtt_measurements.csv")]
-    pub output_file: String,
-    
     /// Use datagram extension instead of streams
     #[arg(long, default_value = "true")]
     use_datagrams: bool,
+
+    /// Accept 0-RTT data from clients resuming an earlier session
+    #[arg(long)]
+    zero_rtt: bool,
+
+    #[command(flatten)]
+    echo: EchoOptions,
+
+    /// Server ticks per second, which tick-batched echoes are sent on
+    #[arg(long, default_value_t = TICK_RATE)]
+    tick_rate: u64,
+
+    /// Write the echo mode and server residence times here when each session ends
+    #[arg(long, default_value = "webtransport_server_summary.json")]
+    summary_file: String,
+
+    #[command(flatten)]
+    manifest: ManifestOptions,
+
+    #[command(flatten)]
+    metrics: MetricsOptions,
+}
+
+// A server whose TLS config accepts early data, which web_transport_quinn's builder leaves off
+fn zero_rtt_server(addr: std::net::SocketAddr, chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> anyhow::Result<web_transport_quinn::Server> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)?;
+    // WebTransport runs over HTTP/3; QUIC requires the maximum early data size to be either 0 or u32::MAX
+    crypto.alpn_protocols = vec![b"h3".to_vec()];
+    crypto.max_early_data_size = u32::MAX;
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(crypto)?;
+    let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+
+    let endpoint = quinn::Endpoint::server(config, addr)?;
+    Ok(web_transport_quinn::Server::new(endpoint))
 }
 
 #[tokio::main]
@@ -44,8 +82,15 @@ async fn main() -> anyhow::Result<()> {
     env_logger::init_from_env(env);
 
     let args = Args::parse();
+    // On SIGINT, stop accepting and let open sessions write their summaries first
+    let stop = Stop::on_signals()?;
 
-    log::info!("Using tick rate of {} ticks per second ({}µs per tick)", TICK_RATE, TICK_DURATION_MICROS);
+    let tick_duration = Duration::from_micros(1_000_000 / args.tick_rate.max(1));
+    log::info!("Using tick rate of {} ticks per second ({}µs per tick)", args.tick_rate, tick_duration.as_micros());
+    let manifest = RunManifest::start("webtransport_server", &args, serde_json::json!({ "tick_rate": args.tick_rate }), &args.manifest);
+    log::info!("Run manifest saved to {}", manifest.save()?.display());
+    let manifest = Arc::new(Mutex::new(manifest));
+    let metrics = Metrics::start(&args.metrics, "webtransport_server", Side::Server).map_err(|e| anyhow::anyhow!("{}", e))?;
 
     // Read the PEM certificate chain
     let chain = fs::File::open(&args.tls_cert).context("failed to open cert file")?;
@@ -63,93 +108,130 @@ async fn main() -> anyhow::Result<()> {
         .context("failed to load private key")?
         .context("missing private key")?;
 
-    let mut server = web_transport_quinn::ServerBuilder::new()
-        .with_addr(args.addr)
-        .with_certificate(chain, key)?;
+    let mut server = if args.zero_rtt {
+        zero_rtt_server(args.addr, chain, key)?
+    } else {
+        web_transport_quinn::ServerBuilder::new()
+            .with_addr(args.addr)
+            .with_certificate(chain, key)?
+    };
 
-    log::info!("listening on {}", args.addr);
+    log::info!("listening on {}{}", args.addr, if args.zero_rtt { " (0-RTT enabled)" } else { "" });
     
     if args.use_datagrams {
         log::info!("Server configured to use WebTransport datagrams");
     } else {
         log::info!("Server configured to use WebTransport bidirectional streams");
     }
+    log::info!("Echo mode: {:?}", args.echo.echo_mode);
 
-    while let Some(conn) = server.accept().await {
-        let output_file = args.output_file.clone();
+    let mut connections = JoinSet::new();
+    loop {
+        let conn = tokio::select! {
+            conn = server.accept() => match conn {
+                Some(conn) => conn,
+                None => break,
+            },
+            _ = stop.wait() => break,
+        };
+        while connections.try_join_next().is_some() {}
+        let metrics = metrics.clone();
         let use_datagrams = args.use_datagrams;
-        tokio::spawn(async move {
-            match run_conn(conn, output_file, use_datagrams).await {
+        let echo = args.echo.clone();
+        let summary_file = args.summary_file.clone();
+        let manifest = manifest.clone();
+        let stop = stop.clone();
+        connections.spawn(async move {
+            match run_conn(conn, use_datagrams, echo, tick_duration, summary_file, manifest, metrics, stop).await {
                 Ok(_) => log::info!("connection completed"),
                 Err(err) => log::error!("connection failed: {}", err),
             }
         });
     }
+    while connections.join_next().await.is_some() {}
 
     Ok(())
 }
 
-async fn run_conn(request: web_transport_quinn::Request, output_file: String, use_datagrams: bool) -> anyhow::Result<()> {
+#[allow(clippy::too_many_arguments)]
+async fn run_conn(
+    request: web_transport_quinn::Request,
+    use_datagrams: bool,
+    echo: EchoOptions,
+    tick_duration: Duration,
+    summary_file: String,
+    manifest: Arc<Mutex<RunManifest>>,
+    metrics: Metrics,
+    stop: Stop,
+) -> anyhow::Result<()> {
     log::info!("received WebTransport request: {}", request.url());
 
     let session = request.ok().await.context("failed to accept session")?;
     log::info!("accepted session");
 
-    if let Err(err) = run_session(session, output_file, use_datagrams).await {
-        log::error!("session error: {}", err);
+    let session_metrics = metrics.session(&session.remote_address().to_string());
+    let stats = Arc::new(Mutex::new(EchoStats::new(&echo, tick_duration).with_metrics(session_metrics.clone())));
+    tokio::select! {
+        result = run_session(session, use_datagrams, echo, tick_duration, stats.clone(), session_metrics.clone()) => {
+            if let Err(err) = result {
+                log::error!("session error: {}", err);
+            }
+        }
+        _ = stop.wait() => log::info!("stopping; closing session"),
+    }
+    session_metrics.end();
+    if let Err(err) = stats.lock().await.save(&summary_file) {
+        log::error!("failed to save server summary: {}", err);
+    }
+    // The server runs until killed, so "finished" is the last time a session ended
+    let mut manifest = manifest.lock().await;
+    manifest.finish(&[&summary_file]);
+    if let Err(err) = manifest.save() {
+        log::error!("failed to update run manifest: {}", err);
     }
 
     Ok(())
 }
 
-async fn run_session(session: Session, output_file: String, use_datagrams: bool) -> anyhow::Result<()> {
-    // Open CSV file for writing RTT measurements
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(output_file)
-        .await?;
-
-    // Write CSV header if file is empty
-    if file.metadata().await?.len() == 0 {
-        file.write_all(b"timestamp,rtt_ms\n").await?;
-    }
-    
+async fn run_session(
+    session: Session,
+    use_datagrams: bool,
+    echo: EchoOptions,
+    tick_duration: Duration,
+    stats: Arc<Mutex<EchoStats>>,
+    metrics: SessionMetrics,
+) -> anyhow::Result<()> {
     if use_datagrams {
         // Using datagram extension
         log::info!("Starting datagram-based session...");
         
-        // Message files are shared between tasks
-        let file = Arc::new(Mutex::new(file));
-        
         // The session object needs to be shared among tasks
         let session = Arc::new(session);
         let session_for_receiver = session.clone();
         let session_for_first = session.clone();
         
-        // Create a channel for processing received datagrams
-        let (tx, mut rx) = mpsc::channel::<Bytes>(100);
-        
-        // Create a queue for processing datagrams on the tick schedule
-        let datagram_queue = Arc::new(Mutex::new(Vec::<Bytes>::new()));
-        let datagram_queue_clone = datagram_queue.clone();
+        // Create a channel for processing received datagrams, tagged with their arrival time
+        let (tx, mut rx) = mpsc::channel::<(Bytes, u64)>(100);
         
         // Datagram receiver task - continuously read datagrams and forward them to processing
+        let received = metrics.clone();
         let receiver_task = tokio::spawn(async move {
             loop {
                 match session_for_receiver.read_datagram().await {
                     Ok(datagram) => {
+                        let recv_us = now_micros();
+                        received.received(1);
                         // Log the message if it contains a valid tick value
                         if let Ok(text) = std::str::from_utf8(&datagram) {
                             if let Ok(json) = serde_json::from_str::<Value>(text) {
                                 if let Some(tick) = json.get("tick") {
-                                    log::info!("Received tick {} datagram", tick);
+                                    log::trace!("Received tick {} datagram", tick);
                                 }
                             }
                         }
                         
                         // Forward the datagram to the processing queue
-                        if let Err(e) = tx.send(datagram).await {
+                        if let Err(e) = tx.send((datagram, recv_us)).await {
                             log::error!("Failed to forward datagram: {}", e);
                             break;
                         }
@@ -163,21 +245,14 @@ async fn run_session(session: Session, output_file: String, use_datagrams: bool)
             log::info!("Datagram receiver task completed");
         });
         
-        // Datagram queue task - collect datagrams from channel to be processed by tick
-        let queue_task = tokio::spawn(async move {
-            while let Some(datagram) = rx.recv().await {
-                let mut queue = datagram_queue_clone.lock().await;
-                queue.push(datagram);
-            }
-            log::info!("Datagram queue task completed");
-        });
-        
         // The first received datagram might be important for synchronization
         // Wait for at least one datagram before starting the tick processing
         log::info!("Waiting for first datagram from client...");
         
         let first_datagram = match tokio::time::timeout(Duration::from_secs(30), session_for_first.read_datagram()).await {
             Ok(Ok(datagram)) => {
+                let recv_us = now_micros();
+                metrics.received(1);
                 log::info!("Received first datagram, starting echo processing");
                 // Try to extract and log tick information
                 if let Ok(text) = std::str::from_utf8(&datagram) {
@@ -187,7 +262,7 @@ async fn run_session(session: Session, output_file: String, use_datagrams: bool)
                         }
                     }
                 }
-                Some(datagram)
+                Some((datagram, recv_us))
             },
             Ok(Err(e)) => {
                 log::error!("Error receiving first datagram: {:?}", e);
@@ -203,67 +278,54 @@ async fn run_session(session: Session, output_file: String, use_datagrams: bool)
         let session_for_tick = session.clone();
         
         // Echo the first datagram immediately if we got one
-        if let Some(datagram) = first_datagram {
-            if let Err(e) = session.send_datagram(datagram.clone()) {
+        if let Some((datagram, recv_us)) = first_datagram {
+            let send_us = now_micros();
+            if let Err(e) = session.send_datagram(Bytes::from(stamp_echo(&datagram, recv_us, send_us))) {
                 log::error!("Error echoing first datagram: {:?}", e);
             } else {
                 log::info!("Echoed first datagram");
-                
-                // Log the first measurement
-                let timestamp = chrono::Utc::now().timestamp();
-                let measurement = format!("{},0\n", timestamp);
-                let mut file_guard = file.lock().await;
-                file_guard.write_all(measurement.as_bytes()).await?;
+                stats.lock().await.echoed(recv_us, send_us);
             }
         }
         
-        // Main tick processing task
+        // Echo task: echo each datagram as it arrives, or batch them on the server tick
+        let tick_stats = stats.clone();
         let tick_task = tokio::spawn(async move {
-            let tick_duration = Duration::from_micros(TICK_DURATION_MICROS);
             let mut tick_interval = interval(tick_duration);
+            let mut backlog = VecDeque::new();
             
             loop {
-                // Wait for the next tick
-                tick_interval.tick().await;
-                let tick_start = Instant::now();
-                
-                // Process all datagrams in the queue (up to MAX_DATAGRAMS_PER_TICK)
-                let datagrams_to_process = {
-                    let mut queue = datagram_queue.lock().await;
-                    let count = queue.len().min(MAX_DATAGRAMS_PER_TICK);
-                    if count == 0 {
-                        vec![] // No datagrams to process
-                    } else {
-                        // Take only the first MAX_DATAGRAMS_PER_TICK datagrams
-                        let datagrams = queue[0..count].to_vec();
-                        // Remove the processed datagrams
-                        queue.drain(0..count);
-                        datagrams
+                let tick_start;
+                let datagrams_to_process = if echo.immediate() {
+                    let Some(datagram) = rx.recv().await else { break };
+                    tick_start = Instant::now();
+                    vec![datagram]
+                } else {
+                    // Wait for the next tick, then take this tick's share of the queued datagrams
+                    tick_interval.tick().await;
+                    tick_start = Instant::now();
+                    while let Ok(datagram) = rx.try_recv() {
+                        backlog.push_back(datagram);
                     }
+                    let batch = echo.take_tick(&mut backlog);
+                    tick_stats.lock().await.tick(batch.len(), backlog.len());
+                    batch
                 };
                 
                 // Echo each datagram back
-                for datagram in datagrams_to_process {
-                    match session_for_tick.send_datagram(datagram.clone()) {
+                for (datagram, recv_us) in datagrams_to_process {
+                    let send_us = now_micros();
+                    match session_for_tick.send_datagram(Bytes::from(stamp_echo(&datagram, recv_us, send_us))) {
                         Ok(_) => {
+                            tick_stats.lock().await.echoed(recv_us, send_us);
                             // Try to log the tick number if it's a JSON datagram
                             if let Ok(text) = std::str::from_utf8(&datagram) {
                                 if let Ok(json) = serde_json::from_str::<Value>(text) {
                                     if let Some(tick) = json.get("tick") {
-                                        log::info!("Echoed tick {} datagram", tick);
+                                        log::trace!("Echoed tick {} datagram", tick);
                                     }
                                 }
                             }
-                            
-                            // Log the timestamp for this tick
-                            let timestamp = chrono::Utc::now().timestamp();
-                            let measurement = format!("{},0\n", timestamp); // RTT measured on client
-                            
-                            // Write to file with mutex protection
-                            let mut file_guard = file.lock().await;
-                            if let Err(e) = file_guard.write_all(measurement.as_bytes()).await {
-                                log::error!("Error writing to file: {:?}", e);
-                            }
                         },
                         Err(e) => {
                             log::error!("Error echoing datagram: {:?}", e);
@@ -274,9 +336,10 @@ async fn run_session(session: Session, output_file: String, use_datagrams: bool)
                 
                 // Log time spent in this tick for debugging
                 let elapsed = tick_start.elapsed();
-                if elapsed > Duration::from_micros(TICK_DURATION_MICROS) {
+                if elapsed > tick_duration {
+                    tick_stats.lock().await.overrun();
                     log::warn!("Tick processing took {}µs, exceeding tick duration of {}µs", 
-                               elapsed.as_micros(), TICK_DURATION_MICROS);
+                               elapsed.as_micros(), tick_duration.as_micros());
                 }
             }
         });
@@ -284,8 +347,8 @@ async fn run_session(session: Session, output_file: String, use_datagrams: bool)
         // Wait for any task to complete (which means the connection is closing)
         tokio::select! {
             _ = receiver_task => log::info!("Datagram receiver task completed"),
-            _ = queue_task => log::info!("Datagram queue task completed"),
-            _ = tick_task => log::info!("Tick task completed"),
+            _ = tick_task => log::info!("Echo task completed"),
+            _ = sample_path(&session, &metrics) => (),
         }
         
         log::info!("Datagram session ended");
@@ -298,125 +361,116 @@ async fn run_session(session: Session, output_file: String, use_datagrams: bool)
         let (mut send, mut recv) = session.accept_bi().await?;
         log::info!("accepted stream");
 
-        let mut buf = vec![0u8; 1024];
+        // Messages are newline-framed, since a stream read can split or join them
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut framer = LineFramer::default();
         
         // Wait for the first simulation message to synchronize tick timing
         log::info!("waiting for first tick message from client...");
-        match recv.read(&mut buf).await? {
-            Some(size) => {
-                log::info!("received first tick message, starting tick loop");
-                // Echo back the first message immediately
-                let first_msg = &buf[..size];
-                send.write_all(first_msg).await?;
-                
-                // If message is JSON, try to extract tick number for logging
-                if let Ok(text) = std::str::from_utf8(first_msg) {
-                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(text) {
-                        if let Some(tick) = json.get("tick") {
-                            log::info!("first message is tick {}", tick);
-                        }
-                    }
-                }
-                
-                // Log the first measurement
-                let timestamp = chrono::Utc::now().timestamp();
-                let measurement = format!("{},0\n", timestamp);
-                file.write_all(measurement.as_bytes()).await?;
-            },
-            None => {
+        let (first_messages, recv_us) = loop {
+            let Some(size) = recv.read(&mut buf).await? else {
                 log::info!("client closed connection before sending first message");
                 return Ok(());
+            };
+            let messages = framer.push(&buf[..size]);
+            if !messages.is_empty() {
+                break (messages, now_micros());
             }
         };
+        metrics.received(first_messages.len() as u64);
+        log::info!("received first tick message, starting tick loop");
+        // Echo back the first message, and anything that arrived with it, immediately
+        for first_msg in &first_messages {
+            let send_us = now_micros();
+            send.write_all(&frame_line(&stamp_echo(first_msg, recv_us, send_us))).await?;
+            stats.lock().await.echoed(recv_us, send_us);
+        }
         
-        // Set up message processing channel
-        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(100);
+        // If message is JSON, try to extract tick number for logging
+        if let Ok(text) = std::str::from_utf8(&first_messages[0]) {
+            if let Ok(json) = serde_json::from_str::<serde_json::Value>(text) {
+                if let Some(tick) = json.get("tick") {
+                    log::info!("first message is tick {}", tick);
+                }
+            }
+        }
         
-        // Shared message queue for tick processing
-        let message_queue = Arc::new(Mutex::new(Vec::<Vec<u8>>::new()));
-        let message_queue_clone = message_queue.clone();
+        // Set up message processing channel, each message tagged with its arrival time
+        let (tx, mut rx) = mpsc::channel::<(Vec<u8>, u64)>(100);
         
         // Receiver task: process incoming messages and add them to the queue
+        let received = metrics.clone();
         let receiver_task = tokio::spawn(async move {
-            let mut buffer = vec![0u8; 1024];
-            
-            while let Some(size) = match recv.read(&mut buffer).await {
+            'read: while let Some(size) = match recv.read(&mut buf).await {
                 Ok(s) => s,
                 Err(e) => {
                     log::error!("Error reading from stream: {:?}", e);
                     None
                 }
             } {
-                let message = buffer[..size].to_vec();
+                let recv_us = now_micros();
+                let messages = framer.push(&buf[..size]);
+                received.received(messages.len() as u64);
                 
-                // Log the message if it's valid JSON with a tick number
-                if let Ok(text) = std::str::from_utf8(&message) {
-                    if let Ok(json) = serde_json::from_str::<Value>(text) {
-                        if let Some(tick) = json.get("tick") {
-                            log::info!("Received tick {} message", tick);
+                for message in messages {
+                    // Log the message if it's valid JSON with a tick number
+                    if let Ok(text) = std::str::from_utf8(&message) {
+                        if let Ok(json) = serde_json::from_str::<Value>(text) {
+                            if let Some(tick) = json.get("tick") {
+                                log::trace!("Received tick {} message", tick);
+                            }
                         }
                     }
-                }
-                
-                // Queue the message for processing in the next tick
-                if let Err(e) = tx.send(message).await {
-                    log::error!("Failed to send message to processing queue: {}", e);
-                    break;
+                    
+                    // Queue the message for processing in the next tick
+                    if let Err(e) = tx.send((message, recv_us)).await {
+                        log::error!("Failed to send message to processing queue: {}", e);
+                        break 'read;
+                    }
                 }
             }
             
             log::info!("Client disconnected or stream closed");
         });
         
-        // Message processor: collect messages from the channel into the queue
-        let processor_task = tokio::spawn(async move {
-            while let Some(message) = rx.recv().await {
-                let mut queue = message_queue_clone.lock().await;
-                queue.push(message);
-            }
-        });
-        
-        // Tick task: process messages on each tick
+        // Echo task: echo each message as it arrives, or batch them on the server tick
+        let tick_stats = stats.clone();
         let tick_task = tokio::spawn(async move {
-            let tick_duration = Duration::from_micros(TICK_DURATION_MICROS);
             let mut tick_interval = interval(tick_duration);
-            let file = Arc::new(Mutex::new(file));
+            let mut backlog = VecDeque::new();
             
             loop {
-                // Wait for the next tick
-                tick_interval.tick().await;
-                let tick_start = Instant::now();
-                
-                // Process all messages in the queue
-                let messages_to_process = {
-                    let mut queue = message_queue.lock().await;
-                    let messages = queue.clone();
-                    queue.clear();
-                    messages
+                let tick_start;
+                let messages_to_process = if echo.immediate() {
+                    let Some(message) = rx.recv().await else { return };
+                    tick_start = Instant::now();
+                    vec![message]
+                } else {
+                    // Wait for the next tick, then take this tick's share of the queued messages
+                    tick_interval.tick().await;
+                    tick_start = Instant::now();
+                    while let Ok(message) = rx.try_recv() {
+                        backlog.push_back(message);
+                    }
+                    let batch = echo.take_tick(&mut backlog);
+                    tick_stats.lock().await.tick(batch.len(), backlog.len());
+                    batch
                 };
                 
                 // Echo each message back
-                for message in messages_to_process {
-                    match send.write_all(&message).await {
+                for (message, recv_us) in messages_to_process {
+                    let send_us = now_micros();
+                    match send.write_all(&frame_line(&stamp_echo(&message, recv_us, send_us))).await {
                         Ok(_) => {
+                            tick_stats.lock().await.echoed(recv_us, send_us);
                             // Try to log the tick number if it's a JSON message
                             if let Ok(text) = std::str::from_utf8(&message) {
                                 if let Ok(json) = serde_json::from_str::<Value>(text) {
                                     if let Some(tick) = json.get("tick") {
-                                        log::info!("Echoed tick {} message", tick);
+                                        log::trace!("Echoed tick {} message", tick);
                                     }
                                 }
                             }
-                            
-                            // Log the timestamp for this tick
-                            let timestamp = chrono::Utc::now().timestamp();
-                            let measurement = format!("{},0\n", timestamp); // RTT measured on client
-                            
-                            // Write to file with mutex protection
-                            let mut file_guard = file.lock().await;
-                            if let Err(e) = file_guard.write_all(measurement.as_bytes()).await {
-                                log::error!("Error writing to file: {:?}", e);
-                            }
                         },
                         Err(e) => {
                             log::error!("Error sending message: {}", e);
@@ -427,9 +481,10 @@ async fn run_session(session: Session, output_file: String, use_datagrams: bool)
                 
                 // Log time spent in this tick for debugging
                 let elapsed = tick_start.elapsed();
-                if elapsed > Duration::from_micros(TICK_DURATION_MICROS) {
+                if elapsed > tick_duration {
+                    tick_stats.lock().await.overrun();
                     log::warn!("Tick processing took {}µs, exceeding tick duration of {}µs", 
-                            elapsed.as_micros(), TICK_DURATION_MICROS);
+                            elapsed.as_micros(), tick_duration.as_micros());
                 }
             }
         });
@@ -437,12 +492,21 @@ async fn run_session(session: Session, output_file: String, use_datagrams: bool)
         // Wait for any task to complete (which means the connection is closing)
         tokio::select! {
             _ = receiver_task => log::info!("Receiver task completed"),
-            _ = processor_task => log::info!("Processor task completed"),
-            _ = tick_task => log::info!("Tick task completed"),
+            _ = tick_task => log::info!("Echo task completed"),
+            _ = sample_path(&session, &metrics) => (),
         }
 
         log::info!("Stream closed");
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+// Keep the connection's quinn path stats current for /metrics; runs until the session ends
+async fn sample_path(conn: &quinn::Connection, metrics: &SessionMetrics) {
+    let mut every = interval(Duration::from_secs(1));
+    loop {
+        every.tick().await;
+        metrics.quic(conn);
+    }
+}
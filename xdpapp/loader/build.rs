@@ -0,0 +1,23 @@
+// Compile the kernel program with clang so the loader can embed it.
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    let source = PathBuf::from("../xdp_drop.c");
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("xdp_drop.o");
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    // The uapi headers live under the multiarch include directory on Debian and Ubuntu
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let status = Command::new(env::var("CLANG").unwrap_or_else(|_| "clang".to_string()))
+        .args(["-O2", "-g", "-Wall", "-target", "bpf"])
+        .arg(format!("-I/usr/include/{}-linux-gnu", arch))
+        .arg("-c")
+        .arg(&source)
+        .arg("-o")
+        .arg(&out)
+        .status()
+        .expect("failed to run clang; install clang and libbpf-dev, or set CLANG");
+    assert!(status.success(), "clang failed to compile {}", source.display());
+}
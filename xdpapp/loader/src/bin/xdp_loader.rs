@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use aya::programs::{Xdp, XdpFlags};
+use aya::{include_bytes_aligned, EbpfLoader};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
+use xdp_loader::maps::{DropMaps, PIN_PATH};
+use xdp_loader::options::DropOptions;
+
+/// Names of the maps pinned by xdp_drop.c
+const MAPS: [&str; 3] = ["config", "ports", "counters"];
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Generic XDP; works on any interface, including veth
+    Skb,
+    /// Native XDP in the NIC driver
+    Drv,
+}
+
+/// Drop a share of benchmark packets in XDP, adjustable while attached.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// bpffs directory the maps are pinned in
+    #[arg(long, default_value = PIN_PATH)]
+    pin_path: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Attach to an interface and stay in the foreground; Ctrl-C detaches
+    Attach {
+        #[arg(long)]
+        iface: String,
+
+        #[arg(long, value_enum, default_value_t = Mode::Skb)]
+        mode: Mode,
+
+        #[command(flatten)]
+        drop: DropOptions,
+
+        /// Write the per-port counters here every --stats-interval-ms
+        #[arg(long, default_value = "xdp_stats.json")]
+        stats_file: PathBuf,
+
+        #[arg(long, default_value = "1000")]
+        stats_interval_ms: u64,
+    },
+    /// Change what the attached program drops
+    Set {
+        #[command(flatten)]
+        drop: DropOptions,
+
+        /// Zero the counters as well
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Print the per-port counters
+    Stats {
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+fn save_stats(path: &Path, iface: &str, maps: &DropMaps) -> Result<(), Box<dyn std::error::Error>> {
+    // Read back rather than taken from the arguments, since `set` may have changed it
+    let stats = json!({
+        "interface": iface,
+        "config": maps.active()?,
+        "ports": maps.stats()?,
+    });
+    fs::write(path, serde_json::to_string_pretty(&stats)?)?;
+    Ok(())
+}
+
+fn attach(
+    pin_path: &Path,
+    iface: &str,
+    mode: Mode,
+    drop: &DropOptions,
+    stats_file: &Path,
+    interval: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(pin_path)?;
+    let mut ebpf = EbpfLoader::new()
+        .map_pin_path(pin_path)
+        .load(include_bytes_aligned!(concat!(env!("OUT_DIR"), "/xdp_drop.o")))?;
+    let program: &mut Xdp = ebpf.program_mut("xdp_drop").ok_or("xdp_drop program missing from object")?.try_into()?;
+    program.load()?;
+    let flags = match mode {
+        Mode::Skb => XdpFlags::SKB_MODE,
+        Mode::Drv => XdpFlags::DRV_MODE,
+    };
+    let link = program.attach(iface, flags)?;
+
+    // Maps left pinned by an earlier run are reused, so start from a clean slate
+    let mut maps = DropMaps::open(pin_path)?;
+    maps.configure(drop)?;
+    maps.reset_counters()?;
+    println!(
+        "Attached to {} ({:?}): dropping {}% of {:?} on ports {:?} ({:?})",
+        iface, mode, drop.drop_rate, drop.protocol, drop.ports, drop.direction
+    );
+    println!("Maps pinned in {}; change them with `xdp_loader set`", pin_path.display());
+
+    let running = Arc::new(AtomicBool::new(true));
+    let handler = running.clone();
+    ctrlc::set_handler(move || handler.store(false, Ordering::SeqCst))?;
+    while running.load(Ordering::SeqCst) {
+        thread::sleep(interval);
+        if let Err(e) = save_stats(stats_file, iface, &maps) {
+            eprintln!("Failed to write {}: {}", stats_file.display(), e);
+        }
+    }
+    save_stats(stats_file, iface, &maps)?;
+    println!("Counters saved to {}", stats_file.display());
+
+    program.detach(link)?;
+    for name in MAPS {
+        fs::remove_file(pin_path.join(name))?;
+    }
+    // Only removed if nothing else was pinned alongside
+    let _ = fs::remove_dir(pin_path);
+    println!("Detached from {}", iface);
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    match args.command {
+        Command::Attach { iface, mode, drop, stats_file, stats_interval_ms } => attach(
+            &args.pin_path,
+            &iface,
+            mode,
+            &drop,
+            &stats_file,
+            Duration::from_millis(stats_interval_ms.max(1)),
+        ),
+        Command::Set { drop, reset } => {
+            let mut maps = DropMaps::open(&args.pin_path)?;
+            maps.configure(&drop)?;
+            if reset {
+                maps.reset_counters()?;
+            }
+            println!("Dropping {}% of {:?} on ports {:?} ({:?})", drop.drop_rate, drop.protocol, drop.ports, drop.direction);
+            Ok(())
+        }
+        Command::Stats { json } => {
+            let maps = DropMaps::open(&args.pin_path)?;
+            let (config, stats) = (maps.active()?, maps.stats()?);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&json!({ "config": config, "ports": stats }))?);
+                return Ok(());
+            }
+            println!(
+                "Dropping {}% of {} on ports {:?} ({})",
+                config.drop_rate, config.protocol, config.ports, config.direction
+            );
+            println!("{:>6} {:>5} {:>12} {:>12} {:>9}", "port", "proto", "passed", "dropped", "dropped%");
+            for port in stats {
+                println!(
+                    "{:>6} {:>5} {:>12} {:>12} {:>9.3}",
+                    port.port, port.protocol, port.counters.passed, port.counters.dropped, port.drop_ratio * 100.0
+                );
+            }
+            Ok(())
+        }
+    }
+}
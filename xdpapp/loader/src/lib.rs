@@ -0,0 +1,2 @@
+pub mod maps;
+pub mod options;
@@ -0,0 +1,152 @@
+// The maps shared with xdp_drop.c. The loader pins them under PIN_PATH, so `set`
+// and `stats` can reach them from another process while the program stays attached.
+use std::collections::BTreeMap;
+use std::path::Path;
+use aya::maps::{Array, HashMap, Map, MapData, MapError, PerCpuHashMap};
+use serde::Serialize;
+use crate::options::{DropOptions, Protocol};
+
+/// bpffs directory the maps are pinned in
+pub const PIN_PATH: &str = "/sys/fs/bpf/ons_xdp";
+
+pub const MATCH_DST_PORT: u8 = 1;
+pub const MATCH_SRC_PORT: u8 = 2;
+
+/// `struct drop_config` in xdp_drop.c
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DropConfig {
+    pub protocol: u8,
+    pub direction: u8,
+    pub pad: u16,
+    pub drop_threshold: u32,
+}
+
+/// `struct port_key` in xdp_drop.c
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PortKey {
+    pub port: u16,
+    pub protocol: u8,
+    pub pad: u8,
+}
+
+/// `struct port_counters` in xdp_drop.c
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct PortCounters {
+    pub passed: u64,
+    pub dropped: u64,
+    pub passed_bytes: u64,
+    pub dropped_bytes: u64,
+}
+
+// SAFETY: plain repr(C) structs of integers with explicit padding, matching the C layout
+unsafe impl aya::Pod for DropConfig {}
+unsafe impl aya::Pod for PortKey {}
+unsafe impl aya::Pod for PortCounters {}
+
+/// Counters for one port, summed over CPUs
+#[derive(Debug, Clone, Serialize)]
+pub struct PortStats {
+    pub port: u16,
+    pub protocol: &'static str,
+    #[serde(flatten)]
+    pub counters: PortCounters,
+    /// Fraction of matching packets dropped so far
+    pub drop_ratio: f64,
+}
+
+/// What the attached program is currently dropping, read back from the maps
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveConfig {
+    pub protocol: &'static str,
+    pub ports: Vec<u16>,
+    pub direction: &'static str,
+    /// Percent
+    pub drop_rate: f64,
+}
+
+pub struct DropMaps {
+    config: Array<MapData, DropConfig>,
+    ports: HashMap<MapData, u16, u8>,
+    counters: PerCpuHashMap<MapData, PortKey, PortCounters>,
+}
+
+impl DropMaps {
+    /// Open the maps pinned in `dir` by a running loader.
+    pub fn open(dir: &Path) -> Result<Self, MapError> {
+        let pinned = |name: &str| MapData::from_pin(dir.join(name)).and_then(Map::from_map_data);
+        Ok(DropMaps {
+            config: Array::try_from(pinned("config")?)?,
+            ports: HashMap::try_from(pinned("ports")?)?,
+            counters: PerCpuHashMap::try_from(pinned("counters")?)?,
+        })
+    }
+
+    /// Replace the protocol, direction, drop probability and port list.
+    pub fn configure(&mut self, options: &DropOptions) -> Result<(), MapError> {
+        // Clear the config first so no packet sees the new ports with the old rate
+        self.config.set(0, DropConfig::default(), 0)?;
+        let stale: Vec<u16> = self.ports.keys().collect::<Result<_, _>>()?;
+        for port in stale.iter().filter(|port| !options.ports.contains(port)) {
+            self.ports.remove(port)?;
+        }
+        for port in &options.ports {
+            self.ports.insert(port, 1, 0)?;
+        }
+        self.config.set(0, options.config(), 0)
+    }
+
+    pub fn active(&self) -> Result<ActiveConfig, MapError> {
+        let config = self.config.get(&0, 0)?;
+        let mut ports: Vec<u16> = self.ports.keys().collect::<Result<_, _>>()?;
+        ports.sort_unstable();
+        let direction = match config.direction {
+            MATCH_DST_PORT => "dst",
+            MATCH_SRC_PORT => "src",
+            d if d == MATCH_DST_PORT | MATCH_SRC_PORT => "both",
+            _ => "none",
+        };
+        Ok(ActiveConfig {
+            protocol: Protocol::name(config.protocol),
+            ports,
+            direction,
+            drop_rate: config.drop_threshold as f64 / u32::MAX as f64 * 100.0,
+        })
+    }
+
+    pub fn reset_counters(&mut self) -> Result<(), MapError> {
+        let keys: Vec<PortKey> = self.counters.keys().collect::<Result<_, _>>()?;
+        for key in &keys {
+            self.counters.remove(key)?;
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<Vec<PortStats>, MapError> {
+        let mut totals: BTreeMap<PortKey, PortCounters> = BTreeMap::new();
+        for entry in self.counters.iter() {
+            let (key, per_cpu) = entry?;
+            let total = totals.entry(key).or_default();
+            for cpu in per_cpu.iter() {
+                total.passed += cpu.passed;
+                total.dropped += cpu.dropped;
+                total.passed_bytes += cpu.passed_bytes;
+                total.dropped_bytes += cpu.dropped_bytes;
+            }
+        }
+        Ok(totals
+            .into_iter()
+            .map(|(key, counters)| {
+                let packets = counters.passed + counters.dropped;
+                PortStats {
+                    port: key.port,
+                    protocol: Protocol::name(key.protocol),
+                    counters,
+                    drop_ratio: if packets == 0 { 0.0 } else { counters.dropped as f64 / packets as f64 },
+                }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,82 @@
+// What the XDP program drops, as given on the command line.
+use clap::ValueEnum;
+use crate::maps::{DropConfig, MATCH_DST_PORT, MATCH_SRC_PORT};
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    /// TCP and UDP
+    Any,
+}
+
+impl Protocol {
+    pub fn number(self) -> u8 {
+        match self {
+            Protocol::Tcp => IPPROTO_TCP,
+            Protocol::Udp => IPPROTO_UDP,
+            Protocol::Any => 0,
+        }
+    }
+
+    pub fn name(number: u8) -> &'static str {
+        match number {
+            IPPROTO_TCP => "tcp",
+            IPPROTO_UDP => "udp",
+            0 => "any",
+            _ => "other",
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Packets addressed to one of the ports, i.e. arriving at the server
+    Dst,
+    /// Packets sent from one of the ports, i.e. arriving at the client
+    Src,
+    Both,
+}
+
+/// Drop settings shared by `attach` and `set`
+#[derive(clap::Args, Debug, Clone)]
+pub struct DropOptions {
+    #[arg(long, value_enum, default_value_t = Protocol::Udp)]
+    pub protocol: Protocol,
+
+    /// Ports to drop on, comma-separated; no ports means nothing is dropped
+    #[arg(long, value_delimiter = ',')]
+    pub ports: Vec<u16>,
+
+    /// Which port of the packet has to match
+    #[arg(long, value_enum, default_value_t = Direction::Dst)]
+    pub direction: Direction,
+
+    /// Chance of dropping a matching packet, in percent
+    #[arg(long, default_value = "0", value_parser = parse_rate)]
+    pub drop_rate: f64,
+}
+
+impl DropOptions {
+    pub fn config(&self) -> DropConfig {
+        let direction = match self.direction {
+            Direction::Dst => MATCH_DST_PORT,
+            Direction::Src => MATCH_SRC_PORT,
+            Direction::Both => MATCH_DST_PORT | MATCH_SRC_PORT,
+        };
+        // The program drops when a random u32 falls below the threshold
+        let threshold = (self.drop_rate / 100.0 * u32::MAX as f64).round() as u32;
+        DropConfig { protocol: self.protocol.number(), direction, pad: 0, drop_threshold: threshold }
+    }
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    let percent: f64 = value.trim_end_matches('%').parse().map_err(|_| format!("invalid percentage '{}'", value))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("drop rate '{}' is outside 0-100", value));
+    }
+    Ok(percent)
+}
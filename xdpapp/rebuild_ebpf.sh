@@ -1,4 +1,7 @@
 #!/bin/bash
+# Attach the XDP drop program to eno2, dropping one in <loss_rate> packets to <port>.
+# The program stays attached while this runs; Ctrl-C detaches it. To change the
+# rate without reattaching, use `xdp_loader set` from another shell (see HOW_TO_RUN).
 
 if [ "$#" -ne 3 ]; then
     echo "Usage: $0 <protocol> <port> <loss_rate>"
@@ -9,24 +12,23 @@ PROTOCOL=$1
 PORT=$2
 LOSS_RATE=$3
 
-if [[ "$PROTOCOL" == "tcp" ]]; then
-    PROTOCOL_VALUE=IPPROTO_TCP
-elif [[ "$PROTOCOL" == "udp" ]]; then
-    PROTOCOL_VALUE=IPPROTO_UDP
-else
+if [[ "$PROTOCOL" != "tcp" && "$PROTOCOL" != "udp" ]]; then
     echo "Invalid protocol. Use 'tcp' or 'udp'."
     exit 1
 fi
 
-cd /users/dorlando/ons/xdpapp
+# loss_rate is "one in N", as the old DROP_RATE macro was; 0 drops nothing
+if [[ "$LOSS_RATE" -eq 0 ]]; then
+    DROP_PERCENT=0
+else
+    DROP_PERCENT=$(awk "BEGIN { print 100 / $LOSS_RATE }")
+fi
 
-# Compile the XDP program with dynamic parameters
-clang -O2 -Wall -target bpf \
-    -DDROP_PROTOCOL=$PROTOCOL_VALUE \
-    -DDROP_PORT=$PORT \
-    -DDROP_RATE=$LOSS_RATE \
-    -c xdp_kernel.c -o xdp_kernel.o
+cd /users/dorlando/ons/xdpapp/loader
+cargo build --release
 
-# Detach any existing XDP program and attach the new one
+# Detach anything attached the old way before loading
 ip link set dev eno2 xdpgeneric off
-ip link set dev eno2 xdpgeneric obj xdp_kernel.o sec xdp_sock
+
+./target/release/xdp_loader attach --iface eno2 --mode skb \
+    --protocol "$PROTOCOL" --ports "$PORT" --drop-rate "$DROP_PERCENT"
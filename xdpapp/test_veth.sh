@@ -0,0 +1,79 @@
+#!/bin/bash
+# Check the XDP drop program on a veth pair, without touching a real NIC.
+#
+# Creates namespace ons_xdp_test holding veth1 (10.200.0.2) and leaves veth0
+# (10.200.0.1) in the host, attaches the program to veth0 in skb mode, sends UDP
+# from the namespace to the host and checks the counters. Needs root.
+# Usage: sudo ./test_veth.sh [drop_rate_percent] [packets]
+set -euo pipefail
+
+DROP_RATE=${1:-10}
+PACKETS=${2:-2000}
+PORT=4444
+NS=ons_xdp_test
+PIN_PATH=/sys/fs/bpf/ons_xdp_test
+STATS=$(mktemp)
+LOADER=./loader/target/release/xdp_loader
+
+cd "$(dirname "$0")"
+cargo build --release --manifest-path loader/Cargo.toml
+
+cleanup() {
+    [[ -n "${LOADER_PID:-}" ]] && kill -INT "$LOADER_PID" 2>/dev/null && wait "$LOADER_PID" || true
+    ip netns del "$NS" 2>/dev/null || true
+    ip link del veth0 2>/dev/null || true
+    rm -f "$STATS"
+}
+trap cleanup EXIT
+
+ip netns add "$NS"
+ip link add veth0 type veth peer name veth1
+ip link set veth1 netns "$NS"
+ip addr add 10.200.0.1/24 dev veth0
+ip link set veth0 up
+ip -n "$NS" addr add 10.200.0.2/24 dev veth1
+ip -n "$NS" link set veth1 up
+ip -n "$NS" link set lo up
+
+"$LOADER" --pin-path "$PIN_PATH" attach --iface veth0 --mode skb \
+    --protocol udp --ports "$PORT" --drop-rate "$DROP_RATE" --stats-file "$STATS" &
+LOADER_PID=$!
+sleep 1
+
+# Nothing needs to listen: the counters are taken before the stack sees the packet
+ip netns exec "$NS" python3 - "$PACKETS" "$PORT" <<'PY'
+import socket, sys
+packets, port = int(sys.argv[1]), int(sys.argv[2])
+s = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+for i in range(packets):
+    s.sendto(i.to_bytes(4, "big"), ("10.200.0.1", port))
+PY
+sleep 0.5
+
+"$LOADER" --pin-path "$PIN_PATH" stats --json | python3 -c "
+import json, sys
+stats = json.load(sys.stdin)
+port = next(p for p in stats['ports'] if p['port'] == $PORT)
+total = port['passed'] + port['dropped']
+print(f\"{total} packets: {port['passed']} passed, {port['dropped']} dropped ({port['drop_ratio'] * 100:.2f}%)\")
+assert total == $PACKETS, f'expected $PACKETS packets, counted {total}'
+assert abs(port['drop_ratio'] * 100 - $DROP_RATE) < max(3.0, $DROP_RATE * 0.3), 'drop ratio far from $DROP_RATE%'
+"
+
+# Changing the rate on the running program takes effect without reattaching
+"$LOADER" --pin-path "$PIN_PATH" set --protocol udp --ports "$PORT" --drop-rate 0 --reset
+ip netns exec "$NS" python3 -c "
+import socket
+s = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+for i in range(100): s.sendto(b'x', ('10.200.0.1', $PORT))
+"
+sleep 0.5
+"$LOADER" --pin-path "$PIN_PATH" stats --json | python3 -c "
+import json, sys
+stats = json.load(sys.stdin)
+port = next(p for p in stats['ports'] if p['port'] == $PORT)
+print(f\"after --reset at 0%: {port['passed']} passed, {port['dropped']} dropped\")
+assert port['passed'] == 100, f'expected 100 packets passed, counted {port[\"passed\"]}'
+assert port['dropped'] == 0, f'expected nothing dropped at 0%, counted {port[\"dropped\"]}'
+"
+echo "PASS"
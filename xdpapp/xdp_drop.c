@@ -0,0 +1,162 @@
+// Random packet drop for the benchmark ports, configured at runtime through BPF maps.
+//
+// The loader (xdpapp/loader) pins the maps under /sys/fs/bpf/ons_xdp so the drop
+// probability, protocol and ports can be changed while the program stays attached,
+// and the per-port counters read back by the benchmark.
+#include <linux/bpf.h>
+#include <linux/if_ether.h>
+#include <linux/in.h>
+#include <linux/ip.h>
+#include <linux/ipv6.h>
+#include <linux/tcp.h>
+#include <linux/udp.h>
+#include <bpf/bpf_endian.h>
+#include <bpf/bpf_helpers.h>
+
+#define MATCH_DST_PORT 1
+#define MATCH_SRC_PORT 2
+
+// Keep in step with DropConfig in loader/src/maps.rs
+struct drop_config {
+    __u8 protocol;        // IPPROTO_TCP, IPPROTO_UDP, or 0 for both
+    __u8 direction;       // MATCH_DST_PORT and/or MATCH_SRC_PORT
+    __u16 pad;
+    __u32 drop_threshold; // drop when bpf_get_prandom_u32() < threshold; 0 never drops
+};
+
+// Keep in step with PortKey in loader/src/maps.rs
+struct port_key {
+    __u16 port;
+    __u8 protocol;
+    __u8 pad;
+};
+
+// Keep in step with PortCounters in loader/src/maps.rs
+struct port_counters {
+    __u64 passed;
+    __u64 dropped;
+    __u64 passed_bytes;
+    __u64 dropped_bytes;
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, __u32);
+    __type(value, struct drop_config);
+    __uint(pinning, LIBBPF_PIN_BY_NAME);
+} config SEC(".maps");
+
+// Ports to act on, in host byte order
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 64);
+    __type(key, __u16);
+    __type(value, __u8);
+    __uint(pinning, LIBBPF_PIN_BY_NAME);
+} ports SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_HASH);
+    __uint(max_entries, 256);
+    __type(key, struct port_key);
+    __type(value, struct port_counters);
+    __uint(pinning, LIBBPF_PIN_BY_NAME);
+} counters SEC(".maps");
+
+static __always_inline void count(__u16 port, __u8 protocol, __u64 bytes, int dropped) {
+    struct port_key key = { .port = port, .protocol = protocol };
+    struct port_counters *c = bpf_map_lookup_elem(&counters, &key);
+    if (!c) {
+        struct port_counters zero = {};
+        bpf_map_update_elem(&counters, &key, &zero, BPF_NOEXIST);
+        c = bpf_map_lookup_elem(&counters, &key);
+        if (!c)
+            return;
+    }
+    // Per-CPU values, so plain increments are safe
+    if (dropped) {
+        c->dropped++;
+        c->dropped_bytes += bytes;
+    } else {
+        c->passed++;
+        c->passed_bytes += bytes;
+    }
+}
+
+// The configured port this packet is to or from, or 0 if none
+static __always_inline __u16 matched_port(__u8 direction, __u16 src, __u16 dst) {
+    if ((direction & MATCH_DST_PORT) && bpf_map_lookup_elem(&ports, &dst))
+        return dst;
+    if ((direction & MATCH_SRC_PORT) && bpf_map_lookup_elem(&ports, &src))
+        return src;
+    return 0;
+}
+
+SEC("xdp")
+int xdp_drop(struct xdp_md *ctx) {
+    void *data = (void *)(long)ctx->data;
+    void *data_end = (void *)(long)ctx->data_end;
+    __u32 zero = 0;
+
+    struct drop_config *cfg = bpf_map_lookup_elem(&config, &zero);
+    if (!cfg)
+        return XDP_PASS;
+
+    struct ethhdr *eth = data;
+    if ((void *)(eth + 1) > data_end)
+        return XDP_PASS;
+
+    __u8 protocol;
+    void *l4;
+    if (eth->h_proto == bpf_htons(ETH_P_IP)) {
+        struct iphdr *ip = (void *)(eth + 1);
+        if ((void *)(ip + 1) > data_end || ip->ihl < 5)
+            return XDP_PASS;
+        protocol = ip->protocol;
+        l4 = (void *)ip + ip->ihl * 4;
+    } else if (eth->h_proto == bpf_htons(ETH_P_IPV6)) {
+        // Extension headers are not followed; the benchmark traffic doesn't use them
+        struct ipv6hdr *ip6 = (void *)(eth + 1);
+        if ((void *)(ip6 + 1) > data_end)
+            return XDP_PASS;
+        protocol = ip6->nexthdr;
+        l4 = ip6 + 1;
+    } else {
+        return XDP_PASS;
+    }
+
+    if (cfg->protocol && protocol != cfg->protocol)
+        return XDP_PASS;
+
+    __u16 src, dst;
+    if (protocol == IPPROTO_TCP) {
+        struct tcphdr *tcp = l4;
+        if ((void *)(tcp + 1) > data_end)
+            return XDP_PASS;
+        src = bpf_ntohs(tcp->source);
+        dst = bpf_ntohs(tcp->dest);
+    } else if (protocol == IPPROTO_UDP) {
+        struct udphdr *udp = l4;
+        if ((void *)(udp + 1) > data_end)
+            return XDP_PASS;
+        src = bpf_ntohs(udp->source);
+        dst = bpf_ntohs(udp->dest);
+    } else {
+        return XDP_PASS;
+    }
+
+    __u16 port = matched_port(cfg->direction, src, dst);
+    if (!port)
+        return XDP_PASS;
+
+    __u64 bytes = data_end - data;
+    if (cfg->drop_threshold && bpf_get_prandom_u32() < cfg->drop_threshold) {
+        count(port, protocol, bytes, 1);
+        return XDP_DROP;
+    }
+    count(port, protocol, bytes, 0);
+    return XDP_PASS;
+}
+
+char _license[] SEC("license") = "GPL";